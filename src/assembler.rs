@@ -0,0 +1,886 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::decoder;
+use crate::disassembler::{csr_number, register_number};
+use crate::instruction::*;
+use crate::memory::Memory;
+
+/// An assembly error, with the 1-based source line it was found on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+/// An image that does not fit in the memory it was loaded into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadError {
+    pub base: u32,
+    pub size: usize,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "image at 0x{:08x} ({} bytes) does not fit in memory", self.base, self.size)
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Assembled program: `bytes` belong at address `base`.
+#[derive(Debug, Clone, Default)]
+pub struct Image {
+    pub base: u32,
+    pub bytes: Vec<u8>,
+    pub symbols: HashMap<String, u32>,
+}
+
+impl Image {
+    /// Address one past the last assembled byte.
+    pub fn end(&self) -> u32 {
+        self.base.saturating_add(self.bytes.len() as u32)
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<u32> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Copy the image into memory at its base address.
+    pub fn load_into(&self, memory: &mut Memory) -> Result<(), LoadError> {
+        if self.base as usize + self.bytes.len() > memory.size() {
+            return Err(LoadError { base: self.base, size: self.bytes.len() });
+        }
+        memory.write_bytes(self.base as usize, &self.bytes);
+        Ok(())
+    }
+}
+
+/// Assemble RV32 source text.
+///
+/// Supports every instruction the decoder implements, labels, the common pseudo-instructions
+/// (`li`, `la`, `call`, `tail`, `ret`, `mv`, `j`, `beqz`, `csrr`, ...), the directives
+/// `.word .half .byte .ascii .asciz .zero .align .balign .org .equ` and `%hi`/`%lo` operators.
+/// Assembly starts at address 0 unless an `.org` precedes the first emitted byte.
+pub fn assemble(source: &str) -> Result<Image, AssembleError> {
+    let statements = parse(source)?;
+    let mut assembler = Assembler {
+        symbols: HashMap::new(),
+        li_sizes: HashMap::new(),
+        output: Output::default(),
+        final_pass: false,
+    };
+    assembler.pass(&statements)?;
+    assembler.output = Output::default();
+    assembler.final_pass = true;
+    assembler.pass(&statements)?;
+
+    let output = assembler.output;
+    Ok(Image {
+        base: output.base.unwrap_or(output.location),
+        bytes: output.bytes,
+        symbols: assembler.symbols.into_iter().map(|(name, value)| (name, value as u32)).collect(),
+    })
+}
+
+enum Kind {
+    Label(String),
+    Directive(String, String),
+    Instruction(String, String),
+}
+
+struct Statement {
+    line: usize,
+    kind: Kind,
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'
+}
+
+/// Remove a trailing `#` or `//` comment, ignoring comment characters inside quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' | '\'' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            '/' if !in_string && line[i..].starts_with("//") => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse(source: &str) -> Result<Vec<Statement>, AssembleError> {
+    let mut statements = Vec::new();
+    for (index, raw) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut rest = strip_comment(raw).trim();
+
+        // Any number of labels may precede the statement
+        while let Some(colon) = rest.find(':') {
+            let name = rest[..colon].trim();
+            if name.is_empty() || !name.chars().all(is_symbol_char) || name.starts_with(|c: char| c.is_ascii_digit()) {
+                break;
+            }
+            statements.push(Statement { line, kind: Kind::Label(name.to_string()) });
+            rest = rest[colon + 1..].trim();
+        }
+        if rest.is_empty() {
+            continue;
+        }
+
+        let (head, tail) = match rest.find(char::is_whitespace) {
+            Some(split) => (&rest[..split], rest[split..].trim()),
+            None => (rest, ""),
+        };
+        let head = head.to_ascii_lowercase();
+        let kind = if head.starts_with('.') {
+            Kind::Directive(head, tail.to_string())
+        } else {
+            Kind::Instruction(head, tail.to_string())
+        };
+        statements.push(Statement { line, kind });
+    }
+    Ok(statements)
+}
+
+/// Split comma-separated operands, keeping commas inside quotes and parentheses.
+fn split_operands(text: &str) -> Vec<&str> {
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => depth -= 1,
+            ',' if !in_string && depth == 0 => {
+                operands.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !text.trim().is_empty() {
+        operands.push(text[start..].trim());
+    }
+    operands
+}
+
+/// Decode the contents of a `"..."` string literal.
+fn parse_string(text: &str) -> Result<Vec<u8>, String> {
+    let inner = text
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a string literal, found `{}`", text))?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('r') => b'\r',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('"') => b'"',
+            Some('\'') => b'\'',
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                u8::from_str_radix(&hex, 16).map_err(|_| format!("bad escape `\\x{}`", hex))?
+            }
+            other => return Err(format!("unknown escape `\\{}`", other.unwrap_or(' '))),
+        };
+        bytes.push(escaped);
+    }
+    Ok(bytes)
+}
+
+enum EvalError {
+    /// A symbol that is not (yet) defined
+    Unresolved(String),
+    Syntax(String),
+}
+
+/// Recursive-descent parser and evaluator for operand expressions.
+struct Expression<'a> {
+    text: &'a [u8],
+    position: usize,
+    symbols: &'a HashMap<String, i64>,
+    pc: i64,
+}
+
+impl Expression<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.text.get(self.position).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.text[self.position..].starts_with(token.as_bytes()) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn syntax<T>(&self, message: &str) -> Result<T, EvalError> {
+        Err(EvalError::Syntax(format!("{} in `{}`", message, String::from_utf8_lossy(self.text))))
+    }
+
+    fn or(&mut self) -> Result<i64, EvalError> {
+        let mut value = self.and()?;
+        while self.eat("|") {
+            value |= self.and()?;
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<i64, EvalError> {
+        let mut value = self.additive()?;
+        while self.eat("&") {
+            value &= self.additive()?;
+        }
+        Ok(value)
+    }
+
+    fn additive(&mut self) -> Result<i64, EvalError> {
+        let mut value = self.multiplicative()?;
+        loop {
+            if self.eat("+") {
+                value = value.wrapping_add(self.multiplicative()?);
+            } else if self.eat("-") {
+                value = value.wrapping_sub(self.multiplicative()?);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<i64, EvalError> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat("*") {
+                value = value.wrapping_mul(self.unary()?);
+            } else if self.eat("/") {
+                let divisor = self.unary()?;
+                if divisor == 0 {
+                    return self.syntax("division by zero");
+                }
+                value = value.wrapping_div(divisor);
+            } else if self.eat("<<") {
+                value = value.wrapping_shl(self.unary()? as u32);
+            } else if self.eat(">>") {
+                value = value.wrapping_shr(self.unary()? as u32);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<i64, EvalError> {
+        if self.eat("-") {
+            Ok(self.unary()?.wrapping_neg())
+        } else if self.eat("~") {
+            Ok(!self.unary()?)
+        } else if self.eat("+") {
+            self.unary()
+        } else {
+            self.primary()
+        }
+    }
+
+    fn parenthesised(&mut self) -> Result<i64, EvalError> {
+        if !self.eat("(") {
+            return self.syntax("expected `(`");
+        }
+        let value = self.or()?;
+        if !self.eat(")") {
+            return self.syntax("expected `)`");
+        }
+        Ok(value)
+    }
+
+    fn primary(&mut self) -> Result<i64, EvalError> {
+        if self.eat("%hi") {
+            return Ok(hi(self.parenthesised()?));
+        }
+        if self.eat("%lo") {
+            return Ok(lo(self.parenthesised()?));
+        }
+        self.skip_whitespace();
+        match self.text.get(self.position) {
+            Some(b'(') => self.parenthesised(),
+            Some(b'\'') => {
+                let c = self.text.get(self.position + 1).copied();
+                if self.text.get(self.position + 2) != Some(&b'\'') || c.is_none() {
+                    return self.syntax("bad character literal");
+                }
+                self.position += 3;
+                Ok(c.unwrap_or(0) as i64)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let start = self.position;
+                while self.text.get(self.position).is_some_and(|c| c.is_ascii_alphanumeric()) {
+                    self.position += 1;
+                }
+                let literal = String::from_utf8_lossy(&self.text[start..self.position]).to_ascii_lowercase();
+                let parsed = if let Some(hex) = literal.strip_prefix("0x") {
+                    i64::from_str_radix(hex, 16)
+                } else if let Some(binary) = literal.strip_prefix("0b") {
+                    i64::from_str_radix(binary, 2)
+                } else {
+                    literal.parse::<i64>()
+                };
+                parsed.or_else(|_| self.syntax("bad number"))
+            }
+            Some(&c) if is_symbol_char(c as char) => {
+                let start = self.position;
+                while self.text.get(self.position).is_some_and(|&c| is_symbol_char(c as char)) {
+                    self.position += 1;
+                }
+                let name = String::from_utf8_lossy(&self.text[start..self.position]).to_string();
+                if name == "." {
+                    return Ok(self.pc);
+                }
+                self.symbols.get(&name).copied().ok_or(EvalError::Unresolved(name))
+            }
+            _ => self.syntax("expected a value"),
+        }
+    }
+}
+
+/// Upper 20 bits for `lui`/`auipc`, rounded so that adding [`lo`] gives back `value`.
+fn hi(value: i64) -> i64 {
+    ((value + 0x800) >> 12) & 0xfffff
+}
+
+/// Sign-extended low 12 bits, the companion of [`hi`].
+fn lo(value: i64) -> i64 {
+    ((value & 0xfff) ^ 0x800) - 0x800
+}
+
+fn fits_signed(value: i64, bits: u32) -> bool {
+    let limit = 1i64 << (bits - 1);
+    (-limit..limit).contains(&value)
+}
+
+/// Output location and bytes emitted so far.
+#[derive(Default)]
+struct Output {
+    base: Option<u32>,
+    location: u32,
+    bytes: Vec<u8>,
+}
+
+impl Output {
+    /// Append `bytes` at the location, unless they pass the end of the address space.
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.check_room(bytes.len() as u64)?;
+        let base = *self.base.get_or_insert(self.location);
+        let offset = (self.location - base) as usize;
+        self.bytes.resize(offset, 0);
+        self.bytes.extend_from_slice(bytes);
+        self.location += bytes.len() as u32;
+        Ok(())
+    }
+
+    fn check_room(&self, count: u64) -> Result<(), String> {
+        if self.location as u64 + count > u32::MAX as u64 {
+            return Err(format!("{} bytes at 0x{:08x} pass the end of the address space", count, self.location));
+        }
+        Ok(())
+    }
+}
+
+struct Assembler {
+    symbols: HashMap<String, i64>,
+    /// Size chosen for each `li` during the first pass, by statement index
+    li_sizes: HashMap<usize, u32>,
+    output: Output,
+    final_pass: bool,
+}
+
+fn error(line: usize, message: String) -> AssembleError {
+    AssembleError { line, message }
+}
+
+impl Assembler {
+    fn pass(&mut self, statements: &[Statement]) -> Result<(), AssembleError> {
+        for (index, statement) in statements.iter().enumerate() {
+            let line = statement.line;
+            match &statement.kind {
+                Kind::Label(name) => {
+                    if !self.final_pass && self.symbols.insert(name.clone(), self.output.location as i64).is_some() {
+                        return Err(error(line, format!("symbol `{}` is already defined", name)));
+                    }
+                }
+                Kind::Directive(name, arguments) => {
+                    self.directive(name, arguments).map_err(|message| error(line, message))?;
+                }
+                Kind::Instruction(mnemonic, operands) => {
+                    let operands = split_operands(operands);
+                    let size = self.instruction_size(index, mnemonic, &operands);
+                    if self.final_pass {
+                        let words = self.instruction(mnemonic, &operands, size).map_err(|message| error(line, message))?;
+                        debug_assert_eq!(words.len() as u32 * 4, size);
+                        for word in words {
+                            self.output.emit(&word.to_le_bytes()).map_err(|message| error(line, message))?;
+                        }
+                    } else {
+                        self.output.emit(&vec![0; size as usize]).map_err(|message| error(line, message))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate an expression; unresolved symbols are only an error in the final pass.
+    fn eval(&self, text: &str) -> Result<i64, String> {
+        match self.try_eval(text) {
+            Ok(value) => Ok(value),
+            Err(EvalError::Unresolved(_)) if !self.final_pass => Ok(0),
+            Err(EvalError::Unresolved(name)) => Err(format!("undefined symbol `{}`", name)),
+            Err(EvalError::Syntax(message)) => Err(message),
+        }
+    }
+
+    fn try_eval(&self, text: &str) -> Result<i64, EvalError> {
+        let mut expression = Expression {
+            text: text.as_bytes(),
+            position: 0,
+            symbols: &self.symbols,
+            pc: self.output.location as i64,
+        };
+        let value = expression.or()?;
+        expression.skip_whitespace();
+        if expression.position != text.len() {
+            return expression.syntax("unexpected trailing characters");
+        }
+        Ok(value)
+    }
+
+    /// Evaluate an expression that must be resolvable in the first pass.
+    fn eval_now(&self, text: &str) -> Result<i64, String> {
+        match self.try_eval(text) {
+            Ok(value) => Ok(value),
+            Err(EvalError::Unresolved(name)) => Err(format!("`{}` must be defined before use here", name)),
+            Err(EvalError::Syntax(message)) => Err(message),
+        }
+    }
+
+    fn directive(&mut self, name: &str, arguments: &str) -> Result<(), String> {
+        let operands = split_operands(arguments);
+        match name {
+            ".word" | ".4byte" | ".long" => {
+                for operand in operands {
+                    let value = self.eval(operand)?;
+                    if !(-(1i64 << 31)..1i64 << 32).contains(&value) {
+                        return Err(format!("value {} does not fit in a word", value));
+                    }
+                    self.output.emit(&(value as u32).to_le_bytes())?;
+                }
+            }
+            ".half" | ".2byte" | ".short" => {
+                for operand in operands {
+                    let value = self.eval(operand)?;
+                    if !(-(1i64 << 15)..1i64 << 16).contains(&value) {
+                        return Err(format!("value {} does not fit in a halfword", value));
+                    }
+                    self.output.emit(&(value as u16).to_le_bytes())?;
+                }
+            }
+            ".byte" => {
+                for operand in operands {
+                    let value = self.eval(operand)?;
+                    if !(-128..256).contains(&value) {
+                        return Err(format!("value {} does not fit in a byte", value));
+                    }
+                    self.output.emit(&[value as u8])?;
+                }
+            }
+            ".ascii" | ".asciz" | ".string" => {
+                for operand in operands {
+                    let mut bytes = parse_string(operand)?;
+                    if name != ".ascii" {
+                        bytes.push(0);
+                    }
+                    self.output.emit(&bytes)?;
+                }
+            }
+            ".zero" | ".space" | ".skip" => {
+                let count = self.eval_now(operands.first().copied().unwrap_or(""))?;
+                let fill = match operands.get(1) {
+                    Some(fill) => self.eval(fill)? as u8,
+                    None => 0,
+                };
+                if count < 0 {
+                    return Err(format!("negative size {}", count));
+                }
+                // Before allocating the fill
+                self.output.check_room(count as u64)?;
+                self.output.emit(&vec![fill; count as usize])?;
+            }
+            ".align" | ".p2align" | ".balign" => {
+                let operand = self.eval_now(operands.first().copied().unwrap_or(""))?;
+                let alignment = if name == ".balign" { operand } else { 1i64 << operand.clamp(0, 31) };
+                if alignment <= 0 || alignment & (alignment - 1) != 0 {
+                    return Err(format!("alignment {} is not a power of two", alignment));
+                }
+                let padding = (alignment - self.output.location as i64 % alignment) % alignment;
+                self.output.emit(&vec![0; padding as usize])?;
+            }
+            ".org" => {
+                let target = self.eval_now(operands.first().copied().unwrap_or(""))?;
+                if target < self.output.location as i64 && self.output.base.is_some() {
+                    return Err(format!(".org 0x{:x} moves the location backwards", target));
+                }
+                if !(0..1i64 << 32).contains(&target) {
+                    return Err(format!(".org 0x{:x} is outside the address space", target));
+                }
+                self.output.location = target as u32;
+            }
+            ".equ" | ".set" => {
+                let (symbol, value) = match operands[..] {
+                    [symbol, value] => (symbol, value),
+                    _ => return Err(format!("{} expects a name and a value", name)),
+                };
+                let value = self.eval(value)?;
+                self.symbols.insert(symbol.to_string(), value);
+            }
+            ".text" | ".data" | ".bss" | ".rodata" | ".section" | ".globl" | ".global" | ".local"
+            | ".type" | ".size" | ".option" | ".file" | ".ident" | ".attribute" => {}
+            _ => return Err(format!("unknown directive `{}`", name)),
+        }
+        Ok(())
+    }
+
+    /// Size in bytes of an instruction statement; fixed in the first pass.
+    fn instruction_size(&mut self, index: usize, mnemonic: &str, operands: &[&str]) -> u32 {
+        match mnemonic {
+            "la" | "lla" | "call" | "tail" => 8,
+            "li" => {
+                if let Some(&size) = self.li_sizes.get(&index) {
+                    return size;
+                }
+                let size = match operands.get(1).map(|value| self.try_eval(value)) {
+                    Some(Ok(value)) if fits_signed(value as i32 as i64, 12) || lo(value) == 0 => 4,
+                    _ => 8,
+                };
+                self.li_sizes.insert(index, size);
+                size
+            }
+            _ => 4,
+        }
+    }
+
+    fn register(&self, operand: Option<&&str>) -> Result<u8, String> {
+        let operand = operand.ok_or("missing register operand")?;
+        register_number(operand).ok_or_else(|| format!("`{}` is not a register", operand))
+    }
+
+    /// Signed immediate that must fit in `bits` bits.
+    fn immediate(&self, operand: Option<&&str>, bits: u32) -> Result<i32, String> {
+        let operand = operand.ok_or("missing immediate operand")?;
+        let value = self.eval(operand)?;
+        if self.final_pass && !fits_signed(value, bits) {
+            return Err(format!("immediate {} does not fit in {} signed bits", value, bits));
+        }
+        Ok(value as i32)
+    }
+
+    /// Unsigned immediate that must be below `limit`.
+    fn unsigned(&self, operand: Option<&&str>, limit: i64) -> Result<i32, String> {
+        let operand = operand.ok_or("missing immediate operand")?;
+        let value = self.eval(operand)?;
+        if !(0..limit).contains(&value) {
+            return Err(format!("immediate {} is outside 0..{}", value, limit));
+        }
+        Ok(value as i32)
+    }
+
+    /// Pc-relative offset to a branch or jump target, which must fit in `bits` bits.
+    fn offset(&self, operand: Option<&&str>, bits: u32) -> Result<i32, String> {
+        let operand = operand.ok_or("missing target operand")?;
+        let offset = self.eval(operand)? - self.output.location as i64;
+        if offset & 1 != 0 {
+            return Err(format!("target offset {} is not even", offset));
+        }
+        if !fits_signed(offset, bits) {
+            return Err(format!("target offset {} is out of range", offset));
+        }
+        Ok(offset as i32)
+    }
+
+    /// `imm(reg)` memory operand; the immediate may be omitted.
+    fn memory_operand(&self, operand: Option<&&str>) -> Result<(i32, u8), String> {
+        let operand = operand.ok_or("missing memory operand")?;
+        let open = operand
+            .strip_suffix(')')
+            .and_then(|inner| inner.rfind('('))
+            .ok_or_else(|| format!("expected `offset(register)`, found `{}`", operand))?;
+        let register = &operand[open + 1..operand.len() - 1];
+        let register = register_number(register.trim()).ok_or_else(|| format!("`{}` is not a register", register))?;
+        let offset = operand[..open].trim();
+        let offset = if offset.is_empty() { 0 } else { self.immediate(Some(&offset), 12)? };
+        Ok((offset, register))
+    }
+
+    fn csr(&self, operand: Option<&&str>) -> Result<u16, String> {
+        let operand = operand.ok_or("missing CSR operand")?;
+        csr_number(operand).ok_or_else(|| format!("`{}` is not a CSR", operand))
+    }
+
+    fn fence_set(&self, operand: Option<&&str>) -> Result<u8, String> {
+        let operand = operand.ok_or("missing fence operand")?;
+        if *operand == "0" {
+            return Ok(0);
+        }
+        operand.chars().try_fold(0, |set, c| match c {
+            'i' => Ok(set | 8),
+            'o' => Ok(set | 4),
+            'r' => Ok(set | 2),
+            'w' => Ok(set | 1),
+            _ => Err(format!("bad fence set `{}`", operand)),
+        })
+    }
+
+    /// Encode one source instruction (or pseudo-instruction) to machine words.
+    fn instruction(&self, mnemonic: &str, operands: &[&str], size: u32) -> Result<Vec<u32>, String> {
+        let o = operands;
+        let expect = |count: usize| -> Result<(), String> {
+            if o.len() == count {
+                Ok(())
+            } else {
+                Err(format!("`{}` expects {} operands, found {}", mnemonic, count, o.len()))
+            }
+        };
+        let reg = |i: usize| self.register(o.get(i));
+        let r = |m: &str, rd: u8, rs1: u8, rs2: u8| encode(m, RTypeInstruction { rd, rs1, rs2 }.encode());
+        let i = |m: &str, rd: u8, rs1: u8, imm: i32| encode(m, ITypeInstruction { rd, rs1, imm }.encode());
+        let b = |m: &str, rs1: u8, rs2: u8, imm: i32| encode(m, BTypeInstruction { rs1, rs2, imm }.encode());
+        let u = |m: &str, rd: u8, imm: i64| encode(m, UTypeInstruction { rd, imm: (imm << 12) as i32 }.encode());
+        let j = |rd: u8, imm: i32| encode("jal", JTypeInstruction { rd, imm }.encode());
+        let csr = |m: &str, rd: u8, csr: u16, rs1: u8| encode(m, CsrInstruction { rd, rs1, csr }.encode());
+
+        let words = match mnemonic {
+            // Pseudo-instructions
+            "nop" => { expect(0)?; vec![i("addi", 0, 0, 0)] }
+            "li" => {
+                expect(2)?;
+                let rd = reg(0)?;
+                let value = self.eval(o[1])?;
+                if !(-(1i64 << 31)..1i64 << 32).contains(&value) {
+                    return Err(format!("value {} does not fit in 32 bits", value));
+                }
+                // Sign-extend 32-bit values so %hi/%lo split them like the hardware adds them
+                let value = value as i32 as i64;
+                match size {
+                    8 => vec![u("lui", rd, hi(value)), i("addi", rd, rd, lo(value) as i32)],
+                    _ if fits_signed(value, 12) => vec![i("addi", rd, 0, value as i32)],
+                    _ if lo(value) == 0 => vec![u("lui", rd, hi(value))],
+                    _ => return Err(format!("value of `{}` changed between passes", o[1])),
+                }
+            }
+            "la" | "lla" => {
+                expect(2)?;
+                let rd = reg(0)?;
+                let offset = self.eval(o[1])? - self.output.location as i64;
+                vec![u("auipc", rd, hi(offset)), i("addi", rd, rd, lo(offset) as i32)]
+            }
+            "call" | "tail" => {
+                expect(1)?;
+                let (link, scratch) = if mnemonic == "call" { (1, 1) } else { (0, 6) };
+                let offset = self.eval(o[0])? - self.output.location as i64;
+                vec![u("auipc", scratch, hi(offset)), i("jalr", link, scratch, lo(offset) as i32)]
+            }
+            "mv" => { expect(2)?; vec![i("addi", reg(0)?, reg(1)?, 0)] }
+            "not" => { expect(2)?; vec![i("xori", reg(0)?, reg(1)?, -1)] }
+            "neg" => { expect(2)?; vec![r("sub", reg(0)?, 0, reg(1)?)] }
+            "seqz" => { expect(2)?; vec![i("sltiu", reg(0)?, reg(1)?, 1)] }
+            "snez" => { expect(2)?; vec![r("sltu", reg(0)?, 0, reg(1)?)] }
+            "sltz" => { expect(2)?; vec![r("slt", reg(0)?, reg(1)?, 0)] }
+            "sgtz" => { expect(2)?; vec![r("slt", reg(0)?, 0, reg(1)?)] }
+            "beqz" => { expect(2)?; vec![b("beq", reg(0)?, 0, self.offset(o.get(1), 13)?)] }
+            "bnez" => { expect(2)?; vec![b("bne", reg(0)?, 0, self.offset(o.get(1), 13)?)] }
+            "blez" => { expect(2)?; vec![b("bge", 0, reg(0)?, self.offset(o.get(1), 13)?)] }
+            "bgez" => { expect(2)?; vec![b("bge", reg(0)?, 0, self.offset(o.get(1), 13)?)] }
+            "bltz" => { expect(2)?; vec![b("blt", reg(0)?, 0, self.offset(o.get(1), 13)?)] }
+            "bgtz" => { expect(2)?; vec![b("blt", 0, reg(0)?, self.offset(o.get(1), 13)?)] }
+            "bgt" => { expect(3)?; vec![b("blt", reg(1)?, reg(0)?, self.offset(o.get(2), 13)?)] }
+            "ble" => { expect(3)?; vec![b("bge", reg(1)?, reg(0)?, self.offset(o.get(2), 13)?)] }
+            "bgtu" => { expect(3)?; vec![b("bltu", reg(1)?, reg(0)?, self.offset(o.get(2), 13)?)] }
+            "bleu" => { expect(3)?; vec![b("bgeu", reg(1)?, reg(0)?, self.offset(o.get(2), 13)?)] }
+            "j" => { expect(1)?; vec![j(0, self.offset(o.first(), 21)?)] }
+            "jr" => { expect(1)?; vec![i("jalr", 0, reg(0)?, 0)] }
+            "ret" => { expect(0)?; vec![i("jalr", 0, 1, 0)] }
+            "jal" if o.len() == 1 => vec![j(1, self.offset(o.first(), 21)?)],
+            "jalr" if o.len() == 1 => vec![i("jalr", 1, reg(0)?, 0)],
+            "csrr" => { expect(2)?; vec![csr("csrrs", reg(0)?, self.csr(o.get(1))?, 0)] }
+            "csrw" | "csrs" | "csrc" => {
+                expect(2)?;
+                let base = format!("csrr{}", &mnemonic[3..]);
+                vec![csr(&base, 0, self.csr(o.first())?, reg(1)?)]
+            }
+            "csrwi" | "csrsi" | "csrci" => {
+                expect(2)?;
+                let base = format!("csrr{}", &mnemonic[3..]);
+                vec![csr(&base, 0, self.csr(o.first())?, self.unsigned(o.get(1), 32)? as u8)]
+            }
+            "rdcycle" | "rdtime" | "rdinstret" | "rdcycleh" | "rdtimeh" | "rdinstreth" => {
+                expect(1)?;
+                vec![csr("csrrs", reg(0)?, self.csr(Some(&&mnemonic[2..]))?, 0)]
+            }
+            "fence.tso" => {
+                expect(0)?;
+                vec![encode("fence", FenceInstruction { rd: 0, rs1: 0, fm: 0b1000, pred: 0b0011, succ: 0b0011 }.encode())]
+            }
+            "pause" => {
+                expect(0)?;
+                vec![encode("fence", FenceInstruction { rd: 0, rs1: 0, fm: 0, pred: 0b0001, succ: 0 }.encode())]
+            }
+            _ => vec![self.base_instruction(mnemonic, operands)?],
+        };
+        Ok(words)
+    }
+
+    /// Encode a real instruction from its decoder-table entry.
+    fn base_instruction(&self, mnemonic: &str, operands: &[&str]) -> Result<u32, String> {
+        let o = operands;
+        // Atomics take optional .aq/.rl ordering suffixes
+        let (base, aq, rl) = match mnemonic.rsplit_once('.') {
+            Some((base, "aq")) => (base, true, false),
+            Some((base, "rl")) => (base, false, true),
+            Some((base, "aqrl")) => (base, true, true),
+            _ => (mnemonic, false, false),
+        };
+        let pattern = decoder::pattern(base).ok_or_else(|| format!("unknown instruction `{}`", mnemonic))?;
+        if (aq || rl) && pattern.format != Format::Atomic {
+            return Err(format!("unknown instruction `{}`", mnemonic));
+        }
+        let count = |expected: usize| -> Result<(), String> {
+            if o.len() == expected {
+                Ok(())
+            } else {
+                Err(format!("`{}` expects {} operands, found {}", mnemonic, expected, o.len()))
+            }
+        };
+        let reg = |i: usize| self.register(o.get(i));
+
+        let operands = match pattern.format {
+            Format::R if base == "sfence.vma" => {
+                let rs1 = if o.is_empty() { 0 } else { reg(0)? };
+                let rs2 = if o.len() < 2 { 0 } else { reg(1)? };
+                if o.len() > 2 {
+                    count(2)?;
+                }
+                RTypeInstruction { rd: 0, rs1, rs2 }.encode()
+            }
+            Format::R => {
+                count(3)?;
+                RTypeInstruction { rd: reg(0)?, rs1: reg(1)?, rs2: reg(2)? }.encode()
+            }
+            Format::I => match base {
+                "fence.i" => {
+                    count(0)?;
+                    0
+                }
+                "slli" | "srli" | "srai" => {
+                    count(3)?;
+                    ITypeInstruction { rd: reg(0)?, rs1: reg(1)?, imm: self.unsigned(o.get(2), 32)? }.encode_shamt()
+                }
+                "lb" | "lh" | "lw" | "lbu" | "lhu" | "jalr" if o.len() == 2 => {
+                    let (imm, rs1) = self.memory_operand(o.get(1))?;
+                    ITypeInstruction { rd: reg(0)?, rs1, imm }.encode()
+                }
+                _ => {
+                    count(3)?;
+                    ITypeInstruction { rd: reg(0)?, rs1: reg(1)?, imm: self.immediate(o.get(2), 12)? }.encode()
+                }
+            },
+            Format::S => {
+                count(2)?;
+                let (imm, rs1) = self.memory_operand(o.get(1))?;
+                STypeInstruction { rs1, rs2: reg(0)?, imm }.encode()
+            }
+            Format::B => {
+                count(3)?;
+                BTypeInstruction { rs1: reg(0)?, rs2: reg(1)?, imm: self.offset(o.get(2), 13)? }.encode()
+            }
+            Format::U => {
+                count(2)?;
+                let imm = self.unsigned(o.get(1), 1 << 20)?;
+                UTypeInstruction { rd: reg(0)?, imm: imm << 12 }.encode()
+            }
+            Format::J => {
+                count(2)?;
+                JTypeInstruction { rd: reg(0)?, imm: self.offset(o.get(1), 21)? }.encode()
+            }
+            Format::Atomic if base == "lr.w" => {
+                count(2)?;
+                let (offset, rs1) = self.memory_operand(o.get(1))?;
+                if offset != 0 {
+                    return Err(format!("`{}` takes no address offset", mnemonic));
+                }
+                AtomicInstruction { rd: reg(0)?, rs1, rs2: 0, aq, rl }.encode()
+            }
+            Format::Atomic => {
+                count(3)?;
+                let (offset, rs1) = self.memory_operand(o.get(2))?;
+                if offset != 0 {
+                    return Err(format!("`{}` takes no address offset", mnemonic));
+                }
+                AtomicInstruction { rd: reg(0)?, rs1, rs2: reg(1)?, aq, rl }.encode()
+            }
+            Format::Csr => {
+                count(3)?;
+                let rs1 = if base.ends_with('i') { self.unsigned(o.get(2), 32)? as u8 } else { reg(2)? };
+                CsrInstruction { rd: reg(0)?, rs1, csr: self.csr(o.get(1))? }.encode()
+            }
+            Format::Fence => {
+                let (pred, succ) = match o.len() {
+                    0 => (0b1111, 0b1111),
+                    2 => (self.fence_set(o.first())?, self.fence_set(o.get(1))?),
+                    _ => return Err(format!("`{}` expects 0 or 2 operands, found {}", mnemonic, o.len())),
+                };
+                FenceInstruction { rd: 0, rs1: 0, fm: 0, pred, succ }.encode()
+            }
+            Format::System => {
+                count(0)?;
+                0
+            }
+        };
+        Ok(pattern.bits | (operands & !pattern.mask))
+    }
+}
+
+/// Combine a decoder-table entry with encoded operand fields.
+fn encode(mnemonic: &str, operands: u32) -> u32 {
+    let pattern = decoder::pattern(mnemonic).expect("pseudo-instructions expand to table entries");
+    pattern.bits | (operands & !pattern.mask)
+}
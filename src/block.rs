@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::decode_cache::PAGE_SIZE;
+use crate::instruction::Instruction;
+use crate::jit::{self, NativeBlock, StoreHelper};
+use crate::memory::Memory;
+
+/// Longest block translated, in instructions.
+pub const MAX_BLOCK_LENGTH: usize = 64;
+
+/// Register-register and register-immediate operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+}
+
+impl AluOp {
+    #[inline]
+    pub fn apply(self, a: u32, b: u32) -> u32 {
+        match self {
+            AluOp::Add => a.wrapping_add(b),
+            AluOp::Sub => a.wrapping_sub(b),
+            AluOp::Sll => a << (b & 0x1F),
+            AluOp::Slt => ((a as i32) < (b as i32)) as u32,
+            AluOp::Sltu => (a < b) as u32,
+            AluOp::Xor => a ^ b,
+            AluOp::Srl => a >> (b & 0x1F),
+            AluOp::Sra => ((a as i32) >> (b & 0x1F)) as u32,
+            AluOp::Or => a | b,
+            AluOp::And => a & b,
+            AluOp::Mul => a.wrapping_mul(b),
+            AluOp::Mulh => ((a as i32 as i64 * b as i32 as i64) >> 32) as u32,
+            AluOp::Mulhsu => ((a as i32 as i64 * b as i64) >> 32) as u32,
+            AluOp::Mulhu => ((a as u64 * b as u64) >> 32) as u32,
+            // Division by zero and signed overflow produce the results the spec mandates
+            AluOp::Div if b == 0 => u32::MAX,
+            AluOp::Div => (a as i32).wrapping_div(b as i32) as u32,
+            AluOp::Divu if b == 0 => u32::MAX,
+            AluOp::Divu => a / b,
+            AluOp::Rem if b == 0 => a,
+            AluOp::Rem => (a as i32).wrapping_rem(b as i32) as u32,
+            AluOp::Remu if b == 0 => a,
+            AluOp::Remu => a % b,
+        }
+    }
+
+    /// The operation computed by a register-register, register-immediate or M-extension
+    /// instruction.
+    pub fn of(instruction: &Instruction) -> Option<AluOp> {
+        Some(match instruction {
+            Instruction::Add(_) | Instruction::Addi(_) => AluOp::Add,
+            Instruction::Sub(_) => AluOp::Sub,
+            Instruction::Sll(_) | Instruction::Slli(_) => AluOp::Sll,
+            Instruction::Slt(_) | Instruction::Slti(_) => AluOp::Slt,
+            Instruction::Sltu(_) | Instruction::Sltiu(_) => AluOp::Sltu,
+            Instruction::Xor(_) | Instruction::Xori(_) => AluOp::Xor,
+            Instruction::Srl(_) | Instruction::Srli(_) => AluOp::Srl,
+            Instruction::Sra(_) | Instruction::Srai(_) => AluOp::Sra,
+            Instruction::Or(_) | Instruction::Ori(_) => AluOp::Or,
+            Instruction::And(_) | Instruction::Andi(_) => AluOp::And,
+            Instruction::Mul(_) => AluOp::Mul,
+            Instruction::Mulh(_) => AluOp::Mulh,
+            Instruction::Mulhsu(_) => AluOp::Mulhsu,
+            Instruction::Mulhu(_) => AluOp::Mulhu,
+            Instruction::Div(_) => AluOp::Div,
+            Instruction::Divu(_) => AluOp::Divu,
+            Instruction::Rem(_) => AluOp::Rem,
+            Instruction::Remu(_) => AluOp::Remu,
+            _ => return None,
+        })
+    }
+}
+
+/// Conditional branch comparisons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Ltu,
+    Geu,
+}
+
+impl Condition {
+    #[inline]
+    pub fn holds(self, a: u32, b: u32) -> bool {
+        match self {
+            Condition::Eq => a == b,
+            Condition::Ne => a != b,
+            Condition::Lt => (a as i32) < (b as i32),
+            Condition::Ge => (a as i32) >= (b as i32),
+            Condition::Ltu => a < b,
+            Condition::Geu => a >= b,
+        }
+    }
+}
+
+/// One translated instruction, with its operands extracted and anything depending on
+/// its pc resolved at translation time. Control transfers only end a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// rd = value, for LUI and AUIPC.
+    Li { rd: u8, value: u32 },
+    Alu { op: AluOp, rd: u8, rs1: u8, rs2: u8 },
+    AluImm { op: AluOp, rd: u8, rs1: u8, imm: u32 },
+    Load { rd: u8, rs1: u8, offset: u32, size: u32, signed: bool },
+    Store { rs1: u8, rs2: u8, offset: u32, size: u32 },
+    Branch { condition: Condition, rs1: u8, rs2: u8, target: u32 },
+    Jal { rd: u8, target: u32 },
+    Jalr { rd: u8, rs1: u8, offset: u32 },
+}
+
+impl Op {
+    /// Translate the instruction at `pc`, or `None` if it has to be interpreted:
+    /// atomics, fences, CSR accesses and privileged instructions may change state that
+    /// blocks rely on staying fixed.
+    pub fn translate(instruction: &Instruction, pc: u32) -> Option<Op> {
+        Some(match *instruction {
+            Instruction::Lui(u) => Op::Li { rd: u.rd, value: u.imm as u32 },
+            Instruction::Auipc(u) => Op::Li { rd: u.rd, value: pc.wrapping_add(u.imm as u32) },
+            Instruction::Jal(j) => Op::Jal { rd: j.rd, target: pc.wrapping_add(j.imm as u32) },
+            Instruction::Jalr(i) => Op::Jalr { rd: i.rd, rs1: i.rs1, offset: i.imm as u32 },
+            Instruction::Beq(b) | Instruction::Bne(b) | Instruction::Blt(b) | Instruction::Bge(b)
+            | Instruction::Bltu(b) | Instruction::Bgeu(b) => {
+                let condition = match instruction {
+                    Instruction::Beq(_) => Condition::Eq,
+                    Instruction::Bne(_) => Condition::Ne,
+                    Instruction::Blt(_) => Condition::Lt,
+                    Instruction::Bge(_) => Condition::Ge,
+                    Instruction::Bltu(_) => Condition::Ltu,
+                    _ => Condition::Geu,
+                };
+                Op::Branch { condition, rs1: b.rs1, rs2: b.rs2, target: pc.wrapping_add(b.imm as u32) }
+            }
+            Instruction::Lb(i) | Instruction::Lh(i) | Instruction::Lw(i) | Instruction::Lbu(i) | Instruction::Lhu(i) => {
+                let (size, signed) = match instruction {
+                    Instruction::Lb(_) => (1, true),
+                    Instruction::Lh(_) => (2, true),
+                    Instruction::Lw(_) => (4, false),
+                    Instruction::Lbu(_) => (1, false),
+                    _ => (2, false),
+                };
+                Op::Load { rd: i.rd, rs1: i.rs1, offset: i.imm as u32, size, signed }
+            }
+            Instruction::Sb(s) | Instruction::Sh(s) | Instruction::Sw(s) => {
+                let size = match instruction {
+                    Instruction::Sb(_) => 1,
+                    Instruction::Sh(_) => 2,
+                    _ => 4,
+                };
+                Op::Store { rs1: s.rs1, rs2: s.rs2, offset: s.imm as u32, size }
+            }
+            Instruction::Addi(i) | Instruction::Slti(i) | Instruction::Sltiu(i) | Instruction::Xori(i)
+            | Instruction::Ori(i) | Instruction::Andi(i) | Instruction::Slli(i) | Instruction::Srli(i)
+            | Instruction::Srai(i) => Op::AluImm { op: AluOp::of(instruction)?, rd: i.rd, rs1: i.rs1, imm: i.imm as u32 },
+            Instruction::Add(r) | Instruction::Sub(r) | Instruction::Sll(r) | Instruction::Slt(r)
+            | Instruction::Sltu(r) | Instruction::Xor(r) | Instruction::Srl(r) | Instruction::Sra(r)
+            | Instruction::Or(r) | Instruction::And(r) | Instruction::Mul(r) | Instruction::Mulh(r)
+            | Instruction::Mulhsu(r) | Instruction::Mulhu(r) | Instruction::Div(r) | Instruction::Divu(r)
+            | Instruction::Rem(r) | Instruction::Remu(r) => {
+                Op::Alu { op: AluOp::of(instruction)?, rd: r.rd, rs1: r.rs1, rs2: r.rs2 }
+            }
+            _ => return None,
+        })
+    }
+
+    fn ends_block(&self) -> bool {
+        matches!(self, Op::Branch { .. } | Op::Jal { .. } | Op::Jalr { .. })
+    }
+}
+
+/// Straight-line code starting at `start`, ending at a control transfer, before an
+/// instruction that must be interpreted, at a page boundary or after
+/// [`MAX_BLOCK_LENGTH`] instructions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: u32,
+    pub ops: Vec<Op>,
+    /// Instruction words and decodings, reported to hooks.
+    pub instructions: Vec<(u32, Instruction)>,
+}
+
+impl Block {
+    /// Translate the block at `pc`, or `None` if its first instruction cannot be
+    /// fetched, decoded or translated.
+    pub fn translate(memory: &Memory, pc: u32) -> Option<Block> {
+        if !pc.is_multiple_of(4) {
+            return None;
+        }
+        let mut block = Block { start: pc, ops: Vec::new(), instructions: Vec::new() };
+        let mut address = pc;
+        while block.ops.len() < MAX_BLOCK_LENGTH {
+            if address as u64 + 4 > memory.size() as u64 {
+                break;
+            }
+            let raw = memory.fetch_word(address as usize);
+            let Ok(instruction) = Instruction::decode(raw) else { break };
+            let Some(op) = Op::translate(&instruction, address) else { break };
+            block.ops.push(op);
+            block.instructions.push((raw, instruction));
+            address = address.wrapping_add(4);
+            if op.ends_block() || address.is_multiple_of(PAGE_SIZE) {
+                break;
+            }
+        }
+        (!block.ops.is_empty()).then_some(block)
+    }
+
+    /// Address after the last instruction, where the block falls through to.
+    pub fn end(&self) -> u32 {
+        self.start.wrapping_add(4 * self.ops.len() as u32)
+    }
+}
+
+/// Host code of a block, once it is hot.
+enum Native {
+    /// Executions so far.
+    Cold(u32),
+    Compiled(Arc<NativeBlock>),
+    Failed,
+}
+
+/// Translated blocks by start address, with the links chaining each block to the
+/// blocks it exited to. A write to a translated instruction drops every block.
+#[derive(Default)]
+pub struct BlockCache {
+    blocks: Vec<Arc<Block>>,
+    starts: HashMap<u32, usize>,
+    // Up to two (exit address, block) successors per block
+    links: Vec<[Option<(u32, usize)>; 2]>,
+    // One bit per translated instruction word
+    code: Vec<u64>,
+    generation: u64,
+    jit: bool,
+    native: Vec<Native>,
+    // Link slots of compiled blocks waiting for the block at an address to compile
+    unlinked: HashMap<u32, Vec<(usize, usize)>>,
+    // Compiled code dropped while it may still be running, freed on the next lookup
+    retired: Vec<Native>,
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index of the block at `pc`, translating it if needed; `from` is the block that
+    /// exited to `pc`, which is linked to it so the next lookup avoids the hash map.
+    pub fn lookup(&mut self, memory: &Memory, pc: u32, from: Option<usize>) -> Option<usize> {
+        self.retired.clear();
+        if let Some(from) = from {
+            if let Some((_, index)) = self.links[from].iter().flatten().find(|(address, _)| *address == pc) {
+                return Some(*index);
+            }
+        }
+        let index = match self.starts.get(&pc) {
+            Some(&index) => index,
+            None => {
+                let block = Block::translate(memory, pc)?;
+                for word in (block.start / 4) as usize..(block.end() / 4) as usize {
+                    if word / 64 >= self.code.len() {
+                        self.code.resize(word / 64 + 1, 0);
+                    }
+                    self.code[word / 64] |= 1 << (word % 64);
+                }
+                self.blocks.push(Arc::new(block));
+                self.links.push([None; 2]);
+                self.native.push(Native::Cold(0));
+                self.starts.insert(pc, self.blocks.len() - 1);
+                self.blocks.len() - 1
+            }
+        };
+        if let Some(from) = from {
+            let links = &mut self.links[from];
+            // An indirect jump with many targets keeps relinking its second slot
+            let slot = if links[0].is_none() { 0 } else { 1 };
+            links[slot] = Some((pc, index));
+        }
+        Some(index)
+    }
+
+    pub fn block(&self, index: usize) -> Arc<Block> {
+        Arc::clone(&self.blocks[index])
+    }
+
+    /// Compile blocks to host code once they have run [`jit::JIT_THRESHOLD`] times.
+    pub fn set_jit(&mut self, enabled: bool) {
+        self.jit = enabled;
+    }
+
+    pub fn jit(&self) -> bool {
+        self.jit
+    }
+
+    /// Count an execution of block `index` and return its host code, compiling it with
+    /// `store` as the store helper when it becomes hot.
+    pub fn native(&mut self, index: usize, store: StoreHelper) -> Option<Arc<NativeBlock>> {
+        if !self.jit {
+            return None;
+        }
+        match &mut self.native[index] {
+            Native::Compiled(code) => Some(Arc::clone(code)),
+            Native::Failed => None,
+            Native::Cold(count) => {
+                *count += 1;
+                if *count < jit::JIT_THRESHOLD {
+                    return None;
+                }
+                let Some(code) = jit::compile(&self.blocks[index], store) else {
+                    self.native[index] = Native::Failed;
+                    return None;
+                };
+                let code = Arc::new(code);
+                self.link(index, &code);
+                self.native[index] = Native::Compiled(Arc::clone(&code));
+                Some(code)
+            }
+        }
+    }
+
+    /// Chain the newly compiled block `index` to the compiled blocks it exits to, and
+    /// compiled blocks that exit to its start to it.
+    fn link(&mut self, index: usize, code: &NativeBlock) {
+        let start = self.blocks[index].start;
+        for (slot, &target) in code.exits().iter().enumerate() {
+            if target == start {
+                code.link(slot, code);
+                continue;
+            }
+            match self.starts.get(&target).map(|&next| &self.native[next]) {
+                Some(Native::Compiled(next)) => code.link(slot, next),
+                _ => self.unlinked.entry(target).or_default().push((index, slot)),
+            }
+        }
+        for (previous, slot) in self.unlinked.remove(&start).unwrap_or_default() {
+            if let Native::Compiled(previous) = &self.native[previous] {
+                previous.link(slot, code);
+            }
+        }
+    }
+
+    /// Number of blocks compiled to host code.
+    pub fn compiled(&self) -> usize {
+        self.native.iter().filter(|native| matches!(native, Native::Compiled(_))).count()
+    }
+
+    /// Incremented whenever blocks are dropped, invalidating their indices.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Note an aligned store to `address`, dropping all blocks if it overwrites
+    /// translated code.
+    #[inline]
+    pub fn invalidate(&mut self, address: u32) {
+        let word = (address / 4) as usize;
+        if self.code.get(word / 64).is_some_and(|bits| bits & (1 << (word % 64)) != 0) {
+            self.clear();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.starts.clear();
+        self.links.clear();
+        self.code.clear();
+        // A store from compiled code may be dropping the code it returns to
+        self.retired.append(&mut self.native);
+        self.unlinked.clear();
+        self.generation += 1;
+    }
+}
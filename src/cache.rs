@@ -0,0 +1,459 @@
+//! Set-associative caches: split L1 instruction and data caches and an optional
+//! unified L2, observing the fetches, loads and stores of a CPU.
+//!
+//! Caches hold tags only; memory stays the single copy of the data, so caching never
+//! changes what a program computes. The L2 is neither inclusive nor exclusive: it sees
+//! the L1 misses and the writes the L1s pass down, and evicting from it leaves the L1s
+//! alone.
+//!
+//! Each access costs the hit latency of every level it reaches, plus the memory latency
+//! if it misses them all. Writes passed down, written through or written back, drain
+//! through a write buffer and cost nothing.
+
+use std::fmt::{self, Write as _};
+
+use crate::hooks::Hooks;
+
+/// Line chosen for eviction from a full set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replacement {
+    /// Least recently used.
+    Lru,
+    /// First filled.
+    Fifo,
+    /// Pseudo-random, from a fixed seed so runs repeat.
+    Random,
+}
+
+/// When stores reach the next level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// On eviction of the dirty line.
+    WriteBack,
+    /// On every store.
+    WriteThrough,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Capacity in bytes.
+    pub size: u32,
+    /// Lines per set.
+    pub associativity: u32,
+    /// Bytes per line.
+    pub line_size: u32,
+    pub replacement: Replacement,
+    pub write: WritePolicy,
+    /// Whether a store that misses fills the line; otherwise it only goes to the next level.
+    pub write_allocate: bool,
+    /// Cycles to serve a hit.
+    pub latency: u64,
+}
+
+impl CacheConfig {
+    /// A write-back, write-allocate LRU cache hit in one cycle.
+    pub fn new(size: u32, associativity: u32, line_size: u32) -> Self {
+        CacheConfig {
+            size,
+            associativity,
+            line_size,
+            replacement: Replacement::Lru,
+            write: WritePolicy::WriteBack,
+            write_allocate: true,
+            latency: 1,
+        }
+    }
+
+    pub fn sets(&self) -> u32 {
+        self.size / (self.associativity * self.line_size)
+    }
+}
+
+impl Default for CacheConfig {
+    /// 16 KiB, four ways of 64-byte lines.
+    fn default() -> Self {
+        CacheConfig::new(16 * 1024, 4, 64)
+    }
+}
+
+/// Default configuration of an L2: 256 KiB, eight ways of 64-byte lines, hit in 10 cycles.
+pub fn default_l2() -> CacheConfig {
+    CacheConfig { latency: 10, ..CacheConfig::new(256 * 1024, 8, 64) }
+}
+
+/// Cycles to reach memory past the last cache.
+pub const DEFAULT_MEMORY_LATENCY: u64 = 100;
+
+/// A cache geometry that cannot be built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheError {
+    /// Size, associativity and line size must be powers of two, the line at least a
+    /// word, with room for at least one set.
+    Geometry { size: u32, associativity: u32, line_size: u32 },
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::Geometry { size, associativity, line_size } => write!(
+                f,
+                "invalid cache of {} bytes, {} ways of {}-byte lines: sizes must be powers of two and fit a set",
+                size, associativity, line_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+/// Accesses of one cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub reads: u64,
+    pub read_misses: u64,
+    pub writes: u64,
+    pub write_misses: u64,
+    /// Valid lines replaced by a fill.
+    pub evictions: u64,
+    /// Evicted lines that were dirty and written to the next level.
+    pub writebacks: u64,
+}
+
+impl CacheStats {
+    pub fn accesses(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.read_misses + self.write_misses
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.accesses() - self.misses()
+    }
+
+    /// Fraction of accesses that missed.
+    pub fn miss_rate(&self) -> f64 {
+        self.misses() as f64 / self.accesses().max(1) as f64
+    }
+}
+
+/// What an access asked of the next level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Access {
+    pub hit: bool,
+    /// The line was read from the next level to fill it.
+    pub fill: bool,
+    /// The store went on to the next level.
+    pub write_through: bool,
+    /// Address of a dirty line evicted to make room, written to the next level.
+    pub writeback: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Line {
+    valid: bool,
+    dirty: bool,
+    tag: u32,
+    // Access counter when last used and when filled
+    used: u64,
+    filled: u64,
+}
+
+/// One level of cache.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    config: CacheConfig,
+    lines: Vec<Line>,
+    stats: CacheStats,
+    clock: u64,
+    random: u32,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Result<Self, CacheError> {
+        let CacheConfig { size, associativity, line_size, .. } = config;
+        let powers = [size, associativity, line_size].iter().all(|value| value.is_power_of_two());
+        if !powers || line_size < 4 || (associativity as u64 * line_size as u64) > size as u64 {
+            return Err(CacheError::Geometry { size, associativity, line_size });
+        }
+        let lines = vec![Line::default(); (size / line_size) as usize];
+        Ok(Cache { config, lines, stats: CacheStats::default(), clock: 0, random: 0x2545_f491 })
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    /// Whether the line holding `address` is cached.
+    pub fn contains(&self, address: u32) -> bool {
+        let (set, tag) = self.locate(address);
+        self.set(set).iter().any(|line| line.valid && line.tag == tag)
+    }
+
+    fn locate(&self, address: u32) -> (usize, u32) {
+        let line = address / self.config.line_size;
+        let sets = self.config.sets();
+        ((line % sets) as usize, line / sets)
+    }
+
+    fn set(&self, set: usize) -> &[Line] {
+        let ways = self.config.associativity as usize;
+        &self.lines[set * ways..(set + 1) * ways]
+    }
+
+    /// Read (`write` false) or write the line holding `address`.
+    pub fn access(&mut self, address: u32, write: bool) -> Access {
+        let (set, tag) = self.locate(address);
+        let ways = self.config.associativity as usize;
+        self.clock += 1;
+        let clock = self.clock;
+        let write_back = self.config.write == WritePolicy::WriteBack;
+        let mut access = Access { write_through: write && !write_back, ..Access::default() };
+        if write {
+            self.stats.writes += 1;
+        } else {
+            self.stats.reads += 1;
+        }
+
+        let start = set * ways;
+        if let Some(line) = self.lines[start..start + ways].iter_mut().find(|line| line.valid && line.tag == tag) {
+            line.used = clock;
+            line.dirty |= write && write_back;
+            access.hit = true;
+            return access;
+        }
+        if write {
+            self.stats.write_misses += 1;
+            if !self.config.write_allocate {
+                access.write_through = true;
+                return access;
+            }
+        } else {
+            self.stats.read_misses += 1;
+        }
+
+        let way = self.victim(start, ways);
+        let sets = self.config.sets();
+        let line = &mut self.lines[start + way];
+        if line.valid {
+            self.stats.evictions += 1;
+            if line.dirty {
+                self.stats.writebacks += 1;
+                access.writeback = Some((line.tag * sets + set as u32) * self.config.line_size);
+            }
+        }
+        *line = Line { valid: true, dirty: write && write_back, tag, used: clock, filled: clock };
+        access.fill = true;
+        access
+    }
+
+    /// Way of the set starting at line `start` to fill: an invalid one, or the one the
+    /// replacement policy picks.
+    fn victim(&mut self, start: usize, ways: usize) -> usize {
+        let set = &self.lines[start..start + ways];
+        if let Some(way) = set.iter().position(|line| !line.valid) {
+            return way;
+        }
+        match self.config.replacement {
+            Replacement::Lru => (0..ways).min_by_key(|&way| set[way].used).unwrap_or(0),
+            Replacement::Fifo => (0..ways).min_by_key(|&way| set[way].filled).unwrap_or(0),
+            Replacement::Random => {
+                // xorshift32
+                self.random ^= self.random << 13;
+                self.random ^= self.random >> 17;
+                self.random ^= self.random << 5;
+                self.random as usize % ways
+            }
+        }
+    }
+
+    fn summary(&self, name: &str, text: &mut String) {
+        let config = &self.config;
+        let stats = &self.stats;
+        let _ = writeln!(
+            text,
+            "{:<4} {} KiB, {}-way, {} B lines: {} reads ({} misses), {} writes ({} misses), {:.2}% miss rate, {} evictions, {} writebacks",
+            name,
+            config.size / 1024,
+            config.associativity,
+            config.line_size,
+            stats.reads,
+            stats.read_misses,
+            stats.writes,
+            stats.write_misses,
+            100.0 * stats.miss_rate(),
+            stats.evictions,
+            stats.writebacks
+        );
+    }
+}
+
+/// L1 instruction and data caches and an optional L2 in front of memory, gathered as
+/// CPU [`Hooks`].
+#[derive(Debug, Clone)]
+pub struct CacheHierarchy {
+    l1i: Cache,
+    l1d: Cache,
+    l2: Option<Cache>,
+    memory_latency: u64,
+    /// Lines read from and writes reaching memory.
+    pub memory_reads: u64,
+    pub memory_writes: u64,
+    /// Cycles spent on fetches and on loads and stores.
+    pub fetch_cycles: u64,
+    pub data_cycles: u64,
+}
+
+impl CacheHierarchy {
+    pub fn new(l1i: CacheConfig, l1d: CacheConfig, l2: Option<CacheConfig>) -> Result<Self, CacheError> {
+        Ok(CacheHierarchy {
+            l1i: Cache::new(l1i)?,
+            l1d: Cache::new(l1d)?,
+            l2: l2.map(Cache::new).transpose()?,
+            memory_latency: DEFAULT_MEMORY_LATENCY,
+            memory_reads: 0,
+            memory_writes: 0,
+            fetch_cycles: 0,
+            data_cycles: 0,
+        })
+    }
+
+    pub fn memory_latency(&self) -> u64 {
+        self.memory_latency
+    }
+
+    pub fn set_memory_latency(&mut self, cycles: u64) {
+        self.memory_latency = cycles;
+    }
+
+    pub fn l1i(&self) -> &Cache {
+        &self.l1i
+    }
+
+    pub fn l1d(&self) -> &Cache {
+        &self.l1d
+    }
+
+    pub fn l2(&self) -> Option<&Cache> {
+        self.l2.as_ref()
+    }
+
+    /// Fetch the instruction at `pc`; returns the cycles it took.
+    pub fn fetch(&mut self, pc: u32) -> u64 {
+        let cycles = self.lines(pc, 4, false, true);
+        self.fetch_cycles += cycles;
+        cycles
+    }
+
+    /// Load `size` bytes from `address`; returns the cycles it took.
+    pub fn read(&mut self, address: u32, size: u32) -> u64 {
+        let cycles = self.lines(address, size, false, false);
+        self.data_cycles += cycles;
+        cycles
+    }
+
+    /// Store `size` bytes to `address`; returns the cycles it took.
+    pub fn write(&mut self, address: u32, size: u32) -> u64 {
+        let cycles = self.lines(address, size, true, false);
+        self.data_cycles += cycles;
+        cycles
+    }
+
+    /// Access every L1 line the `size` bytes at `address` touch; a misaligned access
+    /// spanning two lines costs both.
+    fn lines(&mut self, address: u32, size: u32, write: bool, instruction: bool) -> u64 {
+        let l1 = if instruction { &self.l1i } else { &self.l1d };
+        let line_size = l1.config.line_size;
+        let first = address / line_size;
+        let last = address.wrapping_add(size.max(1) - 1) / line_size;
+        let mut cycles = 0;
+        let mut line = first;
+        loop {
+            cycles += self.access_l1(line.wrapping_mul(line_size), write, instruction);
+            if line == last {
+                break cycles;
+            }
+            line = line.wrapping_add(1);
+        }
+    }
+
+    fn access_l1(&mut self, address: u32, write: bool, instruction: bool) -> u64 {
+        let l1 = if instruction { &mut self.l1i } else { &mut self.l1d };
+        let latency = l1.config.latency;
+        let access = l1.access(address, write);
+        if let Some(victim) = access.writeback {
+            self.next_level(victim, true);
+        }
+        if access.write_through {
+            self.next_level(address, true);
+        }
+        if access.fill {
+            latency + self.next_level(address, false)
+        } else {
+            latency
+        }
+    }
+
+    /// Read or write a line below the L1s; returns the cycles a read took.
+    fn next_level(&mut self, address: u32, write: bool) -> u64 {
+        let Some(l2) = &mut self.l2 else {
+            return self.memory(write);
+        };
+        let latency = l2.config.latency;
+        let access = l2.access(address, write);
+        if access.writeback.is_some() {
+            self.memory(true);
+        }
+        if access.write_through {
+            self.memory(true);
+        }
+        let fill = if access.fill { self.memory(false) } else { 0 };
+        if write {
+            0
+        } else {
+            latency + fill
+        }
+    }
+
+    fn memory(&mut self, write: bool) -> u64 {
+        if write {
+            self.memory_writes += 1;
+            0
+        } else {
+            self.memory_reads += 1;
+            self.memory_latency
+        }
+    }
+
+    /// Human-readable report of every level.
+    pub fn summary(&self) -> String {
+        let mut text = String::new();
+        self.l1i.summary("L1I", &mut text);
+        self.l1d.summary("L1D", &mut text);
+        if let Some(l2) = &self.l2 {
+            l2.summary("L2", &mut text);
+        }
+        let _ = writeln!(text, "memory {} line reads, {} writes", self.memory_reads, self.memory_writes);
+        let _ = writeln!(text, "cycles {} fetching, {} loading and storing", self.fetch_cycles, self.data_cycles);
+        text
+    }
+}
+
+impl Hooks for CacheHierarchy {
+    fn fetch(&mut self, pc: u32, _raw: u32) {
+        CacheHierarchy::fetch(self, pc);
+    }
+
+    fn memory_read(&mut self, address: u32, size: u32, _value: u32) {
+        self.read(address, size);
+    }
+
+    fn memory_write(&mut self, address: u32, size: u32, _value: u32) {
+        self.write(address, size);
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::cpu::{Cpu, StopReason};
+use crate::memory::Memory;
+use crate::trace::Commit;
+
+/// Steps allowed without a retired instruction (traps into handlers) before giving up.
+const MAX_STEPS_WITHOUT_COMMIT: usize = 64;
+
+/// One instruction from a reference commit log, as written by `spike --log-commits`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReferenceCommit {
+    /// 1-based line number in the log.
+    pub line: usize,
+    pub pc: u32,
+    pub raw: u32,
+    /// Integer register writes (`xN 0x...`), excluding x0.
+    pub register_writes: Vec<(u8, u32)>,
+    pub text: String,
+}
+
+/// Parse the commit lines of a Spike log. Other lines (`-l` instruction lines,
+/// exception messages) are ignored, as are CSR, FP and memory fields.
+pub fn parse_commit_log(text: &str) -> Vec<ReferenceCommit> {
+    text.lines().enumerate().filter_map(|(index, line)| parse_commit_line(index + 1, line)).collect()
+}
+
+/// `core   0: 3 0x00000000 (0x00500513) x10 0x00000005 mem 0x00000040`
+fn parse_commit_line(line_number: usize, line: &str) -> Option<ReferenceCommit> {
+    let (_, rest) = line.trim().strip_prefix("core")?.split_once(':')?;
+    let mut fields = rest.split_whitespace();
+    let privilege = fields.next()?;
+    if privilege.len() != 1 || !privilege.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let pc = parse_hex(fields.next()?)?;
+    let raw = parse_hex(fields.next()?.strip_prefix('(')?.strip_suffix(')')?)?;
+    let mut register_writes = Vec::new();
+    while let Some(field) = fields.next() {
+        if let Some(register) = field.strip_prefix('x').and_then(|number| number.parse::<u8>().ok()) {
+            let value = parse_hex(fields.next()?)?;
+            if register != 0 {
+                register_writes.push((register, value));
+            }
+        }
+    }
+    Some(ReferenceCommit { line: line_number, pc, raw, register_writes, text: line.trim().to_string() })
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    // 64-bit Spike logs sign-extend RV32 values; keep the low 32 bits
+    u64::from_str_radix(text.strip_prefix("0x")?, 16).ok().map(|value| value as u32)
+}
+
+/// The first point where the simulator and the reference disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    Pc { expected: u32, actual: u32 },
+    Instruction { expected: u32, actual: u32 },
+    RegisterWrite { expected: Vec<(u8, u32)>, actual: Vec<(u8, u32)> },
+    /// The simulator stopped (or stopped retiring instructions) while reference commits remained.
+    Stopped(StopReason),
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let writes = |writes: &[(u8, u32)]| {
+            let text: Vec<String> = writes.iter().map(|(register, value)| format!("x{}=0x{:08x}", register, value)).collect();
+            if text.is_empty() { "none".to_string() } else { text.join(" ") }
+        };
+        match self {
+            Divergence::Pc { expected, actual } => write!(f, "pc mismatch: expected 0x{:08x}, got 0x{:08x}", expected, actual),
+            Divergence::Instruction { expected, actual } => {
+                write!(f, "instruction mismatch: expected 0x{:08x}, got 0x{:08x}", expected, actual)
+            }
+            Divergence::RegisterWrite { expected, actual } => {
+                write!(f, "rd mismatch: expected {}, got {}", writes(expected), writes(actual))
+            }
+            Divergence::Stopped(stop) => write!(f, "simulator stopped early: {}", stop),
+        }
+    }
+}
+
+/// Outcome of a lockstep run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CosimReport {
+    /// Reference commits that matched.
+    pub matched: usize,
+    pub divergence: Option<Divergence>,
+    /// The reference commit at the divergence, and the simulator's commit if it retired one.
+    pub expected: Option<ReferenceCommit>,
+    pub actual: Option<String>,
+    /// Commit lines leading up to the divergence, oldest first.
+    pub before: Vec<String>,
+    /// Reference lines following the divergence.
+    pub after: Vec<String>,
+}
+
+impl CosimReport {
+    pub fn passed(&self) -> bool {
+        self.divergence.is_none()
+    }
+}
+
+impl fmt::Display for CosimReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(divergence) = &self.divergence else {
+            return write!(f, "co-simulation passed: {} instructions matched", self.matched);
+        };
+        writeln!(f, "divergence after {} matching instructions: {}", self.matched, divergence)?;
+        for line in &self.before {
+            writeln!(f, "    {}", line)?;
+        }
+        if let Some(expected) = &self.expected {
+            writeln!(f, "  reference (line {}):", expected.line)?;
+            writeln!(f, "  > {}", expected.text)?;
+        }
+        writeln!(f, "  simulator:")?;
+        writeln!(f, "  > {}", self.actual.as_deref().unwrap_or("(no instruction retired)"))?;
+        if !self.after.is_empty() {
+            writeln!(f, "  reference continues:")?;
+            for line in &self.after {
+                writeln!(f, "    {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run `cpu` in lockstep with `reference`, comparing pc, instruction bits and integer
+/// register writes of every retired instruction. Leading reference commits before the
+/// simulator's start pc (a boot ROM, for example) are skipped. `context` commit lines
+/// before and after a divergence are kept for the report.
+pub fn cosimulate(cpu: &mut Cpu, memory: &mut Memory, reference: &[ReferenceCommit], context: usize) -> CosimReport {
+    cpu.set_commit_log(true);
+    let start = reference.iter().position(|commit| commit.pc == cpu.pc()).unwrap_or(0);
+    let mut report = CosimReport { matched: 0, divergence: None, expected: None, actual: None, before: Vec::new(), after: Vec::new() };
+    let mut history: VecDeque<String> = VecDeque::with_capacity(context + 1);
+    let mut stopped: Option<StopReason> = None;
+
+    for (index, expected) in reference.iter().enumerate().skip(start) {
+        let (divergence, actual) = match stopped {
+            Some(stop) => (Some(Divergence::Stopped(stop)), None),
+            None => match next_commit(cpu, memory) {
+                (Some(commit), stop) => {
+                    if matches!(stop, StopReason::Halted { .. } | StopReason::Trap { .. }) {
+                        stopped = Some(stop);
+                    }
+                    (compare(expected, &commit), Some(commit.spike_commit()))
+                }
+                (None, stop) => (Some(Divergence::Stopped(stop)), None),
+            },
+        };
+        if let Some(divergence) = divergence {
+            report.divergence = Some(divergence);
+            report.expected = Some(expected.clone());
+            report.actual = actual;
+            report.before = history.into();
+            report.after = reference[index + 1..].iter().take(context).map(|commit| commit.text.clone()).collect();
+            return report;
+        }
+        report.matched += 1;
+        if history.len() == context {
+            history.pop_front();
+        }
+        if context > 0 {
+            history.push_back(expected.text.clone());
+        }
+    }
+    report
+}
+
+/// Step until an instruction retires, returning its commit and the last stop reason.
+fn next_commit(cpu: &mut Cpu, memory: &mut Memory) -> (Option<Commit>, StopReason) {
+    for _ in 0..MAX_STEPS_WITHOUT_COMMIT {
+        let stop = cpu.step(memory);
+        if let Some(commit) = cpu.last_commit().filter(|commit| !commit.trap) {
+            return (Some(commit.clone()), stop);
+        }
+        if stop != StopReason::InstructionLimit {
+            return (None, stop);
+        }
+    }
+    (None, StopReason::InstructionLimit)
+}
+
+fn compare(expected: &ReferenceCommit, actual: &Commit) -> Option<Divergence> {
+    if expected.pc != actual.pc {
+        return Some(Divergence::Pc { expected: expected.pc, actual: actual.pc });
+    }
+    if expected.raw != actual.raw {
+        return Some(Divergence::Instruction { expected: expected.raw, actual: actual.raw });
+    }
+    if expected.register_writes != actual.register_writes {
+        return Some(Divergence::RegisterWrite {
+            expected: expected.register_writes.clone(),
+            actual: actual.register_writes.clone(),
+        });
+    }
+    None
+}
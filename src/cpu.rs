@@ -1,215 +1,1353 @@
+use std::collections::BTreeSet;
+use std::ffi::c_void;
+use std::fmt;
+
+use crate::block::{AluOp, BlockCache, Op};
+use crate::csr::{self, *};
+use crate::decode_cache::DecodeCache;
+use crate::jit::{self, NativeBlock};
 use crate::riscv_sim::*;
+use crate::reverse::{StoreUndo, Undo};
+use crate::rvwmo::StoreBuffer;
+use crate::snapshot::Snapshot;
+use crate::trace::{Commit, MemoryAccess};
+
+/// Exit system call number (newlib / proxy kernel convention), passed in a7.
+pub const SYS_EXIT: u32 = 93;
+
+const REG_A0: usize = 10;
+const REG_A7: usize = 17;
+
+/// Memory accesses a watchpoint triggers on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+impl WatchKind {
+    fn matches(&self, write: bool) -> bool {
+        match self {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::Access => true,
+        }
+    }
+}
+
+/// A data watchpoint over `length` bytes starting at `address`. With a `condition`,
+/// it only triggers when the value read or written equals it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub address: u32,
+    pub length: u32,
+    pub kind: WatchKind,
+    pub condition: Option<u32>,
+}
+
+impl Watchpoint {
+    pub fn new(address: u32, length: u32, kind: WatchKind) -> Self {
+        Watchpoint { address, length, kind, condition: None }
+    }
+
+    pub fn with_condition(mut self, value: u32) -> Self {
+        self.condition = Some(value);
+        self
+    }
+
+    /// Whether a read (or, with `write`, a write) of `value` to `size` bytes at `address`
+    /// triggers this watchpoint.
+    pub fn triggers(&self, address: u32, size: u32, write: bool, value: u32) -> bool {
+        let start = self.address as u64;
+        let end = start + self.length as u64;
+        let overlaps = (address as u64) < end && start < address as u64 + size as u64;
+        self.kind.matches(write) && overlaps && self.condition.is_none_or(|condition| condition == value)
+    }
+}
+
+/// Why [`Cpu::run_for`] returned control to the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The requested number of instructions were executed.
+    InstructionLimit,
+    /// Execution reached a breakpoint address (not yet executed), or retired an
+    /// EBREAK while no trap handler was installed.
+    Breakpoint { pc: u32 },
+    /// The guest made the exit system call while no trap handler was installed.
+    Halted { exit_code: u32 },
+    /// A trap was raised while its trap vector was zero, i.e. no handler is installed.
+    /// The faulting instruction has not been retired and `pc` points at it.
+    Trap { trap: Trap, pc: u32 },
+    /// WFI retired while no enabled interrupt was pending; `pc` is the WFI.
+    WaitForInterrupt { pc: u32 },
+    /// Reverse execution reached the oldest recorded state, at `pc`.
+    NoHistory { pc: u32 },
+    /// The instruction at `pc` read or wrote `address` and triggered a watchpoint of `kind`.
+    /// The instruction has retired. For reads `old` and `new` are both the value read.
+    Watchpoint { pc: u32, address: u32, kind: WatchKind, write: bool, old: u32, new: u32 },
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::InstructionLimit => write!(f, "instruction limit reached"),
+            StopReason::Breakpoint { pc } => write!(f, "breakpoint at 0x{:08x}", pc),
+            StopReason::Halted { exit_code } => write!(f, "halted with exit code {}", exit_code),
+            StopReason::Trap { trap, pc } => write!(f, "unhandled trap at 0x{:08x}: {}", pc, trap),
+            StopReason::WaitForInterrupt { pc } => write!(f, "waiting for interrupt at 0x{:08x}", pc),
+            StopReason::NoHistory { pc } => write!(f, "no more reverse-execution history at 0x{:08x}", pc),
+            StopReason::Watchpoint { pc, address, write: false, new, .. } => {
+                write!(f, "read watchpoint at 0x{:08x}: value 0x{:x} read by 0x{:08x}", address, new, pc)
+            }
+            StopReason::Watchpoint { pc, address, old, new, .. } => {
+                write!(f, "watchpoint at 0x{:08x}: 0x{:x} -> 0x{:x} written by 0x{:08x}", address, old, new, pc)
+            }
+        }
+    }
+}
 
-pub struct Cpu {
+/// A single RV32 hart. `H` observes execution; see [`Hooks`].
+pub struct Cpu<H: Hooks = NoHooks> {
     registers: [u32; 32],
     pc: u32,
+    privilege: Privilege,
+    csrs: CsrFile,
+    reservation: Option<u32>,
+    breakpoints: BTreeSet<u32>,
+    watchpoints: Vec<Watchpoint>,
+    // First watchpoint hit by the executing instruction
+    watch_hit: Option<StopReason>,
+    commit_log: bool,
+    // A trap handler was entered since the last instruction
+    trap_entered: bool,
+    // The executing instruction wrote minstret or mcycle, so retiring it must not
+    // count it there
+    instret_written: bool,
+    cycle_written: bool,
+    // Effects of the executing instruction, then of the last retired one
+    commit: Option<Commit>,
+    last_commit: Option<Commit>,
+    undo_log: bool,
+    // State overwritten by the executing instruction, then by the last step
+    undo: Option<Undo>,
+    last_undo: Option<Undo>,
+    decode_cache: Option<DecodeCache>,
+    blocks: Option<BlockCache>,
+    // Address and size of each store since the log was last drained
+    store_log: Option<Vec<(u32, u32)>>,
+    store_buffer: Option<StoreBuffer>,
+    hooks: H,
 }
 
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Cpu {
-
     pub fn new() -> Self {
-        // Fetch 32-bit instruction from memory at the PC location
+        Cpu::with_hooks(NoHooks)
+    }
+}
+
+impl<H: Hooks> Cpu<H> {
+    pub fn with_hooks(hooks: H) -> Self {
         Cpu {
             registers: [0; 32],
             pc: 0,
+            privilege: Privilege::Machine,
+            csrs: CsrFile::new(0),
+            reservation: None,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            commit_log: false,
+            trap_entered: false,
+            instret_written: false,
+            cycle_written: false,
+            commit: None,
+            last_commit: None,
+            undo_log: false,
+            undo: None,
+            last_undo: None,
+            decode_cache: Some(DecodeCache::new()),
+            blocks: Some(BlockCache::new()),
+            store_log: None,
+            store_buffer: None,
+            hooks,
+        }
+    }
+
+    pub fn hooks(&self) -> &H {
+        &self.hooks
+    }
+
+    pub fn hooks_mut(&mut self) -> &mut H {
+        &mut self.hooks
+    }
+
+    pub fn into_hooks(self) -> H {
+        self.hooks
+    }
+
+    /// Replace the hooks, keeping all architectural and debug state.
+    pub fn attach_hooks<G: Hooks>(self, hooks: G) -> Cpu<G> {
+        Cpu {
+            registers: self.registers,
+            pc: self.pc,
+            privilege: self.privilege,
+            csrs: self.csrs,
+            reservation: self.reservation,
+            breakpoints: self.breakpoints,
+            watchpoints: self.watchpoints,
+            watch_hit: self.watch_hit,
+            commit_log: self.commit_log,
+            trap_entered: self.trap_entered,
+            instret_written: self.instret_written,
+            cycle_written: self.cycle_written,
+            commit: self.commit,
+            last_commit: self.last_commit,
+            undo_log: self.undo_log,
+            undo: self.undo,
+            last_undo: self.last_undo,
+            decode_cache: self.decode_cache,
+            blocks: self.blocks,
+            store_log: self.store_log,
+            store_buffer: self.store_buffer,
+            hooks,
         }
     }
 
     pub fn print_registers(&self) {
-        let mut i = 0;
-        for r in self.registers {
+        for (i, r) in self.registers.iter().enumerate() {
             print!("R{} : {}\t", i, r);
-            i += 1;
         }
     }
 
-    fn fetch(&self, memory: &Memory) -> u32 {
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u32) {
+        self.pc = pc;
+    }
+
+    pub fn register(&self, register: u8) -> u32 {
+        self.registers[register as usize & 0x1f]
+    }
+
+    /// Write a general-purpose register; writes to x0 are ignored.
+    pub fn set_register(&mut self, register: u8, value: u32) {
+        if register != 0 {
+            if let Some(undo) = &mut self.undo {
+                undo.registers.push((register, self.registers[register as usize & 0x1f]));
+            }
+            self.registers[register as usize & 0x1f] = value;
+            self.hooks.register_write(register, value);
+            if let Some(commit) = &mut self.commit {
+                commit.register_writes.push((register, value));
+            }
+        }
+    }
+
+    pub fn privilege(&self) -> Privilege {
+        self.privilege
+    }
+
+    pub fn set_privilege(&mut self, privilege: Privilege) {
+        self.privilege = privilege;
+    }
+
+    /// Read a CSR without privilege checks, or `None` if it does not exist.
+    pub fn read_csr(&self, csr: u16) -> Option<u32> {
+        self.csrs.read(csr)
+    }
+
+    /// Write a CSR without privilege checks. Returns `false` if it does not exist.
+    pub fn write_csr(&mut self, csr: u16, value: u32) -> bool {
+        self.csrs.write(csr, value)
+    }
+
+    pub fn csrs(&self) -> &CsrFile {
+        &self.csrs
+    }
+
+    /// Set mhartid, which is read-only to software.
+    pub fn set_hart_id(&mut self, hartid: u32) {
+        self.csrs.mhartid = hartid;
+    }
+
+    /// Number of instructions retired (minstret).
+    pub fn instret(&self) -> u64 {
+        self.csrs.instret
+    }
+
+    /// Raise or clear an interrupt line in mip, as a device or embedding host would.
+    pub fn set_interrupt_pending(&mut self, interrupt: Interrupt, pending: bool) {
+        if pending {
+            self.csrs.mip |= interrupt.mask();
+        } else {
+            self.csrs.mip &= !interrupt.mask();
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, address: u32) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u32) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn has_breakpoint(&self, address: u32) -> bool {
+        self.breakpoints.contains(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u32> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    /// Remove every watchpoint of `kind` covering exactly `length` bytes at `address`.
+    pub fn remove_watchpoint(&mut self, address: u32, length: u32, kind: WatchKind) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| (w.address, w.length, w.kind) != (address, length, kind));
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Record the register, CSR and memory effects of each retired instruction.
+    pub fn set_commit_log(&mut self, enabled: bool) {
+        self.commit_log = enabled;
+        self.last_commit = None;
+    }
+
+    /// Effects of the instruction executed by the last step, if the commit log is
+    /// enabled and that step executed one. Check [`Commit::trap`] for instructions
+    /// that raised an exception rather than retiring.
+    pub fn last_commit(&self) -> Option<&Commit> {
+        self.last_commit.as_ref()
+    }
+
+    /// Keep decoded instructions in a per-page cache instead of decoding every fetch
+    /// (enabled by default). Stores and FENCE.I invalidate it; see
+    /// [`Cpu::flush_decode_cache`] for memory written by the host.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = enabled.then(DecodeCache::new);
+    }
+
+    /// Execute straight-line code as translated basic blocks (enabled by default).
+    /// Blocks only run while no breakpoints or watchpoints are set and the commit and
+    /// undo logs are off; otherwise every instruction is interpreted on its own.
+    pub fn set_block_translation(&mut self, enabled: bool) {
+        self.blocks = enabled.then(BlockCache::new);
+    }
+
+    /// Compile hot translated blocks to x86-64 host code (off by default), enabling
+    /// block translation if needed. Returns `false`, leaving it off, on other hosts.
+    /// Compiled code only runs for hooks that observe nothing (see [`Hooks::OBSERVES`])
+    /// and with little-endian data; its results are those of the interpreter.
+    pub fn set_jit(&mut self, enabled: bool) -> bool {
+        if !enabled {
+            if let Some(blocks) = &mut self.blocks {
+                blocks.set_jit(false);
+            }
+            return true;
+        }
+        if !jit::SUPPORTED {
+            return false;
+        }
+        self.blocks.get_or_insert_with(BlockCache::new).set_jit(true);
+        true
+    }
+
+    /// Number of blocks currently compiled to host code.
+    pub fn compiled_blocks(&self) -> usize {
+        self.blocks.as_ref().map_or(0, BlockCache::compiled)
+    }
+
+    /// Forget all decoded instructions and translated blocks. Call this after writing
+    /// code to memory from outside the CPU while it has been running.
+    pub fn flush_decode_cache(&mut self) {
+        if let Some(cache) = &mut self.decode_cache {
+            cache.clear();
+        }
+        if let Some(blocks) = &mut self.blocks {
+            blocks.clear();
+        }
+    }
+
+    /// Record the address and size of every store, for other harts sharing the memory
+    /// to observe with [`Cpu::remote_store`].
+    pub fn set_store_log(&mut self, enabled: bool) {
+        self.store_log = enabled.then(Vec::new);
+    }
+
+    /// Move the stores logged since the last call to the end of `stores`.
+    pub fn drain_stores(&mut self, stores: &mut Vec<(u32, u32)>) {
+        if let Some(log) = &mut self.store_log {
+            stores.append(log);
+        }
+    }
+
+    /// Note a store of `size` bytes at `address` by another hart: it breaks an LR
+    /// reservation covering those bytes and may have overwritten cached code.
+    pub fn remote_store(&mut self, address: u32, size: u32) {
+        let (start, end) = (address as u64, address as u64 + size as u64);
+        if self.reservation.is_some_and(|reserved| start < reserved as u64 + 4 && (reserved as u64) < end) {
+            self.reservation = None;
+        }
+        if let Some(cache) = &mut self.decode_cache {
+            cache.invalidate(address);
+        }
+        if let Some(blocks) = &mut self.blocks {
+            blocks.invalidate(address);
+        }
+    }
+
+    /// Hold stores in a [`StoreBuffer`] instead of writing them to memory (off by
+    /// default). Buffered stores only reach memory when drained through
+    /// [`Cpu::store_buffer_mut`]; disabling the buffer discards them.
+    pub fn set_store_buffer(&mut self, enabled: bool) {
+        self.store_buffer = enabled.then(StoreBuffer::new);
+    }
+
+    pub fn store_buffer(&self) -> Option<&StoreBuffer> {
+        self.store_buffer.as_ref()
+    }
+
+    pub fn store_buffer_mut(&mut self) -> Option<&mut StoreBuffer> {
+        self.store_buffer.as_mut()
+    }
+
+    /// Record the state each step overwrites, so that it can be undone.
+    pub fn set_undo_log(&mut self, enabled: bool) {
+        self.undo_log = enabled;
+        self.last_undo = None;
+    }
+
+    /// State overwritten by the last step, if the undo log is enabled and the step
+    /// changed anything. Taking it leaves `None` until the next step.
+    pub fn take_undo(&mut self) -> Option<Undo> {
+        self.last_undo.take()
+    }
+
+    /// Revert a step recorded in the undo log. Steps must be undone newest first.
+    pub fn undo(&mut self, undo: &Undo, memory: &mut Memory) {
+        for store in undo.stores.iter().rev() {
+            if let Some(cache) = &mut self.decode_cache {
+                cache.invalidate(store.address);
+            }
+            if let Some(blocks) = &mut self.blocks {
+                blocks.invalidate(store.address);
+            }
+            memory.write_bytes(store.address as usize, &store.bytes[..store.size as usize]);
+        }
+        for &(register, value) in undo.registers.iter().rev() {
+            self.registers[register as usize] = value;
+        }
+        self.pc = undo.pc;
+        self.privilege = undo.privilege;
+        self.reservation = undo.reservation;
+        self.csrs = undo.csrs.clone();
+        self.trap_entered = undo.trap_entered;
+        self.watch_hit = None;
+        self.commit = None;
+        self.last_commit = None;
+        self.sync_data_endianness(memory);
+    }
+
+    /// Capture the architectural state of the hart and `memory`. Breakpoints,
+    /// watchpoints and hooks are debug state and not part of a snapshot.
+    pub fn snapshot(&self, memory: &Memory) -> Snapshot {
+        Snapshot {
+            registers: self.registers,
+            pc: self.pc,
+            privilege: self.privilege,
+            reservation: self.reservation,
+            csrs: self.csrs.clone(),
+            memory: memory.bytes().to_vec(),
+            data_endianness: self.data_endianness(),
+        }
+    }
+
+    /// Return the hart and `memory` to the state captured in `snapshot`.
+    pub fn restore(&mut self, snapshot: &Snapshot, memory: &mut Memory) {
+        self.registers = snapshot.registers;
+        self.pc = snapshot.pc;
+        self.privilege = snapshot.privilege;
+        self.reservation = snapshot.reservation;
+        self.csrs = snapshot.csrs.clone();
+        self.watch_hit = None;
+        self.trap_entered = false;
+        self.commit = None;
+        self.last_commit = None;
+        self.flush_decode_cache();
+        memory.replace_bytes(&snapshot.memory);
+        self.sync_data_endianness(memory);
+    }
+
+    /// Data endianness selected by mstatus.MBE/SBE/UBE for the current privilege mode.
+    pub fn data_endianness(&self) -> Endianness {
+        let big = match self.privilege {
+            Privilege::Machine => self.csrs.mstatush & MSTATUSH_MBE != 0,
+            Privilege::Supervisor => self.csrs.mstatush & MSTATUSH_SBE != 0,
+            Privilege::User => self.csrs.mstatus & MSTATUS_UBE != 0,
+        };
+        if big { Endianness::Big } else { Endianness::Little }
+    }
+
+    /// Point `memory`'s data accesses at the byte order of the current privilege mode.
+    /// This is the only place the CPU sets it; call it whenever the privilege mode or
+    /// mstatus may have changed before memory is accessed again.
+    fn sync_data_endianness(&self, memory: &mut Memory) {
+        memory.set_data_endianness(self.data_endianness());
+    }
+
+    fn fetch(&self, memory: &Memory) -> Result<u32, Exception> {
         // Fetch 32-bit instruction from memory at the PC location
         let addr: usize = self.pc as usize;
-        let instruction: u32 = memory.load_word(addr);
-        return instruction;
+        if self.pc & 0b11 != 0 {
+            return Err(Exception::InstructionAddressMisaligned(self.pc));
+        }
+        if addr + 4 > memory.size() {
+            return Err(Exception::InstructionAccessFault(self.pc));
+        }
+        Ok(memory.fetch_word(addr))
+    }
+
+    fn decode(&self, instruction: u32) -> Result<Instruction, Exception> {
+        Instruction::decode(instruction).map_err(|_| Exception::IllegalInstruction(instruction))
+    }
+
+    /// Fetch the instruction word at pc and decode it, through the decode cache if enabled.
+    /// A decoding failure is reported separately, since the word was still fetched.
+    fn fetch_decoded(&mut self, memory: &Memory) -> Result<(u32, Result<Instruction, Exception>), Exception> {
+        let pc = self.pc;
+        if let Some((raw, instruction)) = self.decode_cache.as_ref().filter(|_| pc & 0b11 == 0).and_then(|cache| cache.get(pc)) {
+            return Ok((raw, Ok(instruction)));
+        }
+        let raw = self.fetch(memory)?;
+        let instruction = self.decode(raw);
+        if let (Some(cache), Ok(instruction)) = (&mut self.decode_cache, instruction) {
+            cache.insert(pc, raw, instruction);
+        }
+        Ok((raw, instruction))
+    }
+
+    /// Load `size` bytes (1, 2 or 4), zero-extended.
+    fn load(&mut self, memory: &Memory, address: u32, size: u32) -> Result<u32, Exception> {
+        if !address.is_multiple_of(size) {
+            return Err(Exception::LoadAddressMisaligned(address));
+        }
+        if address as u64 + size as u64 > memory.size() as u64 {
+            return Err(Exception::LoadAccessFault(address));
+        }
+        let value = match &self.store_buffer {
+            Some(buffer) => buffer.load(memory, address, size),
+            None => read_sized(memory, address, size),
+        };
+        self.hooks.memory_read(address, size, value);
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(address, size, false, value, value);
+        }
+        if let Some(commit) = &mut self.commit {
+            commit.loads.push(MemoryAccess { address, size, value });
+        }
+        Ok(value)
+    }
+
+    /// Store the low `size` bytes (1, 2 or 4) of `value`.
+    fn store(&mut self, memory: &mut Memory, address: u32, size: u32, value: u32) -> Result<(), Exception> {
+        if !address.is_multiple_of(size) {
+            return Err(Exception::StoreAddressMisaligned(address));
+        }
+        if address as u64 + size as u64 > memory.size() as u64 {
+            return Err(Exception::StoreAccessFault(address));
+        }
+        let addr = address as usize;
+        let new = if size == 4 { value } else { value & ((1 << (8 * size)) - 1) };
+        if let Some(undo) = &mut self.undo {
+            let mut bytes = [0; 4];
+            bytes[..size as usize].copy_from_slice(&memory.bytes()[addr..addr + size as usize]);
+            let old = read_sized(memory, address, size);
+            undo.stores.push(StoreUndo { address, size, bytes, old, new });
+        }
+        if !self.watchpoints.is_empty() {
+            let old = read_sized(memory, address, size);
+            self.check_watchpoints(address, size, true, old, new);
+        }
+        self.hooks.memory_write(address, size, new);
+        if let Some(commit) = &mut self.commit {
+            commit.stores.push(MemoryAccess { address, size, value: new });
+        }
+        if let Some(cache) = &mut self.decode_cache {
+            cache.invalidate(address);
+        }
+        if let Some(blocks) = &mut self.blocks {
+            blocks.invalidate(address);
+        }
+        if let Some(log) = &mut self.store_log {
+            log.push((address, size));
+        }
+        if let Some(buffer) = &mut self.store_buffer {
+            buffer.push(address, size, value, memory.data_endianness());
+            return Ok(());
+        }
+        match size {
+            1 => memory.store_byte(addr, value as u8),
+            2 => memory.store_halfword(addr, value as u16),
+            _ => memory.store_word(addr, value),
+        }
+        Ok(())
+    }
+
+    fn check_watchpoints(&mut self, address: u32, size: u32, write: bool, old: u32, new: u32) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+        let hit = self.watchpoints.iter().find(|w| w.triggers(address, size, write, new));
+        if let Some(watchpoint) = hit {
+            self.watch_hit = Some(StopReason::Watchpoint { pc: self.pc, address, kind: watchpoint.kind, write, old, new });
+        }
+    }
+
+    /// Control transfer target; IALIGN is 32 since the C extension is not implemented.
+    fn jump_target(&self, target: u32) -> Result<u32, Exception> {
+        if target & 0b11 != 0 {
+            return Err(Exception::InstructionAddressMisaligned(target));
+        }
+        Ok(target)
     }
 
-    fn decode(&self, instruction: u32) -> Instruction {
-        let base_inst: BaseInstruction = BaseInstruction::decode_instruction_by_opcode(instruction);
-        Instruction::decode_instruction_funct3_funct7(base_inst)
+    fn branch(&mut self, taken: bool, offset: i32) -> Result<u32, Exception> {
+        let next_pc = if taken {
+            self.jump_target(self.pc.wrapping_add(offset as u32))?
+        } else {
+            self.pc.wrapping_add(4)
+        };
+        self.hooks.branch(self.pc, taken);
+        Ok(next_pc)
     }
 
-    fn execute(&mut self, instruction: Instruction, memory: &mut Memory) {
+    /// Execute one decoded instruction and return the address of the next one.
+    fn execute(&mut self, instruction: Instruction, raw: u32, memory: &mut Memory) -> Result<u32, Exception> {
+        let next_pc = self.pc.wrapping_add(4);
         match instruction {
             // U-type instructions
             Instruction::Lui(u) => {
-                self.registers[u.rd as usize] = u.imm as u32;
+                self.set_register(u.rd, u.imm as u32);
             }
-            
+
             Instruction::Auipc(u) => {
-                self.registers[u.rd as usize] = self.pc.wrapping_add(u.imm as u32);
+                self.set_register(u.rd, self.pc.wrapping_add(u.imm as u32));
             }
-    
+
             // J-type instructions
             Instruction::Jal(j) => {
-                let ret_addr = self.pc.wrapping_add(4);
-                self.pc = self.pc.wrapping_add(j.imm as u32);
-                self.registers[j.rd as usize] = ret_addr;
+                let target = self.jump_target(self.pc.wrapping_add(j.imm as u32))?;
+                self.set_register(j.rd, next_pc);
+                return Ok(target);
             }
-    
+
             // I-type instructions
             Instruction::Jalr(i) => {
-                let ret_addr = self.pc.wrapping_add(4);
-                self.pc = (self.registers[i.rs1 as usize].wrapping_add(i.imm as u32)) & !1;
-                self.registers[i.rd as usize] = ret_addr;
+                let target = self.jump_target(self.registers[i.rs1 as usize].wrapping_add(i.imm as u32) & !1)?;
+                self.set_register(i.rd, next_pc);
+                return Ok(target);
             }
             Instruction::Lb(i) => {
-                let addr = self.registers[i.rs1 as usize].wrapping_add(i.imm as u32) as usize;
-                self.registers[i.rd as usize] = memory.load_byte(addr);
+                let addr = self.registers[i.rs1 as usize].wrapping_add(i.imm as u32);
+                let value = self.load(memory, addr, 1)?;
+                self.set_register(i.rd, value as u8 as i8 as i32 as u32);
             }
             Instruction::Lh(i) => {
-                let addr = self.registers[i.rs1 as usize].wrapping_add(i.imm as u32) as usize;
-                self.registers[i.rd as usize] = memory.load_halfword(addr);
+                let addr = self.registers[i.rs1 as usize].wrapping_add(i.imm as u32);
+                let value = self.load(memory, addr, 2)?;
+                self.set_register(i.rd, value as u16 as i16 as i32 as u32);
             }
             Instruction::Lw(i) => {
-                let addr = self.registers[i.rs1 as usize].wrapping_add(i.imm as u32) as usize;
-                self.registers[i.rd as usize] = memory.load_word(addr);
+                let addr = self.registers[i.rs1 as usize].wrapping_add(i.imm as u32);
+                let value = self.load(memory, addr, 4)?;
+                self.set_register(i.rd, value);
             }
             Instruction::Lbu(i) => {
-                let addr = self.registers[i.rs1 as usize].wrapping_add(i.imm as u32) as usize;
-                self.registers[i.rd as usize] = memory.load_byte(addr);
+                let addr = self.registers[i.rs1 as usize].wrapping_add(i.imm as u32);
+                let value = self.load(memory, addr, 1)?;
+                self.set_register(i.rd, value);
             }
             Instruction::Lhu(i) => {
-                let addr = self.registers[i.rs1 as usize].wrapping_add(i.imm as u32) as usize;
-                self.registers[i.rd as usize] = memory.load_halfword(addr);
+                let addr = self.registers[i.rs1 as usize].wrapping_add(i.imm as u32);
+                let value = self.load(memory, addr, 2)?;
+                self.set_register(i.rd, value);
             }
             Instruction::Addi(i) => {
-                self.registers[i.rd as usize] = self.registers[i.rs1 as usize].wrapping_add(i.imm as u32);
+                self.set_register(i.rd, self.registers[i.rs1 as usize].wrapping_add(i.imm as u32));
             }
             Instruction::Slti(i) => {
-                self.registers[i.rd as usize] = if (self.registers[i.rs1 as usize] as i32) < i.imm as i32 { 1 } else { 0 };
+                self.set_register(i.rd, ((self.registers[i.rs1 as usize] as i32) < i.imm) as u32);
             }
             Instruction::Sltiu(i) => {
-                self.registers[i.rd as usize] = if (self.registers[i.rs1 as usize] as u32) < (i.imm as u32) { 1 } else { 0 };
+                self.set_register(i.rd, (self.registers[i.rs1 as usize] < (i.imm as u32)) as u32);
             }
             Instruction::Xori(i) => {
-                self.registers[i.rd as usize] = self.registers[i.rs1 as usize] ^ (i.imm as u32);
+                self.set_register(i.rd, self.registers[i.rs1 as usize] ^ (i.imm as u32));
             }
             Instruction::Ori(i) => {
-                self.registers[i.rd as usize] = self.registers[i.rs1 as usize] | (i.imm as u32);
+                self.set_register(i.rd, self.registers[i.rs1 as usize] | (i.imm as u32));
             }
             Instruction::Andi(i) => {
-                self.registers[i.rd as usize] = self.registers[i.rs1 as usize] & (i.imm as u32);
+                self.set_register(i.rd, self.registers[i.rs1 as usize] & (i.imm as u32));
             }
             Instruction::Slli(i) => {
-                self.registers[i.rd as usize] = self.registers[i.rs1 as usize] << (i.imm & 0x1F);
+                self.set_register(i.rd, self.registers[i.rs1 as usize] << (i.imm & 0x1F));
             }
             Instruction::Srli(i) => {
-                self.registers[i.rd as usize] = ((self.registers[i.rs1 as usize] as u32 >> (i.imm & 0x1F))) as u32;
+                self.set_register(i.rd, self.registers[i.rs1 as usize] >> (i.imm & 0x1F));
             }
             Instruction::Srai(i) => {
-                self.registers[i.rd as usize] = (self.registers[i.rs1 as usize] >> (i.imm & 0x1F)) as u32;
+                self.set_register(i.rd, ((self.registers[i.rs1 as usize] as i32) >> (i.imm & 0x1F)) as u32);
             }
-    
+
             // S-type instructions
             Instruction::Sb(s) => {
-                let addr = self.registers[s.rs1 as usize].wrapping_add(s.imm as u32) as usize;
-                memory.store_byte(addr, self.registers[s.rs2 as usize] as u8);
+                let addr = self.registers[s.rs1 as usize].wrapping_add(s.imm as u32);
+                self.store(memory, addr, 1, self.registers[s.rs2 as usize])?;
             }
 
             Instruction::Sh(s) => {
-                let addr = self.registers[s.rs1 as usize].wrapping_add(s.imm as u32) as usize;
-                memory.store_halfword(addr, self.registers[s.rs2 as usize] as u16);
+                let addr = self.registers[s.rs1 as usize].wrapping_add(s.imm as u32);
+                self.store(memory, addr, 2, self.registers[s.rs2 as usize])?;
             }
 
             Instruction::Sw(s) => {
-                let addr = self.registers[s.rs1 as usize].wrapping_add(s.imm as u32) as usize;
-                memory.store_word(addr, self.registers[s.rs2 as usize]);
+                let addr = self.registers[s.rs1 as usize].wrapping_add(s.imm as u32);
+                self.store(memory, addr, 4, self.registers[s.rs2 as usize])?;
             }
-    
+
             // B-type instructions
             Instruction::Beq(b) => {
-                if self.registers[b.rs1 as usize] == self.registers[b.rs2 as usize] {
-                    self.pc = self.pc.wrapping_add(b.imm as u32);
-                }
+                return self.branch(self.registers[b.rs1 as usize] == self.registers[b.rs2 as usize], b.imm);
             }
-            
             Instruction::Bne(b) => {
-                if self.registers[b.rs1 as usize] != self.registers[b.rs2 as usize] {
-                    self.pc = self.pc.wrapping_add(b.imm as u32);
-                }
+                return self.branch(self.registers[b.rs1 as usize] != self.registers[b.rs2 as usize], b.imm);
             }
             Instruction::Blt(b) => {
-                if (self.registers[b.rs1 as usize] as i32) < (self.registers[b.rs2 as usize] as i32) {
-                    self.pc = self.pc.wrapping_add(b.imm as u32);
-                }
+                return self.branch((self.registers[b.rs1 as usize] as i32) < (self.registers[b.rs2 as usize] as i32), b.imm);
             }
             Instruction::Bge(b) => {
-                if (self.registers[b.rs1 as usize] as i32) >= (self.registers[b.rs2 as usize] as i32) {
-                    self.pc = self.pc.wrapping_add(b.imm as u32);
-                }
+                return self.branch((self.registers[b.rs1 as usize] as i32) >= (self.registers[b.rs2 as usize] as i32), b.imm);
             }
             Instruction::Bltu(b) => {
-                if (self.registers[b.rs1 as usize] as u32) < (self.registers[b.rs2 as usize] as u32) {
-                    self.pc = self.pc.wrapping_add(b.imm as u32);
-                }
+                return self.branch(self.registers[b.rs1 as usize] < self.registers[b.rs2 as usize], b.imm);
             }
             Instruction::Bgeu(b) => {
-                if (self.registers[b.rs1 as usize] as u32) >= (self.registers[b.rs2 as usize] as u32) {
-                    self.pc = self.pc.wrapping_add(b.imm as u32);
-                }
+                return self.branch(self.registers[b.rs1 as usize] >= self.registers[b.rs2 as usize], b.imm);
             }
-    
+
             // R-type instructions
             Instruction::Add(r) => {
-                self.registers[r.rd as usize] = self.registers[r.rs1 as usize].wrapping_add(self.registers[r.rs2 as usize]);
+                self.set_register(r.rd, self.registers[r.rs1 as usize].wrapping_add(self.registers[r.rs2 as usize]));
             }
-
             Instruction::Sub(r) => {
-                self.registers[r.rd as usize] = self.registers[r.rs1 as usize].wrapping_sub(self.registers[r.rs2 as usize]);
+                self.set_register(r.rd, self.registers[r.rs1 as usize].wrapping_sub(self.registers[r.rs2 as usize]));
             }
             Instruction::Sll(r) => {
-                self.registers[r.rd as usize] = self.registers[r.rs1 as usize] << (self.registers[r.rs2 as usize] & 0x1F);
+                self.set_register(r.rd, self.registers[r.rs1 as usize] << (self.registers[r.rs2 as usize] & 0x1F));
             }
             Instruction::Slt(r) => {
-                self.registers[r.rd as usize] = if (self.registers[r.rs1 as usize] as i32) < (self.registers[r.rs2 as usize] as i32) { 1 } else { 0 };
+                self.set_register(r.rd, ((self.registers[r.rs1 as usize] as i32) < (self.registers[r.rs2 as usize] as i32)) as u32);
             }
             Instruction::Sltu(r) => {
-                self.registers[r.rd as usize] = if (self.registers[r.rs1 as usize] as u32) < (self.registers[r.rs2 as usize] as u32) { 1 } else { 0 };
+                self.set_register(r.rd, (self.registers[r.rs1 as usize] < self.registers[r.rs2 as usize]) as u32);
             }
             Instruction::Xor(r) => {
-                self.registers[r.rd as usize] = self.registers[r.rs1 as usize] ^ self.registers[r.rs2 as usize];
+                self.set_register(r.rd, self.registers[r.rs1 as usize] ^ self.registers[r.rs2 as usize]);
             }
             Instruction::Srl(r) => {
-                self.registers[r.rd as usize] = (self.registers[r.rs1 as usize] as u32 >> (self.registers[r.rs2 as usize] & 0x1F)) as u32;
+                self.set_register(r.rd, self.registers[r.rs1 as usize] >> (self.registers[r.rs2 as usize] & 0x1F));
             }
             Instruction::Sra(r) => {
-                self.registers[r.rd as usize] = self.registers[r.rs1 as usize] >> (self.registers[r.rs2 as usize] & 0x1F);
+                self.set_register(r.rd, ((self.registers[r.rs1 as usize] as i32) >> (self.registers[r.rs2 as usize] & 0x1F)) as u32);
             }
             Instruction::Or(r) => {
-                self.registers[r.rd as usize] = self.registers[r.rs1 as usize] | self.registers[r.rs2 as usize];
+                self.set_register(r.rd, self.registers[r.rs1 as usize] | self.registers[r.rs2 as usize]);
             }
             Instruction::And(r) => {
-                self.registers[r.rd as usize] = self.registers[r.rs1 as usize] & self.registers[r.rs2 as usize];
+                self.set_register(r.rd, self.registers[r.rs1 as usize] & self.registers[r.rs2 as usize]);
             }
-    
-            // Default case for unhandled instructions
-            _ => {
-                eprintln!("Unknown instruction: {:?}", instruction);
+
+            // M extension
+            Instruction::Mul(_) | Instruction::Mulh(_) | Instruction::Mulhsu(_) | Instruction::Mulhu(_)
+            | Instruction::Div(_) | Instruction::Divu(_) | Instruction::Rem(_) | Instruction::Remu(_) => {
+                self.execute_muldiv(instruction);
+            }
+
+            // A extension
+            Instruction::LrW(a) => {
+                let addr = self.registers[a.rs1 as usize];
+                let value = self.load(memory, addr, 4)?;
+                self.reservation = Some(addr);
+                self.set_register(a.rd, value);
+            }
+            Instruction::ScW(a) => {
+                let addr = self.registers[a.rs1 as usize];
+                let success = self.reservation == Some(addr);
+                if success {
+                    self.store(memory, addr, 4, self.registers[a.rs2 as usize])?;
+                } else if !addr.is_multiple_of(4) {
+                    return Err(Exception::StoreAddressMisaligned(addr));
+                }
+                self.reservation = None;
+                self.set_register(a.rd, !success as u32);
+            }
+            Instruction::AmoswapW(a) | Instruction::AmoaddW(a) | Instruction::AmoxorW(a) | Instruction::AmoandW(a)
+            | Instruction::AmoorW(a) | Instruction::AmominW(a) | Instruction::AmomaxW(a) | Instruction::AmominuW(a)
+            | Instruction::AmomaxuW(a) => {
+                let addr = self.registers[a.rs1 as usize];
+                // AMOs report store/AMO faults, even for the load half
+                if !addr.is_multiple_of(4) {
+                    return Err(Exception::StoreAddressMisaligned(addr));
+                }
+                let old = self.load(memory, addr, 4).map_err(|_| Exception::StoreAccessFault(addr))?;
+                let operand = self.registers[a.rs2 as usize];
+                let new = match instruction {
+                    Instruction::AmoswapW(_) => operand,
+                    Instruction::AmoaddW(_) => old.wrapping_add(operand),
+                    Instruction::AmoxorW(_) => old ^ operand,
+                    Instruction::AmoandW(_) => old & operand,
+                    Instruction::AmoorW(_) => old | operand,
+                    Instruction::AmominW(_) => (old as i32).min(operand as i32) as u32,
+                    Instruction::AmomaxW(_) => (old as i32).max(operand as i32) as u32,
+                    Instruction::AmominuW(_) => old.min(operand),
+                    _ => old.max(operand),
+                };
+                self.store(memory, addr, 4, new)?;
+                self.set_register(a.rd, old);
+            }
+
+            // Zicsr
+            Instruction::Csrrw(c) | Instruction::Csrrs(c) | Instruction::Csrrc(c) | Instruction::Csrrwi(c)
+            | Instruction::Csrrsi(c) | Instruction::Csrrci(c) => {
+                self.execute_csr(instruction, c, raw)?;
+            }
+
+            // Memory ordering is trivially satisfied by a single in-order hart
+            Instruction::Fence(_) => {}
+            Instruction::FenceI(_) => self.flush_decode_cache(),
+
+            // Privileged instructions
+            Instruction::Ecall => {
+                return Err(match self.privilege {
+                    Privilege::User => Exception::EnvironmentCallFromU,
+                    Privilege::Supervisor => Exception::EnvironmentCallFromS,
+                    Privilege::Machine => Exception::EnvironmentCallFromM,
+                });
+            }
+            Instruction::Ebreak => {
+                return Err(Exception::Breakpoint(self.pc));
+            }
+            Instruction::Mret => {
+                if self.privilege != Privilege::Machine {
+                    return Err(Exception::IllegalInstruction(raw));
+                }
+                let mstatus = self.csrs.mstatus;
+                self.privilege = Privilege::from_bits(mstatus >> 11).unwrap_or(Privilege::User);
+                let mie = if mstatus & MSTATUS_MPIE != 0 { MSTATUS_MIE } else { 0 };
+                self.csrs.mstatus = (mstatus & !(MSTATUS_MIE | MSTATUS_MPP)) | mie | MSTATUS_MPIE;
+                if self.privilege != Privilege::Machine {
+                    self.csrs.mstatus &= !MSTATUS_MPRV;
+                }
+                return Ok(self.csrs.mepc);
+            }
+            Instruction::Sret => {
+                let trapped = self.privilege == Privilege::Supervisor && self.csrs.mstatus & MSTATUS_TSR != 0;
+                if self.privilege < Privilege::Supervisor || trapped {
+                    return Err(Exception::IllegalInstruction(raw));
+                }
+                let mstatus = self.csrs.mstatus;
+                self.privilege = if mstatus & MSTATUS_SPP != 0 { Privilege::Supervisor } else { Privilege::User };
+                let sie = if mstatus & MSTATUS_SPIE != 0 { MSTATUS_SIE } else { 0 };
+                self.csrs.mstatus = (mstatus & !(MSTATUS_SIE | MSTATUS_SPP | MSTATUS_MPRV)) | sie | MSTATUS_SPIE;
+                return Ok(self.csrs.sepc);
+            }
+            Instruction::Wfi => {
+                let trapped = self.privilege < Privilege::Machine && self.csrs.mstatus & MSTATUS_TW != 0;
+                if self.privilege == Privilege::User || trapped {
+                    return Err(Exception::IllegalInstruction(raw));
+                }
+            }
+            Instruction::SfenceVma(_) => {
+                let trapped = self.privilege == Privilege::Supervisor && self.csrs.mstatus & MSTATUS_TVM != 0;
+                if self.privilege == Privilege::User || trapped {
+                    return Err(Exception::IllegalInstruction(raw));
+                }
             }
         }
+        Ok(next_pc)
     }
-    
 
-    pub fn run(&mut self, memory: &mut Memory) {
-        loop {
-            let instruction = self.fetch(memory);
-            let decoded = self.decode(instruction);
-            self.execute(decoded, memory);
-            self.pc = self.pc.wrapping_add(4);
+    fn execute_muldiv(&mut self, instruction: Instruction) {
+        let (Instruction::Mul(r) | Instruction::Mulh(r) | Instruction::Mulhsu(r) | Instruction::Mulhu(r)
+        | Instruction::Div(r) | Instruction::Divu(r) | Instruction::Rem(r) | Instruction::Remu(r)) = instruction else {
+            return;
+        };
+        let a = self.registers[r.rs1 as usize];
+        let b = self.registers[r.rs2 as usize];
+        let Some(op) = AluOp::of(&instruction) else { return };
+        let value = op.apply(a, b);
+        self.set_register(r.rd, value);
+    }
 
-            // Break if necessary, e.g., upon a specific condition or instruction
-            if self.pc as usize >= memory.size() {  // Example condition
-                break;
+    fn execute_csr(&mut self, instruction: Instruction, c: CsrInstruction, raw: u32) -> Result<(), Exception> {
+        let illegal = Exception::IllegalInstruction(raw);
+        let register = self.registers[c.rs1 as usize];
+        let zimm = c.rs1 as u32;
+        // CSRRS/CSRRC with a zero source do not write, so read-only CSRs can be read with them
+        let (new, write): (fn(u32, u32) -> u32, bool) = match instruction {
+            Instruction::Csrrw(_) => (|_, source| source, true),
+            Instruction::Csrrs(_) => (|old, source| old | source, c.rs1 != 0),
+            Instruction::Csrrc(_) => (|old, source| old & !source, c.rs1 != 0),
+            Instruction::Csrrwi(_) => (|_, source| source, true),
+            Instruction::Csrrsi(_) => (|old, source| old | source, zimm != 0),
+            _ => (|old, source| old & !source, zimm != 0),
+        };
+        let source = match instruction {
+            Instruction::Csrrw(_) | Instruction::Csrrs(_) | Instruction::Csrrc(_) => register,
+            _ => zimm,
+        };
+        if !csr::accessible(c.csr, self.privilege, write) || !self.csrs.counter_enabled(c.csr, self.privilege) {
+            return Err(illegal);
+        }
+        if c.csr == SATP && self.privilege == Privilege::Supervisor && self.csrs.mstatus & MSTATUS_TVM != 0 {
+            return Err(illegal);
+        }
+        let old = self.csrs.read(c.csr).ok_or(illegal)?;
+        if c.rd != 0 || !matches!(instruction, Instruction::Csrrw(_) | Instruction::Csrrwi(_)) {
+            self.hooks.csr_read(c.csr, old);
+        }
+        if write {
+            self.csrs.write(c.csr, new(old, source));
+            self.instret_written |= matches!(c.csr, MINSTRET | MINSTRETH);
+            self.cycle_written |= matches!(c.csr, MCYCLE | MCYCLEH);
+            let value = self.csrs.read(c.csr).unwrap_or(0);
+            self.hooks.csr_write(c.csr, value);
+            if let Some(commit) = &mut self.commit {
+                commit.csr_writes.push((c.csr, value));
             }
-        }   
+        }
+        self.set_register(c.rd, old);
+        Ok(())
+    }
+
+    /// Highest-priority interrupt that is pending, enabled and not masked at the
+    /// current privilege level.
+    fn pending_interrupt(&self) -> Option<Interrupt> {
+        let pending = self.csrs.mip & self.csrs.mie;
+        if pending == 0 {
+            return None;
+        }
+        let machine_enabled = self.privilege < Privilege::Machine || self.csrs.mstatus & MSTATUS_MIE != 0;
+        let supervisor_enabled = self.privilege < Privilege::Supervisor
+            || (self.privilege == Privilege::Supervisor && self.csrs.mstatus & MSTATUS_SIE != 0);
+        Interrupt::PRIORITY.into_iter().find(|interrupt| {
+            let delegated = self.csrs.mideleg & interrupt.mask() != 0;
+            let enabled = if delegated { supervisor_enabled } else { machine_enabled };
+            pending & interrupt.mask() != 0 && enabled
+        })
     }
 
+    /// Enter the trap handler for `trap` raised at `pc`, delegating to S-mode where
+    /// medeleg/mideleg allow. Returns a stop reason instead if no handler is installed.
+    fn take_trap(&mut self, trap: Trap, pc: u32) -> Option<StopReason> {
+        self.hooks.trap(trap, pc);
+        let delegated = self.privilege <= Privilege::Supervisor
+            && match trap {
+                Trap::Exception(exception) => self.csrs.medeleg & (1 << exception.code()) != 0,
+                Trap::Interrupt(interrupt) => self.csrs.mideleg & interrupt.mask() != 0,
+            };
+        let tvec = if delegated { self.csrs.stvec } else { self.csrs.mtvec };
+        if tvec & !0b11 == 0 {
+            return Some(StopReason::Trap { trap, pc });
+        }
+
+        let mstatus = self.csrs.mstatus;
+        if delegated {
+            self.csrs.sepc = pc;
+            self.csrs.scause = trap.cause();
+            self.csrs.stval = trap.tval();
+            let spie = if mstatus & MSTATUS_SIE != 0 { MSTATUS_SPIE } else { 0 };
+            let spp = if self.privilege == Privilege::Supervisor { MSTATUS_SPP } else { 0 };
+            self.csrs.mstatus = (mstatus & !(MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP)) | spie | spp;
+            self.privilege = Privilege::Supervisor;
+        } else {
+            self.csrs.mepc = pc;
+            self.csrs.mcause = trap.cause();
+            self.csrs.mtval = trap.tval();
+            let mpie = if mstatus & MSTATUS_MIE != 0 { MSTATUS_MPIE } else { 0 };
+            let mpp = (self.privilege as u32) << 11;
+            self.csrs.mstatus = (mstatus & !(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP)) | mpie | mpp;
+            self.privilege = Privilege::Machine;
+        }
+
+        self.trap_entered = true;
+        // Vectored mode sends interrupts to base + 4 * cause
+        let base = tvec & !0b11;
+        self.pc = match trap {
+            Trap::Interrupt(interrupt) if tvec & 0b11 == 1 => base.wrapping_add(4 * interrupt.code()),
+            _ => base,
+        };
+        None
+    }
+
+    /// Handle an exception raised by the instruction at `pc`. EBREAK and the exit
+    /// system call are serviced by the host when no trap handler is installed.
+    fn raise(&mut self, exception: Exception, pc: u32) -> Option<StopReason> {
+        let stop = self.take_trap(Trap::Exception(exception), pc)?;
+        match exception {
+            Exception::Breakpoint(_) => {
+                self.retire(pc.wrapping_add(4));
+                self.hooks.post_execute(pc, &Instruction::Ebreak, self.pc);
+                Some(StopReason::Breakpoint { pc })
+            }
+            Exception::EnvironmentCallFromU | Exception::EnvironmentCallFromS | Exception::EnvironmentCallFromM
+                if self.registers[REG_A7] == SYS_EXIT =>
+            {
+                self.retire(pc.wrapping_add(4));
+                self.hooks.post_execute(pc, &Instruction::Ecall, self.pc);
+                Some(StopReason::Halted { exit_code: self.registers[REG_A0] })
+            }
+            _ => Some(stop),
+        }
+    }
+
+    fn retire(&mut self, next_pc: u32) {
+        self.pc = next_pc;
+        self.last_commit = self.commit.take().map(|commit| Commit { next_pc, ..commit });
+        if !std::mem::take(&mut self.instret_written) {
+            self.csrs.instret = self.csrs.instret.wrapping_add(1);
+        }
+        if !std::mem::take(&mut self.cycle_written) {
+            self.csrs.cycle = self.csrs.cycle.wrapping_add(1);
+        }
+    }
+
+    /// Execute one instruction, taking any pending interrupt first.
+    fn step_instruction(&mut self, memory: &mut Memory) -> Option<StopReason> {
+        if self.undo_log {
+            self.undo = Some(Undo {
+                pc: self.pc,
+                privilege: self.privilege,
+                reservation: self.reservation,
+                csrs: self.csrs.clone(),
+                trap_entered: self.trap_entered,
+                registers: Vec::new(),
+                stores: Vec::new(),
+            });
+        }
+        let stop = self.execute_step(memory);
+        // A trap with no handler leaves the state untouched
+        self.last_undo = self.undo.take().filter(|_| !matches!(stop, Some(StopReason::Trap { .. })));
+        stop
+    }
+
+    fn execute_step(&mut self, memory: &mut Memory) -> Option<StopReason> {
+        self.sync_data_endianness(memory);
+        if let Some(interrupt) = self.pending_interrupt() {
+            if let Some(stop) = self.take_trap(Trap::Interrupt(interrupt), self.pc) {
+                return Some(stop);
+            }
+            self.sync_data_endianness(memory);
+        }
+
+        let pc = self.pc;
+        self.last_commit = None;
+        if self.commit_log {
+            let mut commit = Commit::new(self.csrs.mhartid, self.privilege, pc);
+            commit.interrupt = std::mem::take(&mut self.trap_entered);
+            self.commit = Some(commit);
+        }
+        let result = self.fetch_decoded(memory).and_then(|(raw, instruction)| {
+            self.hooks.fetch(pc, raw);
+            if let Some(commit) = &mut self.commit {
+                commit.raw = raw;
+            }
+            let instruction = instruction?;
+            if let Some(commit) = &mut self.commit {
+                let [rs1, rs2] = instruction.sources().map(|source| source.map_or((0, 0), |r| (r, self.registers[r as usize])));
+                (commit.rs1, commit.rs2) = (rs1, rs2);
+            }
+            self.hooks.pre_execute(pc, &instruction);
+            let next_pc = self.execute(instruction, raw, memory)?;
+            Ok((instruction, next_pc))
+        });
+        match result {
+            Ok((instruction, next_pc)) => {
+                self.retire(next_pc);
+                self.hooks.post_execute(pc, &instruction, next_pc);
+                if let Some(stop) = self.watch_hit.take() {
+                    return Some(stop);
+                }
+                if instruction == Instruction::Wfi && self.csrs.mip & self.csrs.mie == 0 {
+                    return Some(StopReason::WaitForInterrupt { pc });
+                }
+                None
+            }
+            Err(exception) => {
+                self.watch_hit = None;
+                let stop = self.raise(exception, pc);
+                if let Some(commit) = self.commit.take() {
+                    // The exception was not serviced by the host, so the instruction did not retire
+                    self.last_commit = Some(Commit {
+                        next_pc: self.pc,
+                        trap: true,
+                        halt: stop.is_some(),
+                        register_writes: Vec::new(),
+                        csr_writes: Vec::new(),
+                        loads: Vec::new(),
+                        stores: Vec::new(),
+                        ..commit
+                    });
+                } else if let (Some(StopReason::Halted { .. }), Some(commit)) = (stop, &mut self.last_commit) {
+                    commit.halt = true;
+                }
+                stop
+            }
+        }
+    }
+
+    /// Whether translated blocks may run: they skip the per-instruction breakpoint,
+    /// watchpoint, commit and undo bookkeeping.
+    fn block_translation_active(&self) -> bool {
+        self.blocks.is_some()
+            && self.breakpoints.is_empty()
+            && self.watchpoints.is_empty()
+            && !self.commit_log
+            && !self.undo_log
+            && self.store_buffer.is_none()
+    }
+
+    /// Run translated blocks for up to `budget` instructions, returning the number
+    /// executed and why execution stopped, if it did. Returns early, with the pc at the
+    /// next instruction, when that instruction has to be interpreted or after a trap.
+    ///
+    /// Blocks hold no CSR, atomic or privileged instructions, so the privilege mode,
+    /// data endianness and pending interrupts stay fixed while they run and only need
+    /// checking on entry. The pc and the counters are written back when a block exits;
+    /// an exception inside a block first retires the instructions before it.
+    fn run_blocks(&mut self, memory: &mut Memory, budget: u64) -> (u64, Option<StopReason>) {
+        if self.pending_interrupt().is_some() {
+            return (0, None);
+        }
+        self.sync_data_endianness(memory);
+        self.last_commit = None;
+        self.last_undo = None;
+        let mut executed = 0;
+        let mut from = None;
+        let mut interpret = false;
+        while executed < budget {
+            let Some(blocks) = &mut self.blocks else { break };
+            let generation = blocks.generation();
+            let Some(index) = blocks.lookup(memory, self.pc, from) else { break };
+            let block = blocks.block(index);
+            let limit = (budget - executed).min(block.ops.len() as u64) as usize;
+            let compile = !interpret
+                && !H::OBSERVES
+                && limit == block.ops.len()
+                && memory.data_endianness() == Endianness::Little;
+            if let Some(native) = compile.then(|| blocks.native(index, jit_store::<H>)).flatten() {
+                // Compiled code continues into the compiled blocks it is linked to and
+                // leaves the instruction it cannot execute to the interpreter
+                let (retired, pc, exit) = self.run_native(&native, memory, budget - executed);
+                self.pc = pc;
+                self.csrs.instret = self.csrs.instret.wrapping_add(retired);
+                self.csrs.cycle = self.csrs.cycle.wrapping_add(retired);
+                executed += retired;
+                interpret = exit == jit::Exit::Interpret;
+                from = None;
+                continue;
+            }
+            interpret = false;
+
+            let mut pc = block.start;
+            let mut retired = 0;
+            let mut exit = None;
+            for (op, &(raw, instruction)) in block.ops.iter().zip(&block.instructions).take(limit) {
+                self.hooks.fetch(pc, raw);
+                self.hooks.pre_execute(pc, &instruction);
+                match self.execute_op(*op, pc, memory) {
+                    Ok(next_pc) => {
+                        retired += 1;
+                        self.hooks.post_execute(pc, &instruction, next_pc);
+                        pc = next_pc;
+                    }
+                    Err(exception) => {
+                        exit = Some(exception);
+                        break;
+                    }
+                }
+                // A store to translated code dropped this block
+                if matches!(op, Op::Store { .. })
+                    && self.blocks.as_ref().is_some_and(|blocks| blocks.generation() != generation)
+                {
+                    break;
+                }
+            }
+            self.pc = pc;
+            self.csrs.instret = self.csrs.instret.wrapping_add(retired);
+            self.csrs.cycle = self.csrs.cycle.wrapping_add(retired);
+            executed += retired;
+            if let Some(exception) = exit {
+                return (executed + 1, self.raise(exception, pc));
+            }
+            if self.blocks.as_ref().is_some_and(|blocks| blocks.generation() != generation) {
+                from = None;
+            } else {
+                from = Some(index);
+            }
+        }
+        (executed, None)
+    }
+
+    /// Run compiled code for up to `budget` instructions, returning the instructions
+    /// it retired, the next pc and why it returned.
+    fn run_native(&mut self, native: &NativeBlock, memory: &mut Memory, budget: u64) -> (u64, u32, jit::Exit) {
+        let cpu: *mut Self = self;
+        let (contents, size) = (memory.as_mut_ptr(), memory.size());
+        let memory: *mut Memory = memory;
+        // Safety: the registers and memory stay valid for the call, and are only
+        // accessed by the compiled code and through `jit_store`, one at a time. Stores
+        // do not resize the memory, so `contents` stays valid across them.
+        unsafe {
+            let registers = std::ptr::addr_of_mut!((*cpu).registers) as *mut u32;
+            native.call(registers, contents, size, cpu as *mut c_void, memory as *mut c_void, budget)
+        }
+    }
+
+    /// `set_register` without the undo and commit logs, which are off while blocks run.
+    #[inline]
+    fn write_register(&mut self, register: u8, value: u32) {
+        if register != 0 {
+            self.registers[register as usize] = value;
+            self.hooks.register_write(register, value);
+        }
+    }
+
+    /// Execute a translated instruction at `pc` and return the address of the next one.
+    #[inline]
+    fn execute_op(&mut self, op: Op, pc: u32, memory: &mut Memory) -> Result<u32, Exception> {
+        let next_pc = pc.wrapping_add(4);
+        match op {
+            Op::Li { rd, value } => self.write_register(rd, value),
+            Op::Alu { op, rd, rs1, rs2 } => {
+                self.write_register(rd, op.apply(self.registers[rs1 as usize], self.registers[rs2 as usize]));
+            }
+            Op::AluImm { op, rd, rs1, imm } => self.write_register(rd, op.apply(self.registers[rs1 as usize], imm)),
+            Op::Load { rd, rs1, offset, size, signed } => {
+                let value = self.load(memory, self.registers[rs1 as usize].wrapping_add(offset), size)?;
+                let value = match (signed, size) {
+                    (true, 1) => value as u8 as i8 as i32 as u32,
+                    (true, 2) => value as u16 as i16 as i32 as u32,
+                    _ => value,
+                };
+                self.write_register(rd, value);
+            }
+            Op::Store { rs1, rs2, offset, size } => {
+                let address = self.registers[rs1 as usize].wrapping_add(offset);
+                self.store(memory, address, size, self.registers[rs2 as usize])?;
+            }
+            Op::Branch { condition, rs1, rs2, target } => {
+                let taken = condition.holds(self.registers[rs1 as usize], self.registers[rs2 as usize]);
+                let next_pc = if taken { self.jump_target(target)? } else { next_pc };
+                self.hooks.branch(pc, taken);
+                return Ok(next_pc);
+            }
+            Op::Jal { rd, target } => {
+                let target = self.jump_target(target)?;
+                self.write_register(rd, next_pc);
+                return Ok(target);
+            }
+            Op::Jalr { rd, rs1, offset } => {
+                let target = self.jump_target(self.registers[rs1 as usize].wrapping_add(offset) & !1)?;
+                self.write_register(rd, next_pc);
+                return Ok(target);
+            }
+        }
+        Ok(next_pc)
+    }
+
+    /// Execute exactly one instruction; equivalent to `run_for(memory, 1)`.
+    /// A breakpoint at the current pc does not stop a single step.
+    pub fn step(&mut self, memory: &mut Memory) -> StopReason {
+        self.run_for(memory, 1)
+    }
+
+    /// Execute up to `max_instructions` instructions. Breakpoints are checked before
+    /// each instruction except the first, so a run can resume from a breakpoint.
+    pub fn run_for(&mut self, memory: &mut Memory, max_instructions: u64) -> StopReason {
+        let mut executed = 0;
+        while executed < max_instructions {
+            if executed > 0 && self.breakpoints.contains(&self.pc) {
+                return StopReason::Breakpoint { pc: self.pc };
+            }
+            if self.block_translation_active() {
+                let (count, stop) = self.run_blocks(memory, max_instructions - executed);
+                if let Some(stop) = stop {
+                    return stop;
+                }
+                executed += count;
+                if count > 0 {
+                    continue;
+                }
+            }
+            if let Some(stop) = self.step_instruction(memory) {
+                return stop;
+            }
+            executed += 1;
+        }
+        StopReason::InstructionLimit
+    }
+
+    /// Run until something other than the instruction limit stops execution.
+    pub fn run(&mut self, memory: &mut Memory) -> StopReason {
+        loop {
+            match self.run_for(memory, u64::MAX) {
+                StopReason::InstructionLimit => continue,
+                stop => return stop,
+            }
+        }
+    }
+
+}
+
+/// Store helper for code compiled for a `Cpu<H>`; see [`jit::StoreHelper`].
+extern "C" fn jit_store<H: Hooks>(cpu: *mut c_void, memory: *mut c_void, address: u32, value: u32, size: u32) -> u32 {
+    // Safety: compiled code passes back the pointers `run_native` gave it
+    let (cpu, memory) = unsafe { (&mut *(cpu as *mut Cpu<H>), &mut *(memory as *mut Memory)) };
+    let generation = cpu.blocks.as_ref().map(BlockCache::generation);
+    match cpu.store(memory, address, size, value) {
+        Err(_) => jit::STORE_FAULT,
+        Ok(()) if cpu.blocks.as_ref().map(BlockCache::generation) != generation => jit::STORE_CODE_WRITTEN,
+        Ok(()) => jit::STORE_OK,
+    }
+}
+
+/// Zero-extended value of `size` bytes (1, 2 or 4) in the current data byte order.
+fn read_sized(memory: &Memory, address: u32, size: u32) -> u32 {
+    let addr = address as usize;
+    match size {
+        1 => memory.load_byte(addr),
+        2 => memory.load_halfword(addr),
+        _ => memory.load_word(addr),
+    }
 }
@@ -0,0 +1,274 @@
+use crate::trap::Interrupt;
+
+// CSR address Definitions
+pub const SSTATUS: u16 = 0x100;
+pub const SIE: u16 = 0x104;
+pub const STVEC: u16 = 0x105;
+pub const SCOUNTEREN: u16 = 0x106;
+pub const SSCRATCH: u16 = 0x140;
+pub const SEPC: u16 = 0x141;
+pub const SCAUSE: u16 = 0x142;
+pub const STVAL: u16 = 0x143;
+pub const SIP: u16 = 0x144;
+pub const SATP: u16 = 0x180;
+pub const MSTATUS: u16 = 0x300;
+pub const MISA: u16 = 0x301;
+pub const MEDELEG: u16 = 0x302;
+pub const MIDELEG: u16 = 0x303;
+pub const MIE: u16 = 0x304;
+pub const MTVEC: u16 = 0x305;
+pub const MCOUNTEREN: u16 = 0x306;
+pub const MSTATUSH: u16 = 0x310;
+pub const MSCRATCH: u16 = 0x340;
+pub const MEPC: u16 = 0x341;
+pub const MCAUSE: u16 = 0x342;
+pub const MTVAL: u16 = 0x343;
+pub const MIP: u16 = 0x344;
+pub const MCYCLE: u16 = 0xb00;
+pub const MINSTRET: u16 = 0xb02;
+pub const MCYCLEH: u16 = 0xb80;
+pub const MINSTRETH: u16 = 0xb82;
+pub const CYCLE: u16 = 0xc00;
+pub const TIME: u16 = 0xc01;
+pub const INSTRET: u16 = 0xc02;
+pub const CYCLEH: u16 = 0xc80;
+pub const TIMEH: u16 = 0xc81;
+pub const INSTRETH: u16 = 0xc82;
+pub const MVENDORID: u16 = 0xf11;
+pub const MARCHID: u16 = 0xf12;
+pub const MIMPID: u16 = 0xf13;
+pub const MHARTID: u16 = 0xf14;
+
+// mstatus / mstatush field Definitions
+pub const MSTATUS_SIE: u32 = 1 << 1;
+pub const MSTATUS_MIE: u32 = 1 << 3;
+pub const MSTATUS_SPIE: u32 = 1 << 5;
+pub const MSTATUS_UBE: u32 = 1 << 6;
+pub const MSTATUS_MPIE: u32 = 1 << 7;
+pub const MSTATUS_SPP: u32 = 1 << 8;
+pub const MSTATUS_MPP: u32 = 0b11 << 11;
+pub const MSTATUS_MPRV: u32 = 1 << 17;
+pub const MSTATUS_SUM: u32 = 1 << 18;
+pub const MSTATUS_MXR: u32 = 1 << 19;
+pub const MSTATUS_TVM: u32 = 1 << 20;
+pub const MSTATUS_TW: u32 = 1 << 21;
+pub const MSTATUS_TSR: u32 = 1 << 22;
+pub const MSTATUSH_SBE: u32 = 1 << 4;
+pub const MSTATUSH_MBE: u32 = 1 << 5;
+
+const MSTATUS_WRITABLE: u32 = MSTATUS_SIE | MSTATUS_MIE | MSTATUS_SPIE | MSTATUS_UBE | MSTATUS_MPIE
+    | MSTATUS_SPP | MSTATUS_MPP | MSTATUS_MPRV | MSTATUS_SUM | MSTATUS_MXR | MSTATUS_TVM | MSTATUS_TW | MSTATUS_TSR;
+const SSTATUS_VISIBLE: u32 = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_UBE | MSTATUS_SPP | MSTATUS_SUM | MSTATUS_MXR;
+const MSTATUSH_WRITABLE: u32 = MSTATUSH_SBE | MSTATUSH_MBE;
+
+// Exceptions that may be delegated (codes 0-9) and supervisor interrupt bits
+const MEDELEG_WRITABLE: u32 = 0x3ff;
+const SUPERVISOR_INTERRUPTS: u32 = 0x222;
+const MIE_WRITABLE: u32 = 0xaaa;
+
+// misa: MXL = 32, extensions A, I, M, S, U
+const MISA_VALUE: u32 = 1 << 30 | 1 << 0 | 1 << 8 | 1 << 12 | 1 << 18 | 1 << 20;
+
+/// Privilege modes, ordered from least to most privileged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Privilege {
+    User = 0,
+    Supervisor = 1,
+    Machine = 3,
+}
+
+impl Privilege {
+    /// Decode a two-bit xPP field; the reserved value 2 is not a mode.
+    pub fn from_bits(bits: u32) -> Option<Privilege> {
+        match bits & 0b11 {
+            0 => Some(Privilege::User),
+            1 => Some(Privilege::Supervisor),
+            3 => Some(Privilege::Machine),
+            _ => None,
+        }
+    }
+
+    /// Single-letter name as used in traces (`U`, `S`, `M`).
+    pub fn letter(&self) -> char {
+        match self {
+            Privilege::User => 'U',
+            Privilege::Supervisor => 'S',
+            Privilege::Machine => 'M',
+        }
+    }
+}
+
+/// Whether `csr` can be accessed from `privilege`, following the CSR address
+/// convention: bits [9:8] give the lowest privilege, bits [11:10] == 11 mean read-only.
+pub fn accessible(csr: u16, privilege: Privilege, write: bool) -> bool {
+    let minimum = ((csr >> 8) & 0b11) as u8;
+    let read_only = (csr >> 10) & 0b11 == 0b11;
+    minimum <= privilege as u8 && !(write && read_only)
+}
+
+/// Machine and supervisor CSR state of one hart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsrFile {
+    pub mstatus: u32,
+    pub mstatush: u32,
+    pub mhartid: u32,
+    pub mtvec: u32,
+    pub medeleg: u32,
+    pub mideleg: u32,
+    pub mie: u32,
+    pub mip: u32,
+    pub mscratch: u32,
+    pub mepc: u32,
+    pub mcause: u32,
+    pub mtval: u32,
+    pub mcounteren: u32,
+    pub stvec: u32,
+    pub sscratch: u32,
+    pub sepc: u32,
+    pub scause: u32,
+    pub stval: u32,
+    pub satp: u32,
+    pub scounteren: u32,
+    pub cycle: u64,
+    pub instret: u64,
+}
+
+impl CsrFile {
+    pub fn new(hartid: u32) -> Self {
+        CsrFile {
+            mstatus: 0,
+            mstatush: 0,
+            mhartid: hartid,
+            mtvec: 0,
+            medeleg: 0,
+            mideleg: 0,
+            mie: 0,
+            mip: 0,
+            mscratch: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+            mcounteren: 0,
+            stvec: 0,
+            sscratch: 0,
+            sepc: 0,
+            scause: 0,
+            stval: 0,
+            satp: 0,
+            scounteren: 0,
+            cycle: 0,
+            instret: 0,
+        }
+    }
+
+    /// Read a CSR, or `None` if it does not exist. There is no timer device, so
+    /// `time` reads the cycle count.
+    pub fn read(&self, csr: u16) -> Option<u32> {
+        let value = match csr {
+            SSTATUS => self.mstatus & SSTATUS_VISIBLE,
+            SIE => self.mie & self.mideleg,
+            STVEC => self.stvec,
+            SCOUNTEREN => self.scounteren,
+            SSCRATCH => self.sscratch,
+            SEPC => self.sepc,
+            SCAUSE => self.scause,
+            STVAL => self.stval,
+            SIP => self.mip & self.mideleg,
+            SATP => self.satp,
+            MSTATUS => self.mstatus,
+            MSTATUSH => self.mstatush,
+            MISA => MISA_VALUE,
+            MEDELEG => self.medeleg,
+            MIDELEG => self.mideleg,
+            MIE => self.mie,
+            MTVEC => self.mtvec,
+            MCOUNTEREN => self.mcounteren,
+            MSCRATCH => self.mscratch,
+            MEPC => self.mepc,
+            MCAUSE => self.mcause,
+            MTVAL => self.mtval,
+            MIP => self.mip,
+            MCYCLE | CYCLE | TIME => self.cycle as u32,
+            MCYCLEH | CYCLEH | TIMEH => (self.cycle >> 32) as u32,
+            MINSTRET | INSTRET => self.instret as u32,
+            MINSTRETH | INSTRETH => (self.instret >> 32) as u32,
+            MVENDORID | MARCHID | MIMPID => 0,
+            MHARTID => self.mhartid,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Whether the user-level counter `csr` may be read from `privilege`: below
+    /// M-mode it needs its mcounteren bit, and in U-mode its scounteren bit as well.
+    /// Every other CSR is unaffected.
+    pub fn counter_enabled(&self, csr: u16, privilege: Privilege) -> bool {
+        if !matches!(csr, CYCLE..=0xc1f | CYCLEH..=0xc9f) {
+            return true;
+        }
+        let bit = 1 << (csr & 0x1f);
+        match privilege {
+            Privilege::Machine => true,
+            Privilege::Supervisor => self.mcounteren & bit != 0,
+            Privilege::User => self.mcounteren & self.scounteren & bit != 0,
+        }
+    }
+
+    /// Write a CSR, applying its WARL rules. Returns `false` if the CSR does not exist.
+    pub fn write(&mut self, csr: u16, value: u32) -> bool {
+        match csr {
+            SSTATUS => self.mstatus = (self.mstatus & !SSTATUS_VISIBLE) | (value & SSTATUS_VISIBLE),
+            SIE => self.mie = (self.mie & !self.mideleg) | (value & self.mideleg),
+            STVEC => self.stvec = legal_tvec(self.stvec, value),
+            SCOUNTEREN => self.scounteren = value,
+            SSCRATCH => self.sscratch = value,
+            SEPC => self.sepc = value & !0b11,
+            SCAUSE => self.scause = value,
+            STVAL => self.stval = value,
+            SIP => {
+                let writable = self.mideleg & Interrupt::SupervisorSoftware.mask();
+                self.mip = (self.mip & !writable) | (value & writable);
+            }
+            // Only the Bare translation mode is implemented; other modes are ignored
+            SATP => {
+                if value >> 31 == 0 {
+                    self.satp = value;
+                }
+            }
+            MSTATUS => {
+                let mut mstatus = (self.mstatus & !MSTATUS_WRITABLE) | (value & MSTATUS_WRITABLE);
+                if Privilege::from_bits(mstatus >> 11).is_none() {
+                    mstatus = (mstatus & !MSTATUS_MPP) | (self.mstatus & MSTATUS_MPP);
+                }
+                self.mstatus = mstatus;
+            }
+            MSTATUSH => self.mstatush = value & MSTATUSH_WRITABLE,
+            MISA => {}
+            MEDELEG => self.medeleg = value & MEDELEG_WRITABLE,
+            MIDELEG => self.mideleg = value & SUPERVISOR_INTERRUPTS,
+            MIE => self.mie = value & MIE_WRITABLE,
+            MTVEC => self.mtvec = legal_tvec(self.mtvec, value),
+            MCOUNTEREN => self.mcounteren = value,
+            MSCRATCH => self.mscratch = value,
+            MEPC => self.mepc = value & !0b11,
+            MCAUSE => self.mcause = value,
+            MTVAL => self.mtval = value,
+            MIP => self.mip = (self.mip & !SUPERVISOR_INTERRUPTS) | (value & SUPERVISOR_INTERRUPTS),
+            MCYCLE => self.cycle = (self.cycle & !0xffff_ffff) | value as u64,
+            MCYCLEH => self.cycle = (self.cycle & 0xffff_ffff) | (value as u64) << 32,
+            MINSTRET => self.instret = (self.instret & !0xffff_ffff) | value as u64,
+            MINSTRETH => self.instret = (self.instret & 0xffff_ffff) | (value as u64) << 32,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// xtvec keeps its previous mode if the reserved modes 2 or 3 are written.
+fn legal_tvec(old: u32, value: u32) -> u32 {
+    if value & 0b11 >= 2 {
+        (value & !0b11) | (old & 0b11)
+    } else {
+        value
+    }
+}
@@ -0,0 +1,376 @@
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+
+use crate::cpu::{Cpu, StopReason, WatchKind, Watchpoint};
+use crate::disassembler::{csr_name, csr_number, disassemble, register_name, register_number};
+use crate::instruction::*;
+use crate::memory::Memory;
+use crate::reverse::History;
+use crate::snapshot::Snapshot;
+use crate::symbols::{parse_number, SymbolTable};
+
+const HELP: &str = "\
+step [n]            (s)   execute n instructions, entering calls
+next                (n)   execute one instruction, stepping over calls
+continue            (c)   run until a breakpoint, watchpoint or other stop
+reverse-step [n]    (rs)  undo n instructions
+reverse-continue    (rc)  run backwards to the previous breakpoint or watchpoint write
+break [loc]         (b)   set a breakpoint, or list breakpoints
+delete <loc>        (d)   remove a breakpoint
+watch [loc] [len] [if <value>]  (w)  stop on writes to len bytes at loc, or list watchpoints
+rwatch <loc> [len] [if <value>]      stop on reads
+awatch <loc> [len] [if <value>]      stop on reads and writes
+unwatch <loc>             remove the watchpoints at loc
+regs                (r)   show all registers
+print <reg>         (p)   show a register, pc or CSR
+set <reg> <value>         modify a register, pc or CSR
+x <loc> [words]           dump memory as words
+write <loc> <value> [b|h|w]  modify memory
+disas [loc] [count] (l)   disassemble around pc or loc
+save <file>               write a snapshot of registers, CSRs and memory
+restore <file>            return to a saved snapshot
+help                (h)   show this text
+quit                (q)   leave the debugger
+locations are numbers (decimal or 0x hex), symbols, or symbol+offset";
+
+/// Interactive debugger over a `Cpu` and its `Memory`. Execution is recorded so that
+/// it can be reversed; editing registers or memory discards the recording.
+pub struct Debugger {
+    pub cpu: Cpu,
+    pub memory: Memory,
+    history: History,
+    symbols: SymbolTable,
+    last_command: String,
+    quit: bool,
+}
+
+impl Debugger {
+    pub fn new(cpu: Cpu, memory: Memory, symbols: SymbolTable) -> Self {
+        Debugger { cpu, memory, history: History::new(), symbols, last_command: String::new(), quit: false }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.quit
+    }
+
+    /// Read commands from `input` until `quit` or end of input, writing output and a prompt to `output`.
+    pub fn repl(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        writeln!(output, "{}", self.current_instruction())?;
+        let mut lines = input.lines();
+        while !self.quit {
+            write!(output, "(rvdb) ")?;
+            output.flush()?;
+            let Some(line) = lines.next() else { break };
+            match self.execute(&line?) {
+                Ok(text) if text.is_empty() => {}
+                Ok(text) => writeln!(output, "{}", text)?,
+                Err(message) => writeln!(output, "error: {}", message)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Run one command line and return its output. An empty line repeats the previous command.
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let line = if line.trim().is_empty() { self.last_command.clone() } else { line.trim().to_string() };
+        self.last_command.clone_from(&line);
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else { return Ok(String::new()) };
+        let arguments: Vec<&str> = words.collect();
+        match command {
+            "s" | "step" => {
+                let count = match arguments.first() {
+                    Some(text) => parse_number(text).ok_or_else(|| format!("invalid count '{}'", text))?,
+                    None => 1,
+                };
+                Ok(self.resume(count as u64, None))
+            }
+            "n" | "next" => Ok(self.next()),
+            "c" | "continue" => Ok(self.resume(u64::MAX, None)),
+            "rs" | "reverse-step" => {
+                let count = match arguments.first() {
+                    Some(text) => parse_number(text).ok_or_else(|| format!("invalid count '{}'", text))?,
+                    None => 1,
+                };
+                let stop = self.history.reverse_for(&mut self.cpu, &mut self.memory, count as u64);
+                Ok(self.report(stop, None))
+            }
+            "rc" | "reverse-continue" => {
+                let stop = self.history.reverse_for(&mut self.cpu, &mut self.memory, u64::MAX);
+                Ok(self.report(stop, None))
+            }
+            "b" | "break" => match arguments.first() {
+                Some(location) => {
+                    let address = self.location(location)?;
+                    self.cpu.add_breakpoint(address);
+                    Ok(format!("breakpoint at {}", self.describe(address)))
+                }
+                None => Ok(self.cpu.breakpoints().map(|address| self.describe(address)).collect::<Vec<_>>().join("\n")),
+            },
+            "d" | "delete" => {
+                let address = self.location(arguments.first().ok_or("missing location")?)?;
+                if !self.cpu.remove_breakpoint(address) {
+                    return Err(format!("no breakpoint at {}", self.describe(address)));
+                }
+                Ok(String::new())
+            }
+            "w" | "watch" => self.watch(WatchKind::Write, &arguments),
+            "rwatch" => self.watch(WatchKind::Read, &arguments),
+            "awatch" => self.watch(WatchKind::Access, &arguments),
+            "unwatch" => {
+                let address = self.location(arguments.first().ok_or("missing location")?)?;
+                let matching: Vec<Watchpoint> =
+                    self.cpu.watchpoints().iter().filter(|w| w.address == address).copied().collect();
+                if matching.is_empty() {
+                    return Err(format!("no watchpoint at {}", self.describe(address)));
+                }
+                for w in matching {
+                    self.cpu.remove_watchpoint(w.address, w.length, w.kind);
+                }
+                Ok(String::new())
+            }
+            "r" | "regs" => Ok(self.registers()),
+            "p" | "print" => {
+                let name = arguments.first().ok_or("missing register")?;
+                let value = self.read_register(name)?;
+                Ok(format!("{} = 0x{:08x} ({})", name, value, value as i32))
+            }
+            "set" => {
+                let [name, value] = arguments[..] else { return Err("usage: set <reg> <value>".to_string()) };
+                let value = self.location(value)?;
+                self.write_register(name, value)?;
+                self.history.clear();
+                Ok(String::new())
+            }
+            "x" => {
+                let address = self.location(arguments.first().ok_or("missing location")?)?;
+                let count = match arguments.get(1) {
+                    Some(text) => parse_number(text).ok_or_else(|| format!("invalid count '{}'", text))?,
+                    None => 8,
+                };
+                self.dump(address, count)
+            }
+            "write" => {
+                let result = self.poke(&arguments)?;
+                self.history.clear();
+                Ok(result)
+            }
+            "l" | "disas" => {
+                let address = match arguments.first() {
+                    Some(location) => self.location(location)?,
+                    None => self.cpu.pc().saturating_sub(16),
+                };
+                let count = match arguments.get(1) {
+                    Some(text) => parse_number(text).ok_or_else(|| format!("invalid count '{}'", text))?,
+                    None => 10,
+                };
+                Ok(self.disassembly(address & !0b11, count))
+            }
+            "save" => {
+                let path = arguments.first().ok_or("missing file")?;
+                std::fs::write(path, self.cpu.snapshot(&self.memory).to_bytes()).map_err(|error| format!("{}: {}", path, error))?;
+                Ok(format!("saved snapshot at {}", self.describe(self.cpu.pc())))
+            }
+            "restore" => {
+                let path = arguments.first().ok_or("missing file")?;
+                let data = std::fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+                let snapshot = Snapshot::from_bytes(&data).map_err(|error| format!("{}: {}", path, error))?;
+                self.cpu.restore(&snapshot, &mut self.memory);
+                self.history.clear();
+                Ok(self.current_instruction())
+            }
+            "h" | "help" => Ok(HELP.to_string()),
+            "q" | "quit" => {
+                self.quit = true;
+                Ok(String::new())
+            }
+            _ => Err(format!("unknown command '{}', try 'help'", command)),
+        }
+    }
+
+    fn location(&self, text: &str) -> Result<u32, String> {
+        self.symbols.parse_address(text).ok_or_else(|| format!("unknown location '{}'", text))
+    }
+
+    /// `0x00000010 <main+0x4>`, or just the hex address if no symbol precedes it.
+    fn describe(&self, address: u32) -> String {
+        let label = self.symbols.label(address);
+        if label.is_empty() { format!("0x{:08x}", address) } else { format!("0x{:08x} {}", address, label) }
+    }
+
+    fn instruction_line(&self, address: u32) -> String {
+        let marker = if address == self.cpu.pc() { "=>" } else { "  " };
+        match self.read(address, 4) {
+            Some(word) => format!("{} {}:\t{:08x}\t{}", marker, self.describe(address), word, disassemble(word, address)),
+            None => format!("{} {}:\t<inaccessible>", marker, self.describe(address)),
+        }
+    }
+
+    fn current_instruction(&self) -> String {
+        self.instruction_line(self.cpu.pc())
+    }
+
+    fn disassembly(&self, start: u32, count: u32) -> String {
+        (0..count).map(|index| self.instruction_line(start.wrapping_add(4 * index))).collect::<Vec<_>>().join("\n")
+    }
+
+    /// Execute up to `count` instructions, stopping early at breakpoints, watchpoints,
+    /// `until` (a temporary breakpoint) or any other stop reason.
+    fn resume(&mut self, count: u64, until: Option<u32>) -> String {
+        let temporary = until.filter(|&address| self.cpu.add_breakpoint(address));
+        let stop = self.history.run_for(&mut self.cpu, &mut self.memory, count);
+        if let Some(address) = temporary {
+            self.cpu.remove_breakpoint(address);
+        }
+        self.report(stop, temporary)
+    }
+
+    /// Describe `stop` followed by the instruction at pc. `temporary` is an internal
+    /// breakpoint whose hit is not reported.
+    fn report(&self, stop: StopReason, temporary: Option<u32>) -> String {
+        let report = match stop {
+            StopReason::InstructionLimit => String::new(),
+            StopReason::Breakpoint { pc } if Some(pc) == temporary => String::new(),
+            StopReason::Breakpoint { pc } => format!("breakpoint at {}\n", self.describe(pc)),
+            StopReason::Watchpoint { pc, address, write: true, old, new, .. } => format!(
+                "watchpoint {}: 0x{:x} -> 0x{:x} (written by instruction at {})\n",
+                self.describe(address),
+                old,
+                new,
+                self.describe(pc)
+            ),
+            StopReason::Watchpoint { pc, address, new, .. } => format!(
+                "watchpoint {}: value 0x{:x} (read by instruction at {})\n",
+                self.describe(address),
+                new,
+                self.describe(pc)
+            ),
+            stop => format!("{}\n", stop),
+        };
+        report + &self.current_instruction()
+    }
+
+    /// Step over calls: a JAL/JALR that links into ra runs until it returns to the next instruction.
+    fn next(&mut self) -> String {
+        let pc = self.cpu.pc();
+        let is_call = matches!(
+            self.read(pc, 4).map(Instruction::decode),
+            Some(Ok(Instruction::Jal(JTypeInstruction { rd: 1, .. }) | Instruction::Jalr(ITypeInstruction { rd: 1, .. })))
+        );
+        if is_call { self.resume(u64::MAX, Some(pc.wrapping_add(4))) } else { self.resume(1, None) }
+    }
+
+    fn watch(&mut self, kind: WatchKind, arguments: &[&str]) -> Result<String, String> {
+        let Some(location) = arguments.first() else {
+            let lines: Vec<String> = self
+                .cpu
+                .watchpoints()
+                .iter()
+                .map(|w| {
+                    let condition = w.condition.map(|value| format!(" if 0x{:x}", value)).unwrap_or_default();
+                    format!("{:?} {} length {}{}", w.kind, self.describe(w.address), w.length, condition)
+                })
+                .collect();
+            return Ok(lines.join("\n"));
+        };
+        let address = self.location(location)?;
+        let (length, condition) = match arguments[1..] {
+            [] => (None, None),
+            ["if", value] => (None, Some(value)),
+            [length] => (Some(length), None),
+            [length, "if", value] => (Some(length), Some(value)),
+            _ => return Err("usage: watch <loc> [len] [if <value>]".to_string()),
+        };
+        let length = match length {
+            Some(text) => parse_number(text).filter(|&length| length > 0).ok_or_else(|| format!("invalid length '{}'", text))?,
+            None => 4,
+        };
+        let mut watchpoint = Watchpoint::new(address, length, kind);
+        if let Some(value) = condition {
+            watchpoint = watchpoint.with_condition(self.location(value)?);
+        }
+        self.cpu.add_watchpoint(watchpoint);
+        Ok(format!("watchpoint at {}", self.describe(address)))
+    }
+
+    fn registers(&self) -> String {
+        let mut text = String::new();
+        for register in 0..32u8 {
+            let separator = if register % 4 == 3 { "\n" } else { "  " };
+            let _ = write!(text, "{:<4} 0x{:08x}{}", register_name(register), self.cpu.register(register), separator);
+        }
+        let _ = write!(text, "pc   {}  mode {:?}", self.describe(self.cpu.pc()), self.cpu.privilege());
+        text
+    }
+
+    fn read_register(&self, name: &str) -> Result<u32, String> {
+        if name == "pc" {
+            return Ok(self.cpu.pc());
+        }
+        if let Some(register) = register_number(name) {
+            return Ok(self.cpu.register(register));
+        }
+        let csr = csr_number(name).ok_or_else(|| format!("unknown register '{}'", name))?;
+        self.cpu.read_csr(csr).ok_or_else(|| format!("unimplemented CSR '{}'", name))
+    }
+
+    fn write_register(&mut self, name: &str, value: u32) -> Result<(), String> {
+        if name == "pc" {
+            self.cpu.set_pc(value);
+        } else if let Some(register) = register_number(name) {
+            self.cpu.set_register(register, value);
+        } else {
+            let csr = csr_number(name).ok_or_else(|| format!("unknown register '{}'", name))?;
+            if !self.cpu.write_csr(csr, value) {
+                return Err(format!("cannot write CSR '{}'", csr_name(csr).unwrap_or(name)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Little-endian value of `size` bytes at `address`, or `None` if outside memory.
+    fn read(&self, address: u32, size: u32) -> Option<u32> {
+        if address as u64 + size as u64 > self.memory.size() as u64 {
+            return None;
+        }
+        Some((0..size).fold(0, |value, index| value | self.memory.load_byte((address + index) as usize) << (8 * index)))
+    }
+
+    fn dump(&self, address: u32, count: u32) -> Result<String, String> {
+        let mut text = String::new();
+        for index in 0..count {
+            let current = address.wrapping_add(4 * index);
+            if index % 4 == 0 {
+                if index > 0 {
+                    text.push('\n');
+                }
+                let _ = write!(text, "{}:", self.describe(current));
+            }
+            let word = self.read(current, 4).ok_or_else(|| format!("0x{:08x} is outside memory", current))?;
+            let _ = write!(text, " 0x{:08x}", word);
+        }
+        Ok(text)
+    }
+
+    fn poke(&mut self, arguments: &[&str]) -> Result<String, String> {
+        let (location, value, width) = match arguments {
+            [location, value] => (location, value, "w"),
+            [location, value, width] => (location, value, *width),
+            _ => return Err("usage: write <loc> <value> [b|h|w]".to_string()),
+        };
+        let address = self.location(location)?;
+        let value = self.location(value)?;
+        let size = match width {
+            "b" => 1,
+            "h" => 2,
+            "w" => 4,
+            _ => return Err(format!("invalid width '{}'", width)),
+        };
+        if address as u64 + size as u64 > self.memory.size() as u64 {
+            return Err(format!("0x{:08x} is outside memory", address));
+        }
+        self.memory.write_bytes(address as usize, &value.to_le_bytes()[..size]);
+        self.cpu.flush_decode_cache();
+        Ok(String::new())
+    }
+}
@@ -0,0 +1,57 @@
+use crate::instruction::Instruction;
+
+/// Bytes of memory covered by one cache page.
+pub const PAGE_SIZE: u32 = 4096;
+const SLOTS: usize = (PAGE_SIZE / 4) as usize;
+
+/// Instruction words of one page and their decodings.
+type Page = [Option<(u32, Instruction)>; SLOTS];
+
+/// Pre-decoded instructions, kept per 4 KiB page of memory.
+///
+/// A page is decoded lazily, one instruction word at a time, as execution fetches from
+/// it. The owner must invalidate words whenever their contents may have changed: the CPU
+/// does so for its own stores and drops everything on FENCE.I, while writes made by the
+/// host behind its back call for [`DecodeCache::clear`].
+#[derive(Debug, Clone, Default)]
+pub struct DecodeCache {
+    // Indexed by page number
+    pages: Vec<Option<Box<Page>>>,
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The cached word and decoding at the word-aligned address `pc`.
+    #[inline]
+    pub fn get(&self, pc: u32) -> Option<(u32, Instruction)> {
+        let page = self.pages.get((pc / PAGE_SIZE) as usize)?.as_ref()?;
+        page[(pc % PAGE_SIZE / 4) as usize]
+    }
+
+    /// Remember that the word at `pc` is `raw`, decoding to `instruction`.
+    pub fn insert(&mut self, pc: u32, raw: u32, instruction: Instruction) {
+        let index = (pc / PAGE_SIZE) as usize;
+        if index >= self.pages.len() {
+            self.pages.resize(index + 1, None);
+        }
+        let page = self.pages[index].get_or_insert_with(|| Box::new([None; SLOTS]));
+        page[(pc % PAGE_SIZE / 4) as usize] = Some((raw, instruction));
+    }
+
+    /// Drop the word holding `address`, which is about to be written by an aligned
+    /// store. Data sharing a page with code then leaves the rest of the page cached.
+    #[inline]
+    pub fn invalidate(&mut self, address: u32) {
+        if let Some(Some(page)) = self.pages.get_mut((address / PAGE_SIZE) as usize) {
+            page[(address % PAGE_SIZE / 4) as usize] = None;
+        }
+    }
+
+    /// Drop every page.
+    pub fn clear(&mut self) {
+        self.pages.clear();
+    }
+}
@@ -0,0 +1,211 @@
+use std::fmt;
+
+use crate::instruction::*;
+use crate::opcode::opcode::*;
+
+// Masks selecting the fixed bits of each encoding shape
+const MASK_OPCODE: u32 = 0x0000007f;
+const MASK_FUNCT3: u32 = 0x0000707f;
+const MASK_FUNCT7: u32 = 0xfe00707f;
+const MASK_FUNCT5: u32 = 0xf800707f;
+const MASK_FUNCT5_RS2: u32 = 0xf9f0707f;
+const MASK_FUNCT7_RD: u32 = 0xfe007fff;
+const MASK_EXACT: u32 = 0xffffffff;
+
+/// Why a 32-bit word could not be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Encodings the specification defines as illegal: an all-zero parcel or all ones.
+    Illegal(u32),
+    /// Encodings in space the specification leaves reserved or custom, including
+    /// unassigned function codes within an implemented major opcode.
+    Reserved(u32),
+    /// Valid encodings of a standard extension or instruction length this simulator
+    /// does not implement (compressed, floating point, vector, 48-bit and longer).
+    Unsupported(u32),
+}
+
+impl DecodeError {
+    /// The instruction word that failed to decode.
+    pub fn word(&self) -> u32 {
+        match *self {
+            DecodeError::Illegal(word) | DecodeError::Reserved(word) | DecodeError::Unsupported(word) => word,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Illegal(word) => write!(f, "illegal instruction 0x{:08x}", word),
+            DecodeError::Reserved(word) => write!(f, "reserved instruction encoding 0x{:08x}", word),
+            DecodeError::Unsupported(word) => write!(f, "unsupported instruction encoding 0x{:08x}", word),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// One row of the decoder table: `instruction & mask == bits` identifies the instruction.
+pub struct Pattern {
+    pub mnemonic: &'static str,
+    pub format: Format,
+    pub mask: u32,
+    pub bits: u32,
+    pub decode: fn(u32) -> Instruction,
+}
+
+const fn op(opcode: u8) -> u32 {
+    opcode as u32
+}
+
+const fn op_f3(opcode: u8, funct3: u8) -> u32 {
+    opcode as u32 | (funct3 as u32) << 12
+}
+
+const fn op_f3_f7(opcode: u8, funct3: u8, funct7: u8) -> u32 {
+    op_f3(opcode, funct3) | (funct7 as u32) << 25
+}
+
+const fn amo(funct5: u8) -> u32 {
+    op_f3(OPCODE_AMO, FUNCT3_AMO_W) | (funct5 as u32) << 27
+}
+
+const fn system(funct12: u16) -> u32 {
+    op_f3(OPCODE_SYSTEM, FUNCT3_PRIV) | (funct12 as u32) << 20
+}
+
+macro_rules! pattern {
+    ($mnemonic:literal, $format:ident, $mask:expr, $bits:expr, $variant:ident($ctor:path)) => {
+        Pattern {
+            mnemonic: $mnemonic,
+            format: Format::$format,
+            mask: $mask,
+            bits: $bits,
+            decode: |word| Instruction::$variant($ctor(word)),
+        }
+    };
+    ($mnemonic:literal, $format:ident, $mask:expr, $bits:expr, $variant:ident) => {
+        Pattern {
+            mnemonic: $mnemonic,
+            format: Format::$format,
+            mask: $mask,
+            bits: $bits,
+            decode: |_| Instruction::$variant,
+        }
+    };
+}
+
+/// Every instruction the simulator implements: RV32I, M, A, Zicsr, Zifencei and the
+/// machine/supervisor privileged instructions.
+pub static PATTERNS: &[Pattern] = &[
+    // RV32I
+    pattern!("lui", U, MASK_OPCODE, op(OPCODE_LUI), Lui(UTypeInstruction::from_word)),
+    pattern!("auipc", U, MASK_OPCODE, op(OPCODE_AUIPC), Auipc(UTypeInstruction::from_word)),
+    pattern!("jal", J, MASK_OPCODE, op(OPCODE_JAL), Jal(JTypeInstruction::from_word)),
+    pattern!("jalr", I, MASK_FUNCT3, op_f3(OPCODE_JALR, FUNCT3_JALR), Jalr(ITypeInstruction::from_word)),
+    pattern!("beq", B, MASK_FUNCT3, op_f3(OPCODE_BRANCH, FUNCT3_BEQ), Beq(BTypeInstruction::from_word)),
+    pattern!("bne", B, MASK_FUNCT3, op_f3(OPCODE_BRANCH, FUNCT3_BNE), Bne(BTypeInstruction::from_word)),
+    pattern!("blt", B, MASK_FUNCT3, op_f3(OPCODE_BRANCH, FUNCT3_BLT), Blt(BTypeInstruction::from_word)),
+    pattern!("bge", B, MASK_FUNCT3, op_f3(OPCODE_BRANCH, FUNCT3_BGE), Bge(BTypeInstruction::from_word)),
+    pattern!("bltu", B, MASK_FUNCT3, op_f3(OPCODE_BRANCH, FUNCT3_BLTU), Bltu(BTypeInstruction::from_word)),
+    pattern!("bgeu", B, MASK_FUNCT3, op_f3(OPCODE_BRANCH, FUNCT3_BGEU), Bgeu(BTypeInstruction::from_word)),
+    pattern!("lb", I, MASK_FUNCT3, op_f3(OPCODE_LOAD, FUNCT3_LB), Lb(ITypeInstruction::from_word)),
+    pattern!("lh", I, MASK_FUNCT3, op_f3(OPCODE_LOAD, FUNCT3_LH), Lh(ITypeInstruction::from_word)),
+    pattern!("lw", I, MASK_FUNCT3, op_f3(OPCODE_LOAD, FUNCT3_LW), Lw(ITypeInstruction::from_word)),
+    pattern!("lbu", I, MASK_FUNCT3, op_f3(OPCODE_LOAD, FUNCT3_LBU), Lbu(ITypeInstruction::from_word)),
+    pattern!("lhu", I, MASK_FUNCT3, op_f3(OPCODE_LOAD, FUNCT3_LHU), Lhu(ITypeInstruction::from_word)),
+    pattern!("sb", S, MASK_FUNCT3, op_f3(OPCODE_STORE, FUNCT3_SB), Sb(STypeInstruction::from_word)),
+    pattern!("sh", S, MASK_FUNCT3, op_f3(OPCODE_STORE, FUNCT3_SH), Sh(STypeInstruction::from_word)),
+    pattern!("sw", S, MASK_FUNCT3, op_f3(OPCODE_STORE, FUNCT3_SW), Sw(STypeInstruction::from_word)),
+    pattern!("addi", I, MASK_FUNCT3, op_f3(OPCODE_OP_IMM, FUNCT3_ADD), Addi(ITypeInstruction::from_word)),
+    pattern!("slti", I, MASK_FUNCT3, op_f3(OPCODE_OP_IMM, FUNCT3_SLT), Slti(ITypeInstruction::from_word)),
+    pattern!("sltiu", I, MASK_FUNCT3, op_f3(OPCODE_OP_IMM, FUNCT3_SLTU), Sltiu(ITypeInstruction::from_word)),
+    pattern!("xori", I, MASK_FUNCT3, op_f3(OPCODE_OP_IMM, FUNCT3_XOR), Xori(ITypeInstruction::from_word)),
+    pattern!("ori", I, MASK_FUNCT3, op_f3(OPCODE_OP_IMM, FUNCT3_OR), Ori(ITypeInstruction::from_word)),
+    pattern!("andi", I, MASK_FUNCT3, op_f3(OPCODE_OP_IMM, FUNCT3_AND), Andi(ITypeInstruction::from_word)),
+    pattern!("slli", I, MASK_FUNCT7, op_f3_f7(OPCODE_OP_IMM, FUNCT3_SLL, FUNCT7_ALL), Slli(ITypeInstruction::from_word_shamt)),
+    pattern!("srli", I, MASK_FUNCT7, op_f3_f7(OPCODE_OP_IMM, FUNCT3_SRL, FUNCT7_ALL), Srli(ITypeInstruction::from_word_shamt)),
+    pattern!("srai", I, MASK_FUNCT7, op_f3_f7(OPCODE_OP_IMM, FUNCT3_SRL, FUNCT7_SRA), Srai(ITypeInstruction::from_word_shamt)),
+    pattern!("add", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_ADD, FUNCT7_ALL), Add(RTypeInstruction::from_word)),
+    pattern!("sub", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_ADD, FUNCT7_SUB), Sub(RTypeInstruction::from_word)),
+    pattern!("sll", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_SLL, FUNCT7_ALL), Sll(RTypeInstruction::from_word)),
+    pattern!("slt", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_SLT, FUNCT7_ALL), Slt(RTypeInstruction::from_word)),
+    pattern!("sltu", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_SLTU, FUNCT7_ALL), Sltu(RTypeInstruction::from_word)),
+    pattern!("xor", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_XOR, FUNCT7_ALL), Xor(RTypeInstruction::from_word)),
+    pattern!("srl", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_SRL, FUNCT7_ALL), Srl(RTypeInstruction::from_word)),
+    pattern!("sra", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_SRL, FUNCT7_SRA), Sra(RTypeInstruction::from_word)),
+    pattern!("or", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_OR, FUNCT7_ALL), Or(RTypeInstruction::from_word)),
+    pattern!("and", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_AND, FUNCT7_ALL), And(RTypeInstruction::from_word)),
+    pattern!("fence", Fence, MASK_FUNCT3, op_f3(OPCODE_MISC_MEM, FUNCT3_FENCE), Fence(FenceInstruction::from_word)),
+    pattern!("ecall", System, MASK_EXACT, system(FUNCT12_ECALL), Ecall),
+    pattern!("ebreak", System, MASK_EXACT, system(FUNCT12_EBREAK), Ebreak),
+
+    // Zifencei
+    pattern!("fence.i", I, MASK_FUNCT3, op_f3(OPCODE_MISC_MEM, FUNCT3_FENCE_I), FenceI(ITypeInstruction::from_word)),
+
+    // Zicsr
+    pattern!("csrrw", Csr, MASK_FUNCT3, op_f3(OPCODE_SYSTEM, FUNCT3_CSRRW), Csrrw(CsrInstruction::from_word)),
+    pattern!("csrrs", Csr, MASK_FUNCT3, op_f3(OPCODE_SYSTEM, FUNCT3_CSRRS), Csrrs(CsrInstruction::from_word)),
+    pattern!("csrrc", Csr, MASK_FUNCT3, op_f3(OPCODE_SYSTEM, FUNCT3_CSRRC), Csrrc(CsrInstruction::from_word)),
+    pattern!("csrrwi", Csr, MASK_FUNCT3, op_f3(OPCODE_SYSTEM, FUNCT3_CSRRWI), Csrrwi(CsrInstruction::from_word)),
+    pattern!("csrrsi", Csr, MASK_FUNCT3, op_f3(OPCODE_SYSTEM, FUNCT3_CSRRSI), Csrrsi(CsrInstruction::from_word)),
+    pattern!("csrrci", Csr, MASK_FUNCT3, op_f3(OPCODE_SYSTEM, FUNCT3_CSRRCI), Csrrci(CsrInstruction::from_word)),
+
+    // M extension
+    pattern!("mul", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_MUL, FUNCT7_MULDIV), Mul(RTypeInstruction::from_word)),
+    pattern!("mulh", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_MULH, FUNCT7_MULDIV), Mulh(RTypeInstruction::from_word)),
+    pattern!("mulhsu", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_MULHSU, FUNCT7_MULDIV), Mulhsu(RTypeInstruction::from_word)),
+    pattern!("mulhu", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_MULHU, FUNCT7_MULDIV), Mulhu(RTypeInstruction::from_word)),
+    pattern!("div", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_DIV, FUNCT7_MULDIV), Div(RTypeInstruction::from_word)),
+    pattern!("divu", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_DIVU, FUNCT7_MULDIV), Divu(RTypeInstruction::from_word)),
+    pattern!("rem", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_REM, FUNCT7_MULDIV), Rem(RTypeInstruction::from_word)),
+    pattern!("remu", R, MASK_FUNCT7, op_f3_f7(OPCODE_OP, FUNCT3_REMU, FUNCT7_MULDIV), Remu(RTypeInstruction::from_word)),
+
+    // A extension
+    pattern!("lr.w", Atomic, MASK_FUNCT5_RS2, amo(FUNCT5_LR), LrW(AtomicInstruction::from_word)),
+    pattern!("sc.w", Atomic, MASK_FUNCT5, amo(FUNCT5_SC), ScW(AtomicInstruction::from_word)),
+    pattern!("amoswap.w", Atomic, MASK_FUNCT5, amo(FUNCT5_AMOSWAP), AmoswapW(AtomicInstruction::from_word)),
+    pattern!("amoadd.w", Atomic, MASK_FUNCT5, amo(FUNCT5_AMOADD), AmoaddW(AtomicInstruction::from_word)),
+    pattern!("amoxor.w", Atomic, MASK_FUNCT5, amo(FUNCT5_AMOXOR), AmoxorW(AtomicInstruction::from_word)),
+    pattern!("amoand.w", Atomic, MASK_FUNCT5, amo(FUNCT5_AMOAND), AmoandW(AtomicInstruction::from_word)),
+    pattern!("amoor.w", Atomic, MASK_FUNCT5, amo(FUNCT5_AMOOR), AmoorW(AtomicInstruction::from_word)),
+    pattern!("amomin.w", Atomic, MASK_FUNCT5, amo(FUNCT5_AMOMIN), AmominW(AtomicInstruction::from_word)),
+    pattern!("amomax.w", Atomic, MASK_FUNCT5, amo(FUNCT5_AMOMAX), AmomaxW(AtomicInstruction::from_word)),
+    pattern!("amominu.w", Atomic, MASK_FUNCT5, amo(FUNCT5_AMOMINU), AmominuW(AtomicInstruction::from_word)),
+    pattern!("amomaxu.w", Atomic, MASK_FUNCT5, amo(FUNCT5_AMOMAXU), AmomaxuW(AtomicInstruction::from_word)),
+
+    // Privileged
+    pattern!("sret", System, MASK_EXACT, system(FUNCT12_SRET), Sret),
+    pattern!("mret", System, MASK_EXACT, system(FUNCT12_MRET), Mret),
+    pattern!("wfi", System, MASK_EXACT, system(FUNCT12_WFI), Wfi),
+    pattern!("sfence.vma", R, MASK_FUNCT7_RD, op_f3_f7(OPCODE_SYSTEM, FUNCT3_PRIV, FUNCT7_SFENCE_VMA), SfenceVma(RTypeInstruction::from_word)),
+];
+
+/// Look up the decoder table entry for a mnemonic.
+pub fn pattern(mnemonic: &str) -> Option<&'static Pattern> {
+    PATTERNS.iter().find(|p| p.mnemonic == mnemonic)
+}
+
+/// Decode a 32-bit instruction word by matching it against [`PATTERNS`].
+///
+/// Every input produces either an instruction or a [`DecodeError`]; decoding never panics.
+pub fn decode(instruction: u32) -> Result<Instruction, DecodeError> {
+    if instruction & 0xffff == 0 || instruction == u32::MAX {
+        return Err(DecodeError::Illegal(instruction));
+    }
+    // Bits [1:0] != 11 is a 16-bit parcel, bits [4:2] == 111 a 48-bit or longer one
+    if instruction & 0b11 != 0b11 || instruction & 0b11100 == 0b11100 {
+        return Err(DecodeError::Unsupported(instruction));
+    }
+
+    if let Some(pattern) = PATTERNS.iter().find(|p| instruction & p.mask == p.bits) {
+        return Ok((pattern.decode)(instruction));
+    }
+
+    match (instruction & MASK_OPCODE) as u8 {
+        OPCODE_LOAD_FP | OPCODE_STORE_FP | OPCODE_MADD | OPCODE_MSUB | OPCODE_NMSUB | OPCODE_NMADD
+        | OPCODE_OP_FP | OPCODE_OP_V => Err(DecodeError::Unsupported(instruction)),
+        _ => Err(DecodeError::Reserved(instruction)),
+    }
+}
@@ -0,0 +1,261 @@
+use std::fmt;
+
+use crate::instruction::*;
+
+/// ABI register names, indexed by register number.
+pub const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+    "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+    "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+pub fn register_name(register: u8) -> &'static str {
+    ABI_NAMES[(register & 0x1f) as usize]
+}
+
+/// Register number for an ABI name (`a0`, `fp`) or architectural name (`x10`).
+pub fn register_number(name: &str) -> Option<u8> {
+    if name == "fp" {
+        return Some(8);
+    }
+    if let Some(n) = name.strip_prefix('x').and_then(|n| n.parse::<u8>().ok()) {
+        return (n < 32).then_some(n);
+    }
+    ABI_NAMES.iter().position(|&abi| abi == name).map(|n| n as u8)
+}
+
+/// Standard CSR names, as printed by objdump.
+pub fn csr_name(csr: u16) -> Option<&'static str> {
+    let name = match csr {
+        0x001 => "fflags",
+        0x002 => "frm",
+        0x003 => "fcsr",
+        0xc00 => "cycle",
+        0xc01 => "time",
+        0xc02 => "instret",
+        0xc80 => "cycleh",
+        0xc81 => "timeh",
+        0xc82 => "instreth",
+        0x100 => "sstatus",
+        0x104 => "sie",
+        0x105 => "stvec",
+        0x106 => "scounteren",
+        0x140 => "sscratch",
+        0x141 => "sepc",
+        0x142 => "scause",
+        0x143 => "stval",
+        0x144 => "sip",
+        0x180 => "satp",
+        0xf11 => "mvendorid",
+        0xf12 => "marchid",
+        0xf13 => "mimpid",
+        0xf14 => "mhartid",
+        0x300 => "mstatus",
+        0x301 => "misa",
+        0x302 => "medeleg",
+        0x303 => "mideleg",
+        0x304 => "mie",
+        0x305 => "mtvec",
+        0x306 => "mcounteren",
+        0x310 => "mstatush",
+        0x340 => "mscratch",
+        0x341 => "mepc",
+        0x342 => "mcause",
+        0x343 => "mtval",
+        0x344 => "mip",
+        0xb00 => "mcycle",
+        0xb02 => "minstret",
+        0xb80 => "mcycleh",
+        0xb82 => "minstreth",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// CSR number for a standard CSR name or a numeric CSR address.
+pub fn csr_number(name: &str) -> Option<u16> {
+    let number = if let Some(hex) = name.strip_prefix("0x") {
+        u16::from_str_radix(hex, 16).ok()
+    } else if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.parse::<u16>().ok()
+    } else {
+        return (0..0x1000).find(|&csr| csr_name(csr) == Some(name));
+    };
+    number.filter(|&csr| csr < 0x1000)
+}
+
+fn csr(csr: u16) -> String {
+    match csr_name(csr) {
+        Some(name) => name.to_string(),
+        None => format!("0x{:x}", csr),
+    }
+}
+
+fn fence_set(bits: u8) -> String {
+    let set: String = [(8, 'i'), (4, 'o'), (2, 'r'), (1, 'w')]
+        .iter()
+        .filter(|(bit, _)| bits & bit != 0)
+        .map(|(_, c)| *c)
+        .collect();
+    if set.is_empty() { "0".to_string() } else { set }
+}
+
+fn amo_suffix(a: &AtomicInstruction) -> &'static str {
+    match (a.aq, a.rl) {
+        (false, false) => "",
+        (true, false) => ".aq",
+        (false, true) => ".rl",
+        (true, true) => ".aqrl",
+    }
+}
+
+/// Control transfer target: an absolute address when the pc is known,
+/// otherwise an offset relative to the instruction (`.+8`).
+fn target(pc: Option<u32>, offset: i32) -> String {
+    match pc {
+        Some(pc) => format!("{:x}", pc.wrapping_add(offset as u32)),
+        None if offset < 0 => format!(".-{}", offset.unsigned_abs()),
+        None => format!(".+{}", offset),
+    }
+}
+
+/// Mnemonic and operand text, applying the objdump pseudo-instruction aliases.
+fn render(instruction: &Instruction, pc: Option<u32>) -> (String, String) {
+    let r = register_name;
+    let mnemonic = instruction.mnemonic();
+    let plain = |operands: String| (mnemonic.to_string(), operands);
+    let alias = |name: &str, operands: String| (name.to_string(), operands);
+
+    match *instruction {
+        Instruction::Addi(i) if i.rd == 0 && i.rs1 == 0 && i.imm == 0 => alias("nop", String::new()),
+        Instruction::Addi(i) if i.rs1 == 0 => alias("li", format!("{},{}", r(i.rd), i.imm)),
+        Instruction::Addi(i) if i.imm == 0 => alias("mv", format!("{},{}", r(i.rd), r(i.rs1))),
+        Instruction::Xori(i) if i.imm == -1 => alias("not", format!("{},{}", r(i.rd), r(i.rs1))),
+        Instruction::Sltiu(i) if i.imm == 1 => alias("seqz", format!("{},{}", r(i.rd), r(i.rs1))),
+        Instruction::Sub(o) if o.rs1 == 0 => alias("neg", format!("{},{}", r(o.rd), r(o.rs2))),
+        Instruction::Sltu(o) if o.rs1 == 0 => alias("snez", format!("{},{}", r(o.rd), r(o.rs2))),
+        Instruction::Slt(o) if o.rs2 == 0 => alias("sltz", format!("{},{}", r(o.rd), r(o.rs1))),
+        Instruction::Slt(o) if o.rs1 == 0 => alias("sgtz", format!("{},{}", r(o.rd), r(o.rs2))),
+
+        Instruction::Slli(i) | Instruction::Srli(i) | Instruction::Srai(i) => {
+            plain(format!("{},{},0x{:x}", r(i.rd), r(i.rs1), i.imm))
+        }
+        Instruction::Addi(i) | Instruction::Slti(i) | Instruction::Sltiu(i) | Instruction::Xori(i)
+        | Instruction::Ori(i) | Instruction::Andi(i) => plain(format!("{},{},{}", r(i.rd), r(i.rs1), i.imm)),
+
+        Instruction::Lb(i) | Instruction::Lh(i) | Instruction::Lw(i) | Instruction::Lbu(i)
+        | Instruction::Lhu(i) => plain(format!("{},{}({})", r(i.rd), i.imm, r(i.rs1))),
+
+        Instruction::Jalr(i) if i.rd == 0 && i.rs1 == 1 && i.imm == 0 => alias("ret", String::new()),
+        Instruction::Jalr(i) if i.rd == 0 && i.imm == 0 => alias("jr", r(i.rs1).to_string()),
+        Instruction::Jalr(i) if i.rd == 1 && i.imm == 0 => plain(r(i.rs1).to_string()),
+        Instruction::Jalr(i) => plain(format!("{},{}({})", r(i.rd), i.imm, r(i.rs1))),
+
+        Instruction::FenceI(_) => plain(String::new()),
+
+        Instruction::Sb(s) | Instruction::Sh(s) | Instruction::Sw(s) => {
+            plain(format!("{},{}({})", r(s.rs2), s.imm, r(s.rs1)))
+        }
+
+        Instruction::Beq(b) if b.rs2 == 0 => alias("beqz", format!("{},{}", r(b.rs1), target(pc, b.imm))),
+        Instruction::Bne(b) if b.rs2 == 0 => alias("bnez", format!("{},{}", r(b.rs1), target(pc, b.imm))),
+        Instruction::Bge(b) if b.rs1 == 0 => alias("blez", format!("{},{}", r(b.rs2), target(pc, b.imm))),
+        Instruction::Bge(b) if b.rs2 == 0 => alias("bgez", format!("{},{}", r(b.rs1), target(pc, b.imm))),
+        Instruction::Blt(b) if b.rs2 == 0 => alias("bltz", format!("{},{}", r(b.rs1), target(pc, b.imm))),
+        Instruction::Blt(b) if b.rs1 == 0 => alias("bgtz", format!("{},{}", r(b.rs2), target(pc, b.imm))),
+        Instruction::Beq(b) | Instruction::Bne(b) | Instruction::Blt(b) | Instruction::Bge(b)
+        | Instruction::Bltu(b) | Instruction::Bgeu(b) => {
+            plain(format!("{},{},{}", r(b.rs1), r(b.rs2), target(pc, b.imm)))
+        }
+
+        Instruction::Lui(u) | Instruction::Auipc(u) => {
+            plain(format!("{},0x{:x}", r(u.rd), (u.imm as u32) >> 12))
+        }
+
+        Instruction::Jal(j) if j.rd == 0 => alias("j", target(pc, j.imm)),
+        Instruction::Jal(j) if j.rd == 1 => plain(target(pc, j.imm)),
+        Instruction::Jal(j) => plain(format!("{},{}", r(j.rd), target(pc, j.imm))),
+
+        Instruction::Fence(f) if f.fm == 0b1000 && f.pred == 0b0011 && f.succ == 0b0011 => {
+            alias("fence.tso", String::new())
+        }
+        Instruction::Fence(f) if f.pred == 0b0001 && f.succ == 0 => alias("pause", String::new()),
+        Instruction::Fence(f) if f.pred == 0b1111 && f.succ == 0b1111 => plain(String::new()),
+        Instruction::Fence(f) => plain(format!("{},{}", fence_set(f.pred), fence_set(f.succ))),
+
+        Instruction::LrW(a) => (format!("{}{}", mnemonic, amo_suffix(&a)), format!("{},({})", r(a.rd), r(a.rs1))),
+        Instruction::ScW(a) | Instruction::AmoswapW(a) | Instruction::AmoaddW(a) | Instruction::AmoxorW(a)
+        | Instruction::AmoandW(a) | Instruction::AmoorW(a) | Instruction::AmominW(a) | Instruction::AmomaxW(a)
+        | Instruction::AmominuW(a) | Instruction::AmomaxuW(a) => (
+            format!("{}{}", mnemonic, amo_suffix(&a)),
+            format!("{},{},({})", r(a.rd), r(a.rs2), r(a.rs1)),
+        ),
+
+        Instruction::Csrrs(c) if c.rs1 == 0 => match c.csr {
+            0xc00 | 0xc01 | 0xc02 | 0xc80 | 0xc81 | 0xc82 => {
+                (format!("rd{}", csr(c.csr)), r(c.rd).to_string())
+            }
+            _ => alias("csrr", format!("{},{}", r(c.rd), csr(c.csr))),
+        },
+        Instruction::Csrrw(c) if c.rd == 0 => alias("csrw", format!("{},{}", csr(c.csr), r(c.rs1))),
+        Instruction::Csrrs(c) if c.rd == 0 => alias("csrs", format!("{},{}", csr(c.csr), r(c.rs1))),
+        Instruction::Csrrc(c) if c.rd == 0 => alias("csrc", format!("{},{}", csr(c.csr), r(c.rs1))),
+        Instruction::Csrrwi(c) if c.rd == 0 => alias("csrwi", format!("{},{}", csr(c.csr), c.rs1)),
+        Instruction::Csrrsi(c) if c.rd == 0 => alias("csrsi", format!("{},{}", csr(c.csr), c.rs1)),
+        Instruction::Csrrci(c) if c.rd == 0 => alias("csrci", format!("{},{}", csr(c.csr), c.rs1)),
+        Instruction::Csrrw(c) | Instruction::Csrrs(c) | Instruction::Csrrc(c) => {
+            plain(format!("{},{},{}", r(c.rd), csr(c.csr), r(c.rs1)))
+        }
+        Instruction::Csrrwi(c) | Instruction::Csrrsi(c) | Instruction::Csrrci(c) => {
+            plain(format!("{},{},{}", r(c.rd), csr(c.csr), c.rs1))
+        }
+
+        Instruction::SfenceVma(o) if o.rs1 == 0 && o.rs2 == 0 => plain(String::new()),
+        Instruction::SfenceVma(o) if o.rs2 == 0 => plain(r(o.rs1).to_string()),
+        Instruction::SfenceVma(o) => plain(format!("{},{}", r(o.rs1), r(o.rs2))),
+
+        Instruction::Add(o) | Instruction::Sub(o) | Instruction::Sll(o) | Instruction::Slt(o)
+        | Instruction::Sltu(o) | Instruction::Xor(o) | Instruction::Srl(o) | Instruction::Sra(o)
+        | Instruction::Or(o) | Instruction::And(o) | Instruction::Mul(o) | Instruction::Mulh(o)
+        | Instruction::Mulhsu(o) | Instruction::Mulhu(o) | Instruction::Div(o) | Instruction::Divu(o)
+        | Instruction::Rem(o) | Instruction::Remu(o) => plain(format!("{},{},{}", r(o.rd), r(o.rs1), r(o.rs2))),
+
+        Instruction::Ecall | Instruction::Ebreak | Instruction::Sret | Instruction::Mret
+        | Instruction::Wfi => plain(String::new()),
+    }
+}
+
+fn join(mnemonic: String, operands: String) -> String {
+    if operands.is_empty() {
+        mnemonic
+    } else {
+        format!("{}\t{}", mnemonic, operands)
+    }
+}
+
+impl Instruction {
+    /// objdump-style text for this instruction located at `pc`, with branch and
+    /// jump targets resolved to absolute addresses.
+    pub fn disassemble(&self, pc: u32) -> String {
+        let (mnemonic, operands) = render(self, Some(pc));
+        join(mnemonic, operands)
+    }
+}
+
+/// objdump-style text for a raw instruction word located at `pc`.
+pub fn disassemble(instruction: u32, pc: u32) -> String {
+    match Instruction::decode(instruction) {
+        Ok(decoded) => decoded.disassemble(pc),
+        Err(_) => format!(".4byte\t0x{:x}", instruction),
+    }
+}
+
+/// Branch and jump targets are printed relative to the instruction (`.+8`)
+/// since the pc is not known; use [`Instruction::disassemble`] to resolve them.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (mnemonic, operands) = render(self, None);
+        f.write_str(&join(mnemonic, operands))
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::memory::Memory;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+const SHN_UNDEF: u16 = 0;
+
+const EHDR_SIZE: usize = 52;
+const PHDR_SIZE: usize = 32;
+const SHDR_SIZE: usize = 40;
+const SYM_SIZE: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElfError {
+    /// The file is not an ELF file at all.
+    NotElf,
+    /// An ELF file this loader cannot handle (wrong class, byte order, machine or type).
+    Unsupported(&'static str),
+    /// A header or table extends past the end of the file.
+    Truncated,
+    /// A loadable segment does not fit in the simulated memory.
+    SegmentOutOfRange { address: u32, size: u32 },
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElfError::NotElf => write!(f, "not an ELF file"),
+            ElfError::Unsupported(what) => write!(f, "unsupported ELF file: {}", what),
+            ElfError::Truncated => write!(f, "truncated ELF file"),
+            ElfError::SegmentOutOfRange { address, size } => {
+                write!(f, "segment at 0x{:08x} ({} bytes) does not fit in memory", address, size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ElfError {}
+
+/// A `PT_LOAD` segment: `bytes` belong at `address`, followed by zeros up to `memory_size`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub address: u32,
+    pub bytes: Vec<u8>,
+    pub memory_size: u32,
+}
+
+/// A statically linked little-endian RV32 executable.
+#[derive(Debug, Clone, Default)]
+pub struct ElfFile {
+    pub entry: u32,
+    pub segments: Vec<Segment>,
+    pub symbols: HashMap<String, u32>,
+}
+
+impl ElfFile {
+    /// Parse an `ET_EXEC` file, keeping its loadable segments and the named
+    /// function, object and untyped symbols of its symbol table.
+    pub fn parse(data: &[u8]) -> Result<ElfFile, ElfError> {
+        if data.len() < 4 || data[..4] != ELF_MAGIC {
+            return Err(ElfError::NotElf);
+        }
+        if data.len() < EHDR_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if data[4] != ELFCLASS32 {
+            return Err(ElfError::Unsupported("not a 32-bit file"));
+        }
+        if data[5] != ELFDATA2LSB {
+            return Err(ElfError::Unsupported("not little-endian"));
+        }
+        if half(data, 18)? != EM_RISCV {
+            return Err(ElfError::Unsupported("not a RISC-V file"));
+        }
+        if half(data, 16)? != ET_EXEC {
+            return Err(ElfError::Unsupported("not an executable"));
+        }
+
+        let entry = word(data, 24)?;
+        let phoff = word(data, 28)? as usize;
+        let shoff = word(data, 32)? as usize;
+        let phnum = half(data, 44)? as usize;
+        let shnum = half(data, 48)? as usize;
+
+        let mut segments = Vec::new();
+        for index in 0..phnum {
+            let header = phoff + index * PHDR_SIZE;
+            if word(data, header)? != PT_LOAD {
+                continue;
+            }
+            let offset = word(data, header + 4)? as usize;
+            let address = word(data, header + 12)?;
+            let file_size = word(data, header + 16)? as usize;
+            let memory_size = word(data, header + 20)?;
+            segments.push(Segment { address, bytes: bytes(data, offset, file_size)?.to_vec(), memory_size });
+        }
+
+        let mut symbols = HashMap::new();
+        for index in 0..shnum {
+            let header = shoff + index * SHDR_SIZE;
+            if word(data, header + 4)? != SHT_SYMTAB {
+                continue;
+            }
+            let table = bytes(data, word(data, header + 16)? as usize, word(data, header + 20)? as usize)?;
+            let strtab_header = shoff + word(data, header + 24)? as usize * SHDR_SIZE;
+            let strings = bytes(data, word(data, strtab_header + 16)? as usize, word(data, strtab_header + 20)? as usize)?;
+            for entry in table.chunks_exact(SYM_SIZE) {
+                let kind = entry[12] & 0xf;
+                if kind == STT_SECTION || kind == STT_FILE || half(entry, 14)? == SHN_UNDEF {
+                    continue;
+                }
+                let name = string(strings, word(entry, 0)? as usize);
+                // Skip mapping symbols ($x, $d) and assembler-local labels
+                if name.is_empty() || name.starts_with('$') || name.starts_with(".L") {
+                    continue;
+                }
+                symbols.insert(name.to_string(), word(entry, 4)?);
+            }
+        }
+
+        Ok(ElfFile { entry, segments, symbols })
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<u32> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Address one past the highest byte of any loadable segment.
+    pub fn end(&self) -> u32 {
+        self.segments.iter().map(|segment| segment.address.saturating_add(segment.memory_size)).max().unwrap_or(0)
+    }
+
+    /// Copy every segment into memory, zero-filling up to each segment's memory size.
+    pub fn load_into(&self, memory: &mut Memory) -> Result<(), ElfError> {
+        for segment in &self.segments {
+            let end = segment.address as u64 + segment.memory_size.max(segment.bytes.len() as u32) as u64;
+            if end > memory.size() as u64 {
+                return Err(ElfError::SegmentOutOfRange { address: segment.address, size: segment.memory_size });
+            }
+            let mut contents = segment.bytes.clone();
+            contents.resize(segment.memory_size.max(segment.bytes.len() as u32) as usize, 0);
+            memory.write_bytes(segment.address as usize, &contents);
+        }
+        Ok(())
+    }
+}
+
+fn bytes(data: &[u8], offset: usize, length: usize) -> Result<&[u8], ElfError> {
+    data.get(offset..offset.checked_add(length).ok_or(ElfError::Truncated)?).ok_or(ElfError::Truncated)
+}
+
+fn half(data: &[u8], offset: usize) -> Result<u16, ElfError> {
+    Ok(u16::from_le_bytes(bytes(data, offset, 2)?.try_into().unwrap()))
+}
+
+fn word(data: &[u8], offset: usize) -> Result<u32, ElfError> {
+    Ok(u32::from_le_bytes(bytes(data, offset, 4)?.try_into().unwrap()))
+}
+
+/// NUL-terminated string at `offset`, or an empty string if it is out of range.
+fn string(table: &[u8], offset: usize) -> &str {
+    let tail = table.get(offset..).unwrap_or_default();
+    let end = tail.iter().position(|&b| b == 0).unwrap_or(tail.len());
+    std::str::from_utf8(&tail[..end]).unwrap_or_default()
+}
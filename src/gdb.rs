@@ -0,0 +1,509 @@
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::cpu::{Cpu, StopReason, WatchKind, Watchpoint};
+use crate::csr::Privilege;
+use crate::disassembler::{csr_name, register_name};
+use crate::memory::Memory;
+use crate::reverse::History;
+use crate::trap::{Exception, Trap};
+
+/// GDB's RISC-V register numbering: x0-x31, pc, then 32 FPRs and fcsr, then CSRs
+/// at 65 + CSR address, and the virtual `priv` register after the CSR space.
+const REGNUM_PC: usize = 32;
+const REGNUM_CSR: usize = 65;
+const REGNUM_PRIV: usize = REGNUM_CSR + 0x1000;
+
+/// Instructions executed between polls for an interrupt (Ctrl-C) from the client.
+const POLL_INTERVAL: u64 = 100_000;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGBUS: u8 = 10;
+const SIGSEGV: u8 = 11;
+
+/// Why the target last stopped, as reported by `?` and after resuming.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stop {
+    Signal(u8),
+    SoftwareBreakpoint,
+    HardwareBreakpoint,
+    Watchpoint(WatchKind, u32),
+    /// Reverse execution ran out of recorded history.
+    HistoryStart,
+    Exited(u32),
+}
+
+/// GDB Remote Serial Protocol stub serving a single hart over TCP. Execution is
+/// recorded, so the client can also step and continue backwards (`bs`, `bc`).
+pub struct GdbServer {
+    pub cpu: Cpu,
+    pub memory: Memory,
+    history: History,
+    software_breakpoints: BTreeSet<u32>,
+    hardware_breakpoints: BTreeSet<u32>,
+    last_stop: Stop,
+}
+
+/// What the connection loop should do after a packet has been handled.
+enum Action {
+    Reply(String),
+    Resume { step: bool, reverse: bool },
+    Close(Option<String>),
+}
+
+impl GdbServer {
+    pub fn new(cpu: Cpu, memory: Memory) -> Self {
+        GdbServer {
+            cpu,
+            memory,
+            history: History::new(),
+            software_breakpoints: BTreeSet::new(),
+            hardware_breakpoints: BTreeSet::new(),
+            last_stop: Stop::Signal(SIGTRAP),
+        }
+    }
+
+    /// Accept one debugger connection on `address` and serve it until it detaches or kills the target.
+    pub fn listen(&mut self, address: impl ToSocketAddrs) -> io::Result<()> {
+        let listener = TcpListener::bind(address)?;
+        let (stream, _) = listener.accept()?;
+        self.serve(stream)
+    }
+
+    pub fn serve(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut connection = Connection { pending: Vec::new() };
+        loop {
+            let packet = match connection.next_packet(&mut stream)? {
+                Incoming::Packet(packet) => packet,
+                Incoming::Interrupt => {
+                    self.last_stop = Stop::Signal(SIGINT);
+                    connection.send(&mut stream, &self.stop_reply())?;
+                    continue;
+                }
+                Incoming::Closed => return Ok(()),
+            };
+            match self.action(&packet) {
+                Action::Reply(reply) => connection.send(&mut stream, &reply)?,
+                Action::Resume { step, reverse } => {
+                    let reply = self.resume(step, reverse, &mut || connection.poll_interrupt(&mut stream));
+                    connection.send(&mut stream, &reply)?;
+                }
+                Action::Close(reply) => {
+                    if let Some(reply) = reply {
+                        connection.send(&mut stream, &reply)?;
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Handle one packet payload (without `$` and checksum) and return the reply payload,
+    /// or `None` if the session ends without a reply. Resuming runs to completion.
+    pub fn handle_packet(&mut self, packet: &str) -> Option<String> {
+        match self.action(packet) {
+            Action::Reply(reply) => Some(reply),
+            Action::Resume { step, reverse } => Some(self.resume(step, reverse, &mut || false)),
+            Action::Close(reply) => reply,
+        }
+    }
+
+    fn action(&mut self, packet: &str) -> Action {
+        let reply = |text: &str| Action::Reply(text.to_string());
+        let Some(command) = packet.chars().next() else { return reply("") };
+        let arguments = &packet[1..];
+        match command {
+            '?' => Action::Reply(self.stop_reply()),
+            'g' => Action::Reply((0..=REGNUM_PC).map(|regnum| hex_word(self.read_register(regnum).unwrap_or(0))).collect()),
+            'G' => {
+                for (regnum, chunk) in arguments.as_bytes().chunks(8).take(REGNUM_PC + 1).enumerate() {
+                    let Some(value) = std::str::from_utf8(chunk).ok().and_then(parse_hex_word) else { return reply("E01") };
+                    self.write_register(regnum, value);
+                }
+                self.history.clear();
+                reply("OK")
+            }
+            'p' => match usize::from_str_radix(arguments, 16).ok().and_then(|regnum| self.read_register(regnum)) {
+                Some(value) => Action::Reply(hex_word(value)),
+                None => reply("E01"),
+            },
+            'P' => {
+                let Some((regnum, value)) = arguments.split_once('=') else { return reply("E01") };
+                match (usize::from_str_radix(regnum, 16), parse_hex_word(value)) {
+                    (Ok(regnum), Some(value)) if self.write_register(regnum, value) => {
+                        self.history.clear();
+                        reply("OK")
+                    }
+                    _ => reply("E01"),
+                }
+            }
+            'm' => match parse_range(arguments).and_then(|(address, length)| self.read_memory(address, length)) {
+                Some(bytes) => Action::Reply(bytes.iter().map(|byte| format!("{:02x}", byte)).collect()),
+                None => reply("E14"),
+            },
+            'M' => {
+                let Some((range, data)) = arguments.split_once(':') else { return reply("E01") };
+                let bytes = parse_hex_bytes(data);
+                match (parse_range(range), bytes) {
+                    (Some((address, length)), Some(bytes)) if bytes.len() == length as usize => {
+                        if !self.write_memory(address, &bytes) {
+                            return reply("E14");
+                        }
+                        self.history.clear();
+                        reply("OK")
+                    }
+                    _ => reply("E01"),
+                }
+            }
+            'Z' | 'z' => self.breakpoint(command == 'Z', arguments),
+            'c' | 's' => {
+                if let Some(address) = parse_hex(arguments) {
+                    self.cpu.set_pc(address);
+                    self.history.clear();
+                }
+                Action::Resume { step: command == 's', reverse: false }
+            }
+            'b' if packet == "bs" || packet == "bc" => Action::Resume { step: packet == "bs", reverse: true },
+            'v' if packet == "vCont?" => reply("vCont;c;C;s;S"),
+            'v' if packet.starts_with("vCont;") => {
+                // A single hart: the first action decides, and signals are ignored
+                let step = packet[6..].starts_with(['s', 'S']);
+                Action::Resume { step, reverse: false }
+            }
+            'v' if packet.starts_with("vKill") => Action::Close(Some("OK".to_string())),
+            'H' | 'T' => reply("OK"),
+            'D' => Action::Close(Some("OK".to_string())),
+            'k' => Action::Close(None),
+            'q' => self.query(packet),
+            _ => reply(""),
+        }
+    }
+
+    fn query(&self, packet: &str) -> Action {
+        let reply = |text: &str| Action::Reply(text.to_string());
+        if packet.starts_with("qSupported") {
+            return reply("PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+;ReverseStep+;ReverseContinue+");
+        }
+        if let Some(request) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, length)) = parse_range(request) else { return reply("E01") };
+            let xml = self.target_description();
+            let start = (offset as usize).min(xml.len());
+            let end = (start + length as usize).min(xml.len());
+            let prefix = if end == xml.len() { "l" } else { "m" };
+            return Action::Reply(format!("{}{}", prefix, &xml[start..end]));
+        }
+        match packet {
+            "qAttached" => reply("1"),
+            "qC" => reply("QC1"),
+            "qfThreadInfo" => reply("m1"),
+            "qsThreadInfo" => reply("l"),
+            _ => reply(""),
+        }
+    }
+
+    /// Target description: the RV32 core registers, every implemented CSR and `priv`.
+    pub fn target_description(&self) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n\
+             <architecture>riscv:rv32</architecture>\n<feature name=\"org.gnu.gdb.riscv.cpu\">\n",
+        );
+        for register in 0..32u8 {
+            let kind = match register {
+                1 => "code_ptr",
+                2 | 8 => "data_ptr",
+                _ => "int",
+            };
+            let _ = writeln!(xml, "<reg name=\"{}\" bitsize=\"32\" type=\"{}\" regnum=\"{}\"/>", register_name(register), kind, register);
+        }
+        let _ = writeln!(xml, "<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"{}\"/>", REGNUM_PC);
+        xml.push_str("</feature>\n<feature name=\"org.gnu.gdb.riscv.csr\">\n");
+        for csr in 0..0x1000u16 {
+            if let (Some(name), Some(_)) = (csr_name(csr), self.cpu.read_csr(csr)) {
+                let _ = writeln!(xml, "<reg name=\"{}\" bitsize=\"32\" regnum=\"{}\"/>", name, REGNUM_CSR + csr as usize);
+            }
+        }
+        xml.push_str("</feature>\n<feature name=\"org.gnu.gdb.riscv.virtual\">\n");
+        let _ = writeln!(xml, "<reg name=\"priv\" bitsize=\"32\" regnum=\"{}\"/>", REGNUM_PRIV);
+        xml.push_str("</feature>\n</target>\n");
+        xml
+    }
+
+    fn read_register(&self, regnum: usize) -> Option<u32> {
+        match regnum {
+            0..=31 => Some(self.cpu.register(regnum as u8)),
+            REGNUM_PC => Some(self.cpu.pc()),
+            REGNUM_PRIV => Some(self.cpu.privilege() as u32),
+            _ if (REGNUM_CSR..REGNUM_PRIV).contains(&regnum) => self.cpu.read_csr((regnum - REGNUM_CSR) as u16),
+            _ => None,
+        }
+    }
+
+    fn write_register(&mut self, regnum: usize, value: u32) -> bool {
+        match regnum {
+            0..=31 => self.cpu.set_register(regnum as u8, value),
+            REGNUM_PC => self.cpu.set_pc(value),
+            REGNUM_PRIV => match Privilege::from_bits(value) {
+                Some(privilege) if value < 4 => self.cpu.set_privilege(privilege),
+                _ => return false,
+            },
+            _ if (REGNUM_CSR..REGNUM_PRIV).contains(&regnum) => return self.cpu.write_csr((regnum - REGNUM_CSR) as u16, value),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Bytes from `address`, truncated at the end of memory; `None` if nothing is readable.
+    fn read_memory(&self, address: u32, length: u32) -> Option<Vec<u8>> {
+        let size = self.memory.size() as u64;
+        if length > 0 && address as u64 >= size {
+            return None;
+        }
+        let end = (address as u64 + length as u64).min(size) as u32;
+        Some((address..end).map(|addr| self.memory.load_byte(addr as usize) as u8).collect())
+    }
+
+    fn write_memory(&mut self, address: u32, bytes: &[u8]) -> bool {
+        if address as u64 + bytes.len() as u64 > self.memory.size() as u64 {
+            return false;
+        }
+        self.memory.write_bytes(address as usize, bytes);
+        self.cpu.flush_decode_cache();
+        true
+    }
+
+    /// `Z`/`z` packets: type 0 and 1 breakpoints, types 2, 3 and 4 write, read and access watchpoints.
+    fn breakpoint(&mut self, insert: bool, arguments: &str) -> Action {
+        let reply = |text: &str| Action::Reply(text.to_string());
+        let mut fields = arguments.split(',');
+        let (Some(kind), Some(address), Some(size)) = (fields.next(), fields.next().and_then(parse_hex), fields.next().and_then(parse_hex)) else {
+            return reply("E01");
+        };
+        match kind {
+            "0" | "1" => {
+                let (set, other) = if kind == "0" {
+                    (&mut self.software_breakpoints, &self.hardware_breakpoints)
+                } else {
+                    (&mut self.hardware_breakpoints, &self.software_breakpoints)
+                };
+                if insert {
+                    set.insert(address);
+                    self.cpu.add_breakpoint(address);
+                } else if set.remove(&address) && !other.contains(&address) {
+                    self.cpu.remove_breakpoint(address);
+                }
+                reply("OK")
+            }
+            "2" | "3" | "4" => {
+                let kind = match kind {
+                    "2" => WatchKind::Write,
+                    "3" => WatchKind::Read,
+                    _ => WatchKind::Access,
+                };
+                if insert {
+                    self.cpu.add_watchpoint(Watchpoint::new(address, size, kind));
+                } else {
+                    self.cpu.remove_watchpoint(address, size, kind);
+                }
+                reply("OK")
+            }
+            _ => reply(""),
+        }
+    }
+
+    fn resume(&mut self, step: bool, reverse: bool, interrupted: &mut dyn FnMut() -> bool) -> String {
+        self.last_stop = self.run(step, reverse, interrupted);
+        self.stop_reply()
+    }
+
+    fn run(&mut self, step: bool, reverse: bool, interrupted: &mut dyn FnMut() -> bool) -> Stop {
+        let chunk = if step { 1 } else { POLL_INTERVAL };
+        let mut first = true;
+        loop {
+            // Breakpoints at the starting pc were reported by the previous stop; going
+            // backwards they are checked after every step
+            if !first && !reverse && self.cpu.has_breakpoint(self.cpu.pc()) {
+                return self.breakpoint_stop(self.cpu.pc());
+            }
+            first = false;
+            let stop = if reverse {
+                self.history.reverse_for(&mut self.cpu, &mut self.memory, chunk)
+            } else {
+                self.history.run_for(&mut self.cpu, &mut self.memory, chunk)
+            };
+            match stop {
+                StopReason::InstructionLimit if step => return Stop::Signal(SIGTRAP),
+                StopReason::InstructionLimit => {}
+                StopReason::Breakpoint { pc } => return self.breakpoint_stop(pc),
+                StopReason::Halted { exit_code } => return Stop::Exited(exit_code),
+                StopReason::Trap { trap, .. } => return Stop::Signal(signal(trap)),
+                StopReason::WaitForInterrupt { .. } => return Stop::Signal(SIGTRAP),
+                StopReason::Watchpoint { address, kind, .. } => return Stop::Watchpoint(kind, address),
+                StopReason::NoHistory { .. } => return Stop::HistoryStart,
+            }
+            if interrupted() {
+                return Stop::Signal(SIGINT);
+            }
+        }
+    }
+
+    fn breakpoint_stop(&self, pc: u32) -> Stop {
+        if self.software_breakpoints.contains(&pc) {
+            Stop::SoftwareBreakpoint
+        } else if self.hardware_breakpoints.contains(&pc) {
+            Stop::HardwareBreakpoint
+        } else {
+            // An EBREAK in the guest
+            Stop::Signal(SIGTRAP)
+        }
+    }
+
+    fn stop_reply(&self) -> String {
+        match self.last_stop {
+            Stop::Signal(signal) => format!("S{:02x}", signal),
+            Stop::SoftwareBreakpoint => format!("T{:02x}swbreak:;", SIGTRAP),
+            Stop::HardwareBreakpoint => format!("T{:02x}hwbreak:;", SIGTRAP),
+            Stop::Watchpoint(WatchKind::Write, address) => format!("T{:02x}watch:{:x};", SIGTRAP, address),
+            Stop::Watchpoint(WatchKind::Read, address) => format!("T{:02x}rwatch:{:x};", SIGTRAP, address),
+            Stop::Watchpoint(WatchKind::Access, address) => format!("T{:02x}awatch:{:x};", SIGTRAP, address),
+            Stop::HistoryStart => format!("T{:02x}replaylog:begin;", SIGTRAP),
+            Stop::Exited(code) => format!("W{:02x}", code as u8),
+        }
+    }
+}
+
+/// Signal reported for a trap with no guest handler.
+fn signal(trap: Trap) -> u8 {
+    match trap {
+        Trap::Exception(Exception::IllegalInstruction(_)) => SIGILL,
+        Trap::Exception(Exception::InstructionAccessFault(_) | Exception::LoadAccessFault(_) | Exception::StoreAccessFault(_)) => SIGSEGV,
+        Trap::Exception(
+            Exception::InstructionAddressMisaligned(_) | Exception::LoadAddressMisaligned(_) | Exception::StoreAddressMisaligned(_),
+        ) => SIGBUS,
+        _ => SIGTRAP,
+    }
+}
+
+/// Registers are sent in target byte order, i.e. little-endian.
+fn hex_word(value: u32) -> String {
+    value.to_le_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex_word(text: &str) -> Option<u32> {
+    let bytes = parse_hex_bytes(text)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn parse_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok()).collect()
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+/// `address,length` in hex.
+fn parse_range(text: &str) -> Option<(u32, u32)> {
+    let (address, length) = text.split_once(',')?;
+    Some((parse_hex(address)?, parse_hex(length)?))
+}
+
+enum Incoming {
+    Packet(String),
+    Interrupt,
+    Closed,
+}
+
+/// Packet framing: `$payload#checksum`, acknowledged with `+` (or `-` to request a resend).
+struct Connection {
+    pending: Vec<u8>,
+}
+
+impl Connection {
+    fn next_packet(&mut self, stream: &mut TcpStream) -> io::Result<Incoming> {
+        loop {
+            // Drop acknowledgements and noise before the start of a packet
+            while let Some(&byte) = self.pending.first() {
+                match byte {
+                    b'$' => break,
+                    0x03 => {
+                        self.pending.remove(0);
+                        return Ok(Incoming::Interrupt);
+                    }
+                    _ => {
+                        self.pending.remove(0);
+                    }
+                }
+            }
+            if let Some(end) = self.pending.iter().position(|&byte| byte == b'#') {
+                if self.pending.len() >= end + 3 {
+                    let frame: Vec<u8> = self.pending.drain(..end + 3).collect();
+                    let payload = &frame[1..end];
+                    let checksum = std::str::from_utf8(&frame[end + 1..]).ok().and_then(|text| u8::from_str_radix(text, 16).ok());
+                    if checksum != Some(checksum_of(payload)) {
+                        stream.write_all(b"-")?;
+                        continue;
+                    }
+                    stream.write_all(b"+")?;
+                    return Ok(Incoming::Packet(String::from_utf8_lossy(payload).into_owned()));
+                }
+            }
+            let mut buffer = [0u8; 4096];
+            let count = stream.read(&mut buffer)?;
+            if count == 0 {
+                return Ok(Incoming::Closed);
+            }
+            self.pending.extend_from_slice(&buffer[..count]);
+        }
+    }
+
+    fn send(&mut self, stream: &mut TcpStream, payload: &str) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 4);
+        frame.push(b'$');
+        for &byte in payload.as_bytes() {
+            // '#', '$', '}' and '*' must be escaped inside a packet
+            if matches!(byte, b'#' | b'$' | b'}' | b'*') {
+                frame.extend_from_slice(&[b'}', byte ^ 0x20]);
+            } else {
+                frame.push(byte);
+            }
+        }
+        let checksum = checksum_of(&frame[1..]);
+        frame.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+        stream.write_all(&frame)
+    }
+
+    /// Check, without blocking, whether the client sent an interrupt (Ctrl-C) byte.
+    fn poll_interrupt(&mut self, stream: &mut TcpStream) -> bool {
+        let mut buffer = [0u8; 256];
+        if stream.set_nonblocking(true).is_ok() {
+            loop {
+                match stream.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(count) => self.pending.extend_from_slice(&buffer[..count]),
+                    Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                }
+            }
+            let _ = stream.set_nonblocking(false);
+        }
+        match self.pending.iter().position(|&byte| byte == 0x03) {
+            Some(index) => {
+                self.pending.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn checksum_of(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
@@ -0,0 +1,206 @@
+use crate::instruction::Instruction;
+use crate::trap::Trap;
+
+/// Observer for instrumentation (profilers, coverage, sanitizers), registered with
+/// [`Cpu::with_hooks`](crate::cpu::Cpu::with_hooks). Every callback defaults to doing
+/// nothing, and the CPU is generic over its hooks, so a default `Cpu` pays nothing for them.
+///
+/// Callbacks fire in program order while an instruction executes: `fetch`, `pre_execute`,
+/// then the memory, register and CSR accesses it makes (and `branch` for a conditional
+/// branch), and finally `post_execute` if it retired or `trap` if it raised an exception. An EBREAK or exit ECALL serviced by the
+/// host retires after its trap, so it gets both.
+pub trait Hooks {
+    /// Whether any callback observes something. Compiled code (see
+    /// [`Cpu::set_jit`](crate::cpu::Cpu::set_jit)) calls no hooks, so it only runs for
+    /// hooks that leave this `false`.
+    const OBSERVES: bool = true;
+
+    /// The instruction word `raw` was fetched from `pc`.
+    #[inline]
+    fn fetch(&mut self, _pc: u32, _raw: u32) {}
+
+    /// The instruction at `pc` decoded and is about to execute.
+    #[inline]
+    fn pre_execute(&mut self, _pc: u32, _instruction: &Instruction) {}
+
+    /// The instruction at `pc` retired; `next_pc` is the address of the next one.
+    #[inline]
+    fn post_execute(&mut self, _pc: u32, _instruction: &Instruction, _next_pc: u32) {}
+
+    /// `size` bytes were loaded from `address`; `value` is zero-extended.
+    #[inline]
+    fn memory_read(&mut self, _address: u32, _size: u32, _value: u32) {}
+
+    /// The low `size` bytes of `value` were stored to `address`.
+    #[inline]
+    fn memory_write(&mut self, _address: u32, _size: u32, _value: u32) {}
+
+    /// A general-purpose register other than x0 was written.
+    #[inline]
+    fn register_write(&mut self, _register: u8, _value: u32) {}
+
+    /// The conditional branch at `pc` went to its target if `taken`, or fell through.
+    /// A taken branch to a misaligned target traps instead.
+    #[inline]
+    fn branch(&mut self, _pc: u32, _taken: bool) {}
+
+    /// `trap` was raised at `pc`, whether or not a handler is installed to take it.
+    #[inline]
+    fn trap(&mut self, _trap: Trap, _pc: u32) {}
+
+    /// A CSR instruction read `csr`. CSRRW and CSRRWI with rd = x0 do not read.
+    #[inline]
+    fn csr_read(&mut self, _csr: u16, _value: u32) {}
+
+    /// A CSR instruction wrote `csr`, which now holds `value`.
+    #[inline]
+    fn csr_write(&mut self, _csr: u16, _value: u32) {}
+}
+
+/// The default hooks, which observe nothing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoHooks;
+
+impl Hooks for NoHooks {
+    const OBSERVES: bool = false;
+}
+
+/// Optional hooks observe only when present.
+impl<H: Hooks> Hooks for Option<H> {
+    const OBSERVES: bool = H::OBSERVES;
+
+    #[inline]
+    fn fetch(&mut self, pc: u32, raw: u32) {
+        if let Some(hooks) = self {
+            hooks.fetch(pc, raw);
+        }
+    }
+
+    #[inline]
+    fn pre_execute(&mut self, pc: u32, instruction: &Instruction) {
+        if let Some(hooks) = self {
+            hooks.pre_execute(pc, instruction);
+        }
+    }
+
+    #[inline]
+    fn post_execute(&mut self, pc: u32, instruction: &Instruction, next_pc: u32) {
+        if let Some(hooks) = self {
+            hooks.post_execute(pc, instruction, next_pc);
+        }
+    }
+
+    #[inline]
+    fn memory_read(&mut self, address: u32, size: u32, value: u32) {
+        if let Some(hooks) = self {
+            hooks.memory_read(address, size, value);
+        }
+    }
+
+    #[inline]
+    fn memory_write(&mut self, address: u32, size: u32, value: u32) {
+        if let Some(hooks) = self {
+            hooks.memory_write(address, size, value);
+        }
+    }
+
+    #[inline]
+    fn register_write(&mut self, register: u8, value: u32) {
+        if let Some(hooks) = self {
+            hooks.register_write(register, value);
+        }
+    }
+
+    #[inline]
+    fn branch(&mut self, pc: u32, taken: bool) {
+        if let Some(hooks) = self {
+            hooks.branch(pc, taken);
+        }
+    }
+
+    #[inline]
+    fn trap(&mut self, trap: Trap, pc: u32) {
+        if let Some(hooks) = self {
+            hooks.trap(trap, pc);
+        }
+    }
+
+    #[inline]
+    fn csr_read(&mut self, csr: u16, value: u32) {
+        if let Some(hooks) = self {
+            hooks.csr_read(csr, value);
+        }
+    }
+
+    #[inline]
+    fn csr_write(&mut self, csr: u16, value: u32) {
+        if let Some(hooks) = self {
+            hooks.csr_write(csr, value);
+        }
+    }
+}
+
+/// A pair of hooks observes with both, the first one first.
+impl<A: Hooks, B: Hooks> Hooks for (A, B) {
+    const OBSERVES: bool = A::OBSERVES || B::OBSERVES;
+
+    #[inline]
+    fn fetch(&mut self, pc: u32, raw: u32) {
+        self.0.fetch(pc, raw);
+        self.1.fetch(pc, raw);
+    }
+
+    #[inline]
+    fn pre_execute(&mut self, pc: u32, instruction: &Instruction) {
+        self.0.pre_execute(pc, instruction);
+        self.1.pre_execute(pc, instruction);
+    }
+
+    #[inline]
+    fn post_execute(&mut self, pc: u32, instruction: &Instruction, next_pc: u32) {
+        self.0.post_execute(pc, instruction, next_pc);
+        self.1.post_execute(pc, instruction, next_pc);
+    }
+
+    #[inline]
+    fn memory_read(&mut self, address: u32, size: u32, value: u32) {
+        self.0.memory_read(address, size, value);
+        self.1.memory_read(address, size, value);
+    }
+
+    #[inline]
+    fn memory_write(&mut self, address: u32, size: u32, value: u32) {
+        self.0.memory_write(address, size, value);
+        self.1.memory_write(address, size, value);
+    }
+
+    #[inline]
+    fn register_write(&mut self, register: u8, value: u32) {
+        self.0.register_write(register, value);
+        self.1.register_write(register, value);
+    }
+
+    #[inline]
+    fn branch(&mut self, pc: u32, taken: bool) {
+        self.0.branch(pc, taken);
+        self.1.branch(pc, taken);
+    }
+
+    #[inline]
+    fn trap(&mut self, trap: Trap, pc: u32) {
+        self.0.trap(trap, pc);
+        self.1.trap(trap, pc);
+    }
+
+    #[inline]
+    fn csr_read(&mut self, csr: u16, value: u32) {
+        self.0.csr_read(csr, value);
+        self.1.csr_read(csr, value);
+    }
+
+    #[inline]
+    fn csr_write(&mut self, csr: u16, value: u32) {
+        self.0.csr_write(csr, value);
+        self.1.csr_write(csr, value);
+    }
+}
@@ -1,41 +1,67 @@
+use std::fmt;
 
-use crate::riscv_sim::opcode::*;
+use crate::decoder::{self, DecodeError};
 
-const MASK_OPCODE: u32 = 0x7f;
-const MASK_FUNCT3: u32 = 0x7000;
-const MASK_FUNCT7: u32 = 0xfe000000;
 const MASK_RD: u32 = 0xf80;
 const MASK_RS1: u32 = 0x0f8000;
 const MASK_RS2: u32 = 0x01f00000;
-const MASK_IMM_I: u32 = 0xfff00000;
-const MASK_IMM_S: u32 = 0xfe000000;
-const MASK_IMM_B: u32 = 0x7e000000;
-const MASK_IMM_U: u32 = 0xfffff000;
-const MASK_IMM_J: u32 = 0xff000000;
+const MASK_CSR: u32 = 0xfff00000;
 
 // Define shifts for different instruction fields
-const SHIFT_OPCODE: u32 = 0;
-const SHIFT_FUNCT3: u32 = 12;
-const SHIFT_FUNCT7: u32 = 25;
 const SHIFT_RD: u32 = 7;
 const SHIFT_RS1: u32 = 15;
 const SHIFT_RS2: u32 = 20;
-const SHIFT_IMM_I: u32 = 20;
-const SHIFT_IMM_S: u32 = 25;
-const SHIFT_IMM_B: u32 = 19;
-const SHIFT_IMM_U: u32 = 12;
-const SHIFT_IMM_J: u32 = 12;
+const SHIFT_CSR: u32 = 20;
 
+/// Sign-extend the low `bit_width` bits (1 to 32) of `value`. Until the decoder table
+/// replaced the opcode switch this only cleared the bits above `bit_width`.
 pub fn sign_extend(value: u32, bit_width: u8) -> u32 {
-    let shift = 32 - bit_width ;
-    ((value << shift) ) >> shift
+    let shift = 32 - bit_width;
+    (((value << shift) as i32) >> shift) as u32
 }
 
 fn extract_field(instruction: u32, mask: u32, shift: u32) -> u32 {
     (instruction & mask) >> shift
 }
 
-#[derive(Debug)]
+fn rd(instruction: u32) -> u8 {
+    extract_field(instruction, MASK_RD, SHIFT_RD) as u8
+}
+
+fn rs1(instruction: u32) -> u8 {
+    extract_field(instruction, MASK_RS1, SHIFT_RS1) as u8
+}
+
+fn rs2(instruction: u32) -> u8 {
+    extract_field(instruction, MASK_RS2, SHIFT_RS2) as u8
+}
+
+fn insert_field(value: u32, mask: u32, shift: u32) -> u32 {
+    (value << shift) & mask
+}
+
+fn registers(rd: u8, rs1: u8, rs2: u8) -> u32 {
+    insert_field(rd as u32, MASK_RD, SHIFT_RD)
+        | insert_field(rs1 as u32, MASK_RS1, SHIFT_RS1)
+        | insert_field(rs2 as u32, MASK_RS2, SHIFT_RS2)
+}
+
+/// Instruction encoding formats, as used by the decoder table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    R,
+    I,
+    S,
+    B,
+    U,
+    J,
+    Atomic,
+    Csr,
+    Fence,
+    System,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     // R-type instructions
     Add(RTypeInstruction),
@@ -45,10 +71,20 @@ pub enum Instruction {
     Sltu(RTypeInstruction),
     Xor(RTypeInstruction),
     Srl(RTypeInstruction),
-    Sra(RTypeInstruction), 
+    Sra(RTypeInstruction),
     Or(RTypeInstruction),
     And(RTypeInstruction),
 
+    // R-type instructions (M extension)
+    Mul(RTypeInstruction),
+    Mulh(RTypeInstruction),
+    Mulhsu(RTypeInstruction),
+    Mulhu(RTypeInstruction),
+    Div(RTypeInstruction),
+    Divu(RTypeInstruction),
+    Rem(RTypeInstruction),
+    Remu(RTypeInstruction),
+
     // I-type instructions
     Addi(ITypeInstruction),
     Slti(ITypeInstruction),
@@ -65,6 +101,7 @@ pub enum Instruction {
     Lbu(ITypeInstruction),
     Lhu(ITypeInstruction),
     Jalr(ITypeInstruction),
+    FenceI(ITypeInstruction),
 
     // S-type instructions
     Sb(STypeInstruction),
@@ -85,201 +122,459 @@ pub enum Instruction {
 
     // J-type instructions
     Jal(JTypeInstruction),
-    
 
-    // Special case for undefined instructions
-    Unknown,
-}
+    // Memory ordering
+    Fence(FenceInstruction),
 
-/// Enum to represent the various instruction types
-#[derive(Debug)]
-pub enum BaseInstruction {
-    RType(RTypeInstruction),
-    IType(ITypeInstruction),
-    SType(STypeInstruction),
-    BType(BTypeInstruction),
-    UType(UTypeInstruction),
-    JType(JTypeInstruction),
-    // Add more instruction types as needed
+    // A extension
+    LrW(AtomicInstruction),
+    ScW(AtomicInstruction),
+    AmoswapW(AtomicInstruction),
+    AmoaddW(AtomicInstruction),
+    AmoxorW(AtomicInstruction),
+    AmoandW(AtomicInstruction),
+    AmoorW(AtomicInstruction),
+    AmominW(AtomicInstruction),
+    AmomaxW(AtomicInstruction),
+    AmominuW(AtomicInstruction),
+    AmomaxuW(AtomicInstruction),
+
+    // Zicsr extension; the immediate forms carry the 5-bit zimm in `rs1`
+    Csrrw(CsrInstruction),
+    Csrrs(CsrInstruction),
+    Csrrc(CsrInstruction),
+    Csrrwi(CsrInstruction),
+    Csrrsi(CsrInstruction),
+    Csrrci(CsrInstruction),
+
+    // Privileged instructions
+    Ecall,
+    Ebreak,
+    Sret,
+    Mret,
+    Wfi,
+    SfenceVma(RTypeInstruction),
 }
 
-/// R-type BaseInstruction
-#[derive(Debug)]
+/// R-type instruction operands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RTypeInstruction {
-    pub opcode: u8,
     pub rd: u8,
-    pub funct3: u8,
     pub rs1: u8,
     pub rs2: u8,
-    pub funct7: u8,
 }
 
-/// I-type BaseInstruction
-#[derive(Debug)]
+/// I-type instruction operands; `imm` is sign-extended (or the shift amount for shifts)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ITypeInstruction {
-    pub opcode: u8,
     pub rd: u8,
-    pub funct3: u8,
     pub rs1: u8,
     pub imm: i32,
 }
 
-/// S-type BaseInstruction
-#[derive(Debug)]
+/// S-type instruction operands; `imm` is sign-extended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct STypeInstruction {
-    pub opcode: u8,
-    pub funct3: u8,
     pub rs1: u8,
     pub rs2: u8,
     pub imm: i32,
 }
 
-/// B-type BaseInstruction
-#[derive(Debug)]
+/// B-type instruction operands; `imm` is the sign-extended byte offset from the branch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BTypeInstruction {
-    pub opcode: u8,
-    pub funct3: u8,
     pub rs1: u8,
     pub rs2: u8,
     pub imm: i32,
 }
 
-/// U-type BaseInstruction
-#[derive(Debug)]
+/// U-type instruction operands; `imm` holds the upper 20 bits in place (low 12 bits zero)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct UTypeInstruction {
-    pub opcode: u8,
     pub rd: u8,
     pub imm: i32,
 }
 
-/// J-type BaseInstruction
-#[derive(Debug)]
+/// J-type instruction operands; `imm` is the sign-extended byte offset from the jump
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct JTypeInstruction {
-    pub opcode: u8,
     pub rd: u8,
     pub imm: i32,
 }
 
-impl BaseInstruction {
-
-    pub fn decode_instruction_by_opcode(instruction: u32) -> BaseInstruction {
-        let opcode: u8 = extract_field(instruction, MASK_OPCODE, SHIFT_OPCODE) as u8;
-        let funct3: u8 = extract_field(instruction, MASK_FUNCT3, SHIFT_FUNCT3) as u8;
-        let funct7: u8 = extract_field(instruction, MASK_FUNCT7, SHIFT_FUNCT7) as u8;
-        let rd: u8 = extract_field(instruction, MASK_RD, SHIFT_RD) as u8;
-        let rs1: u8 = extract_field(instruction, MASK_RS1, SHIFT_RS1) as u8;
-        let rs2: u8 = extract_field(instruction, MASK_RS2, SHIFT_RS2) as u8;
-        let imm_i: i32 = extract_field(instruction, MASK_IMM_I, SHIFT_IMM_I) as i32; // Immediate for I-type
-        let imm_s: i32 = extract_field(instruction, MASK_IMM_S, SHIFT_IMM_S) as i32; // Immediate for S-type
-        let imm_b: i32 = extract_field(instruction, MASK_IMM_B, SHIFT_IMM_B) as i32; // Immediate for B-type
-        let imm_u: i32 = extract_field(instruction, MASK_IMM_U, SHIFT_IMM_U) as i32; // Immediate for U-type
-        let imm_j: i32 = sign_extend(extract_field(instruction, MASK_IMM_J, SHIFT_IMM_J), 20) as i32;  // Sign-extend for J-type
-        
-        println!("{} {} {} ", rd, rs1, rs2);
-
-        match opcode {
-            OPCODE_LUI | OPCODE_AUIPC => BaseInstruction::UType(UTypeInstruction { opcode, rd, imm: imm_u }),
-            OPCODE_JAL => BaseInstruction::JType(JTypeInstruction { opcode, rd, imm: imm_j }),
-            OPCODE_JALR => BaseInstruction::IType(ITypeInstruction { opcode, rd, funct3, rs1, imm: imm_i }),
-            // | OPCODE_BNE | OPCODE_BLT | OPCODE_BGE | OPCODE_BLTU | OPCODE_BGEU
-            OPCODE_BEQ => {
-                BaseInstruction::BType(BTypeInstruction { opcode, funct3, rs1, rs2, imm: imm_b })
-            }
-            // | OPCODE_LH | OPCODE_LW | OPCODE_LBU | OPCODE_LHU
-            OPCODE_LB => {
-                BaseInstruction::IType(ITypeInstruction { opcode, rd, funct3, rs1, imm: imm_i })
-            }
-            // |  OPCODE_SH | OPCODE_SW
-            OPCODE_SB => {
-                BaseInstruction::SType(STypeInstruction { opcode, funct3, rs1, rs2, imm: imm_s })
-            }
-            // | OPCODE_SLTI | OPCODE_SLTIU | OPCODE_XORI | OPCODE_ORI | OPCODE_ANDI | OPCODE_SLLI | OPCODE_SRLI | OPCODE_SRAI
-            OPCODE_ADDI => {
-                BaseInstruction::IType(ITypeInstruction { opcode, rd, funct3, rs1, imm: imm_i })
-            }
-            // | OPCODE_SUB | OPCODE_SLL | OPCODE_SLT | OPCODE_SLTU | OPCODE_XOR | OPCODE_SRL | OPCODE_SRA | OPCODE_OR | OPCODE_AND
-            OPCODE_ADD => {
-                BaseInstruction::RType(RTypeInstruction { opcode, rd, funct3, rs1, rs2, funct7 })
-            }
-            _ => unimplemented!("Unknown opcode: {}", opcode),
+/// A extension operands; `rs2` is always zero for LR.W
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtomicInstruction {
+    pub rd: u8,
+    pub rs1: u8,
+    pub rs2: u8,
+    pub aq: bool,
+    pub rl: bool,
+}
+
+/// Zicsr operands; `rs1` is the zero-extended immediate for the CSRR*I forms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsrInstruction {
+    pub rd: u8,
+    pub rs1: u8,
+    pub csr: u16,
+}
+
+/// FENCE operands; `pred` and `succ` are the IORW bit sets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FenceInstruction {
+    pub rd: u8,
+    pub rs1: u8,
+    pub fm: u8,
+    pub pred: u8,
+    pub succ: u8,
+}
+
+impl RTypeInstruction {
+    pub fn from_word(instruction: u32) -> Self {
+        Self { rd: rd(instruction), rs1: rs1(instruction), rs2: rs2(instruction) }
+    }
+
+    pub fn encode(&self) -> u32 {
+        registers(self.rd, self.rs1, self.rs2)
+    }
+}
+
+impl ITypeInstruction {
+    pub fn from_word(instruction: u32) -> Self {
+        Self { rd: rd(instruction), rs1: rs1(instruction), imm: (instruction as i32) >> 20 }
+    }
+
+    /// Shift-immediate form: `imm` holds the 5-bit shift amount.
+    pub fn from_word_shamt(instruction: u32) -> Self {
+        Self { rd: rd(instruction), rs1: rs1(instruction), imm: rs2(instruction) as i32 }
+    }
+
+    pub fn encode(&self) -> u32 {
+        registers(self.rd, self.rs1, 0) | (self.imm as u32) << 20
+    }
+
+    /// Shift-immediate form: only the low 5 bits of `imm` are encoded.
+    pub fn encode_shamt(&self) -> u32 {
+        registers(self.rd, self.rs1, 0) | insert_field(self.imm as u32, MASK_RS2, SHIFT_RS2)
+    }
+}
+
+impl STypeInstruction {
+    pub fn from_word(instruction: u32) -> Self {
+        let imm = ((instruction & 0xfe000000) as i32 >> 20) | ((instruction >> 7) & 0x1f) as i32;
+        Self { rs1: rs1(instruction), rs2: rs2(instruction), imm }
+    }
+
+    pub fn encode(&self) -> u32 {
+        let imm = self.imm as u32;
+        registers(0, self.rs1, self.rs2) | (imm & 0x1f) << 7 | ((imm >> 5) & 0x7f) << 25
+    }
+}
+
+impl BTypeInstruction {
+    pub fn from_word(instruction: u32) -> Self {
+        let imm = ((instruction as i32 >> 31) << 12)
+            | (((instruction >> 7) & 0x1) << 11) as i32
+            | (((instruction >> 25) & 0x3f) << 5) as i32
+            | (((instruction >> 8) & 0xf) << 1) as i32;
+        Self { rs1: rs1(instruction), rs2: rs2(instruction), imm }
+    }
+
+    pub fn encode(&self) -> u32 {
+        let imm = self.imm as u32;
+        registers(0, self.rs1, self.rs2)
+            | ((imm >> 12) & 0x1) << 31
+            | ((imm >> 5) & 0x3f) << 25
+            | ((imm >> 1) & 0xf) << 8
+            | ((imm >> 11) & 0x1) << 7
+    }
+}
+
+impl UTypeInstruction {
+    pub fn from_word(instruction: u32) -> Self {
+        Self { rd: rd(instruction), imm: (instruction & 0xfffff000) as i32 }
+    }
+
+    pub fn encode(&self) -> u32 {
+        registers(self.rd, 0, 0) | (self.imm as u32 & 0xfffff000)
+    }
+}
+
+impl JTypeInstruction {
+    pub fn from_word(instruction: u32) -> Self {
+        let imm = ((instruction as i32 >> 31) << 20)
+            | (instruction & 0xff000) as i32
+            | (((instruction >> 20) & 0x1) << 11) as i32
+            | (((instruction >> 21) & 0x3ff) << 1) as i32;
+        Self { rd: rd(instruction), imm }
+    }
+
+    pub fn encode(&self) -> u32 {
+        let imm = self.imm as u32;
+        registers(self.rd, 0, 0)
+            | ((imm >> 20) & 0x1) << 31
+            | ((imm >> 1) & 0x3ff) << 21
+            | ((imm >> 11) & 0x1) << 20
+            | (imm & 0xff000)
+    }
+}
+
+impl AtomicInstruction {
+    pub fn from_word(instruction: u32) -> Self {
+        Self {
+            rd: rd(instruction),
+            rs1: rs1(instruction),
+            rs2: rs2(instruction),
+            aq: instruction & (1 << 26) != 0,
+            rl: instruction & (1 << 25) != 0,
+        }
+    }
+
+    pub fn encode(&self) -> u32 {
+        registers(self.rd, self.rs1, self.rs2) | (self.aq as u32) << 26 | (self.rl as u32) << 25
+    }
+}
+
+impl CsrInstruction {
+    pub fn from_word(instruction: u32) -> Self {
+        Self {
+            rd: rd(instruction),
+            rs1: rs1(instruction),
+            csr: extract_field(instruction, MASK_CSR, SHIFT_CSR) as u16,
         }
     }
-    
+
+    pub fn encode(&self) -> u32 {
+        registers(self.rd, self.rs1, 0) | insert_field(self.csr as u32, MASK_CSR, SHIFT_CSR)
+    }
+}
+
+impl FenceInstruction {
+    pub fn from_word(instruction: u32) -> Self {
+        Self {
+            rd: rd(instruction),
+            rs1: rs1(instruction),
+            fm: (instruction >> 28) as u8,
+            pred: ((instruction >> 24) & 0xf) as u8,
+            succ: ((instruction >> 20) & 0xf) as u8,
+        }
+    }
+
+    pub fn encode(&self) -> u32 {
+        registers(self.rd, self.rs1, 0)
+            | ((self.fm & 0xf) as u32) << 28
+            | ((self.pred & 0xf) as u32) << 24
+            | ((self.succ & 0xf) as u32) << 20
+    }
 }
 
+/// An instruction with operands its encoding cannot hold: a register above x31, an
+/// immediate out of range or misaligned, or a field the format fixes (the rd of
+/// SFENCE.VMA, the rs2 of LR.W).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeError(pub Instruction);
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} has operands out of range for `{}`", self.0, self.0.mnemonic())
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
 impl Instruction {
-    pub fn decode_instruction_funct3_funct7(instr: BaseInstruction) -> Instruction {
-        match instr {
-            BaseInstruction::RType(r) => match (r.funct3, r.funct7) {
-                (FUNCT3_ADD, FUNCT7_ALL) => Instruction::Add(r),
-                (FUNCT3_SUB, FUNCT7_SUB) => Instruction::Sub(r),
-                (FUNCT3_SLL, FUNCT7_ALL) => Instruction::Sll(r),
-                (FUNCT3_SLT, FUNCT7_ALL) => Instruction::Slt(r),
-                (FUNCT3_SLTU, FUNCT7_ALL) => Instruction::Sltu(r),
-                (FUNCT3_XOR, FUNCT7_ALL) => Instruction::Xor(r),
-                (FUNCT3_SRL, FUNCT7_ALL) => Instruction::Srl(r),
-                (FUNCT3_SRA, FUNCT7_SRA) => Instruction::Sra(r),
-                (FUNCT3_OR, FUNCT7_ALL) => Instruction::Or(r),
-                (FUNCT3_AND, FUNCT7_ALL) => Instruction::And(r),
-                _ => Instruction::Unknown,
-            },
-            BaseInstruction::IType(i) => 
-            match i.opcode {
-                // | OPCODE_SLTI | OPCODE_SLTIU | OPCODE_XORI | OPCODE_ORI | OPCODE_ANDI | OPCODE_SLLI | OPCODE_SRLI | OPCODE_SRAI
-                OPCODE_ADDI =>
-                    match i.funct3 {
-                        FUNCT3_ADD => Instruction::Addi(i),
-                        FUNCT3_SLL => Instruction::Slli(i),
-                        FUNCT3_SLT => Instruction::Slti(i),
-                        FUNCT3_SLTU => Instruction::Sltiu(i),
-                        FUNCT3_XOR => Instruction::Xori(i),
-                        FUNCT3_OR => Instruction::Ori(i),
-                        FUNCT3_AND => Instruction::Andi(i),
-                        OPCODE_JALR => Instruction::Jalr(i),
-                        _ => Instruction::Unknown,
-                    },
-                // | OPCODE_LH | OPCODE_LW | OPCODE_LBU | OPCODE_LHU
-                OPCODE_LB =>
-                    match i.funct3 {
-                        FUNCT3_LB => Instruction::Lb(i),
-                        FUNCT3_LH => Instruction::Lh(i),
-                        FUNCT3_LW => Instruction::Lw(i),
-                        FUNCT3_LBU => Instruction::Lbu(i),
-                        FUNCT3_LHU => Instruction::Lhu(i),
-                        _ => Instruction::Unknown,
-                    },
-                OPCODE_JALR => Instruction::Jalr(i),
-                _ => Instruction::Unknown,    
-            },
-
-            BaseInstruction::SType(s) => match s.funct3 {
-                FUNCT3_SB => Instruction::Sb(s),
-                FUNCT3_SH => Instruction::Sh(s),
-                FUNCT3_SW => Instruction::Sw(s),
-                _ => Instruction::Unknown,
-            },
-
-            BaseInstruction::BType(b) => match b.funct3 {
-                FUNCT3_BEQ => Instruction::Beq(b),
-                FUNCT3_BNE => Instruction::Bne(b),
-                FUNCT3_BLT => Instruction::Blt(b),
-                FUNCT3_BGE => Instruction::Bge(b),
-                FUNCT3_BLTU => Instruction::Bltu(b),
-                FUNCT3_BGEU => Instruction::Bgeu(b),
-                _ => Instruction::Unknown,
-            },
-            BaseInstruction::UType(u) => match u.opcode {
-                OPCODE_LUI => Instruction::Lui(u),
-                OPCODE_AUIPC => Instruction::Auipc(u),
-                _ => Instruction::Unknown,
-            },
-            BaseInstruction::JType(j) => match j.opcode {
-                OPCODE_JAL => Instruction::Jal(j),
-                _ => Instruction::Unknown,
-            },
+    /// Decode a 32-bit instruction word. See [`decoder::decode`].
+    pub fn decode(instruction: u32) -> Result<Instruction, DecodeError> {
+        decoder::decode(instruction)
+    }
+
+    /// Encode back to a 32-bit instruction word, so that
+    /// `Instruction::decode(i.encode()?) == Ok(i)`. Fails if an operand would not survive
+    /// the round trip.
+    pub fn encode(&self) -> Result<u32, EncodeError> {
+        let pattern = decoder::pattern(self.mnemonic())
+            .expect("every instruction has a decoder table entry");
+        let operands = match *self {
+            Instruction::Add(r) | Instruction::Sub(r) | Instruction::Sll(r) | Instruction::Slt(r)
+            | Instruction::Sltu(r) | Instruction::Xor(r) | Instruction::Srl(r) | Instruction::Sra(r)
+            | Instruction::Or(r) | Instruction::And(r) | Instruction::Mul(r) | Instruction::Mulh(r)
+            | Instruction::Mulhsu(r) | Instruction::Mulhu(r) | Instruction::Div(r) | Instruction::Divu(r)
+            | Instruction::Rem(r) | Instruction::Remu(r) | Instruction::SfenceVma(r) => r.encode(),
+
+            Instruction::Slli(i) | Instruction::Srli(i) | Instruction::Srai(i) => i.encode_shamt(),
+            Instruction::Addi(i) | Instruction::Slti(i) | Instruction::Sltiu(i) | Instruction::Xori(i)
+            | Instruction::Ori(i) | Instruction::Andi(i) | Instruction::Lb(i) | Instruction::Lh(i)
+            | Instruction::Lw(i) | Instruction::Lbu(i) | Instruction::Lhu(i) | Instruction::Jalr(i)
+            | Instruction::FenceI(i) => i.encode(),
+
+            Instruction::Sb(s) | Instruction::Sh(s) | Instruction::Sw(s) => s.encode(),
+
+            Instruction::Beq(b) | Instruction::Bne(b) | Instruction::Blt(b) | Instruction::Bge(b)
+            | Instruction::Bltu(b) | Instruction::Bgeu(b) => b.encode(),
+
+            Instruction::Lui(u) | Instruction::Auipc(u) => u.encode(),
+            Instruction::Jal(j) => j.encode(),
+            Instruction::Fence(f) => f.encode(),
+
+            Instruction::LrW(a) | Instruction::ScW(a) | Instruction::AmoswapW(a) | Instruction::AmoaddW(a)
+            | Instruction::AmoxorW(a) | Instruction::AmoandW(a) | Instruction::AmoorW(a)
+            | Instruction::AmominW(a) | Instruction::AmomaxW(a) | Instruction::AmominuW(a)
+            | Instruction::AmomaxuW(a) => a.encode(),
+
+            Instruction::Csrrw(c) | Instruction::Csrrs(c) | Instruction::Csrrc(c) | Instruction::Csrrwi(c)
+            | Instruction::Csrrsi(c) | Instruction::Csrrci(c) => c.encode(),
+
+            Instruction::Ecall | Instruction::Ebreak | Instruction::Sret | Instruction::Mret
+            | Instruction::Wfi => 0,
+        };
+        let word = pattern.bits | (operands & !pattern.mask);
+        if decoder::decode(word) != Ok(*self) {
+            return Err(EncodeError(*self));
         }
+        Ok(word)
     }
-    
 
-            
+    /// Integer registers read as rs1 and rs2. Unused fields (including the FENCE
+    /// fields and CSR immediates) are `None`.
+    pub fn sources(&self) -> [Option<u8>; 2] {
+        match *self {
+            Instruction::Add(r) | Instruction::Sub(r) | Instruction::Sll(r) | Instruction::Slt(r)
+            | Instruction::Sltu(r) | Instruction::Xor(r) | Instruction::Srl(r) | Instruction::Sra(r)
+            | Instruction::Or(r) | Instruction::And(r) | Instruction::Mul(r) | Instruction::Mulh(r)
+            | Instruction::Mulhsu(r) | Instruction::Mulhu(r) | Instruction::Div(r) | Instruction::Divu(r)
+            | Instruction::Rem(r) | Instruction::Remu(r) | Instruction::SfenceVma(r) => [Some(r.rs1), Some(r.rs2)],
+
+            Instruction::Addi(i) | Instruction::Slti(i) | Instruction::Sltiu(i) | Instruction::Xori(i)
+            | Instruction::Ori(i) | Instruction::Andi(i) | Instruction::Slli(i) | Instruction::Srli(i)
+            | Instruction::Srai(i) | Instruction::Lb(i) | Instruction::Lh(i) | Instruction::Lw(i)
+            | Instruction::Lbu(i) | Instruction::Lhu(i) | Instruction::Jalr(i) => [Some(i.rs1), None],
+
+            Instruction::Sb(s) | Instruction::Sh(s) | Instruction::Sw(s) => [Some(s.rs1), Some(s.rs2)],
+
+            Instruction::Beq(b) | Instruction::Bne(b) | Instruction::Blt(b) | Instruction::Bge(b)
+            | Instruction::Bltu(b) | Instruction::Bgeu(b) => [Some(b.rs1), Some(b.rs2)],
+
+            Instruction::LrW(a) => [Some(a.rs1), None],
+            Instruction::ScW(a) | Instruction::AmoswapW(a) | Instruction::AmoaddW(a) | Instruction::AmoxorW(a)
+            | Instruction::AmoandW(a) | Instruction::AmoorW(a) | Instruction::AmominW(a) | Instruction::AmomaxW(a)
+            | Instruction::AmominuW(a) | Instruction::AmomaxuW(a) => [Some(a.rs1), Some(a.rs2)],
+
+            Instruction::Csrrw(c) | Instruction::Csrrs(c) | Instruction::Csrrc(c) => [Some(c.rs1), None],
+
+            Instruction::Lui(_) | Instruction::Auipc(_) | Instruction::Jal(_) | Instruction::Fence(_)
+            | Instruction::FenceI(_) | Instruction::Csrrwi(_) | Instruction::Csrrsi(_) | Instruction::Csrrci(_)
+            | Instruction::Ecall | Instruction::Ebreak | Instruction::Sret | Instruction::Mret
+            | Instruction::Wfi => [None, None],
+        }
+    }
+
+    /// Integer register written, if any. Writes to x0 are reported as `Some(0)`.
+    pub fn destination(&self) -> Option<u8> {
+        match *self {
+            Instruction::Add(r) | Instruction::Sub(r) | Instruction::Sll(r) | Instruction::Slt(r)
+            | Instruction::Sltu(r) | Instruction::Xor(r) | Instruction::Srl(r) | Instruction::Sra(r)
+            | Instruction::Or(r) | Instruction::And(r) | Instruction::Mul(r) | Instruction::Mulh(r)
+            | Instruction::Mulhsu(r) | Instruction::Mulhu(r) | Instruction::Div(r) | Instruction::Divu(r)
+            | Instruction::Rem(r) | Instruction::Remu(r) => Some(r.rd),
+
+            Instruction::Addi(i) | Instruction::Slti(i) | Instruction::Sltiu(i) | Instruction::Xori(i)
+            | Instruction::Ori(i) | Instruction::Andi(i) | Instruction::Slli(i) | Instruction::Srli(i)
+            | Instruction::Srai(i) | Instruction::Lb(i) | Instruction::Lh(i) | Instruction::Lw(i)
+            | Instruction::Lbu(i) | Instruction::Lhu(i) | Instruction::Jalr(i) => Some(i.rd),
+
+            Instruction::Lui(u) | Instruction::Auipc(u) => Some(u.rd),
+            Instruction::Jal(j) => Some(j.rd),
+
+            Instruction::LrW(a) | Instruction::ScW(a) | Instruction::AmoswapW(a) | Instruction::AmoaddW(a)
+            | Instruction::AmoxorW(a) | Instruction::AmoandW(a) | Instruction::AmoorW(a)
+            | Instruction::AmominW(a) | Instruction::AmomaxW(a) | Instruction::AmominuW(a)
+            | Instruction::AmomaxuW(a) => Some(a.rd),
+
+            Instruction::Csrrw(c) | Instruction::Csrrs(c) | Instruction::Csrrc(c) | Instruction::Csrrwi(c)
+            | Instruction::Csrrsi(c) | Instruction::Csrrci(c) => Some(c.rd),
+
+            Instruction::Sb(_) | Instruction::Sh(_) | Instruction::Sw(_) | Instruction::Beq(_)
+            | Instruction::Bne(_) | Instruction::Blt(_) | Instruction::Bge(_) | Instruction::Bltu(_)
+            | Instruction::Bgeu(_) | Instruction::Fence(_) | Instruction::FenceI(_) | Instruction::SfenceVma(_)
+            | Instruction::Ecall | Instruction::Ebreak | Instruction::Sret | Instruction::Mret
+            | Instruction::Wfi => None,
+        }
+    }
+
+    /// Assembly mnemonic, as listed in the decoder table.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Add(_) => "add",
+            Instruction::Sub(_) => "sub",
+            Instruction::Sll(_) => "sll",
+            Instruction::Slt(_) => "slt",
+            Instruction::Sltu(_) => "sltu",
+            Instruction::Xor(_) => "xor",
+            Instruction::Srl(_) => "srl",
+            Instruction::Sra(_) => "sra",
+            Instruction::Or(_) => "or",
+            Instruction::And(_) => "and",
+            Instruction::Mul(_) => "mul",
+            Instruction::Mulh(_) => "mulh",
+            Instruction::Mulhsu(_) => "mulhsu",
+            Instruction::Mulhu(_) => "mulhu",
+            Instruction::Div(_) => "div",
+            Instruction::Divu(_) => "divu",
+            Instruction::Rem(_) => "rem",
+            Instruction::Remu(_) => "remu",
+            Instruction::Addi(_) => "addi",
+            Instruction::Slti(_) => "slti",
+            Instruction::Sltiu(_) => "sltiu",
+            Instruction::Xori(_) => "xori",
+            Instruction::Ori(_) => "ori",
+            Instruction::Andi(_) => "andi",
+            Instruction::Slli(_) => "slli",
+            Instruction::Srli(_) => "srli",
+            Instruction::Srai(_) => "srai",
+            Instruction::Lb(_) => "lb",
+            Instruction::Lh(_) => "lh",
+            Instruction::Lw(_) => "lw",
+            Instruction::Lbu(_) => "lbu",
+            Instruction::Lhu(_) => "lhu",
+            Instruction::Jalr(_) => "jalr",
+            Instruction::FenceI(_) => "fence.i",
+            Instruction::Sb(_) => "sb",
+            Instruction::Sh(_) => "sh",
+            Instruction::Sw(_) => "sw",
+            Instruction::Beq(_) => "beq",
+            Instruction::Bne(_) => "bne",
+            Instruction::Blt(_) => "blt",
+            Instruction::Bge(_) => "bge",
+            Instruction::Bltu(_) => "bltu",
+            Instruction::Bgeu(_) => "bgeu",
+            Instruction::Lui(_) => "lui",
+            Instruction::Auipc(_) => "auipc",
+            Instruction::Jal(_) => "jal",
+            Instruction::Fence(_) => "fence",
+            Instruction::LrW(_) => "lr.w",
+            Instruction::ScW(_) => "sc.w",
+            Instruction::AmoswapW(_) => "amoswap.w",
+            Instruction::AmoaddW(_) => "amoadd.w",
+            Instruction::AmoxorW(_) => "amoxor.w",
+            Instruction::AmoandW(_) => "amoand.w",
+            Instruction::AmoorW(_) => "amoor.w",
+            Instruction::AmominW(_) => "amomin.w",
+            Instruction::AmomaxW(_) => "amomax.w",
+            Instruction::AmominuW(_) => "amominu.w",
+            Instruction::AmomaxuW(_) => "amomaxu.w",
+            Instruction::Csrrw(_) => "csrrw",
+            Instruction::Csrrs(_) => "csrrs",
+            Instruction::Csrrc(_) => "csrrc",
+            Instruction::Csrrwi(_) => "csrrwi",
+            Instruction::Csrrsi(_) => "csrrsi",
+            Instruction::Csrrci(_) => "csrrci",
+            Instruction::Ecall => "ecall",
+            Instruction::Ebreak => "ebreak",
+            Instruction::Sret => "sret",
+            Instruction::Mret => "mret",
+            Instruction::Wfi => "wfi",
+            Instruction::SfenceVma(_) => "sfence.vma",
+        }
+    }
 }
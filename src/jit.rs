@@ -0,0 +1,570 @@
+//! x86-64 code generation for hot translated blocks.
+//!
+//! Compiled code keeps the guest registers in the CPU's register array and addresses
+//! memory directly for loads, after checking alignment and bounds. Anything it does not
+//! handle leaves through a side exit before the instruction, and the interpreter carries
+//! on from there: unsupported instructions (division), loads that would fault and jumps
+//! to misaligned targets, so traps are raised by the interpreter exactly as without
+//! compilation. Stores call back into the CPU so that they invalidate cached code like
+//! any other store.
+//!
+//! A block ending in a branch, a JAL or a fall-through jumps to the compiled block at
+//! its destination through a slot in a writable page next to the code; the slot leads
+//! back to the interpreter until that block is compiled and linked in. A count of
+//! retired instructions kept in rbp stops the chain before a block would exceed the
+//! budget it was called with.
+
+use std::ffi::c_void;
+
+use crate::block::{AluOp, Block, Condition, Op};
+
+/// Executions of a block before it is compiled.
+pub const JIT_THRESHOLD: u32 = 32;
+
+/// Whether compiled code can run on this host.
+pub const SUPPORTED: bool = cfg!(all(target_arch = "x86_64", target_os = "linux"));
+
+/// Results of a [`StoreHelper`].
+pub const STORE_OK: u32 = 0;
+/// The store faulted and did not happen.
+pub const STORE_FAULT: u32 = 1;
+/// The store overwrote translated code, so the block must not continue.
+pub const STORE_CODE_WRITTEN: u32 = 2;
+
+/// Called by compiled code for every store with the CPU and memory pointers it was
+/// given, the address, the value and the size in bytes.
+pub type StoreHelper = extern "C" fn(*mut c_void, *mut c_void, u32, u32, u32) -> u32;
+
+/// Why compiled code returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// At a block boundary: an indirect jump, an unlinked successor or the budget.
+    Boundary,
+    /// Before an instruction the interpreter has to execute.
+    Interpret,
+    /// After a store to translated code.
+    CodeWritten,
+}
+
+const EXIT_INTERPRET: u64 = 1 << 32;
+const EXIT_CODE_WRITTEN: u64 = 2 << 32;
+
+#[repr(C)]
+struct Return {
+    retired: u64,
+    // Next pc, with the reason in the upper half
+    exit: u64,
+}
+
+// registers, memory contents, memory size, CPU, memory object, budget
+type Entry = unsafe extern "C" fn(*mut u32, *mut u8, u64, *mut c_void, *mut c_void, u64) -> Return;
+
+/// A block compiled to host code.
+pub struct NativeBlock {
+    code: ExecutableCode,
+    // Entry point used by chained blocks, past the prologue
+    chained: usize,
+    // Destination of each link slot
+    exits: Vec<u32>,
+}
+
+impl NativeBlock {
+    /// Run the block and the blocks chained to it, retiring at most `budget`
+    /// instructions. Returns the number retired, the next pc and why it stopped.
+    ///
+    /// # Safety
+    ///
+    /// `registers` must point to the 32 guest registers, `memory` to `size` bytes of
+    /// guest memory that the store helper also writes through `memory_object`, and
+    /// `cpu` and `memory_object` must be what the store helper of every chained block
+    /// expects. Nothing else may access them
+    /// during the call, and no linked block may have been dropped.
+    pub unsafe fn call(
+        &self,
+        registers: *mut u32,
+        memory: *mut u8,
+        size: usize,
+        cpu: *mut c_void,
+        memory_object: *mut c_void,
+        budget: u64,
+    ) -> (u64, u32, Exit) {
+        let entry: Entry = std::mem::transmute(self.code.pointer);
+        let result = entry(registers, memory, size as u64, cpu, memory_object, budget);
+        let exit = match result.exit >> 32 {
+            0 => Exit::Boundary,
+            1 => Exit::Interpret,
+            _ => Exit::CodeWritten,
+        };
+        (result.retired, result.exit as u32, exit)
+    }
+
+    /// Destinations of the block's link slots, by slot.
+    pub fn exits(&self) -> &[u32] {
+        &self.exits
+    }
+
+    /// Make link slot `slot` continue straight into `next`, which must start at the
+    /// slot's destination and outlive this block's use.
+    pub fn link(&self, slot: usize, next: &NativeBlock) {
+        let address = next.code.pointer as usize + next.chained;
+        // Safety: the slots are in the writable page after the code
+        unsafe { self.code.slot(slot).write(address as u64) };
+    }
+}
+
+/// Compile `block`, or `None` if this host cannot run compiled code or executable
+/// memory could not be allocated.
+pub fn compile(block: &Block, store: StoreHelper) -> Option<NativeBlock> {
+    if !SUPPORTED {
+        return None;
+    }
+    let generated = generate(block, store);
+    let code = ExecutableCode::new(&generated.code, generated.slots.len())?;
+    for (slot, &(_, stub)) in generated.slots.iter().enumerate() {
+        // Safety: as in `NativeBlock::link`
+        unsafe { code.slot(slot).write(code.pointer as u64 + stub as u64) };
+    }
+    let exits = generated.slots.iter().map(|&(target, _)| target).collect();
+    Some(NativeBlock { code, chained: generated.chained, exits })
+}
+
+struct Generated {
+    code: Vec<u8>,
+    chained: usize,
+    // Destination and unlinked stub of each slot
+    slots: Vec<(u32, usize)>,
+}
+
+fn generate(block: &Block, store: StoreHelper) -> Generated {
+    let mut e = Emitter::default();
+    // push rbp, rbx, r12-r15; sub rsp, 8 for the budget, keeping the stack 16-byte
+    // aligned for the store helper; mov [rsp], r9
+    e.bytes(&[0x55, 0x53, 0x41, 0x54, 0x41, 0x55, 0x41, 0x56, 0x41, 0x57, 0x48, 0x83, 0xEC, 0x08, 0x4C, 0x89, 0x0C, 0x24]);
+    // rbx = registers, r12 = memory, r13 = size, r14 = cpu, r15 = memory object
+    e.bytes(&[0x48, 0x89, 0xFB, 0x49, 0x89, 0xF4, 0x49, 0x89, 0xD5, 0x49, 0x89, 0xCE, 0x4D, 0x89, 0xC7]);
+    // rbp = instructions retired
+    e.bytes(&[0x31, 0xED]);
+
+    let chained = e.code.len();
+    let length = block.ops.len() as u8;
+    // lea rax, [rbp + length]; cmp rax, [rsp]
+    e.bytes(&[0x48, 0x8D, 0x45, length, 0x48, 0x3B, 0x04, 0x24]);
+    e.jump_if(0x87, Target::Exit(0, block.start as u64));
+
+    let mut pc = block.start;
+    let mut ended = false;
+    for (index, op) in block.ops.iter().enumerate() {
+        let index = index as u8;
+        let side_exit = Target::Exit(index, EXIT_INTERPRET | pc as u64);
+        let next_pc = pc.wrapping_add(4);
+        match *op {
+            Op::Li { rd, value } => e.store_immediate(rd, value),
+            Op::Alu { op, rd, rs1, rs2 } => {
+                if !e.alu(op, rd, rs1, Operand::Register(rs2)) {
+                    e.jump(side_exit);
+                    ended = true;
+                    break;
+                }
+            }
+            Op::AluImm { op, rd, rs1, imm } => {
+                e.alu(op, rd, rs1, Operand::Immediate(imm));
+            }
+            Op::Load { rd, rs1, offset, size, signed } => {
+                e.address(rs1, offset);
+                if size > 1 {
+                    // test eax, size - 1
+                    e.bytes(&[0xA9]);
+                    e.u32(size - 1);
+                    e.jump_if(0x85, side_exit);
+                }
+                // mov ecx, eax; add rcx, size; cmp rcx, r13
+                e.bytes(&[0x89, 0xC1, 0x48, 0x83, 0xC1, size as u8, 0x4C, 0x39, 0xE9]);
+                e.jump_if(0x87, side_exit);
+                // [r12 + rax]
+                match (size, signed) {
+                    (1, false) => e.bytes(&[0x41, 0x0F, 0xB6, 0x04, 0x04]),
+                    (1, true) => e.bytes(&[0x41, 0x0F, 0xBE, 0x04, 0x04]),
+                    (2, false) => e.bytes(&[0x41, 0x0F, 0xB7, 0x04, 0x04]),
+                    (2, true) => e.bytes(&[0x41, 0x0F, 0xBF, 0x04, 0x04]),
+                    _ => e.bytes(&[0x41, 0x8B, 0x04, 0x04]),
+                }
+                e.store_eax(rd);
+            }
+            Op::Store { rs1, rs2, offset, size } => {
+                e.address(rs1, offset);
+                // mov edx, eax; mov ecx, [rs2]; mov r8d, size
+                e.bytes(&[0x89, 0xC2]);
+                e.load(0x4B, rs2);
+                e.bytes(&[0x41, 0xB8]);
+                e.u32(size);
+                // mov rdi, r14; mov rsi, r15; mov rax, store; call rax
+                e.bytes(&[0x4C, 0x89, 0xF7, 0x4C, 0x89, 0xFE, 0x48, 0xB8]);
+                e.u64(store as usize as u64);
+                e.bytes(&[0xFF, 0xD0]);
+                // cmp eax, STORE_FAULT
+                e.bytes(&[0x83, 0xF8, STORE_FAULT as u8]);
+                e.jump_if(0x84, side_exit);
+                e.jump_if(0x87, Target::Exit(index + 1, EXIT_CODE_WRITTEN | next_pc as u64));
+            }
+            Op::Branch { condition, rs1, rs2, target } => {
+                e.load(0x43, rs1);
+                // cmp eax, [rs2]
+                e.bytes(&[0x3B, 0x43, 4 * rs2]);
+                let jcc = match condition {
+                    Condition::Eq => 0x84,
+                    Condition::Ne => 0x85,
+                    Condition::Lt => 0x8C,
+                    Condition::Ge => 0x8D,
+                    Condition::Ltu => 0x82,
+                    Condition::Geu => 0x83,
+                };
+                if target.is_multiple_of(4) {
+                    let taken = e.label();
+                    e.jump_if(jcc, taken);
+                    e.link(length, next_pc);
+                    e.bind(taken);
+                    e.link(length, target);
+                } else {
+                    e.jump_if(jcc, side_exit);
+                    e.link(length, next_pc);
+                }
+                ended = true;
+            }
+            Op::Jal { rd, target } => {
+                if target.is_multiple_of(4) {
+                    e.store_immediate(rd, next_pc);
+                    e.link(length, target);
+                } else {
+                    e.jump(side_exit);
+                }
+                ended = true;
+            }
+            Op::Jalr { rd, rs1, offset } => {
+                e.address(rs1, offset);
+                // and eax, -2; test al, 3
+                e.bytes(&[0x83, 0xE0, 0xFE, 0xA8, 0x03]);
+                e.jump_if(0x85, side_exit);
+                e.store_immediate(rd, next_pc);
+                // mov edx, eax; lea rax, [rbp + length]
+                e.bytes(&[0x89, 0xC2, 0x48, 0x8D, 0x45, length]);
+                e.jump(Target::Epilogue);
+                ended = true;
+            }
+        }
+        pc = next_pc;
+    }
+    if !ended {
+        e.link(length, pc);
+    }
+    let (code, slots) = e.finish();
+    Generated { code, chained, slots }
+}
+
+enum Operand {
+    Register(u8),
+    Immediate(u32),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Epilogue,
+    /// Return after `retired` instructions of this block with rdx = the value.
+    Exit(u8, u64),
+    /// A position in the code, bound later.
+    Label(usize),
+    /// The link slot at this index (a rip-relative memory operand, not a jump target).
+    Slot(usize),
+}
+
+#[derive(Default)]
+struct Emitter {
+    code: Vec<u8>,
+    // rel32 fields to patch, by offset
+    fixups: Vec<(usize, Target)>,
+    labels: Vec<Option<usize>>,
+    // Destination of each slot
+    slots: Vec<u32>,
+}
+
+impl Emitter {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn rel32(&mut self, target: Target) {
+        self.fixups.push((self.code.len(), target));
+        self.u32(0);
+    }
+
+    fn label(&mut self) -> Target {
+        self.labels.push(None);
+        Target::Label(self.labels.len() - 1)
+    }
+
+    fn bind(&mut self, label: Target) {
+        if let Target::Label(index) = label {
+            self.labels[index] = Some(self.code.len());
+        }
+    }
+
+    /// `mov r32, [rbx + 4 * register]`, with modrm 0x43 for eax and 0x4B for ecx.
+    fn load(&mut self, modrm: u8, register: u8) {
+        self.bytes(&[0x8B, modrm, 4 * register]);
+    }
+
+    /// mov [rd], eax, unless rd is x0.
+    fn store_eax(&mut self, rd: u8) {
+        if rd != 0 {
+            self.bytes(&[0x89, 0x43, 4 * rd]);
+        }
+    }
+
+    /// mov dword [rd], value, unless rd is x0.
+    fn store_immediate(&mut self, rd: u8, value: u32) {
+        if rd != 0 {
+            self.bytes(&[0xC7, 0x43, 4 * rd]);
+            self.u32(value);
+        }
+    }
+
+    /// eax = rs1 + offset
+    fn address(&mut self, rs1: u8, offset: u32) {
+        self.load(0x43, rs1);
+        self.bytes(&[0x05]);
+        self.u32(offset);
+    }
+
+    /// Retire the block's `length` instructions and continue at `target` through a
+    /// new link slot: add rbp, length; jmp [rip + slot]
+    fn link(&mut self, length: u8, target: u32) {
+        self.bytes(&[0x48, 0x83, 0xC5, length, 0xFF, 0x25]);
+        self.slots.push(target);
+        self.rel32(Target::Slot(self.slots.len() - 1));
+    }
+
+    /// rd = rs1 `op` operand; `false` if the operation is not supported.
+    fn alu(&mut self, op: AluOp, rd: u8, rs1: u8, operand: Operand) -> bool {
+        if matches!(op, AluOp::Div | AluOp::Divu | AluOp::Rem | AluOp::Remu) {
+            return false;
+        }
+        if rd == 0 {
+            return true;
+        }
+        match (op, operand) {
+            (AluOp::Mulh | AluOp::Mulhsu | AluOp::Mulhu, Operand::Register(rs2)) => {
+                // movsxd or mov for rax and rcx, imul rax, rcx, then the high half
+                let signed = |e: &mut Emitter, modrm: u8, register: u8| e.bytes(&[0x48, 0x63, modrm, 4 * register]);
+                match op {
+                    AluOp::Mulh => {
+                        signed(self, 0x43, rs1);
+                        signed(self, 0x4B, rs2);
+                    }
+                    AluOp::Mulhsu => {
+                        signed(self, 0x43, rs1);
+                        self.load(0x4B, rs2);
+                    }
+                    _ => {
+                        self.load(0x43, rs1);
+                        self.load(0x4B, rs2);
+                    }
+                }
+                self.bytes(&[0x48, 0x0F, 0xAF, 0xC1, 0x48, 0xC1, 0xE8, 0x20]);
+            }
+            (AluOp::Sll | AluOp::Srl | AluOp::Sra, operand) => {
+                let modrm = match op {
+                    AluOp::Sll => 0xE0,
+                    AluOp::Srl => 0xE8,
+                    _ => 0xF8,
+                };
+                self.load(0x43, rs1);
+                match operand {
+                    Operand::Register(rs2) => {
+                        self.load(0x4B, rs2);
+                        self.bytes(&[0xD3, modrm]);
+                    }
+                    Operand::Immediate(imm) => self.bytes(&[0xC1, modrm, (imm & 0x1F) as u8]),
+                }
+            }
+            (AluOp::Slt | AluOp::Sltu, operand) => {
+                self.load(0x43, rs1);
+                match operand {
+                    Operand::Register(rs2) => self.bytes(&[0x3B, 0x43, 4 * rs2]),
+                    Operand::Immediate(imm) => {
+                        self.bytes(&[0x3D]);
+                        self.u32(imm);
+                    }
+                }
+                // setl/setb al; movzx eax, al
+                let setcc = if op == AluOp::Slt { 0x9C } else { 0x92 };
+                self.bytes(&[0x0F, setcc, 0xC0, 0x0F, 0xB6, 0xC0]);
+            }
+            (AluOp::Mul, Operand::Register(rs2)) => {
+                self.load(0x43, rs1);
+                self.bytes(&[0x0F, 0xAF, 0x43, 4 * rs2]);
+            }
+            (_, operand) => {
+                // Opcodes of op eax, r/m32 and op eax, imm32
+                let (memory, immediate) = match op {
+                    AluOp::Add => (0x03, 0x05),
+                    AluOp::Sub => (0x2B, 0x2D),
+                    AluOp::Xor => (0x33, 0x35),
+                    AluOp::Or => (0x0B, 0x0D),
+                    AluOp::And => (0x23, 0x25),
+                    _ => return false,
+                };
+                self.load(0x43, rs1);
+                match operand {
+                    Operand::Register(rs2) => self.bytes(&[memory, 0x43, 4 * rs2]),
+                    Operand::Immediate(imm) => {
+                        self.bytes(&[immediate]);
+                        self.u32(imm);
+                    }
+                }
+            }
+        }
+        self.store_eax(rd);
+        true
+    }
+
+    fn jump(&mut self, target: Target) {
+        self.bytes(&[0xE9]);
+        self.rel32(target);
+    }
+
+    /// A jcc rel32 with the second opcode byte `condition`.
+    fn jump_if(&mut self, condition: u8, target: Target) {
+        self.bytes(&[0x0F, condition]);
+        self.rel32(target);
+    }
+
+    /// Append the epilogue and the exits and resolve references to them. Returns the
+    /// code and, for each slot, its destination and the offset of its unlinked exit.
+    fn finish(mut self) -> (Vec<u8>, Vec<(u32, usize)>) {
+        let epilogue = self.code.len();
+        // add rsp, 8; pop r15-r12, rbx, rbp; ret
+        self.bytes(&[0x48, 0x83, 0xC4, 0x08, 0x41, 0x5F, 0x41, 0x5E, 0x41, 0x5D, 0x41, 0x5C, 0x5B, 0x5D, 0xC3]);
+        let mut exits: Vec<((u8, u64), usize)> = Vec::new();
+        let mut exit = |e: &mut Emitter, retired: u8, value: u64| {
+            if let Some(&(_, position)) = exits.iter().find(|(exit, _)| *exit == (retired, value)) {
+                return position;
+            }
+            // lea rax, [rbp + retired]; mov rdx, value; jmp epilogue
+            let position = e.code.len();
+            e.bytes(&[0x48, 0x8D, 0x45, retired, 0x48, 0xBA]);
+            e.u64(value);
+            e.bytes(&[0xE9]);
+            let rel = epilogue as i64 - (e.code.len() as i64 + 4);
+            e.u32(rel as i32 as u32);
+            exits.push(((retired, value), position));
+            position
+        };
+        // Slots start out leading back to the interpreter, with the block retired
+        let slots: Vec<(u32, usize)> =
+            std::mem::take(&mut self.slots).into_iter().map(|target| (target, exit(&mut self, 0, target as u64))).collect();
+        // Every exit stub goes before the slots, which start on the page after the code
+        let fixups = std::mem::take(&mut self.fixups);
+        for &(_, target) in &fixups {
+            if let Target::Exit(retired, value) = target {
+                exit(&mut self, retired, value);
+            }
+        }
+        let data = self.code.len().next_multiple_of(PAGE_SIZE);
+        for (offset, target) in fixups {
+            let destination = match target {
+                Target::Epilogue => epilogue,
+                Target::Exit(retired, value) => exit(&mut self, retired, value),
+                Target::Label(index) => self.labels[index].expect("unbound label"),
+                Target::Slot(slot) => data + 8 * slot,
+            };
+            let rel = destination as i64 - (offset as i64 + 4);
+            self.code[offset..offset + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+        }
+        (self.code, slots)
+    }
+}
+
+const PAGE_SIZE: usize = 4096;
+
+/// Executable code followed by a writable page of link slots, unmapped on drop.
+struct ExecutableCode {
+    pointer: *mut c_void,
+    // Offset of the slots
+    data: usize,
+    length: usize,
+}
+
+// The code is immutable once mapped and the slots are only written between runs
+unsafe impl Send for ExecutableCode {}
+unsafe impl Sync for ExecutableCode {}
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+mod sys {
+    use std::ffi::c_void;
+
+    pub const PROT_READ: i32 = 1;
+    pub const PROT_WRITE: i32 = 2;
+    pub const PROT_EXEC: i32 = 4;
+    pub const MAP_PRIVATE: i32 = 0x02;
+    pub const MAP_ANONYMOUS: i32 = 0x20;
+
+    extern "C" {
+        pub fn mmap(address: *mut c_void, length: usize, protection: i32, flags: i32, fd: i32, offset: i64) -> *mut c_void;
+        pub fn mprotect(address: *mut c_void, length: usize, protection: i32) -> i32;
+        pub fn munmap(address: *mut c_void, length: usize) -> i32;
+    }
+}
+
+impl ExecutableCode {
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn new(code: &[u8], slots: usize) -> Option<Self> {
+        let data = code.len().next_multiple_of(PAGE_SIZE);
+        let length = data + (8 * slots).max(1).next_multiple_of(PAGE_SIZE);
+        // Written while writable, then the code is made executable and read-only
+        unsafe {
+            let pointer = sys::mmap(
+                std::ptr::null_mut(),
+                length,
+                sys::PROT_READ | sys::PROT_WRITE,
+                sys::MAP_PRIVATE | sys::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if pointer as isize == -1 {
+                return None;
+            }
+            let mapping = ExecutableCode { pointer, data, length };
+            std::ptr::copy_nonoverlapping(code.as_ptr(), pointer as *mut u8, code.len());
+            if sys::mprotect(pointer, data, sys::PROT_READ | sys::PROT_EXEC) != 0 {
+                return None;
+            }
+            Some(mapping)
+        }
+    }
+
+    #[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+    fn new(_code: &[u8], _slots: usize) -> Option<Self> {
+        None
+    }
+
+    /// Pointer to link slot `slot`.
+    unsafe fn slot(&self, slot: usize) -> *mut u64 {
+        (self.pointer as *mut u8).add(self.data + 8 * slot) as *mut u64
+    }
+}
+
+impl Drop for ExecutableCode {
+    fn drop(&mut self) {
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        unsafe {
+            sys::munmap(self.pointer, self.length);
+        }
+    }
+}
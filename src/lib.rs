@@ -1,11 +1,44 @@
 pub mod memory;
 pub mod cpu;
+pub mod hooks;
 pub mod instruction;
+pub mod decoder;
+pub mod decode_cache;
+pub mod block;
+pub mod jit;
+pub mod disassembler;
+pub mod assembler;
+// The inner `opcode` module is the public path downstream code has always used
+#[allow(clippy::module_inception)]
 pub mod opcode;
+pub mod trap;
+pub mod csr;
+pub mod elf;
+pub mod symbols;
+pub mod debugger;
+pub mod gdb;
+pub mod trace;
+pub mod cosim;
+pub mod rvfi;
+pub mod profile;
+pub mod stats;
+pub mod snapshot;
+pub mod reverse;
+pub mod smp;
+pub mod rvwmo;
+pub mod pipeline;
+pub mod predictor;
+pub mod cache;
 
 pub mod riscv_sim {
     pub use crate::cpu::*;
     pub use crate::memory::*;
     pub use crate::instruction::*;
+    pub use crate::decoder::*;
+    pub use crate::disassembler::*;
+    pub use crate::assembler::*;
     pub use crate::opcode::*;
+    pub use crate::trap::*;
+    pub use crate::hooks::{Hooks, NoHooks};
+    pub use crate::csr::{CsrFile, Privilege};
 }
\ No newline at end of file
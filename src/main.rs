@@ -1,11 +1,509 @@
-use riscv_simulator::{cpu::*, memory::Memory};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
+use std::time::Instant;
 
-fn main() {
+use riscv_simulator::assembler::assemble;
+use riscv_simulator::cache::{self, Cache, CacheConfig, CacheHierarchy, Replacement, WritePolicy};
+use riscv_simulator::cosim;
+use riscv_simulator::cpu::*;
+use riscv_simulator::debugger::Debugger;
+use riscv_simulator::elf::ElfFile;
+use riscv_simulator::gdb::GdbServer;
+use riscv_simulator::hooks::Hooks;
+use riscv_simulator::memory::Memory;
+use riscv_simulator::pipeline::{Forwarding, Pipeline, PipelineConfig};
+use riscv_simulator::predictor::{self, BranchPredictor, DirectionPredictor};
+use riscv_simulator::profile::Profiler;
+use riscv_simulator::rvfi::{RvfiFormat, RvfiWriter};
+use riscv_simulator::smp::{System, SystemStop, DEFAULT_QUANTUM};
+use riscv_simulator::snapshot::Snapshot;
+use riscv_simulator::stats::Statistics;
+use riscv_simulator::symbols::SymbolTable;
+
+const USAGE: &str = "usage: riscv_simulator [--debug | --gdb <port> | --cosim <reference.log>] [-l] [--log-commits] [--log <file>] [--rvfi <file> [--rvfi-text]] [--profile <file>] [--flamegraph <file>] [--stats] [--stats-json <file>] [--pipeline [--forwarding <none|mem|full>] [--predictor <static|btfn|bimodal|gshare|tournament>[:entries]]] [--cache <l1i|l1d|l2>:<size>:<ways>:<line>[:lru|fifo|random][:wb|wt][:noalloc][:<latency>]]... [--memory-latency <n>] [--restore <snapshot>] [--save <snapshot>] [--jit] [--harts <n> [--quantum <n>]] [--memory <bytes>] [program.s | program.elf | program.bin]";
+const DEFAULT_MEMORY_SIZE: usize = 1 << 20;
+const REG_SP: u8 = 2;
+// Commit lines shown on each side of a co-simulation divergence
+const COSIM_CONTEXT: usize = 5;
+
+struct Options {
+    debug: bool,
+    gdb_port: Option<u16>,
+    log_instructions: bool,
+    log_commits: bool,
+    log_file: Option<String>,
+    rvfi_file: Option<String>,
+    rvfi_text: bool,
+    cosim: Option<String>,
+    profile_file: Option<String>,
+    flamegraph_file: Option<String>,
+    stats: bool,
+    stats_file: Option<String>,
+    pipeline: Option<PipelineConfig>,
+    predictor: Option<String>,
+    caches: Option<(CacheConfig, CacheConfig, Option<CacheConfig>)>,
+    memory_latency: u64,
+    restore_file: Option<String>,
+    save_file: Option<String>,
+    jit: bool,
+    harts: usize,
+    quantum: u64,
+    /// `--memory`, if given; otherwise `DEFAULT_MEMORY_SIZE`, or the size of a restored snapshot.
+    memory_size: Option<usize>,
+    program: Option<String>,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options {
+        debug: false,
+        gdb_port: None,
+        log_instructions: false,
+        log_commits: false,
+        log_file: None,
+        rvfi_file: None,
+        rvfi_text: false,
+        cosim: None,
+        profile_file: None,
+        flamegraph_file: None,
+        stats: false,
+        stats_file: None,
+        pipeline: None,
+        predictor: None,
+        caches: None,
+        memory_latency: cache::DEFAULT_MEMORY_LATENCY,
+        restore_file: None,
+        save_file: None,
+        jit: false,
+        harts: 1,
+        quantum: DEFAULT_QUANTUM,
+        memory_size: None,
+        program: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--debug" | "-d" => options.debug = true,
+            "--gdb" | "-g" => {
+                let port = args.next().ok_or("--gdb needs a port")?;
+                options.gdb_port = Some(port.parse().map_err(|_| format!("invalid port '{}'", port))?);
+            }
+            "-l" => options.log_instructions = true,
+            "--log-commits" => options.log_commits = true,
+            "--log" => options.log_file = Some(args.next().ok_or("--log needs a file")?),
+            "--rvfi" => options.rvfi_file = Some(args.next().ok_or("--rvfi needs a file")?),
+            "--rvfi-text" => options.rvfi_text = true,
+            "--cosim" => options.cosim = Some(args.next().ok_or("--cosim needs a reference log")?),
+            "--profile" => options.profile_file = Some(args.next().ok_or("--profile needs a file")?),
+            "--flamegraph" => options.flamegraph_file = Some(args.next().ok_or("--flamegraph needs a file")?),
+            "--stats" => options.stats = true,
+            "--stats-json" => options.stats_file = Some(args.next().ok_or("--stats-json needs a file")?),
+            "--pipeline" => {
+                options.pipeline.get_or_insert_with(PipelineConfig::default);
+            }
+            "--forwarding" => {
+                let forwarding = match args.next().ok_or("--forwarding needs none, mem or full")?.as_str() {
+                    "none" => Forwarding::None,
+                    "mem" => Forwarding::Memory,
+                    "full" => Forwarding::Full,
+                    other => return Err(format!("invalid forwarding '{}'", other)),
+                };
+                options.pipeline.get_or_insert_with(PipelineConfig::default).forwarding = forwarding;
+            }
+            "--predictor" => {
+                let spec = args.next().ok_or("--predictor needs a kind")?;
+                direction_predictor(&spec)?;
+                options.pipeline.get_or_insert_with(PipelineConfig::default);
+                options.predictor = Some(spec);
+            }
+            "--cache" => {
+                let spec = args.next().ok_or("--cache needs a level and geometry")?;
+                let (level, config) = cache_config(&spec)?;
+                let caches = options.caches.get_or_insert_with(|| (CacheConfig::default(), CacheConfig::default(), None));
+                match level {
+                    "l1i" => caches.0 = config,
+                    "l1d" => caches.1 = config,
+                    _ => caches.2 = Some(config),
+                }
+            }
+            "--memory-latency" => {
+                let cycles = args.next().ok_or("--memory-latency needs a cycle count")?;
+                options.memory_latency = cycles.parse().map_err(|_| format!("invalid memory latency '{}'", cycles))?;
+                options.caches.get_or_insert_with(|| (CacheConfig::default(), CacheConfig::default(), None));
+            }
+            "--restore" => options.restore_file = Some(args.next().ok_or("--restore needs a snapshot file")?),
+            "--save" => options.save_file = Some(args.next().ok_or("--save needs a snapshot file")?),
+            "--jit" => options.jit = true,
+            "--harts" => {
+                let count = args.next().ok_or("--harts needs a count")?;
+                options.harts = count.parse().ok().filter(|&count| count > 0).ok_or_else(|| format!("invalid hart count '{}'", count))?;
+            }
+            "--quantum" => {
+                let quantum = args.next().ok_or("--quantum needs an instruction count")?;
+                options.quantum = quantum.parse().ok().filter(|&quantum| quantum > 0).ok_or_else(|| format!("invalid quantum '{}'", quantum))?;
+            }
+            "--memory" | "-m" => {
+                let size = args.next().ok_or("--memory needs a size")?;
+                options.memory_size = Some(riscv_simulator::symbols::parse_number(&size)
+                    .ok_or_else(|| format!("invalid memory size '{}'", size))? as usize);
+            }
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'\n{}", arg, USAGE)),
+            _ => options.program = Some(arg),
+        }
+    }
+    // Each of these takes over the run
+    let modes = [("--debug", options.debug), ("--gdb", options.gdb_port.is_some()), ("--cosim", options.cosim.is_some())];
+    let modes: Vec<&str> = modes.iter().filter(|(_, set)| *set).map(|(flag, _)| *flag).collect();
+    if let [mode, others @ ..] = modes.as_slice() {
+        if !others.is_empty() {
+            return Err(format!("{} cannot be combined with {}", mode, others.join(", ")));
+        }
+    }
+    // Debugging, logging and performance models follow a single hart
+    if options.harts > 1 {
+        let single_hart = [
+            ("--debug", options.debug),
+            ("--gdb", options.gdb_port.is_some()),
+            ("-l", options.log_instructions),
+            ("--log-commits", options.log_commits),
+            ("--log", options.log_file.is_some()),
+            ("--rvfi", options.rvfi_file.is_some()),
+            ("--cosim", options.cosim.is_some()),
+            ("--profile", options.profile_file.is_some()),
+            ("--flamegraph", options.flamegraph_file.is_some()),
+            ("--stats", options.stats),
+            ("--stats-json", options.stats_file.is_some()),
+            ("--pipeline", options.pipeline.is_some()),
+            ("--cache", options.caches.is_some()),
+            ("--save", options.save_file.is_some()),
+        ];
+        let unsupported: Vec<&str> = single_hart.iter().filter(|(_, set)| *set).map(|(flag, _)| *flag).collect();
+        if !unsupported.is_empty() {
+            return Err(format!("--harts cannot be combined with {}", unsupported.join(", ")));
+        }
+    }
+    Ok(options)
+}
+
+/// Direction predictor named by `spec`, `kind[:entries]`.
+fn direction_predictor(spec: &str) -> Result<Box<dyn DirectionPredictor>, String> {
+    let (kind, entries) = match spec.split_once(':') {
+        Some((kind, entries)) => {
+            let entries = entries.parse().ok().filter(|&entries| entries > 0).ok_or_else(|| format!("invalid predictor size '{}'", entries))?;
+            (kind, entries)
+        }
+        None => (spec, predictor::DEFAULT_ENTRIES),
+    };
+    Ok(match kind {
+        "static" => Box::new(predictor::Static::NotTaken),
+        "btfn" => Box::new(predictor::Static::BackwardTaken),
+        "bimodal" => Box::new(predictor::Bimodal::new(entries)),
+        "gshare" => Box::new(predictor::Gshare::new(entries, entries.next_power_of_two().trailing_zeros())),
+        "tournament" => Box::new(predictor::Tournament::new(entries)),
+        other => return Err(format!("invalid predictor '{}'", other)),
+    })
+}
+
+/// Cache level and configuration given by `spec`,
+/// `level:size:ways:line[:option...]`, sizes taking an optional `k` suffix.
+fn cache_config(spec: &str) -> Result<(&str, CacheConfig), String> {
+    let invalid = || format!("invalid cache '{}'", spec);
+    let mut fields = spec.split(':');
+    let level = fields.next().filter(|level| ["l1i", "l1d", "l2"].contains(level)).ok_or_else(invalid)?;
+    let mut geometry = [0; 3];
+    for value in &mut geometry {
+        let field = fields.next().ok_or_else(invalid)?;
+        *value = match field.strip_suffix(['k', 'K']) {
+            Some(kib) => riscv_simulator::symbols::parse_number(kib).and_then(|kib| kib.checked_mul(1024)),
+            None => riscv_simulator::symbols::parse_number(field),
+        }
+        .ok_or_else(invalid)?;
+    }
+    let mut config = if level == "l2" { cache::default_l2() } else { CacheConfig::default() };
+    config = CacheConfig { size: geometry[0], associativity: geometry[1], line_size: geometry[2], ..config };
+    for option in fields {
+        match option {
+            "lru" => config.replacement = Replacement::Lru,
+            "fifo" => config.replacement = Replacement::Fifo,
+            "random" => config.replacement = Replacement::Random,
+            "wb" => config.write = WritePolicy::WriteBack,
+            "wt" => config.write = WritePolicy::WriteThrough,
+            "noalloc" => config.write_allocate = false,
+            _ => config.latency = option.parse().map_err(|_| format!("invalid cache option '{}'", option))?,
+        }
+    }
+    Cache::new(config).map_err(|error| error.to_string())?;
+    Ok((level, config))
+}
+
+/// Load an assembly source, ELF executable or raw binary (at address 0). Without a
+/// program the built-in demo program is used.
+fn load(options: &Options) -> Result<(Cpu, Memory, HashMap<String, u32>), String> {
     let mut rv32i:Cpu = Cpu::new();
+    let mut mem_rv:Memory = Memory::with_size(options.memory_size.unwrap_or(DEFAULT_MEMORY_SIZE));
+    let Some(path) = &options.program else {
+        let demo = Memory::new();
+        if demo.size() > mem_rv.size() {
+            return Err(format!("the demo program does not fit in {} bytes of memory", mem_rv.size()));
+        }
+        mem_rv.write_bytes(0, demo.bytes());
+        return Ok((rv32i, mem_rv, HashMap::new()));
+    };
 
-    let mut mem_rv:Memory = Memory::new();
-    rv32i.run(&mut mem_rv);
-    rv32i.print_registers();
+    let data = std::fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+    let symbols = if path.ends_with(".s") || path.ends_with(".S") || path.ends_with(".asm") {
+        let source = String::from_utf8(data).map_err(|_| format!("{}: not UTF-8 text", path))?;
+        let image = assemble(&source).map_err(|error| format!("{}:{}", path, error))?;
+        image.load_into(&mut mem_rv).map_err(|error| format!("{}: {}", path, error))?;
+        rv32i.set_pc(image.symbol("_start").unwrap_or(image.base));
+        image.symbols
+    } else if data.starts_with(b"\x7fELF") {
+        let elf = ElfFile::parse(&data).map_err(|error| format!("{}: {}", path, error))?;
+        elf.load_into(&mut mem_rv).map_err(|error| format!("{}: {}", path, error))?;
+        rv32i.set_pc(elf.entry);
+        elf.symbols
+    } else {
+        if data.len() > mem_rv.size() {
+            return Err(format!("{}: program does not fit in {} bytes of memory", path, mem_rv.size()));
+        }
+        mem_rv.write_bytes(0, &data);
+        HashMap::new()
+    };
+    // The stack starts at the top of memory
+    rv32i.set_register(REG_SP, (mem_rv.size() as u32) & !0xf);
+    Ok((rv32i, mem_rv, symbols))
+}
+
+fn create(path: &str) -> Result<BufWriter<File>, String> {
+    File::create(path).map(BufWriter::new).map_err(|error| format!("{}: {}", path, error))
+}
+
+/// Run one instruction at a time, writing Spike-style instruction (`-l`) and commit
+/// (`--log-commits`) lines for every retired instruction and RVFI records for every
+/// executed one.
+fn run_logged<H: Hooks>(rv32i: &mut Cpu<H>, mem_rv: &mut Memory, options: &Options) -> Result<StopReason, String> {
+    let mut log: Box<dyn Write> = match &options.log_file {
+        Some(path) => Box::new(create(path)?),
+        None => Box::new(BufWriter::new(io::stderr())),
+    };
+    let mut rvfi = match &options.rvfi_file {
+        Some(path) => {
+            let format = if options.rvfi_text { RvfiFormat::Text } else { RvfiFormat::Binary };
+            Some(RvfiWriter::new(create(path)?, format).map_err(|error| format!("{}: {}", path, error))?)
+        }
+        None => None,
+    };
+    let write_error = |error: io::Error| error.to_string();
+
+    rv32i.set_commit_log(true);
+    loop {
+        let stop = rv32i.step(mem_rv);
+        if let Some(commit) = rv32i.last_commit() {
+            if let Some(rvfi) = &mut rvfi {
+                rvfi.write(commit).map_err(write_error)?;
+            }
+            if options.log_instructions && !commit.trap {
+                writeln!(log, "{}", commit.spike_instruction()).map_err(write_error)?;
+            }
+            if options.log_commits && !commit.trap {
+                writeln!(log, "{}", commit.spike_commit()).map_err(write_error)?;
+            }
+        }
+        if stop != StopReason::InstructionLimit {
+            log.flush().map_err(write_error)?;
+            if let Some(rvfi) = rvfi {
+                rvfi.into_inner().flush().map_err(write_error)?;
+            }
+            return Ok(stop);
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let result = parse_options().and_then(|options| Ok((load(&options)?, options)));
+    let ((mut rv32i, mut mem_rv, symbols), options) = match result {
+        Ok(loaded) => loaded,
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Some(path) = &options.restore_file {
+        match read_snapshot(path, options.memory_size) {
+            Ok(snapshot) => rv32i.restore(&snapshot, &mut mem_rv),
+            Err(error) => {
+                eprintln!("{}", error);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if options.jit && !rv32i.set_jit(true) {
+        eprintln!("--jit is not supported on this host; interpreting instead");
+    }
+
+    if options.harts > 1 {
+        return run_system(&rv32i, mem_rv, &options);
+    }
+
+    if let Some(port) = options.gdb_port {
+        let mut server = GdbServer::new(rv32i, mem_rv);
+        eprintln!("waiting for gdb on localhost:{}", port);
+        if let Err(error) = server.listen(("127.0.0.1", port)) {
+            eprintln!("{}", error);
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    if options.debug {
+        let mut debugger = Debugger::new(rv32i, mem_rv, SymbolTable::new(&symbols));
+        if let Err(error) = debugger.repl(io::stdin().lock(), io::stdout()) {
+            eprintln!("{}", error);
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(path) = &options.cosim {
+        let reference = match std::fs::read_to_string(path) {
+            Ok(text) => cosim::parse_commit_log(&text),
+            Err(error) => {
+                eprintln!("{}: {}", path, error);
+                return ExitCode::FAILURE;
+            }
+        };
+        let report = cosim::cosimulate(&mut rv32i, &mut mem_rv, &reference, COSIM_CONTEXT);
+        println!("{}", report);
+        return if report.passed() { ExitCode::SUCCESS } else { ExitCode::FAILURE };
+    }
+
+    let profiler = (options.profile_file.is_some() || options.flamegraph_file.is_some()).then(Profiler::new);
+    let statistics = (options.stats || options.stats_file.is_some()).then(Statistics::new);
+    let mut caches = options.caches.map(|(l1i, l1d, l2)| {
+        let mut caches = CacheHierarchy::new(l1i, l1d, l2).expect("validated when parsed");
+        caches.set_memory_latency(options.memory_latency);
+        caches
+    });
+    let pipeline = options.pipeline.map(|config| {
+        let mut pipeline = Pipeline::new(config);
+        if let Some(spec) = &options.predictor {
+            pipeline.set_predictor(BranchPredictor::new(direction_predictor(spec).expect("validated when parsed")));
+        }
+        if let Some(caches) = caches.take() {
+            pipeline.set_caches(caches);
+        }
+        pipeline
+    });
+    let result = if profiler.is_some() || statistics.is_some() || pipeline.is_some() || caches.is_some() {
+        let mut instrumented = rv32i.attach_hooks((profiler, (statistics, (pipeline, caches))));
+        let started = Instant::now();
+        run(&mut instrumented, &mut mem_rv, &options).and_then(|stop| {
+            let elapsed = started.elapsed();
+            let (profiler, (statistics, (pipeline, caches))) = instrumented.hooks();
+            if let Some(profiler) = profiler {
+                write_profile(profiler, &SymbolTable::new(&symbols), &options)?;
+            }
+            if let Some(statistics) = statistics {
+                if options.stats {
+                    eprint!("{}", statistics.summary(elapsed));
+                }
+                if let Some(path) = &options.stats_file {
+                    std::fs::write(path, statistics.to_json(elapsed)).map_err(|error| format!("{}: {}", path, error))?;
+                }
+            }
+            if let Some(pipeline) = pipeline {
+                eprint!("{}", pipeline.summary());
+            }
+            if let Some(caches) = caches {
+                eprint!("{}", caches.summary());
+            }
+            Ok(finish(&instrumented, stop))
+        })
+    } else {
+        run(&mut rv32i, &mut mem_rv, &options).map(|stop| finish(&rv32i, stop))
+    };
+    result.unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        ExitCode::FAILURE
+    })
 }
 
+/// Run `options.harts` copies of the loaded hart on one memory. Each starts with the
+/// same registers, so programs tell the harts apart by mhartid.
+fn run_system(rv32i: &Cpu, mut mem_rv: Memory, options: &Options) -> ExitCode {
+    let snapshot = rv32i.snapshot(&mem_rv);
+    let harts = (0..options.harts)
+        .map(|_| {
+            let mut hart = Cpu::new();
+            hart.restore(&snapshot, &mut mem_rv);
+            hart.set_jit(options.jit);
+            hart
+        })
+        .collect();
+    let mut system = System::with_harts(harts, mem_rv);
+    system.set_quantum(options.quantum);
+    let stop = system.run();
+    println!("{}", stop);
+    for (index, hart) in system.harts().iter().enumerate() {
+        match system.exit_code(index) {
+            Some(exit_code) => println!("hart {}: exit code {}", index, exit_code),
+            None => println!("hart {}: pc 0x{:08x}", index, hart.pc()),
+        }
+    }
+    match stop {
+        SystemStop::Halted => ExitCode::from(system.exit_code(0).unwrap_or(0) as u8),
+        _ => ExitCode::SUCCESS,
+    }
+}
 
+fn run<H: Hooks>(rv32i: &mut Cpu<H>, mem_rv: &mut Memory, options: &Options) -> Result<StopReason, String> {
+    let stop = if options.log_instructions || options.log_commits || options.rvfi_file.is_some() {
+        run_logged(rv32i, mem_rv, options)?
+    } else {
+        rv32i.run(mem_rv)
+    };
+    if let Some(path) = &options.save_file {
+        std::fs::write(path, rv32i.snapshot(mem_rv).to_bytes()).map_err(|error| format!("{}: {}", path, error))?;
+    }
+    Ok(stop)
+}
+
+/// Read the snapshot at `path`, whose memory must be `memory_size` bytes if given.
+fn read_snapshot(path: &str, memory_size: Option<usize>) -> Result<Snapshot, String> {
+    let data = std::fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+    let snapshot = Snapshot::from_bytes(&data).map_err(|error| format!("{}: {}", path, error))?;
+    match memory_size {
+        Some(size) if size != snapshot.memory.len() => {
+            Err(format!("{}: snapshot has {} bytes of memory, not the {} given by --memory", path, snapshot.memory.len(), size))
+        }
+        _ => Ok(snapshot),
+    }
+}
+
+fn write_profile(profiler: &Profiler, symbols: &SymbolTable, options: &Options) -> Result<(), String> {
+    let outputs = [
+        (&options.profile_file, profiler.annotated(symbols)),
+        (&options.flamegraph_file, profiler.folded(symbols)),
+    ];
+    for (path, text) in outputs {
+        if let Some(path) = path {
+            std::fs::write(path, text).map_err(|error| format!("{}: {}", path, error))?;
+        }
+    }
+    Ok(())
+}
+
+/// Print the stop reason and registers; a halted program's exit code becomes ours.
+fn finish<H: Hooks>(rv32i: &Cpu<H>, stop: StopReason) -> ExitCode {
+    println!("{}", stop);
+    rv32i.print_registers();
+    println!();
+    match stop {
+        StopReason::Halted { exit_code } => ExitCode::from(exit_code as u8),
+        _ => ExitCode::SUCCESS,
+    }
+}
@@ -1,35 +1,97 @@
+/// Byte order used for data accesses.
+///
+/// RISC-V is little-endian by default. Big-endian data accesses can be selected per
+/// privilege mode through mstatus.MBE/SBE/UBE; instruction fetch is always little-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
 pub struct Memory {
         data: Vec<u8>,
+        data_endianness: Endianness,
 }
 
-
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Memory {
     pub fn new() -> Self {
         Self {
-            data: vec![0b00000000, 0b00000000, 0b01010000, 0b10110111, 
-            0b00000000, 0b00000000, 0b01100001, 0b00110111,   
-            0b00000000, 0b00100000, 0b10000001, 0b10110011,],
+            data: vec![0b10110111, 0b01010000, 0b00000000, 0b00000000,
+            0b00110111, 0b01100001, 0b00000000, 0b00000000,
+            0b10110011, 0b10000001, 0b00100000, 0b00000000,],
+            data_endianness: Endianness::Little,
+        }
+    }
+
+    /// Zero-filled memory of `size` bytes.
+    pub fn with_size(size: usize) -> Self {
+        Self {
+            data: vec![0; size],
+            data_endianness: Endianness::Little,
         }
     }
-    
+
     pub fn size(&self) -> usize {
         self.data.len()
     }
 
-    pub fn load_word(&self, address: usize) -> u32 {
+    /// The whole memory contents.
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Raw pointer to the contents, for code that also writes them through `&mut self`
+    /// while the pointer is in use. It stays valid until the memory is resized.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.data.as_mut_ptr()
+    }
+
+    /// Replace the memory contents, resizing to `bytes`.
+    pub fn replace_bytes(&mut self, bytes: &[u8]) {
+        self.data.clear();
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Byte order currently used by data loads and stores.
+    pub fn data_endianness(&self) -> Endianness {
+        self.data_endianness
+    }
+
+    /// Select the byte order of data loads and stores, mirroring the effect of
+    /// mstatus.MBE/SBE/UBE for the privilege mode that is executing.
+    pub fn set_data_endianness(&mut self, endianness: Endianness) {
+        self.data_endianness = endianness;
+    }
+
+    /// Fetch a 32-bit instruction. Instruction parcels are always little-endian,
+    /// regardless of the data endianness.
+    pub fn fetch_word(&self, address: usize) -> u32 {
         // Ensure the address is within bounds and aligned to 4 bytes
-        assert!(address % 4 == 0, "Unaligned memory access");
+        assert!(address.is_multiple_of(4), "Unaligned instruction fetch");
         assert!(address + 4 <= self.data.len(), "Address out of bounds");
 
-        // Load the 4 bytes from memory
-        let byte0: u32 = self.data[address] as u32;
-        let byte1: u32 = self.data[address + 1] as u32;
-        let byte2: u32 = self.data[address + 2] as u32;
-        let byte3: u32 = self.data[address + 3] as u32;
+        let bytes: [u8; 4] = self.data[address..address + 4].try_into().unwrap();
+        u32::from_le_bytes(bytes)
+    }
+
+    pub fn load_word(&self, address: usize) -> u32 {
+        // Ensure the address is within bounds and aligned to 4 bytes
+        assert!(address.is_multiple_of(4), "Unaligned memory access");
+        assert!(address + 4 <= self.data.len(), "Address out of bounds");
 
-        // Combine the bytes into a single 32-bit word (Little Endian)
-        (byte0 << 24) | (byte1 << 16) | (byte2 << 8) | byte3
+        // Combine the 4 bytes into a single 32-bit word
+        let bytes: [u8; 4] = self.data[address..address + 4].try_into().unwrap();
+        match self.data_endianness {
+            Endianness::Little => u32::from_le_bytes(bytes),
+            Endianness::Big => u32::from_be_bytes(bytes),
+        }
     }
 
     pub fn load_byte(&self, address: usize) -> u32 {
@@ -42,15 +104,23 @@ impl Memory {
 
     pub fn load_halfword(&self, address: usize) -> u32 {
         // Ensure the address is within bounds and aligned to 2 bytes
-        assert!(address % 2 == 0, "Unaligned memory access");
+        assert!(address.is_multiple_of(2), "Unaligned memory access");
         assert!(address + 2 <= self.data.len(), "Address out of bounds");
-    
-        // Load the 2 bytes from memory
-        let byte0: u32 = self.data[address] as u32;
-        let byte1: u32 = self.data[address + 1] as u32;
-    
-        // Combine the bytes into a single 16-bit halfword (Little Endian)
-        (byte0 << 8) | byte1
+
+        // Combine the 2 bytes into a single 16-bit halfword
+        let bytes: [u8; 2] = self.data[address..address + 2].try_into().unwrap();
+        match self.data_endianness {
+            Endianness::Little => u16::from_le_bytes(bytes) as u32,
+            Endianness::Big => u16::from_be_bytes(bytes) as u32,
+        }
+    }
+
+    /// Copy a block of bytes into memory, e.g. to load a program image.
+    pub fn write_bytes(&mut self, address: usize, bytes: &[u8]) {
+        // Ensure the whole block is within bounds
+        assert!(address + bytes.len() <= self.data.len(), "Address out of bounds");
+
+        self.data[address..address + bytes.len()].copy_from_slice(bytes);
     }
 
     pub fn store_byte(&mut self, address: usize, value: u8) {
@@ -63,24 +133,28 @@ impl Memory {
 
     pub fn store_halfword(&mut self, address: usize, value: u16) {
         // Ensure the address is within bounds and aligned to 2 bytes
-        assert!(address % 2 == 0, "Unaligned memory access");
+        assert!(address.is_multiple_of(2), "Unaligned memory access");
         assert!(address + 2 <= self.data.len(), "Address out of bounds");
 
-        // Store the 2 bytes at the given address (Little Endian)
-        self.data[address] = (value & 0xFF) as u8;        // Lower byte
-        self.data[address + 1] = (value >> 8) as u8;      // Higher byte
+        // Store the 2 bytes at the given address
+        let bytes = match self.data_endianness {
+            Endianness::Little => value.to_le_bytes(),
+            Endianness::Big => value.to_be_bytes(),
+        };
+        self.data[address..address + 2].copy_from_slice(&bytes);
     }
 
     pub fn store_word(&mut self, address: usize, value: u32) {
         // Ensure the address is within bounds and aligned to 4 bytes
-        assert!(address % 4 == 0, "Unaligned memory access");
+        assert!(address.is_multiple_of(4), "Unaligned memory access");
         assert!(address + 4 <= self.data.len(), "Address out of bounds");
 
-        // Store the 4 bytes at the given address (Little Endian)
-        self.data[address] = (value & 0xFF) as u8;          // Byte 0
-        self.data[address + 1] = ((value >> 8) & 0xFF) as u8; // Byte 1
-        self.data[address + 2] = ((value >> 16) & 0xFF) as u8; // Byte 2
-        self.data[address + 3] = ((value >> 24) & 0xFF) as u8; // Byte 3
+        // Store the 4 bytes at the given address
+        let bytes = match self.data_endianness {
+            Endianness::Little => value.to_le_bytes(),
+            Endianness::Big => value.to_be_bytes(),
+        };
+        self.data[address..address + 4].copy_from_slice(&bytes);
     }
-    
-}
\ No newline at end of file
+
+}
@@ -1,62 +1,55 @@
-
 pub mod opcode {
 
-    // Opcode Definitions
-    pub const OPCODE_LUI: u8 = 0b0110111;   // LUI (Load Upper Immediate)
-    pub const OPCODE_AUIPC: u8 = 0b0010111; // AUIPC (Add Upper Immediate to PC)
-    pub const OPCODE_JAL: u8 = 0b1101111;   // JAL (Jump and Link)
-    pub const OPCODE_JALR: u8 = 0b1100111;  // JALR (Jump and Link Register)
-    pub const OPCODE_BEQ: u8 = 0b1100011;   // BEQ (Branch if Equal)
-    pub const OPCODE_BNE: u8 = 0b1100011;   // BNE (Branch if Not Equal)
-    pub const OPCODE_BLT: u8 = 0b1100011;   // BLT (Branch if Less Than)
-    pub const OPCODE_BGE: u8 = 0b1100011;   // BGE (Branch if Greater or Equal)
-    pub const OPCODE_BLTU: u8 = 0b1100011;  // BLTU (Branch if Less Than Unsigned)
-    pub const OPCODE_BGEU: u8 = 0b1100011;  // BGEU (Branch if Greater or Equal Unsigned)
-    pub const OPCODE_LB: u8 = 0b0000011;    // LB (Load Byte)
-    pub const OPCODE_LH: u8 = 0b0000011;    // LH (Load Halfword)
-    pub const OPCODE_LW: u8 = 0b0000011;    // LW (Load Word)
-    pub const OPCODE_LBU: u8 = 0b0000011;   // LBU (Load Byte Unsigned)
-    pub const OPCODE_LHU: u8 = 0b0000011;   // LHU (Load Halfword Unsigned)
-    pub const OPCODE_SB: u8 = 0b0100011;    // SB (Store Byte)
-    pub const OPCODE_SH: u8 = 0b0100011;    // SH (Store Halfword)
-    pub const OPCODE_SW: u8 = 0b0100011;    // SW (Store Word)
-    pub const OPCODE_ADDI: u8 = 0b0010011;  // ADDI (Add Immediate)
-    pub const OPCODE_SLTI: u8 = 0b0010011;  // SLTI (Set Less Than Immediate)
-    pub const OPCODE_SLTIU: u8 = 0b0010011; // SLTIU (Set Less Than Immediate Unsigned)
-    pub const OPCODE_XORI: u8 = 0b0010011;  // XORI (XOR Immediate)
-    pub const OPCODE_ORI: u8 = 0b0010011;   // ORI (OR Immediate)
-    pub const OPCODE_ANDI: u8 = 0b0010011;  // ANDI (AND Immediate)
-    pub const OPCODE_SLLI: u8 = 0b0010011;  // SLLI (Shift Left Logical Immediate)
-    pub const OPCODE_SRLI: u8 = 0b0010011;  // SRLI (Shift Right Logical Immediate)
-    pub const OPCODE_SRAI: u8 = 0b0010011;  // SRAI (Shift Right Arithmetic Immediate)
-    pub const OPCODE_ADD: u8 = 0b0110011;   // ADD (Add)
-    pub const OPCODE_SUB: u8 = 0b0110011;   // SUB (Subtract)
-    pub const OPCODE_SLL: u8 = 0b0110011;   // SLL (Shift Left Logical)
-    pub const OPCODE_SLT: u8 = 0b0110011;   // SLT (Set Less Than)
-    pub const OPCODE_SLTU: u8 = 0b0110011;  // SLTU (Set Less Than Unsigned)
-    pub const OPCODE_XOR: u8 = 0b0110011;   // XOR (XOR)
-    pub const OPCODE_SRL: u8 = 0b0110011;   // SRL (Shift Right Logical)
-    pub const OPCODE_SRA: u8 = 0b0110011;   // SRA (Shift Right Arithmetic)
-    pub const OPCODE_OR: u8 = 0b0110011;    // OR (OR)
-    pub const OPCODE_AND: u8 = 0b0110011;   // AND (AND)
+    // Major opcode Definitions (bits [6:0])
+    pub const OPCODE_LOAD: u8 = 0b0000011;     // LB, LH, LW, LBU, LHU
+    pub const OPCODE_MISC_MEM: u8 = 0b0001111; // FENCE, FENCE.I
+    pub const OPCODE_OP_IMM: u8 = 0b0010011;   // ADDI, SLTI, SLTIU, XORI, ORI, ANDI, SLLI, SRLI, SRAI
+    pub const OPCODE_AUIPC: u8 = 0b0010111;    // AUIPC (Add Upper Immediate to PC)
+    pub const OPCODE_STORE: u8 = 0b0100011;    // SB, SH, SW
+    pub const OPCODE_AMO: u8 = 0b0101111;      // LR.W, SC.W, AMO*.W
+    pub const OPCODE_OP: u8 = 0b0110011;       // ADD, SUB, SLL, SLT, SLTU, XOR, SRL, SRA, OR, AND, MUL*, DIV*, REM*
+    pub const OPCODE_LUI: u8 = 0b0110111;      // LUI (Load Upper Immediate)
+    pub const OPCODE_BRANCH: u8 = 0b1100011;   // BEQ, BNE, BLT, BGE, BLTU, BGEU
+    pub const OPCODE_JALR: u8 = 0b1100111;     // JALR (Jump and Link Register)
+    pub const OPCODE_JAL: u8 = 0b1101111;      // JAL (Jump and Link)
+    pub const OPCODE_SYSTEM: u8 = 0b1110011;   // ECALL, EBREAK, xRET, WFI, SFENCE.VMA, CSR*
+
+    // Major opcodes of standard extensions this simulator does not implement
+    pub const OPCODE_LOAD_FP: u8 = 0b0000111;  // FLW, FLD, vector loads
+    pub const OPCODE_STORE_FP: u8 = 0b0100111; // FSW, FSD, vector stores
+    pub const OPCODE_MADD: u8 = 0b1000011;     // FMADD.*
+    pub const OPCODE_MSUB: u8 = 0b1000111;     // FMSUB.*
+    pub const OPCODE_NMSUB: u8 = 0b1001011;    // FNMSUB.*
+    pub const OPCODE_NMADD: u8 = 0b1001111;    // FNMADD.*
+    pub const OPCODE_OP_FP: u8 = 0b1010011;    // F/D/Q arithmetic
+    pub const OPCODE_OP_V: u8 = 0b1010111;     // Vector arithmetic
 
-    // funct3 Definitions for R-type, I-type, and S-type
-    pub const FUNCT3_ADD: u8 = 0b000;  // Add
-    pub const FUNCT3_SUB: u8 = 0b000;  // Subtract
+    // funct3 Definitions for R-type and I-type arithmetic
+    pub const FUNCT3_ADD: u8 = 0b000;  // Add / Subtract
     pub const FUNCT3_SLL: u8 = 0b001;  // Shift Left Logical
     pub const FUNCT3_SLT: u8 = 0b010;  // Set Less Than
     pub const FUNCT3_SLTU: u8 = 0b011; // Set Less Than Unsigned
     pub const FUNCT3_XOR: u8 = 0b100;  // XOR
-    pub const FUNCT3_SRL: u8 = 0b101;  // Shift Right Logical
-    pub const FUNCT3_SRA: u8 = 0b101;  // Shift Right Arithmetic
+    pub const FUNCT3_SRL: u8 = 0b101;  // Shift Right Logical / Arithmetic
     pub const FUNCT3_OR: u8 = 0b110;   // OR
     pub const FUNCT3_AND: u8 = 0b111;  // AND
 
     // funct7 Definitions for R-type
-    pub const FUNCT7_SUB: u8 = 0b0100000; // SUB
-    pub const FUNCT7_ALL: u8 = 0b0000000; // ALL
-    pub const FUNCT7_SRA: u8 = 0b0100000; // SRA
-    
+    pub const FUNCT7_ALL: u8 = 0b0000000;    // ADD, SLL, SLT, SLTU, XOR, SRL, OR, AND
+    pub const FUNCT7_SUB: u8 = 0b0100000;    // SUB
+    pub const FUNCT7_SRA: u8 = 0b0100000;    // SRA
+    pub const FUNCT7_MULDIV: u8 = 0b0000001; // M extension
+
+    // funct3 Definitions for the M extension
+    pub const FUNCT3_MUL: u8 = 0b000;    // Multiply
+    pub const FUNCT3_MULH: u8 = 0b001;   // Multiply High Signed x Signed
+    pub const FUNCT3_MULHSU: u8 = 0b010; // Multiply High Signed x Unsigned
+    pub const FUNCT3_MULHU: u8 = 0b011;  // Multiply High Unsigned x Unsigned
+    pub const FUNCT3_DIV: u8 = 0b100;    // Divide
+    pub const FUNCT3_DIVU: u8 = 0b101;   // Divide Unsigned
+    pub const FUNCT3_REM: u8 = 0b110;    // Remainder
+    pub const FUNCT3_REMU: u8 = 0b111;   // Remainder Unsigned
+
     // funct3 Definitions for B-type
     pub const FUNCT3_BEQ: u8 = 0b000;  // Branch if Equal
     pub const FUNCT3_BNE: u8 = 0b001;  // Branch if Not Equal
@@ -77,4 +70,40 @@ pub mod opcode {
     pub const FUNCT3_SH: u8 = 0b001;  // Store Halfword
     pub const FUNCT3_SW: u8 = 0b010;  // Store Word
 
-}
\ No newline at end of file
+    // funct3 Definitions for MISC-MEM and JALR
+    pub const FUNCT3_FENCE: u8 = 0b000;   // Fence
+    pub const FUNCT3_FENCE_I: u8 = 0b001; // Instruction Fence
+    pub const FUNCT3_JALR: u8 = 0b000;    // Jump and Link Register
+
+    // funct3 Definitions for SYSTEM
+    pub const FUNCT3_PRIV: u8 = 0b000;   // ECALL, EBREAK, xRET, WFI, SFENCE.VMA
+    pub const FUNCT3_CSRRW: u8 = 0b001;  // CSR Read/Write
+    pub const FUNCT3_CSRRS: u8 = 0b010;  // CSR Read and Set
+    pub const FUNCT3_CSRRC: u8 = 0b011;  // CSR Read and Clear
+    pub const FUNCT3_CSRRWI: u8 = 0b101; // CSR Read/Write Immediate
+    pub const FUNCT3_CSRRSI: u8 = 0b110; // CSR Read and Set Immediate
+    pub const FUNCT3_CSRRCI: u8 = 0b111; // CSR Read and Clear Immediate
+
+    // funct3 and funct5 Definitions for the A extension
+    pub const FUNCT3_AMO_W: u8 = 0b010;      // Word-sized atomics
+    pub const FUNCT5_LR: u8 = 0b00010;       // Load Reserved
+    pub const FUNCT5_SC: u8 = 0b00011;       // Store Conditional
+    pub const FUNCT5_AMOSWAP: u8 = 0b00001;  // Atomic Swap
+    pub const FUNCT5_AMOADD: u8 = 0b00000;   // Atomic Add
+    pub const FUNCT5_AMOXOR: u8 = 0b00100;   // Atomic XOR
+    pub const FUNCT5_AMOAND: u8 = 0b01100;   // Atomic AND
+    pub const FUNCT5_AMOOR: u8 = 0b01000;    // Atomic OR
+    pub const FUNCT5_AMOMIN: u8 = 0b10000;   // Atomic Minimum
+    pub const FUNCT5_AMOMAX: u8 = 0b10100;   // Atomic Maximum
+    pub const FUNCT5_AMOMINU: u8 = 0b11000;  // Atomic Minimum Unsigned
+    pub const FUNCT5_AMOMAXU: u8 = 0b11100;  // Atomic Maximum Unsigned
+
+    // funct12 Definitions for SYSTEM instructions without operands
+    pub const FUNCT12_ECALL: u16 = 0x000;  // Environment Call
+    pub const FUNCT12_EBREAK: u16 = 0x001; // Environment Breakpoint
+    pub const FUNCT12_SRET: u16 = 0x102;   // Supervisor Return
+    pub const FUNCT12_WFI: u16 = 0x105;    // Wait For Interrupt
+    pub const FUNCT12_MRET: u16 = 0x302;   // Machine Return
+    pub const FUNCT7_SFENCE_VMA: u8 = 0b0001001; // Supervisor Fence for Virtual Memory
+
+}
@@ -0,0 +1,284 @@
+//! Cycle-approximate timing of a classic five-stage in-order pipeline.
+//!
+//! Instructions flow through IF, ID, EX, MEM and WB one per cycle. The model follows
+//! the retired instruction stream and works out the cycle each instruction enters EX,
+//! which is one cycle after the previous one unless it has to wait for:
+//!
+//! - a source register, until a forwarding path or the register file has the value
+//!   (see [`Forwarding`]); a load result exists only after MEM, so a dependent
+//!   instruction right behind a load stalls even with full forwarding;
+//! - the EX stage, which multiplies and divides occupy for several cycles;
+//! - the correct path after a control transfer, fetched `branch_penalty` cycles late
+//!   because the pipeline predicts fall-through, or, given a [`BranchPredictor`], only
+//!   when that mispredicts. A trap redirects fetch the same way;
+//! - a fetch that takes more than a cycle, given a [`CacheHierarchy`] to time it; a
+//!   load or store taking more than a cycle in MEM holds up every instruction behind it.
+//!
+//! Timing never affects the functional results of the CPU it observes.
+
+use std::fmt::Write as _;
+
+use crate::cache::CacheHierarchy;
+use crate::hooks::Hooks;
+use crate::instruction::Instruction;
+use crate::predictor::BranchPredictor;
+use crate::trap::Trap;
+
+/// Paths that deliver results to the EX stage before writeback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Forwarding {
+    /// Operands are read from the register file in ID, after the producer's WB
+    /// (written in the first half of the cycle, read in the second).
+    None,
+    /// From the MEM/WB latch only.
+    Memory,
+    /// From the EX/MEM and MEM/WB latches.
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineConfig {
+    pub forwarding: Forwarding,
+    /// Cycles lost to a control transfer, taken branch or trap, or to a misprediction.
+    pub branch_penalty: u64,
+    /// Cycles a multiply spends in EX.
+    pub mul_latency: u64,
+    /// Cycles a divide or remainder spends in EX.
+    pub div_latency: u64,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig { forwarding: Forwarding::Full, branch_penalty: 2, mul_latency: 3, div_latency: 20 }
+    }
+}
+
+/// Where the cycles went, as counted by a [`Pipeline`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineStats {
+    pub instructions: u64,
+    /// Cycles from the first fetch to the last writeback.
+    pub cycles: u64,
+    /// Waiting for a result that is not a load's.
+    pub data_stalls: u64,
+    /// Waiting for the result of a load.
+    pub load_use_stalls: u64,
+    /// Waiting for a multiply or divide to leave EX.
+    pub structural_stalls: u64,
+    /// Fetching down the wrong path.
+    pub control_stalls: u64,
+    /// Waiting for an instruction fetch to leave the caches.
+    pub fetch_stalls: u64,
+    /// Waiting for a load or store to leave the caches.
+    pub memory_stalls: u64,
+}
+
+impl PipelineStats {
+    /// Cycles per instruction.
+    pub fn cpi(&self) -> f64 {
+        self.cycles as f64 / self.instructions.max(1) as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Producer {
+    // First cycle a consumer can be in EX
+    ready: u64,
+    load: bool,
+}
+
+/// Pipeline timing gathered as CPU [`Hooks`].
+#[derive(Debug)]
+pub struct Pipeline {
+    config: PipelineConfig,
+    stats: PipelineStats,
+    // Cycle the last instruction entered EX and the first cycle EX is free again
+    ex: u64,
+    ex_free: u64,
+    registers: [Producer; 32],
+    // The last instruction redirected fetch
+    redirect: bool,
+    // A trap was raised by the executing instruction
+    trapped: bool,
+    predictor: Option<BranchPredictor>,
+    caches: Option<CacheHierarchy>,
+    // Cycles past the first that the executing instruction's fetch and memory accesses
+    // took, and the first cycle MEM is free again
+    fetch_stall: u64,
+    memory_stall: u64,
+    memory_free: u64,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new(PipelineConfig::default())
+    }
+}
+
+impl Pipeline {
+    pub fn new(config: PipelineConfig) -> Self {
+        Pipeline {
+            config,
+            stats: PipelineStats::default(),
+            // The first instruction is fetched in cycle 0 and reaches EX in cycle 2
+            ex: 1,
+            ex_free: 0,
+            registers: [Producer::default(); 32],
+            redirect: false,
+            trapped: false,
+            predictor: None,
+            caches: None,
+            fetch_stall: 0,
+            memory_stall: 0,
+            memory_free: 0,
+        }
+    }
+
+    /// Fetch along the paths `predictor` predicts instead of falling through.
+    pub fn set_predictor(&mut self, predictor: BranchPredictor) {
+        self.predictor = Some(predictor);
+    }
+
+    pub fn predictor(&self) -> Option<&BranchPredictor> {
+        self.predictor.as_ref()
+    }
+
+    /// Time fetches, loads and stores with `caches` instead of taking a cycle each.
+    pub fn set_caches(&mut self, caches: CacheHierarchy) {
+        self.caches = Some(caches);
+    }
+
+    pub fn caches(&self) -> Option<&CacheHierarchy> {
+        self.caches.as_ref()
+    }
+
+    pub fn config(&self) -> &PipelineConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &PipelineStats {
+        &self.stats
+    }
+
+    /// Cycles in EX for `instruction`.
+    fn latency(&self, instruction: &Instruction) -> u64 {
+        match instruction {
+            Instruction::Mul(_) | Instruction::Mulh(_) | Instruction::Mulhsu(_) | Instruction::Mulhu(_) => {
+                self.config.mul_latency.max(1)
+            }
+            Instruction::Div(_) | Instruction::Divu(_) | Instruction::Rem(_) | Instruction::Remu(_) => {
+                self.config.div_latency.max(1)
+            }
+            _ => 1,
+        }
+    }
+
+    /// Human-readable report of the figures in [`Pipeline::stats`].
+    pub fn summary(&self) -> String {
+        let stats = &self.stats;
+        let mut text = String::new();
+        let _ = writeln!(text, "instructions  {}", stats.instructions);
+        let _ = writeln!(text, "cycles        {}", stats.cycles);
+        let _ = writeln!(text, "CPI           {:.3}", stats.cpi());
+        let _ = writeln!(text, "stalls        {} data, {} load-use, {} structural, {} control", stats.data_stalls, stats.load_use_stalls, stats.structural_stalls, stats.control_stalls);
+        if let Some(caches) = &self.caches {
+            let _ = writeln!(text, "cache stalls  {} fetch, {} memory", stats.fetch_stalls, stats.memory_stalls);
+            let _ = write!(text, "\n{}", caches.summary());
+        }
+        if let Some(predictor) = &self.predictor {
+            let _ = write!(text, "\n{}", predictor.summary(10));
+        }
+        text
+    }
+}
+
+impl Hooks for Pipeline {
+    fn fetch(&mut self, pc: u32, _raw: u32) {
+        if let Some(caches) = &mut self.caches {
+            self.fetch_stall += caches.fetch(pc).saturating_sub(1);
+        }
+    }
+
+    fn memory_read(&mut self, address: u32, size: u32, _value: u32) {
+        if let Some(caches) = &mut self.caches {
+            self.memory_stall += caches.read(address, size).saturating_sub(1);
+        }
+    }
+
+    fn memory_write(&mut self, address: u32, size: u32, _value: u32) {
+        if let Some(caches) = &mut self.caches {
+            self.memory_stall += caches.write(address, size).saturating_sub(1);
+        }
+    }
+
+    fn pre_execute(&mut self, _pc: u32, _instruction: &Instruction) {
+        // A trap taken by the previous instruction sent fetch to its handler
+        if self.trapped {
+            self.redirect = true;
+            self.trapped = false;
+        }
+    }
+
+    fn post_execute(&mut self, pc: u32, instruction: &Instruction, next_pc: u32) {
+        let mut ex = self.ex + 1;
+        if self.redirect {
+            let correct = self.ex + 1 + self.config.branch_penalty;
+            self.stats.control_stalls += correct.saturating_sub(ex);
+            ex = ex.max(correct);
+        }
+        self.stats.fetch_stalls += self.fetch_stall;
+        ex += self.fetch_stall;
+        self.stats.structural_stalls += self.ex_free.saturating_sub(ex);
+        ex = ex.max(self.ex_free);
+        self.stats.memory_stalls += self.memory_free.saturating_sub(ex);
+        ex = ex.max(self.memory_free);
+        for source in instruction.sources().into_iter().flatten().filter(|&source| source != 0) {
+            let producer = self.registers[source as usize];
+            let stall = producer.ready.saturating_sub(ex);
+            if producer.load {
+                self.stats.load_use_stalls += stall;
+            } else {
+                self.stats.data_stalls += stall;
+            }
+            ex = ex.max(producer.ready);
+        }
+
+        let latency = self.latency(instruction);
+        // Instructions whose result comes from memory
+        let load = matches!(
+            instruction,
+            Instruction::Lb(_) | Instruction::Lh(_) | Instruction::Lw(_) | Instruction::Lbu(_) | Instruction::Lhu(_)
+                | Instruction::LrW(_) | Instruction::ScW(_) | Instruction::AmoswapW(_) | Instruction::AmoaddW(_)
+                | Instruction::AmoxorW(_) | Instruction::AmoandW(_) | Instruction::AmoorW(_) | Instruction::AmominW(_)
+                | Instruction::AmomaxW(_) | Instruction::AmominuW(_) | Instruction::AmomaxuW(_)
+        );
+        if let Some(destination) = instruction.destination().filter(|&destination| destination != 0) {
+            // Cycle after the result leaves EX (or MEM for loads)
+            let produced = ex + latency + load as u64;
+            let ready = match self.config.forwarding {
+                Forwarding::Full => produced,
+                Forwarding::Memory => ex + latency + 1,
+                Forwarding::None => ex + latency + 2,
+            } + self.memory_stall;
+            self.registers[destination as usize] = Producer { ready, load };
+        }
+        self.ex = ex;
+        self.ex_free = ex + latency;
+        // The next instruction waits in EX until this one leaves MEM
+        self.memory_free = ex + latency + self.memory_stall;
+        // MEM and WB follow the last cycle in EX
+        self.stats.cycles = ex + latency + self.memory_stall + 2;
+        self.fetch_stall = 0;
+        self.memory_stall = 0;
+        self.stats.instructions += 1;
+        // Other redirects, MRET or a trap, are never predicted
+        let mispredicted = self.predictor.as_mut().and_then(|predictor| predictor.record(pc, instruction, next_pc));
+        self.redirect = mispredicted.unwrap_or(next_pc != pc.wrapping_add(4));
+        // A trap raised by an ECALL or EBREAK the host serviced was this instruction's
+        self.trapped = false;
+    }
+
+    fn trap(&mut self, _trap: Trap, _pc: u32) {
+        self.trapped = true;
+    }
+}
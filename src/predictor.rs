@@ -0,0 +1,358 @@
+//! Branch prediction: direction predictors for conditional branches, a branch target
+//! buffer and a return-address stack, with misprediction statistics per branch.
+//!
+//! A [`BranchPredictor`] predicts the next pc of every control transfer as a fetch
+//! stage would: a conditional branch predicted taken, and any jump, goes to the target
+//! in the BTB, or falls through when the BTB has none; a return goes to the top of the
+//! return-address stack. A prediction is wrong when that pc is not the one executed.
+
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+
+use crate::hooks::Hooks;
+use crate::instruction::Instruction;
+
+/// Predicts the direction of conditional branches.
+pub trait DirectionPredictor: fmt::Debug {
+    /// Whether the branch at `pc` to `target` will be taken.
+    fn predict(&self, pc: u32, target: u32) -> bool;
+
+    /// Learn that the branch at `pc` to `target` was or was not taken.
+    fn update(&mut self, pc: u32, target: u32, taken: bool);
+}
+
+/// Predictions that ignore history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Static {
+    NotTaken,
+    Taken,
+    /// Backward branches, usually loops, taken and forward ones not.
+    BackwardTaken,
+}
+
+impl DirectionPredictor for Static {
+    fn predict(&self, pc: u32, target: u32) -> bool {
+        match self {
+            Static::NotTaken => false,
+            Static::Taken => true,
+            Static::BackwardTaken => target <= pc,
+        }
+    }
+
+    fn update(&mut self, _pc: u32, _target: u32, _taken: bool) {}
+}
+
+/// Two-bit saturating counters, predicting taken from 2 up; they start weakly not taken.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Counters(Vec<u8>);
+
+impl Counters {
+    /// `entries` counters, rounded up to a power of two.
+    fn new(entries: usize) -> Self {
+        Counters(vec![1; entries.max(1).next_power_of_two()])
+    }
+
+    fn index(&self, value: u32) -> usize {
+        value as usize & (self.0.len() - 1)
+    }
+
+    fn taken(&self, value: u32) -> bool {
+        self.0[self.index(value)] >= 2
+    }
+
+    fn update(&mut self, value: u32, taken: bool) {
+        let index = self.index(value);
+        let counter = &mut self.0[index];
+        *counter = if taken { (*counter + 1).min(3) } else { counter.saturating_sub(1) };
+    }
+}
+
+/// A table of two-bit counters indexed by branch address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bimodal {
+    counters: Counters,
+}
+
+impl Bimodal {
+    pub fn new(entries: usize) -> Self {
+        Bimodal { counters: Counters::new(entries) }
+    }
+}
+
+impl DirectionPredictor for Bimodal {
+    fn predict(&self, pc: u32, _target: u32) -> bool {
+        self.counters.taken(pc >> 2)
+    }
+
+    fn update(&mut self, pc: u32, _target: u32, taken: bool) {
+        self.counters.update(pc >> 2, taken);
+    }
+}
+
+/// Two-bit counters indexed by the branch address XORed with the global history of
+/// recent branch outcomes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gshare {
+    counters: Counters,
+    history: u32,
+    history_mask: u32,
+}
+
+impl Gshare {
+    /// `entries` counters and `history_bits` outcomes of history, at most 32.
+    pub fn new(entries: usize, history_bits: u32) -> Self {
+        let history_mask = if history_bits >= 32 { u32::MAX } else { (1 << history_bits) - 1 };
+        Gshare { counters: Counters::new(entries), history: 0, history_mask }
+    }
+
+    fn index(&self, pc: u32) -> u32 {
+        (pc >> 2) ^ self.history
+    }
+}
+
+impl DirectionPredictor for Gshare {
+    fn predict(&self, pc: u32, _target: u32) -> bool {
+        self.counters.taken(self.index(pc))
+    }
+
+    fn update(&mut self, pc: u32, _target: u32, taken: bool) {
+        self.counters.update(self.index(pc), taken);
+        self.history = ((self.history << 1) | taken as u32) & self.history_mask;
+    }
+}
+
+/// A bimodal and a gshare predictor, with a table of two-bit counters per branch
+/// address choosing between them (gshare from 2 up). A chooser learns only when the
+/// two disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tournament {
+    bimodal: Bimodal,
+    gshare: Gshare,
+    chooser: Counters,
+}
+
+impl Tournament {
+    /// Tables of `entries` counters each, with as many bits of history as index bits.
+    pub fn new(entries: usize) -> Self {
+        let bits = entries.max(1).next_power_of_two().trailing_zeros();
+        Tournament { bimodal: Bimodal::new(entries), gshare: Gshare::new(entries, bits), chooser: Counters::new(entries) }
+    }
+}
+
+impl DirectionPredictor for Tournament {
+    fn predict(&self, pc: u32, target: u32) -> bool {
+        if self.chooser.taken(pc >> 2) {
+            self.gshare.predict(pc, target)
+        } else {
+            self.bimodal.predict(pc, target)
+        }
+    }
+
+    fn update(&mut self, pc: u32, target: u32, taken: bool) {
+        let bimodal = self.bimodal.predict(pc, target) == taken;
+        let gshare = self.gshare.predict(pc, target) == taken;
+        if bimodal != gshare {
+            self.chooser.update(pc >> 2, gshare);
+        }
+        self.bimodal.update(pc, target, taken);
+        self.gshare.update(pc, target, taken);
+    }
+}
+
+/// Direct-mapped branch target buffer, tagged with the full branch address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Btb {
+    entries: Vec<Option<(u32, u32)>>,
+}
+
+impl Btb {
+    /// `entries` entries, rounded up to a power of two.
+    pub fn new(entries: usize) -> Self {
+        Btb { entries: vec![None; entries.max(1).next_power_of_two()] }
+    }
+
+    fn index(&self, pc: u32) -> usize {
+        (pc >> 2) as usize & (self.entries.len() - 1)
+    }
+
+    /// The target last taken by the control transfer at `pc`, if still held.
+    pub fn lookup(&self, pc: u32) -> Option<u32> {
+        self.entries[self.index(pc)].filter(|&(tag, _)| tag == pc).map(|(_, target)| target)
+    }
+
+    pub fn update(&mut self, pc: u32, target: u32) {
+        let index = self.index(pc);
+        self.entries[index] = Some((pc, target));
+    }
+}
+
+/// Return addresses of the calls in progress; when full, a call drops the oldest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReturnStack {
+    addresses: Vec<u32>,
+    depth: usize,
+}
+
+impl ReturnStack {
+    pub fn new(depth: usize) -> Self {
+        ReturnStack { addresses: Vec::with_capacity(depth), depth }
+    }
+
+    pub fn push(&mut self, address: u32) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.addresses.len() == self.depth {
+            self.addresses.remove(0);
+        }
+        self.addresses.push(address);
+    }
+
+    pub fn pop(&mut self) -> Option<u32> {
+        self.addresses.pop()
+    }
+}
+
+/// Executions and mispredictions of one branch, or of a kind of branch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchStats {
+    pub executed: u64,
+    pub mispredicted: u64,
+}
+
+impl BranchStats {
+    fn record(&mut self, mispredicted: bool) {
+        self.executed += 1;
+        self.mispredicted += mispredicted as u64;
+    }
+
+    /// Fraction of executions predicted correctly, 1.0 if there were none.
+    pub fn accuracy(&self) -> f64 {
+        1.0 - self.mispredicted as f64 / self.executed.max(1) as f64
+    }
+}
+
+/// Sizes of the tables of a [`BranchPredictor`].
+pub const DEFAULT_ENTRIES: usize = 1024;
+pub const DEFAULT_BTB_ENTRIES: usize = 512;
+pub const DEFAULT_RETURN_STACK_DEPTH: usize = 8;
+
+/// Link registers of the standard calling convention, ra and t0.
+fn is_link(register: u8) -> bool {
+    register == 1 || register == 5
+}
+
+/// A direction predictor, BTB and return-address stack predicting every control
+/// transfer the CPU retires, gathered as CPU [`Hooks`].
+#[derive(Debug)]
+pub struct BranchPredictor {
+    direction: Box<dyn DirectionPredictor>,
+    btb: Btb,
+    stack: ReturnStack,
+    branches: HashMap<u32, BranchStats>,
+    /// Conditional branches.
+    pub conditional: BranchStats,
+    /// JAL and JALR other than returns.
+    pub jumps: BranchStats,
+    /// JALR returning through a link register.
+    pub returns: BranchStats,
+}
+
+impl BranchPredictor {
+    /// Predict with `direction` and the default BTB and return-address stack.
+    pub fn new(direction: Box<dyn DirectionPredictor>) -> Self {
+        Self::with_tables(direction, Btb::new(DEFAULT_BTB_ENTRIES), ReturnStack::new(DEFAULT_RETURN_STACK_DEPTH))
+    }
+
+    pub fn with_tables(direction: Box<dyn DirectionPredictor>, btb: Btb, returns: ReturnStack) -> Self {
+        BranchPredictor {
+            direction,
+            btb,
+            stack: returns,
+            branches: HashMap::new(),
+            conditional: BranchStats::default(),
+            jumps: BranchStats::default(),
+            returns: BranchStats::default(),
+        }
+    }
+
+    /// Predict and learn the control transfer at `pc` that went to `next_pc`. Returns
+    /// whether it was mispredicted, or `None` for other instructions.
+    pub fn record(&mut self, pc: u32, instruction: &Instruction, next_pc: u32) -> Option<bool> {
+        let fall_through = pc.wrapping_add(4);
+        let (predicted, kind) = match *instruction {
+            Instruction::Beq(b) | Instruction::Bne(b) | Instruction::Blt(b) | Instruction::Bge(b)
+            | Instruction::Bltu(b) | Instruction::Bgeu(b) => {
+                let target = pc.wrapping_add(b.imm as u32);
+                let taken = next_pc != fall_through;
+                let predicted = match self.btb.lookup(pc) {
+                    Some(predicted) if self.direction.predict(pc, target) => predicted,
+                    _ => fall_through,
+                };
+                self.direction.update(pc, target, taken);
+                if taken {
+                    self.btb.update(pc, next_pc);
+                }
+                (predicted, &mut self.conditional)
+            }
+            Instruction::Jal(j) => {
+                let predicted = self.btb.lookup(pc).unwrap_or(fall_through);
+                self.btb.update(pc, next_pc);
+                if is_link(j.rd) {
+                    self.stack.push(fall_through);
+                }
+                (predicted, &mut self.jumps)
+            }
+            Instruction::Jalr(i) => {
+                let is_return = i.rd == 0 && is_link(i.rs1);
+                let predicted = if is_return { self.stack.pop() } else { None };
+                let predicted = predicted.or_else(|| self.btb.lookup(pc)).unwrap_or(fall_through);
+                self.btb.update(pc, next_pc);
+                if is_link(i.rd) {
+                    self.stack.push(fall_through);
+                }
+                (predicted, if is_return { &mut self.returns } else { &mut self.jumps })
+            }
+            _ => return None,
+        };
+        let mispredicted = predicted != next_pc;
+        kind.record(mispredicted);
+        self.branches.entry(pc).or_default().record(mispredicted);
+        Some(mispredicted)
+    }
+
+    /// Statistics of the control transfer at `pc`.
+    pub fn branch(&self, pc: u32) -> Option<BranchStats> {
+        self.branches.get(&pc).copied()
+    }
+
+    /// Control transfers by descending mispredictions, ties by address.
+    pub fn branches(&self) -> Vec<(u32, BranchStats)> {
+        let mut branches: Vec<(u32, BranchStats)> = self.branches.iter().map(|(&pc, &stats)| (pc, stats)).collect();
+        branches.sort_by(|a, b| b.1.mispredicted.cmp(&a.1.mispredicted).then(a.0.cmp(&b.0)));
+        branches
+    }
+
+    /// Human-readable report with the `worst` most mispredicted branches.
+    pub fn summary(&self, worst: usize) -> String {
+        let mut text = String::new();
+        for (name, stats) in [("conditional", self.conditional), ("jumps", self.jumps), ("returns", self.returns)] {
+            let accuracy = match stats.executed {
+                0 => "accuracy n/a".to_string(),
+                _ => format!("{:.2}% accurate", 100.0 * stats.accuracy()),
+            };
+            let _ = writeln!(text, "{:<12}  {} executed, {} mispredicted ({})", name, stats.executed, stats.mispredicted, accuracy);
+        }
+        let _ = writeln!(text, "\npc          executed  mispredicted");
+        for (pc, stats) in self.branches().into_iter().take(worst).filter(|(_, stats)| stats.mispredicted > 0) {
+            let _ = writeln!(text, "0x{:08x} {:>9} {:>13}", pc, stats.executed, stats.mispredicted);
+        }
+        text
+    }
+}
+
+impl Hooks for BranchPredictor {
+    fn post_execute(&mut self, pc: u32, instruction: &Instruction, next_pc: u32) {
+        self.record(pc, instruction, next_pc);
+    }
+}
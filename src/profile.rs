@@ -0,0 +1,191 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+
+use crate::disassembler::disassemble;
+use crate::hooks::Hooks;
+use crate::instruction::Instruction;
+use crate::symbols::SymbolTable;
+use crate::trap::Trap;
+
+/// Execution counts of one instruction address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PcProfile {
+    /// The instruction word last fetched from this address.
+    pub raw: u32,
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+/// Per-pc instruction and cycle profiler, attached to a CPU as its [`Hooks`].
+///
+/// The call stack is reconstructed from the RISC-V link register conventions: a JAL or
+/// JALR writing ra or t0 is a call, a JALR through ra or t0 that does not write one is a
+/// return. A trap handler is a frame of its own, unwound by MRET or SRET.
+pub struct Profiler {
+    pcs: BTreeMap<u32, PcProfile>,
+    /// Cycles spent in each call stack, keyed by the entry addresses of its functions.
+    stacks: HashMap<Vec<u32>, u64>,
+    stack: Vec<u32>,
+    // Stack depths at which trap handlers were entered
+    trap_depths: Vec<usize>,
+    // Pc of a trap raised since the last instruction. A host-serviced ECALL or EBREAK
+    // still retires, and a trap without a handler refetches the same pc; only a fetch
+    // elsewhere enters a handler.
+    trap_pending: Option<u32>,
+    cycle_cost: fn(&Instruction) -> u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    /// A profiler counting one cycle per instruction, as mcycle does.
+    pub fn new() -> Self {
+        Self::with_cycle_cost(|_| 1)
+    }
+
+    /// A profiler charging `cycle_cost(instruction)` cycles for each retired instruction.
+    pub fn with_cycle_cost(cycle_cost: fn(&Instruction) -> u64) -> Self {
+        Profiler {
+            pcs: BTreeMap::new(),
+            stacks: HashMap::new(),
+            stack: Vec::new(),
+            trap_depths: Vec::new(),
+            trap_pending: None,
+            cycle_cost,
+        }
+    }
+
+    pub fn pcs(&self) -> &BTreeMap<u32, PcProfile> {
+        &self.pcs
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.pcs.values().map(|profile| profile.instructions).sum()
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.pcs.values().map(|profile| profile.cycles).sum()
+    }
+
+    /// Entry addresses of the functions on the current call stack, outermost first.
+    pub fn call_stack(&self) -> &[u32] {
+        &self.stack
+    }
+
+    /// Listing of every executed instruction with its share of cycles, its instruction
+    /// and cycle counts and its disassembly, grouped under the enclosing symbols.
+    pub fn annotated(&self, symbols: &SymbolTable) -> String {
+        let total = self.cycles().max(1) as f64;
+        let mut listing = format!("{} instructions, {} cycles\n", self.instructions(), self.cycles());
+        let _ = writeln!(listing, "\n  cycles% instructions     cycles  address");
+        let mut function = None;
+        for (&pc, profile) in &self.pcs {
+            let symbol = symbols.symbolize(pc);
+            let name = symbol.map(|(name, _)| name);
+            if name != function {
+                if let Some((name, offset)) = symbol {
+                    let _ = writeln!(listing, "\n{:08x} <{}>:", pc - offset, name);
+                }
+                function = name;
+            }
+            let _ = writeln!(
+                listing,
+                "{:>8.2}% {:>12} {:>10}  0x{:08x}:\t{:08x}\t{}",
+                100.0 * profile.cycles as f64 / total,
+                profile.instructions,
+                profile.cycles,
+                pc,
+                profile.raw,
+                disassemble(profile.raw, pc)
+            );
+        }
+        listing
+    }
+
+    /// Cycles per call stack in the folded format read by flamegraph tools:
+    /// `main;compute;square 42` per line, outermost function first.
+    pub fn folded(&self, symbols: &SymbolTable) -> String {
+        let name = |address: u32| match symbols.symbolize(address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+0x{:x}", name, offset),
+            None => format!("0x{:08x}", address),
+        };
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, cycles)| {
+                let frames: Vec<String> = stack.iter().map(|&address| name(address)).collect();
+                format!("{} {}", frames.join(";"), cycles)
+            })
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    fn call(&mut self, target: u32) {
+        self.stack.push(target);
+    }
+
+    fn ret(&mut self) {
+        // Never unwind past the program entry or out of the current trap handler
+        let floor = self.trap_depths.last().map_or(1, |&depth| depth + 1);
+        if self.stack.len() > floor {
+            self.stack.pop();
+        }
+    }
+}
+
+impl Hooks for Profiler {
+    fn fetch(&mut self, pc: u32, raw: u32) {
+        if self.stack.is_empty() {
+            self.stack.push(pc);
+        }
+        if self.trap_pending.take().is_some_and(|trap_pc| trap_pc != pc) {
+            self.trap_depths.push(self.stack.len());
+            self.stack.push(pc);
+        }
+        self.pcs.entry(pc).or_default().raw = raw;
+    }
+
+    fn post_execute(&mut self, pc: u32, instruction: &Instruction, next_pc: u32) {
+        self.trap_pending = None;
+        let cycles = (self.cycle_cost)(instruction);
+        let profile = self.pcs.entry(pc).or_default();
+        profile.instructions += 1;
+        profile.cycles += cycles;
+        match self.stacks.get_mut(self.stack.as_slice()) {
+            Some(total) => *total += cycles,
+            None => {
+                self.stacks.insert(self.stack.clone(), cycles);
+            }
+        }
+
+        let link = |register: u8| register == 1 || register == 5;
+        match instruction {
+            Instruction::Jal(j) if link(j.rd) => self.call(next_pc),
+            Instruction::Jalr(i) => match (link(i.rd), link(i.rs1)) {
+                (true, true) if i.rd != i.rs1 => {
+                    self.ret();
+                    self.call(next_pc);
+                }
+                (true, _) => self.call(next_pc),
+                (false, true) => self.ret(),
+                (false, false) => {}
+            },
+            Instruction::Mret | Instruction::Sret => {
+                if let Some(depth) = self.trap_depths.pop() {
+                    self.stack.truncate(depth);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn trap(&mut self, _trap: Trap, pc: u32) {
+        self.trap_pending = Some(pc);
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+
+use crate::cpu::{Cpu, StopReason, WatchKind};
+use crate::csr::{CsrFile, Privilege};
+use crate::hooks::Hooks;
+use crate::memory::Memory;
+use crate::snapshot::Snapshot;
+
+/// Steps between checkpoints.
+pub const DEFAULT_INTERVAL: u64 = 10_000;
+/// Checkpoints kept; older history is discarded.
+pub const DEFAULT_CHECKPOINTS: usize = 64;
+
+/// Old contents of memory overwritten by a store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreUndo {
+    pub address: u32,
+    pub size: u32,
+    /// The `size` bytes the store overwrote, in memory order.
+    pub bytes: [u8; 4],
+    /// The values before and after the store, as loads would have read them.
+    pub old: u32,
+    pub new: u32,
+}
+
+/// State one step overwrote, recorded by the CPU's undo log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Undo {
+    pub pc: u32,
+    pub privilege: Privilege,
+    pub reservation: Option<u32>,
+    pub csrs: CsrFile,
+    pub trap_entered: bool,
+    /// Registers written and their previous values, oldest first.
+    pub registers: Vec<(u8, u32)>,
+    /// Stores made, oldest first.
+    pub stores: Vec<StoreUndo>,
+}
+
+/// Recorded execution of a CPU that can be run backwards.
+///
+/// A checkpoint snapshot is taken every `interval` steps and the undo log keeps the
+/// state overwritten by each step since the latest one. Stepping back past a checkpoint
+/// restores the previous checkpoint and replays forward to rebuild its undo log, so
+/// execution must be deterministic: changes made by the host between steps (register
+/// or memory edits, interrupt lines) are not recorded and call for [`History::clear`].
+/// Hooks observe replayed steps again.
+pub struct History {
+    interval: u64,
+    capacity: usize,
+    checkpoints: VecDeque<(u64, Snapshot)>,
+    undo: Vec<Undo>,
+    // Steps recorded since the history began
+    position: u64,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::with_limits(DEFAULT_INTERVAL, DEFAULT_CHECKPOINTS)
+    }
+
+    /// A history checkpointing every `interval` steps and keeping up to `checkpoints`
+    /// of them, so at least `interval * (checkpoints - 1)` steps can be reversed.
+    pub fn with_limits(interval: u64, checkpoints: usize) -> Self {
+        assert!(interval > 0 && checkpoints > 0, "history needs a checkpoint");
+        History { interval, capacity: checkpoints, checkpoints: VecDeque::new(), undo: Vec::new(), position: 0 }
+    }
+
+    /// Forget all recorded steps, e.g. after the host modified the machine state.
+    pub fn clear(&mut self) {
+        self.checkpoints.clear();
+        self.undo.clear();
+    }
+
+    /// Number of steps that can currently be reversed.
+    pub fn depth(&self) -> u64 {
+        self.checkpoints.front().map_or(0, |(start, _)| self.position - start)
+    }
+
+    /// Execute and record one instruction; see [`Cpu::step`].
+    pub fn step<H: Hooks>(&mut self, cpu: &mut Cpu<H>, memory: &mut Memory) -> StopReason {
+        if self.checkpoints.back().is_none_or(|(start, _)| self.position - start >= self.interval) {
+            self.checkpoints.push_back((self.position, cpu.snapshot(memory)));
+            self.undo.clear();
+            if self.checkpoints.len() > self.capacity {
+                self.checkpoints.pop_front();
+            }
+        }
+        cpu.set_undo_log(true);
+        let stop = cpu.step(memory);
+        if let Some(undo) = cpu.take_undo() {
+            self.undo.push(undo);
+            self.position += 1;
+        }
+        stop
+    }
+
+    /// Execute and record up to `max_instructions` instructions, stopping like
+    /// [`Cpu::run_for`].
+    pub fn run_for<H: Hooks>(&mut self, cpu: &mut Cpu<H>, memory: &mut Memory, max_instructions: u64) -> StopReason {
+        for executed in 0..max_instructions {
+            if executed > 0 && cpu.has_breakpoint(cpu.pc()) {
+                return StopReason::Breakpoint { pc: cpu.pc() };
+            }
+            match self.step(cpu, memory) {
+                StopReason::InstructionLimit => {}
+                stop => return stop,
+            }
+        }
+        StopReason::InstructionLimit
+    }
+
+    /// Undo one step. Returns `false` at the start of the history.
+    pub fn reverse_step<H: Hooks>(&mut self, cpu: &mut Cpu<H>, memory: &mut Memory) -> bool {
+        self.back(cpu, memory).is_some()
+    }
+
+    /// Undo up to `max_instructions` steps, stopping after undoing a store that triggers
+    /// a write or access watchpoint (with the pc at the storing instruction), when the pc
+    /// reaches a breakpoint, or at the start of the history. Read watchpoints only
+    /// trigger when running forwards.
+    pub fn reverse_for<H: Hooks>(&mut self, cpu: &mut Cpu<H>, memory: &mut Memory, max_instructions: u64) -> StopReason {
+        for _ in 0..max_instructions {
+            let Some(undo) = self.back(cpu, memory) else { return StopReason::NoHistory { pc: cpu.pc() } };
+            for store in undo.stores.iter().rev() {
+                let watchpoint = cpu.watchpoints().iter().find(|w| {
+                    w.kind != WatchKind::Read && w.triggers(store.address, store.size, true, store.new)
+                });
+                if let Some(watchpoint) = watchpoint {
+                    return StopReason::Watchpoint {
+                        pc: cpu.pc(),
+                        address: store.address,
+                        kind: watchpoint.kind,
+                        write: true,
+                        old: store.old,
+                        new: store.new,
+                    };
+                }
+            }
+            if cpu.has_breakpoint(cpu.pc()) {
+                return StopReason::Breakpoint { pc: cpu.pc() };
+            }
+        }
+        StopReason::InstructionLimit
+    }
+
+    fn back<H: Hooks>(&mut self, cpu: &mut Cpu<H>, memory: &mut Memory) -> Option<Undo> {
+        if self.undo.is_empty() {
+            // At a checkpoint: rebuild the previous segment's undo log by replaying it
+            if self.checkpoints.len() < 2 {
+                return None;
+            }
+            self.checkpoints.pop_back();
+            let (start, snapshot) = self.checkpoints.back()?;
+            let end = self.position;
+            self.position = *start;
+            cpu.restore(snapshot, memory);
+            while self.position < end {
+                if let StopReason::Trap { .. } = self.step(cpu, memory) {
+                    break;
+                }
+            }
+        }
+        let undo = self.undo.pop()?;
+        cpu.undo(&undo, memory);
+        self.position -= 1;
+        Some(undo)
+    }
+}
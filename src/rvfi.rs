@@ -0,0 +1,231 @@
+//! RISC-V Formal Interface (RVFI) trace export.
+//!
+//! Each executed instruction, including one that traps, produces one [`RvfiRecord`].
+//! Memory fields use the unaligned convention: `mem_addr` is the exact address
+//! accessed and the masks and data start at bit 0. An AMO reports both its read and
+//! its write.
+//!
+//! # Text format
+//!
+//! One line per record of space-separated `name=value` pairs in the field order of
+//! [`RvfiRecord`]; masks and data are hex with a `0x` prefix, everything else decimal.
+//!
+//! # Binary format
+//!
+//! An 8-byte header, the magic `RVFI`, a little-endian u16 version (1) and a u16
+//! record size (56), followed by fixed-size little-endian records:
+//!
+//! | offset | size | field     | offset | size | field     |
+//! |--------|------|-----------|--------|------|-----------|
+//! | 0      | 8    | order     | 36     | 4    | mem_rdata |
+//! | 8      | 4    | insn      | 40     | 4    | mem_wdata |
+//! | 12     | 4    | pc_rdata  | 44     | 1    | rs1_addr  |
+//! | 16     | 4    | pc_wdata  | 45     | 1    | rs2_addr  |
+//! | 20     | 4    | rs1_rdata | 46     | 1    | rd_addr   |
+//! | 24     | 4    | rs2_rdata | 47     | 1    | mode      |
+//! | 28     | 4    | rd_wdata  | 48     | 1    | mem_rmask |
+//! | 32     | 4    | mem_addr  | 49     | 1    | mem_wmask |
+//!
+//! Byte 50 holds the flags (bit 0 `trap`, bit 1 `halt`, bit 2 `intr`), byte 51 is
+//! `ixl` (1 for RV32) and bytes 52-55 are reserved and zero.
+
+use std::io::{self, Write};
+
+use crate::trace::Commit;
+
+pub const MAGIC: [u8; 4] = *b"RVFI";
+pub const VERSION: u16 = 1;
+pub const RECORD_SIZE: usize = 56;
+
+const FLAG_TRAP: u8 = 1 << 0;
+const FLAG_HALT: u8 = 1 << 1;
+const FLAG_INTR: u8 = 1 << 2;
+const IXL_32: u8 = 1;
+
+/// The RVFI signals of one instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RvfiRecord {
+    pub order: u64,
+    pub insn: u32,
+    pub trap: bool,
+    pub halt: bool,
+    pub intr: bool,
+    pub mode: u8,
+    pub rs1_addr: u8,
+    pub rs2_addr: u8,
+    pub rs1_rdata: u32,
+    pub rs2_rdata: u32,
+    pub rd_addr: u8,
+    pub rd_wdata: u32,
+    pub pc_rdata: u32,
+    pub pc_wdata: u32,
+    pub mem_addr: u32,
+    pub mem_rmask: u8,
+    pub mem_wmask: u8,
+    pub mem_rdata: u32,
+    pub mem_wdata: u32,
+}
+
+impl RvfiRecord {
+    pub fn from_commit(order: u64, commit: &Commit) -> Self {
+        let (rd_addr, rd_wdata) = commit.register_writes.last().copied().unwrap_or((0, 0));
+        let load = commit.loads.first();
+        let store = commit.stores.first();
+        let mask = |size: u32| ((1u32 << size) - 1) as u8;
+        RvfiRecord {
+            order,
+            insn: commit.raw,
+            trap: commit.trap,
+            halt: commit.halt,
+            intr: commit.interrupt,
+            mode: commit.privilege as u8,
+            rs1_addr: commit.rs1.0,
+            rs2_addr: commit.rs2.0,
+            rs1_rdata: commit.rs1.1,
+            rs2_rdata: commit.rs2.1,
+            rd_addr,
+            rd_wdata,
+            pc_rdata: commit.pc,
+            pc_wdata: commit.next_pc,
+            mem_addr: load.or(store).map_or(0, |access| access.address),
+            mem_rmask: load.map_or(0, |access| mask(access.size)),
+            mem_wmask: store.map_or(0, |access| mask(access.size)),
+            mem_rdata: load.map_or(0, |access| access.value),
+            mem_wdata: store.map_or(0, |access| access.value),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        format!(
+            "order={} insn=0x{:08x} trap={} halt={} intr={} mode={} \
+             rs1_addr={} rs2_addr={} rs1_rdata=0x{:08x} rs2_rdata=0x{:08x} rd_addr={} rd_wdata=0x{:08x} \
+             pc_rdata=0x{:08x} pc_wdata=0x{:08x} mem_addr=0x{:08x} mem_rmask=0x{:x} mem_wmask=0x{:x} \
+             mem_rdata=0x{:08x} mem_wdata=0x{:08x}",
+            self.order,
+            self.insn,
+            self.trap as u8,
+            self.halt as u8,
+            self.intr as u8,
+            self.mode,
+            self.rs1_addr,
+            self.rs2_addr,
+            self.rs1_rdata,
+            self.rs2_rdata,
+            self.rd_addr,
+            self.rd_wdata,
+            self.pc_rdata,
+            self.pc_wdata,
+            self.mem_addr,
+            self.mem_rmask,
+            self.mem_wmask,
+            self.mem_rdata,
+            self.mem_wdata
+        )
+    }
+
+    pub fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0u8; RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&self.order.to_le_bytes());
+        let words = [
+            self.insn,
+            self.pc_rdata,
+            self.pc_wdata,
+            self.rs1_rdata,
+            self.rs2_rdata,
+            self.rd_wdata,
+            self.mem_addr,
+            self.mem_rdata,
+            self.mem_wdata,
+        ];
+        for (index, word) in words.iter().enumerate() {
+            bytes[8 + 4 * index..12 + 4 * index].copy_from_slice(&word.to_le_bytes());
+        }
+        let flags = (self.trap as u8 * FLAG_TRAP) | (self.halt as u8 * FLAG_HALT) | (self.intr as u8 * FLAG_INTR);
+        bytes[44..52].copy_from_slice(&[
+            self.rs1_addr,
+            self.rs2_addr,
+            self.rd_addr,
+            self.mode,
+            self.mem_rmask,
+            self.mem_wmask,
+            flags,
+            IXL_32,
+        ]);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> Self {
+        let word = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        RvfiRecord {
+            order: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            insn: word(8),
+            pc_rdata: word(12),
+            pc_wdata: word(16),
+            rs1_rdata: word(20),
+            rs2_rdata: word(24),
+            rd_wdata: word(28),
+            mem_addr: word(32),
+            mem_rdata: word(36),
+            mem_wdata: word(40),
+            rs1_addr: bytes[44],
+            rs2_addr: bytes[45],
+            rd_addr: bytes[46],
+            mode: bytes[47],
+            mem_rmask: bytes[48],
+            mem_wmask: bytes[49],
+            trap: bytes[50] & FLAG_TRAP != 0,
+            halt: bytes[50] & FLAG_HALT != 0,
+            intr: bytes[50] & FLAG_INTR != 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RvfiFormat {
+    Text,
+    Binary,
+}
+
+/// Writes commits as numbered RVFI records.
+pub struct RvfiWriter<W: Write> {
+    output: W,
+    format: RvfiFormat,
+    order: u64,
+}
+
+impl<W: Write> RvfiWriter<W> {
+    /// Create a writer, emitting the header first for the binary format.
+    pub fn new(mut output: W, format: RvfiFormat) -> io::Result<Self> {
+        if format == RvfiFormat::Binary {
+            output.write_all(&MAGIC)?;
+            output.write_all(&VERSION.to_le_bytes())?;
+            output.write_all(&(RECORD_SIZE as u16).to_le_bytes())?;
+        }
+        Ok(RvfiWriter { output, format, order: 0 })
+    }
+
+    pub fn write(&mut self, commit: &Commit) -> io::Result<()> {
+        let record = RvfiRecord::from_commit(self.order, commit);
+        self.order += 1;
+        match self.format {
+            RvfiFormat::Text => writeln!(self.output, "{}", record.to_text()),
+            RvfiFormat::Binary => self.output.write_all(&record.to_bytes()),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+/// Parse a binary RVFI trace written by [`RvfiWriter`].
+pub fn read_binary(data: &[u8]) -> Option<Vec<RvfiRecord>> {
+    let (header, records) = data.split_at_checked(8)?;
+    if header[..4] != MAGIC || header[4..6] != VERSION.to_le_bytes() || header[6..8] != (RECORD_SIZE as u16).to_le_bytes() {
+        return None;
+    }
+    if !records.len().is_multiple_of(RECORD_SIZE) {
+        return None;
+    }
+    Some(records.chunks_exact(RECORD_SIZE).map(|chunk| RvfiRecord::from_bytes(chunk.try_into().unwrap())).collect())
+}
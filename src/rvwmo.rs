@@ -0,0 +1,371 @@
+//! Store buffering under the RVWMO memory model, and exhaustive exploration of small
+//! multi-hart litmus tests.
+//!
+//! With a [`StoreBuffer`] enabled (see [`Cpu::set_store_buffer`]), a hart's stores wait
+//! in its buffer until they are drained to memory, and its own loads read the newest
+//! buffered bytes first. Stores to different bytes may drain in any order, so other
+//! harts can see them out of order, while stores overlapping an older buffered store
+//! drain after it. A FENCE ordering earlier writes, FENCE.I and the atomics only execute
+//! with an empty buffer, and atomics reach memory as they execute.
+//!
+//! Loads are performed in program order, so outcomes that RVWMO only allows through
+//! reordered loads are not produced.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+
+use crate::cpu::{Cpu, StopReason};
+use crate::decoder::decode;
+use crate::instruction::Instruction;
+use crate::memory::{Endianness, Memory};
+use crate::snapshot::Snapshot;
+
+/// Predecessor bit of FENCE for memory writes.
+const FENCE_W: u8 = 0b0001;
+
+/// States a search visits before giving up.
+pub const DEFAULT_MAX_STATES: usize = 1_000_000;
+
+/// A store waiting in a store buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BufferedStore {
+    pub address: u32,
+    pub size: u32,
+    /// The `size` bytes to write, in memory order.
+    pub bytes: [u8; 4],
+}
+
+impl BufferedStore {
+    fn overlaps(&self, address: u32, size: u32) -> bool {
+        (address as u64) < self.address as u64 + self.size as u64 && (self.address as u64) < address as u64 + size as u64
+    }
+}
+
+/// Stores of one hart not yet visible to the others, oldest first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct StoreBuffer {
+    stores: Vec<BufferedStore>,
+}
+
+impl StoreBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stores(&self) -> &[BufferedStore] {
+        &self.stores
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stores.is_empty()
+    }
+
+    /// Buffer the low `size` bytes of `value`, in the byte order of `endianness`.
+    pub fn push(&mut self, address: u32, size: u32, value: u32, endianness: Endianness) {
+        let mut bytes = [0; 4];
+        match endianness {
+            Endianness::Little => bytes[..size as usize].copy_from_slice(&value.to_le_bytes()[..size as usize]),
+            Endianness::Big => bytes[..size as usize].copy_from_slice(&value.to_be_bytes()[4 - size as usize..]),
+        }
+        self.stores.push(BufferedStore { address, size, bytes });
+    }
+
+    /// Whether store `index` may drain now: no older buffered store overlaps it.
+    pub fn can_drain(&self, index: usize) -> bool {
+        let store = self.stores[index];
+        !self.stores[..index].iter().any(|older| older.overlaps(store.address, store.size))
+    }
+
+    /// Write store `index` to `memory` and remove it from the buffer.
+    pub fn drain(&mut self, index: usize, memory: &mut Memory) -> BufferedStore {
+        let store = self.stores.remove(index);
+        memory.write_bytes(store.address as usize, &store.bytes[..store.size as usize]);
+        store
+    }
+
+    /// Write every store to `memory`, oldest first.
+    pub fn drain_all(&mut self, memory: &mut Memory) {
+        while !self.stores.is_empty() {
+            self.drain(0, memory);
+        }
+    }
+
+    /// Zero-extended value of `size` bytes at `address` as this hart sees it: the
+    /// newest buffered bytes, then memory, in the current data byte order.
+    pub fn load(&self, memory: &Memory, address: u32, size: u32) -> u32 {
+        let mut bytes = [0; 4];
+        for (offset, byte) in bytes[..size as usize].iter_mut().enumerate() {
+            let address = address + offset as u32;
+            let newest = self.stores.iter().rev().find(|store| store.overlaps(address, 1));
+            *byte = match newest {
+                Some(store) => store.bytes[(address - store.address) as usize],
+                None => memory.bytes()[address as usize],
+            };
+        }
+        match memory.data_endianness() {
+            Endianness::Little => u32::from_le_bytes(bytes),
+            Endianness::Big => u32::from_be_bytes(bytes) >> (32 - 8 * size),
+        }
+    }
+}
+
+/// Whether `instruction` only executes once the hart's store buffer is empty.
+pub fn orders_stores(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::Fence(fence) => fence.pred & FENCE_W != 0,
+        Instruction::FenceI(_) => true,
+        other => is_atomic(other),
+    }
+}
+
+fn is_atomic(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::LrW(_)
+            | Instruction::ScW(_)
+            | Instruction::AmoswapW(_)
+            | Instruction::AmoaddW(_)
+            | Instruction::AmoxorW(_)
+            | Instruction::AmoandW(_)
+            | Instruction::AmoorW(_)
+            | Instruction::AmominW(_)
+            | Instruction::AmomaxW(_)
+            | Instruction::AmominuW(_)
+            | Instruction::AmomaxuW(_)
+    )
+}
+
+/// A value a litmus test reads from each final state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Observation {
+    Register { hart: usize, register: u8 },
+    /// The little-endian word at this address.
+    Memory { address: u32 },
+}
+
+/// A program run by several harts on shared memory, each starting at its own pc.
+pub struct Litmus {
+    memory: Memory,
+    starts: Vec<u32>,
+    observations: Vec<Observation>,
+    max_states: usize,
+}
+
+/// Results of [`Litmus::explore`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exploration {
+    pub observations: Vec<Observation>,
+    /// The observed values of every reachable final state, in observation order.
+    pub outcomes: BTreeSet<Vec<u32>>,
+    /// Distinct states visited.
+    pub states: usize,
+    /// Whether every reachable state was visited, rather than the search stopping at
+    /// the state limit.
+    pub complete: bool,
+}
+
+impl Exploration {
+    /// Whether some final state has these observed values.
+    pub fn allows(&self, values: &[u32]) -> bool {
+        self.outcomes.contains(values)
+    }
+
+    /// One line per outcome, such as `hart 0 x10=1, hart 1 x10=0, [0x100]=1`.
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        for outcome in &self.outcomes {
+            let values: Vec<String> = self
+                .observations
+                .iter()
+                .zip(outcome)
+                .map(|(observation, value)| match observation {
+                    Observation::Register { hart, register } => format!("hart {} x{}={}", hart, register, value),
+                    Observation::Memory { address } => format!("[0x{:x}]={}", address, value),
+                })
+                .collect();
+            let _ = writeln!(out, "{}", values.join(", "));
+        }
+        let _ = writeln!(
+            out,
+            "{} outcomes from {} states{}",
+            self.outcomes.len(),
+            self.states,
+            if self.complete { "" } else { " (incomplete: state limit reached)" }
+        );
+        out
+    }
+}
+
+#[derive(Clone)]
+struct Hart {
+    // Architectural state, with the memory held once by the state
+    snapshot: Snapshot,
+    buffer: StoreBuffer,
+    done: bool,
+}
+
+#[derive(Clone)]
+struct State {
+    harts: Vec<Hart>,
+    memory: Vec<u8>,
+}
+
+impl State {
+    /// Hash of everything that affects later execution. The cycle and instret counters
+    /// are left out so that spinning in a loop revisits the same state.
+    fn key(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for hart in &self.harts {
+            let mut snapshot = hart.snapshot.clone();
+            snapshot.csrs.cycle = 0;
+            snapshot.csrs.instret = 0;
+            snapshot.to_bytes().hash(&mut hasher);
+            hart.buffer.hash(&mut hasher);
+            hart.done.hash(&mut hasher);
+        }
+        self.memory.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl Litmus {
+    /// Harts starting at `starts` on `memory`, with mhartid numbering them from 0.
+    pub fn new(memory: Memory, starts: Vec<u32>) -> Self {
+        Litmus { memory, starts, observations: Vec::new(), max_states: DEFAULT_MAX_STATES }
+    }
+
+    /// Record `observation` in every final state.
+    pub fn observe(&mut self, observation: Observation) {
+        self.observations.push(observation);
+    }
+
+    pub fn set_max_states(&mut self, max_states: usize) {
+        self.max_states = max_states;
+    }
+
+    /// Visit every interleaving of the harts' instructions and store buffer drains
+    /// and collect the observations of the final states, where every hart has
+    /// stopped and every buffer is empty. A hart is done when it halts or stops for any
+    /// other reason, such as an unhandled trap. Since the counters are not part of a
+    /// state, tests should not read them.
+    pub fn explore(&self) -> Exploration {
+        let mut memory = Memory::with_size(self.memory.size());
+        let mut cpus: Vec<Cpu> = (0..self.starts.len()).map(|_| Cpu::new()).collect();
+        let harts = cpus
+            .iter_mut()
+            .zip(&self.starts)
+            .enumerate()
+            .map(|(index, (cpu, &start))| {
+                cpu.set_decode_cache(false);
+                cpu.set_store_buffer(true);
+                cpu.set_hart_id(index as u32);
+                cpu.set_pc(start);
+                let mut snapshot = cpu.snapshot(&self.memory);
+                snapshot.memory = Vec::new();
+                Hart { snapshot, buffer: StoreBuffer::new(), done: false }
+            })
+            .collect();
+        let initial = State { harts, memory: self.memory.bytes().to_vec() };
+
+        let mut exploration = Exploration {
+            observations: self.observations.clone(),
+            outcomes: BTreeSet::new(),
+            states: 0,
+            complete: true,
+        };
+        let mut visited = HashSet::new();
+        let mut pending = vec![initial];
+        while let Some(state) = pending.pop() {
+            if !visited.insert(state.key()) {
+                continue;
+            }
+            if visited.len() > self.max_states {
+                exploration.complete = false;
+                break;
+            }
+            let mut successors = 0;
+            for (hart, cpu) in cpus.iter_mut().enumerate() {
+                for index in 0..state.harts[hart].buffer.stores().len() {
+                    if state.harts[hart].buffer.can_drain(index) {
+                        let mut next = state.clone();
+                        memory.replace_bytes(&next.memory);
+                        let store = next.harts[hart].buffer.drain(index, &mut memory);
+                        next.memory = memory.bytes().to_vec();
+                        break_reservations(&mut next, hart, &store);
+                        pending.push(next);
+                        successors += 1;
+                    }
+                }
+                if let Some(next) = self.step(&state, hart, cpu, &mut memory) {
+                    pending.push(next);
+                    successors += 1;
+                }
+            }
+            if successors == 0 && state.harts.iter().all(|hart| hart.done) {
+                exploration.outcomes.insert(self.outcome(&state));
+            }
+        }
+        exploration.states = visited.len().min(self.max_states);
+        exploration
+    }
+
+    /// The state after `hart` executes one instruction, if it can.
+    fn step(&self, state: &State, hart: usize, cpu: &mut Cpu, memory: &mut Memory) -> Option<State> {
+        let current = &state.harts[hart];
+        if current.done {
+            return None;
+        }
+        let pc = current.snapshot.pc as usize;
+        let word = state.memory.get(pc..pc + 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()));
+        let instruction = word.and_then(|word| decode(word).ok());
+        if !current.buffer.is_empty() && instruction.as_ref().is_some_and(orders_stores) {
+            return None;
+        }
+
+        let mut snapshot = current.snapshot.clone();
+        snapshot.memory = state.memory.clone();
+        cpu.restore(&snapshot, memory);
+        if let Some(buffer) = cpu.store_buffer_mut() {
+            *buffer = current.buffer.clone();
+        }
+        let stop = cpu.step(memory);
+
+        let mut next = state.clone();
+        let mut buffer = cpu.store_buffer_mut().map(std::mem::take).unwrap_or_default();
+        // Atomics act on memory directly, so their store is the only one buffered
+        if instruction.as_ref().is_some_and(is_atomic) {
+            for store in buffer.stores().to_vec() {
+                break_reservations(&mut next, hart, &store);
+            }
+            buffer.drain_all(memory);
+        }
+        let mut snapshot = cpu.snapshot(memory);
+        next.memory = std::mem::take(&mut snapshot.memory);
+        next.harts[hart] = Hart { snapshot, buffer, done: stop != StopReason::InstructionLimit };
+        Some(next)
+    }
+
+    fn outcome(&self, state: &State) -> Vec<u32> {
+        self.observations
+            .iter()
+            .map(|observation| match *observation {
+                Observation::Register { hart, register } => state.harts[hart].snapshot.registers[register as usize],
+                Observation::Memory { address } => {
+                    let address = address as usize;
+                    u32::from_le_bytes(state.memory[address..address + 4].try_into().unwrap())
+                }
+            })
+            .collect()
+    }
+}
+
+/// A store by `hart` reached memory, breaking the other harts' reservations on it.
+fn break_reservations(state: &mut State, hart: usize, store: &BufferedStore) {
+    for (index, other) in state.harts.iter_mut().enumerate() {
+        if index != hart && other.snapshot.reservation.is_some_and(|reserved| store.overlaps(reserved, 4)) {
+            other.snapshot.reservation = None;
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::fmt;
+
+use crate::cpu::{Cpu, StopReason};
+use crate::hooks::{Hooks, NoHooks};
+use crate::memory::Memory;
+
+/// Instructions a hart runs before the next one gets its turn.
+pub const DEFAULT_QUANTUM: u64 = 1000;
+
+/// Why [`System::run_for`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemStop {
+    /// The instruction budget ran out.
+    InstructionLimit,
+    /// Every hart has halted; see [`System::exit_code`].
+    Halted,
+    /// Every hart that has not halted is waiting for an interrupt.
+    Idle,
+    /// A hart stopped for another reason, such as a breakpoint or an unhandled trap.
+    Hart { hart: usize, reason: StopReason },
+}
+
+impl fmt::Display for SystemStop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SystemStop::InstructionLimit => write!(f, "instruction limit reached"),
+            SystemStop::Halted => write!(f, "all harts halted"),
+            SystemStop::Idle => write!(f, "all harts waiting for interrupts"),
+            SystemStop::Hart { hart, reason } => write!(f, "hart {}: {}", hart, reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HartState {
+    Running,
+    Waiting,
+    Halted(u32),
+}
+
+/// Harts sharing one memory, with mhartid numbering them from 0.
+///
+/// Harts take turns in order, each running `quantum` instructions or until it stops,
+/// so a run is deterministic and splitting it into several calls does not change it.
+/// Between turns the stores of the hart that ran are shown to the others, breaking
+/// their LR reservations on the bytes written and dropping any code they had cached
+/// from them. A hart waiting for an interrupt is skipped until one is pending, and a
+/// hart that halted is skipped for good.
+pub struct System<H: Hooks = NoHooks> {
+    harts: Vec<Cpu<H>>,
+    states: Vec<HartState>,
+    memory: Memory,
+    quantum: u64,
+    // Hart whose turn it is and the instructions it has run in that turn
+    current: usize,
+    used: u64,
+    // The current hart stopped and runs its first instruction without a breakpoint check
+    resuming: bool,
+    stores: Vec<(u32, u32)>,
+}
+
+impl System {
+    /// `count` harts, all starting at address 0.
+    pub fn new(count: usize, memory: Memory) -> Self {
+        System::with_harts((0..count).map(|_| Cpu::new()).collect(), memory)
+    }
+}
+
+impl<H: Hooks> System<H> {
+    /// Run `harts` on `memory`, setting each one's mhartid to its index.
+    pub fn with_harts(mut harts: Vec<Cpu<H>>, memory: Memory) -> Self {
+        for (index, hart) in harts.iter_mut().enumerate() {
+            hart.set_hart_id(index as u32);
+            hart.set_store_log(true);
+        }
+        System {
+            states: vec![HartState::Running; harts.len()],
+            harts,
+            memory,
+            quantum: DEFAULT_QUANTUM,
+            current: 0,
+            used: 0,
+            resuming: false,
+            stores: Vec::new(),
+        }
+    }
+
+    pub fn quantum(&self) -> u64 {
+        self.quantum
+    }
+
+    /// Set the length of a turn, at least one instruction. A quantum of 1 interleaves
+    /// the harts instruction by instruction.
+    pub fn set_quantum(&mut self, quantum: u64) {
+        self.quantum = quantum.max(1);
+        if self.used >= self.quantum {
+            self.next_turn();
+        }
+    }
+
+    pub fn harts(&self) -> &[Cpu<H>] {
+        &self.harts
+    }
+
+    pub fn hart(&self, hart: usize) -> &Cpu<H> {
+        &self.harts[hart]
+    }
+
+    /// A hart, for inspection or to raise its interrupt lines. Call
+    /// [`Cpu::flush_decode_cache`] on every hart after changing code in memory.
+    pub fn hart_mut(&mut self, hart: usize) -> &mut Cpu<H> {
+        &mut self.harts[hart]
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    /// Exit code of `hart`, once it has halted.
+    pub fn exit_code(&self, hart: usize) -> Option<u32> {
+        match self.states[hart] {
+            HartState::Halted(exit_code) => Some(exit_code),
+            _ => None,
+        }
+    }
+
+    /// Run up to `max_instructions` instructions across all harts.
+    pub fn run_for(&mut self, max_instructions: u64) -> SystemStop {
+        let mut executed = 0;
+        loop {
+            if self.states.iter().all(|state| matches!(state, HartState::Halted(_))) {
+                return SystemStop::Halted;
+            }
+            // Wake harts whose interrupts are pending
+            for (hart, state) in self.harts.iter().zip(&mut self.states) {
+                if *state == HartState::Waiting && hart.csrs().mip & hart.csrs().mie != 0 {
+                    *state = HartState::Running;
+                }
+            }
+            if !self.states.contains(&HartState::Running) {
+                return SystemStop::Idle;
+            }
+            if executed >= max_instructions {
+                return SystemStop::InstructionLimit;
+            }
+            if self.states[self.current] != HartState::Running {
+                self.next_turn();
+                continue;
+            }
+
+            let hart = self.current;
+            // A hart only skips the breakpoint check when resuming, not at each turn
+            let pc = self.harts[hart].pc();
+            if self.used == 0 && !self.resuming && self.harts[hart].has_breakpoint(pc) {
+                self.resuming = true;
+                return SystemStop::Hart { hart, reason: StopReason::Breakpoint { pc } };
+            }
+            self.resuming = false;
+            let budget = (self.quantum - self.used).min(max_instructions - executed);
+            let instret = self.harts[hart].instret();
+            let stop = self.harts[hart].run_for(&mut self.memory, budget);
+            self.share_stores(hart);
+            if stop == StopReason::InstructionLimit {
+                executed += budget;
+                self.used += budget;
+                if self.used == self.quantum {
+                    self.next_turn();
+                }
+                continue;
+            }
+            // Any other stop ends the turn
+            executed += self.harts[hart].instret().wrapping_sub(instret);
+            self.next_turn();
+            match stop {
+                StopReason::Halted { exit_code } => self.states[hart] = HartState::Halted(exit_code),
+                StopReason::WaitForInterrupt { .. } => self.states[hart] = HartState::Waiting,
+                reason => {
+                    // Resume with the stopped hart, as a single hart would
+                    self.current = hart;
+                    self.resuming = true;
+                    return SystemStop::Hart { hart, reason };
+                }
+            }
+        }
+    }
+
+    /// Run until something other than the instruction limit stops the system.
+    pub fn run(&mut self) -> SystemStop {
+        loop {
+            match self.run_for(u64::MAX) {
+                SystemStop::InstructionLimit => continue,
+                stop => return stop,
+            }
+        }
+    }
+
+    fn next_turn(&mut self) {
+        self.current = (self.current + 1) % self.harts.len();
+        self.used = 0;
+    }
+
+    /// Show the stores `hart` made to the other harts.
+    fn share_stores(&mut self, hart: usize) {
+        self.harts[hart].drain_stores(&mut self.stores);
+        for (index, other) in self.harts.iter_mut().enumerate() {
+            if index != hart {
+                for &(address, size) in &self.stores {
+                    other.remote_store(address, size);
+                }
+            }
+        }
+        self.stores.clear();
+    }
+}
@@ -0,0 +1,223 @@
+//! Versioned machine snapshots.
+//!
+//! A snapshot file is the magic `RVSNAP`, a little-endian u16 version (1), then a
+//! sequence of sections, each a 4-byte tag, a u32 payload length and the payload:
+//!
+//! - `CPU `: pc, privilege (u8), LR reservation (u8 present flag, u32 address) and x0-x31.
+//! - `CSRS`: the [`CsrFile`] fields in declaration order, u32 each except the u64
+//!   `cycle` and `instret`.
+//! - `MEM `: data endianness (u8, 0 little, 1 big), memory size (u32, at most
+//!   [`MAX_MEMORY_SIZE`]), then every 4 KiB page holding a non-zero byte as its u32
+//!   offset followed by the page contents (the last page may be shorter).
+//!
+//! All integers are little-endian. The only device state is the interrupt lines held
+//! in mip, which is saved with the CSRs; memory-mapped devices would add sections of
+//! their own.
+
+use std::fmt;
+
+use crate::csr::{CsrFile, Privilege};
+use crate::memory::Endianness;
+
+pub const MAGIC: [u8; 6] = *b"RVSNAP";
+pub const VERSION: u16 = 1;
+/// Largest memory a snapshot may describe. The size is read before any page, so a
+/// corrupt size field is rejected here instead of being allocated.
+pub const MAX_MEMORY_SIZE: u32 = 1 << 30;
+
+const PAGE_SIZE: usize = 4096;
+const SECTION_CPU: [u8; 4] = *b"CPU ";
+const SECTION_CSRS: [u8; 4] = *b"CSRS";
+const SECTION_MEMORY: [u8; 4] = *b"MEM ";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The data does not start with the snapshot magic.
+    NotSnapshot,
+    /// A snapshot written by an incompatible version.
+    UnsupportedVersion(u16),
+    /// A section extends past the end of the data.
+    Truncated,
+    /// A section is missing, unknown or holds an invalid value.
+    Malformed(&'static str),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::NotSnapshot => write!(f, "not a snapshot file"),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "unsupported snapshot version {}", version),
+            SnapshotError::Truncated => write!(f, "truncated snapshot"),
+            SnapshotError::Malformed(what) => write!(f, "malformed snapshot: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// Architectural state of a hart and its memory, taken with
+/// [`Cpu::snapshot`](crate::cpu::Cpu::snapshot).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub registers: [u32; 32],
+    pub pc: u32,
+    pub privilege: Privilege,
+    pub reservation: Option<u32>,
+    pub csrs: CsrFile,
+    pub memory: Vec<u8>,
+    pub data_endianness: Endianness,
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&VERSION.to_le_bytes());
+
+        let mut cpu = Vec::new();
+        cpu.extend_from_slice(&self.pc.to_le_bytes());
+        cpu.push(self.privilege as u8);
+        cpu.push(self.reservation.is_some() as u8);
+        cpu.extend_from_slice(&self.reservation.unwrap_or(0).to_le_bytes());
+        for register in self.registers {
+            cpu.extend_from_slice(&register.to_le_bytes());
+        }
+        push_section(&mut data, SECTION_CPU, &cpu);
+
+        let c = &self.csrs;
+        let mut csrs = Vec::new();
+        for value in [
+            c.mstatus, c.mstatush, c.mhartid, c.mtvec, c.medeleg, c.mideleg, c.mie, c.mip, c.mscratch, c.mepc,
+            c.mcause, c.mtval, c.mcounteren, c.stvec, c.sscratch, c.sepc, c.scause, c.stval, c.satp, c.scounteren,
+        ] {
+            csrs.extend_from_slice(&value.to_le_bytes());
+        }
+        csrs.extend_from_slice(&c.cycle.to_le_bytes());
+        csrs.extend_from_slice(&c.instret.to_le_bytes());
+        push_section(&mut data, SECTION_CSRS, &csrs);
+
+        let mut memory = vec![(self.data_endianness == Endianness::Big) as u8];
+        memory.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        for (index, page) in self.memory.chunks(PAGE_SIZE).enumerate() {
+            if page.iter().any(|&byte| byte != 0) {
+                memory.extend_from_slice(&((index * PAGE_SIZE) as u32).to_le_bytes());
+                memory.extend_from_slice(page);
+            }
+        }
+        push_section(&mut data, SECTION_MEMORY, &memory);
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, SnapshotError> {
+        let mut reader = Reader { data, position: 0 };
+        if !data.starts_with(&MAGIC) {
+            return Err(SnapshotError::NotSnapshot);
+        }
+        reader.take(MAGIC.len())?;
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let (mut cpu, mut csrs, mut memory) = (None, None, None);
+        while reader.position < data.len() {
+            let tag: [u8; 4] = reader.take(4)?.try_into().unwrap();
+            let length = reader.u32()? as usize;
+            let mut section = Reader { data: reader.take(length)?, position: 0 };
+            match tag {
+                SECTION_CPU => cpu = Some(read_cpu(&mut section)?),
+                SECTION_CSRS => csrs = Some(read_csrs(&mut section)?),
+                SECTION_MEMORY => memory = Some(read_memory(&mut section)?),
+                _ => return Err(SnapshotError::Malformed("unknown section")),
+            }
+        }
+
+        let (registers, pc, privilege, reservation) = cpu.ok_or(SnapshotError::Malformed("missing CPU section"))?;
+        let csrs = csrs.ok_or(SnapshotError::Malformed("missing CSR section"))?;
+        let (memory, data_endianness) = memory.ok_or(SnapshotError::Malformed("missing memory section"))?;
+        Ok(Snapshot { registers, pc, privilege, reservation, csrs, memory, data_endianness })
+    }
+}
+
+fn push_section(data: &mut Vec<u8>, tag: [u8; 4], payload: &[u8]) {
+    data.extend_from_slice(&tag);
+    data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    data.extend_from_slice(payload);
+}
+
+fn read_cpu(section: &mut Reader) -> Result<([u32; 32], u32, Privilege, Option<u32>), SnapshotError> {
+    let pc = section.u32()?;
+    let privilege = Privilege::from_bits(section.u8()? as u32).ok_or(SnapshotError::Malformed("invalid privilege"))?;
+    let reserved = section.u8()? != 0;
+    let address = section.u32()?;
+    let mut registers = [0; 32];
+    for register in registers.iter_mut() {
+        *register = section.u32()?;
+    }
+    registers[0] = 0;
+    Ok((registers, pc, privilege, reserved.then_some(address)))
+}
+
+fn read_csrs(section: &mut Reader) -> Result<CsrFile, SnapshotError> {
+    let mut c = CsrFile::new(0);
+    for field in [
+        &mut c.mstatus, &mut c.mstatush, &mut c.mhartid, &mut c.mtvec, &mut c.medeleg, &mut c.mideleg, &mut c.mie,
+        &mut c.mip, &mut c.mscratch, &mut c.mepc, &mut c.mcause, &mut c.mtval, &mut c.mcounteren, &mut c.stvec,
+        &mut c.sscratch, &mut c.sepc, &mut c.scause, &mut c.stval, &mut c.satp, &mut c.scounteren,
+    ] {
+        *field = section.u32()?;
+    }
+    c.cycle = section.u64()?;
+    c.instret = section.u64()?;
+    Ok(c)
+}
+
+fn read_memory(section: &mut Reader) -> Result<(Vec<u8>, Endianness), SnapshotError> {
+    let endianness = match section.u8()? {
+        0 => Endianness::Little,
+        1 => Endianness::Big,
+        _ => return Err(SnapshotError::Malformed("invalid endianness")),
+    };
+    let size = section.u32()?;
+    if size > MAX_MEMORY_SIZE {
+        return Err(SnapshotError::Malformed("memory size too large"));
+    }
+    let mut memory = vec![0; size as usize];
+    while section.position < section.data.len() {
+        let offset = section.u32()? as usize;
+        if !offset.is_multiple_of(PAGE_SIZE) || offset >= memory.len() {
+            return Err(SnapshotError::Malformed("memory page out of range"));
+        }
+        let length = PAGE_SIZE.min(memory.len() - offset);
+        memory[offset..offset + length].copy_from_slice(section.take(length)?);
+    }
+    Ok((memory, endianness))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], SnapshotError> {
+        let bytes = self.data.get(self.position..self.position + length).ok_or(SnapshotError::Truncated)?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
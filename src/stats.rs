@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use crate::decoder;
+use crate::hooks::Hooks;
+use crate::instruction::Instruction;
+use crate::trap::Trap;
+
+/// Instruction mix and memory traffic of a run, gathered as CPU [`Hooks`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Statistics {
+    /// Retired instructions per mnemonic.
+    pub mnemonics: HashMap<&'static str, u64>,
+    /// Conditional branches, by whether their condition held.
+    pub branches_taken: u64,
+    pub branches_not_taken: u64,
+    pub loads: u64,
+    pub load_bytes: u64,
+    pub stores: u64,
+    pub store_bytes: u64,
+    /// Traps that entered a handler, including interrupts. An EBREAK or exit ECALL
+    /// serviced by the host, or a trap that stopped the run, is not counted.
+    pub traps: u64,
+    /// pc of the trap last raised, until the next fetch shows whether it was taken.
+    trap_pending: Option<u32>,
+}
+
+impl Statistics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.mnemonics.values().sum()
+    }
+
+    /// Retired instructions per encoding format, in decoder table order.
+    pub fn formats(&self) -> Vec<(String, u64)> {
+        let mut formats: Vec<(String, u64)> = Vec::new();
+        for pattern in decoder::PATTERNS {
+            let Some(&count) = self.mnemonics.get(pattern.mnemonic) else { continue };
+            let name = format!("{:?}", pattern.format);
+            match formats.iter_mut().find(|(format, _)| *format == name) {
+                Some((_, total)) => *total += count,
+                None => formats.push((name, count)),
+            }
+        }
+        formats
+    }
+
+    /// Millions of instructions per second over `elapsed`.
+    pub fn mips(&self, elapsed: Duration) -> f64 {
+        self.instructions() as f64 / elapsed.as_secs_f64().max(f64::MIN_POSITIVE) / 1e6
+    }
+
+    /// Human-readable report for a run that took `elapsed`.
+    pub fn summary(&self, elapsed: Duration) -> String {
+        let total = self.instructions().max(1) as f64;
+        let mut text = String::new();
+        let _ = writeln!(text, "instructions  {}", self.instructions());
+        let _ = writeln!(text, "elapsed       {:.6} s ({:.2} MIPS)", elapsed.as_secs_f64(), self.mips(elapsed));
+        let _ = writeln!(text, "branches      {} taken, {} not taken", self.branches_taken, self.branches_not_taken);
+        let _ = writeln!(text, "loads         {} ({} bytes)", self.loads, self.load_bytes);
+        let _ = writeln!(text, "stores        {} ({} bytes)", self.stores, self.store_bytes);
+        let _ = writeln!(text, "traps         {}", self.traps);
+        let _ = writeln!(text, "\nformat          count  percent");
+        for (format, count) in self.formats() {
+            let _ = writeln!(text, "{:<10} {:>10} {:>7.2}%", format, count, 100.0 * count as f64 / total);
+        }
+        let _ = writeln!(text, "\nmnemonic        count  percent");
+        for (mnemonic, count) in self.sorted_mnemonics() {
+            let _ = writeln!(text, "{:<10} {:>10} {:>7.2}%", mnemonic, count, 100.0 * count as f64 / total);
+        }
+        text
+    }
+
+    /// The same figures as [`Statistics::summary`] as a JSON object.
+    pub fn to_json(&self, elapsed: Duration) -> String {
+        let object = |entries: Vec<(String, u64)>| {
+            let fields: Vec<String> = entries.iter().map(|(name, count)| format!("\"{}\": {}", name, count)).collect();
+            format!("{{{}}}", fields.join(", "))
+        };
+        let mnemonics = self.sorted_mnemonics().into_iter().map(|(mnemonic, count)| (mnemonic.to_string(), count)).collect();
+        let mut json = String::from("{\n");
+        let _ = writeln!(json, "  \"instructions\": {},", self.instructions());
+        let _ = writeln!(json, "  \"seconds\": {:.6},", elapsed.as_secs_f64());
+        let _ = writeln!(json, "  \"mips\": {:.3},", self.mips(elapsed));
+        let _ = writeln!(json, "  \"branches\": {{\"taken\": {}, \"not_taken\": {}}},", self.branches_taken, self.branches_not_taken);
+        let _ = writeln!(json, "  \"loads\": {{\"count\": {}, \"bytes\": {}}},", self.loads, self.load_bytes);
+        let _ = writeln!(json, "  \"stores\": {{\"count\": {}, \"bytes\": {}}},", self.stores, self.store_bytes);
+        let _ = writeln!(json, "  \"traps\": {},", self.traps);
+        let _ = writeln!(json, "  \"formats\": {},", object(self.formats()));
+        let _ = writeln!(json, "  \"mnemonics\": {}", object(mnemonics));
+        json.push_str("}\n");
+        json
+    }
+
+    /// Mnemonics by descending count, ties by name.
+    fn sorted_mnemonics(&self) -> Vec<(&'static str, u64)> {
+        let mut mnemonics: Vec<(&'static str, u64)> = self.mnemonics.iter().map(|(&mnemonic, &count)| (mnemonic, count)).collect();
+        mnemonics.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        mnemonics
+    }
+}
+
+impl Hooks for Statistics {
+    fn fetch(&mut self, pc: u32, _raw: u32) {
+        if self.trap_pending.take().is_some_and(|trap_pc| trap_pc != pc) {
+            self.traps += 1;
+        }
+    }
+
+    fn post_execute(&mut self, _pc: u32, instruction: &Instruction, _next_pc: u32) {
+        self.trap_pending = None;
+        *self.mnemonics.entry(instruction.mnemonic()).or_insert(0) += 1;
+    }
+
+    fn branch(&mut self, _pc: u32, taken: bool) {
+        if taken {
+            self.branches_taken += 1;
+        } else {
+            self.branches_not_taken += 1;
+        }
+    }
+
+    fn memory_read(&mut self, _address: u32, size: u32, _value: u32) {
+        self.loads += 1;
+        self.load_bytes += size as u64;
+    }
+
+    fn memory_write(&mut self, _address: u32, size: u32, _value: u32) {
+        self.stores += 1;
+        self.store_bytes += size as u64;
+    }
+
+    fn trap(&mut self, _trap: Trap, pc: u32) {
+        self.trap_pending = Some(pc);
+    }
+}
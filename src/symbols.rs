@@ -0,0 +1,76 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Guest symbols, searchable by name and by address.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    by_name: HashMap<String, u32>,
+    by_address: BTreeMap<u32, String>,
+}
+
+impl SymbolTable {
+    pub fn new(symbols: &HashMap<String, u32>) -> Self {
+        let mut by_address = BTreeMap::new();
+        for (name, &address) in symbols {
+            // Prefer the alphabetically first name when several share an address, so output is stable
+            by_address
+                .entry(address)
+                .and_modify(|existing: &mut String| {
+                    if name < existing {
+                        existing.clone_from(name);
+                    }
+                })
+                .or_insert_with(|| name.clone());
+        }
+        SymbolTable { by_name: symbols.clone(), by_address }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn address(&self, name: &str) -> Option<u32> {
+        self.by_name.get(name).copied()
+    }
+
+    /// The closest symbol at or below `address` and the offset from it.
+    pub fn symbolize(&self, address: u32) -> Option<(&str, u32)> {
+        self.by_address.range(..=address).next_back().map(|(&base, name)| (name.as_str(), address - base))
+    }
+
+    /// `<name+offset>` label for `address`, or an empty string if no symbol precedes it.
+    pub fn label(&self, address: u32) -> String {
+        match self.symbolize(address) {
+            Some((name, 0)) => format!("<{}>", name),
+            Some((name, offset)) => format!("<{}+0x{:x}>", name, offset),
+            None => String::new(),
+        }
+    }
+
+    /// Parse an address given as a number (decimal or 0x hex), a symbol, or `symbol+offset`.
+    pub fn parse_address(&self, text: &str) -> Option<u32> {
+        if let Some(value) = parse_number(text) {
+            return Some(value);
+        }
+        if let Some(address) = self.address(text) {
+            return Some(address);
+        }
+        let (name, offset) = text.rsplit_once('+')?;
+        Some(self.address(name.trim())?.wrapping_add(parse_number(offset.trim())?))
+    }
+}
+
+/// Parse a decimal, `0x` hex or `0b` binary number; a leading `-` wraps to two's complement.
+pub fn parse_number(text: &str) -> Option<u32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        u32::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse::<u32>().ok()?
+    };
+    Some(if negative { value.wrapping_neg() } else { value })
+}
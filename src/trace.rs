@@ -0,0 +1,93 @@
+use std::fmt::Write as _;
+
+use crate::csr::Privilege;
+use crate::disassembler::{csr_name, disassemble};
+
+/// A data memory access made by a retired instruction. `value` is the value loaded
+/// or stored, zero-extended from `size` bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: u32,
+    pub size: u32,
+    pub value: u32,
+}
+
+/// Architectural effects of one instruction, recorded when the commit log is enabled.
+/// Instructions that raise an exception are recorded with `trap` set and no effects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub hart: u32,
+    /// Privilege mode the instruction executed in.
+    pub privilege: Privilege,
+    pub pc: u32,
+    pub raw: u32,
+    /// Address of the next instruction: the trap handler for trapping instructions.
+    pub next_pc: u32,
+    /// Source registers and the values read from them; register 0 when unused.
+    pub rs1: (u8, u32),
+    pub rs2: (u8, u32),
+    /// The instruction raised an exception instead of retiring.
+    pub trap: bool,
+    /// The instruction is the first of a trap handler.
+    pub interrupt: bool,
+    /// Execution stopped after this instruction.
+    pub halt: bool,
+    /// General-purpose register writes, excluding x0.
+    pub register_writes: Vec<(u8, u32)>,
+    /// CSR writes with the value the CSR holds afterwards.
+    pub csr_writes: Vec<(u16, u32)>,
+    pub loads: Vec<MemoryAccess>,
+    pub stores: Vec<MemoryAccess>,
+}
+
+impl Commit {
+    pub fn new(hart: u32, privilege: Privilege, pc: u32) -> Self {
+        Commit {
+            hart,
+            privilege,
+            pc,
+            raw: 0,
+            next_pc: pc,
+            rs1: (0, 0),
+            rs2: (0, 0),
+            trap: false,
+            interrupt: false,
+            halt: false,
+            register_writes: Vec::new(),
+            csr_writes: Vec::new(),
+            loads: Vec::new(),
+            stores: Vec::new(),
+        }
+    }
+
+    /// The instruction line Spike prints with `-l`:
+    /// `core   0: 0x00000000 (0x00500513) li      a0, 5`.
+    pub fn spike_instruction(&self) -> String {
+        let text = disassemble(self.raw, self.pc);
+        let text = match text.split_once('\t') {
+            Some((mnemonic, operands)) => format!("{:<8}{}", format!("{} ", mnemonic), operands.replace(',', ", ")),
+            None => text,
+        };
+        format!("core {:3}: 0x{:08x} (0x{:08x}) {}", self.hart, self.pc, self.raw, text.trim_end())
+    }
+
+    /// The commit line Spike prints with `--log-commits`:
+    /// `core   0: 3 0x00000000 (0x00500513) x10 0x00000005 mem 0x00000100 0x00000005`.
+    pub fn spike_commit(&self) -> String {
+        let mut line = format!("core {:3}: {} 0x{:08x} (0x{:08x})", self.hart, self.privilege as u32, self.pc, self.raw);
+        for &(register, value) in &self.register_writes {
+            let _ = write!(line, " x{:<2} 0x{:08x}", register, value);
+        }
+        for &(csr, value) in &self.csr_writes {
+            let name = csr_name(csr).map(str::to_string).unwrap_or_else(|| format!("0x{:03x}", csr));
+            let _ = write!(line, " c{}_{} 0x{:08x}", csr, name, value);
+        }
+        for load in &self.loads {
+            let _ = write!(line, " mem 0x{:08x}", load.address);
+        }
+        for store in &self.stores {
+            let _ = write!(line, " mem 0x{:08x} 0x{:0width$x}", store.address, store.value, width = 2 * store.size as usize);
+        }
+        line
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt;
+
+/// Synchronous exceptions, carrying the value written to xtval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    InstructionAddressMisaligned(u32),
+    InstructionAccessFault(u32),
+    IllegalInstruction(u32),
+    Breakpoint(u32),
+    LoadAddressMisaligned(u32),
+    LoadAccessFault(u32),
+    StoreAddressMisaligned(u32),
+    StoreAccessFault(u32),
+    EnvironmentCallFromU,
+    EnvironmentCallFromS,
+    EnvironmentCallFromM,
+}
+
+impl Exception {
+    /// Exception code written to xcause.
+    pub fn code(&self) -> u32 {
+        match self {
+            Exception::InstructionAddressMisaligned(_) => 0,
+            Exception::InstructionAccessFault(_) => 1,
+            Exception::IllegalInstruction(_) => 2,
+            Exception::Breakpoint(_) => 3,
+            Exception::LoadAddressMisaligned(_) => 4,
+            Exception::LoadAccessFault(_) => 5,
+            Exception::StoreAddressMisaligned(_) => 6,
+            Exception::StoreAccessFault(_) => 7,
+            Exception::EnvironmentCallFromU => 8,
+            Exception::EnvironmentCallFromS => 9,
+            Exception::EnvironmentCallFromM => 11,
+        }
+    }
+
+    /// Value written to xtval: the faulting address or instruction bits, or zero.
+    pub fn tval(&self) -> u32 {
+        match *self {
+            Exception::InstructionAddressMisaligned(value)
+            | Exception::InstructionAccessFault(value)
+            | Exception::IllegalInstruction(value)
+            | Exception::Breakpoint(value)
+            | Exception::LoadAddressMisaligned(value)
+            | Exception::LoadAccessFault(value)
+            | Exception::StoreAddressMisaligned(value)
+            | Exception::StoreAccessFault(value) => value,
+            Exception::EnvironmentCallFromU | Exception::EnvironmentCallFromS | Exception::EnvironmentCallFromM => 0,
+        }
+    }
+}
+
+/// Interrupt causes; the discriminant is the bit in mip/mie and the xcause code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    SupervisorSoftware = 1,
+    MachineSoftware = 3,
+    SupervisorTimer = 5,
+    MachineTimer = 7,
+    SupervisorExternal = 9,
+    MachineExternal = 11,
+}
+
+impl Interrupt {
+    /// Interrupts in the order the privileged specification gives them priority.
+    pub const PRIORITY: [Interrupt; 6] = [
+        Interrupt::MachineExternal,
+        Interrupt::MachineSoftware,
+        Interrupt::MachineTimer,
+        Interrupt::SupervisorExternal,
+        Interrupt::SupervisorSoftware,
+        Interrupt::SupervisorTimer,
+    ];
+
+    pub fn code(&self) -> u32 {
+        *self as u32
+    }
+
+    /// This interrupt's bit in mip and mie.
+    pub fn mask(&self) -> u32 {
+        1 << self.code()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trap {
+    Exception(Exception),
+    Interrupt(Interrupt),
+}
+
+impl Trap {
+    /// Value written to xcause; interrupts set the top bit.
+    pub fn cause(&self) -> u32 {
+        match self {
+            Trap::Exception(exception) => exception.code(),
+            Trap::Interrupt(interrupt) => 1 << 31 | interrupt.code(),
+        }
+    }
+
+    pub fn tval(&self) -> u32 {
+        match self {
+            Trap::Exception(exception) => exception.tval(),
+            Trap::Interrupt(_) => 0,
+        }
+    }
+}
+
+impl From<Exception> for Trap {
+    fn from(exception: Exception) -> Self {
+        Trap::Exception(exception)
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trap::Exception(Exception::InstructionAddressMisaligned(tval)) => {
+                write!(f, "instruction address misaligned (0x{:08x})", tval)
+            }
+            Trap::Exception(Exception::InstructionAccessFault(tval)) => write!(f, "instruction access fault (0x{:08x})", tval),
+            Trap::Exception(Exception::IllegalInstruction(tval)) => write!(f, "illegal instruction (0x{:08x})", tval),
+            Trap::Exception(Exception::Breakpoint(tval)) => write!(f, "breakpoint (0x{:08x})", tval),
+            Trap::Exception(Exception::LoadAddressMisaligned(tval)) => write!(f, "load address misaligned (0x{:08x})", tval),
+            Trap::Exception(Exception::LoadAccessFault(tval)) => write!(f, "load access fault (0x{:08x})", tval),
+            Trap::Exception(Exception::StoreAddressMisaligned(tval)) => {
+                write!(f, "store address misaligned (0x{:08x})", tval)
+            }
+            Trap::Exception(Exception::StoreAccessFault(tval)) => write!(f, "store access fault (0x{:08x})", tval),
+            Trap::Exception(Exception::EnvironmentCallFromU) => write!(f, "environment call from U-mode"),
+            Trap::Exception(Exception::EnvironmentCallFromS) => write!(f, "environment call from S-mode"),
+            Trap::Exception(Exception::EnvironmentCallFromM) => write!(f, "environment call from M-mode"),
+            Trap::Interrupt(interrupt) => write!(f, "{:?} interrupt", interrupt),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use riscv_simulator::riscv_sim::*;
+
+fn words(image: &Image) -> Vec<u32> {
+    image.bytes.chunks(4).map(|c| u32::from_le_bytes(c.try_into().unwrap())).collect()
+}
+
+#[test]
+fn assembles_base_instructions_and_labels() {
+    let image = assemble(
+        "
+        start:
+            addi sp, sp, -16
+            sw   ra, 12(sp)
+        loop:
+            addi a0, a0, -1
+            bnez a0, loop
+            lui  a1, 0x12345
+            ret
+        ",
+    )
+    .unwrap();
+    assert_eq!(words(&image), [0xff010113, 0x00112623, 0xfff50513, 0xfe051ee3, 0x123455b7, 0x00008067]);
+    assert_eq!(image.symbol("loop"), Some(8));
+}
+
+#[test]
+fn expands_pseudo_instructions() {
+    let image = assemble(
+        "
+            li   a0, 5
+            li   a1, 0x12345678
+            la   a2, data
+            call func
+        func:
+            ret
+        data:
+            .word 0
+        ",
+    )
+    .unwrap();
+    let expected = [
+        "li\ta0,5",
+        "lui\ta1,0x12345",
+        "addi\ta1,a1,1656",
+        "auipc\ta2,0x0",
+        "addi\ta2,a2,20",
+        "auipc\tra,0x0",
+        "jalr\tra,8(ra)",
+        "ret",
+    ];
+    for (index, text) in expected.iter().enumerate() {
+        let pc = index as u32 * 4;
+        assert_eq!(disassemble(words(&image)[index], pc), *text);
+    }
+}
+
+#[test]
+fn hi_lo_relocations_recombine() {
+    let image = assemble(
+        "
+            .org 0x100
+            lui  a0, %hi(target)
+            addi a0, a0, %lo(target)
+            .equ target, 0x12345ffc
+        ",
+    )
+    .unwrap();
+    assert_eq!(image.base, 0x100);
+    let [lui, addi] = words(&image)[..] else { panic!() };
+    let (Ok(Instruction::Lui(u)), Ok(Instruction::Addi(i))) = (Instruction::decode(lui), Instruction::decode(addi)) else {
+        panic!()
+    };
+    assert_eq!((u.imm as u32).wrapping_add(i.imm as u32), 0x12345ffc);
+}
+
+#[test]
+fn emits_data_directives() {
+    let image = assemble(
+        "
+            .byte 1, 0xff
+            .half 0xbeef
+            .ascii \"ok\"
+            .asciz \"a\\n\"
+            .align 3
+            .word 0xdeadbeef
+        ",
+    )
+    .unwrap();
+    assert_eq!(image.bytes, [1, 0xff, 0xef, 0xbe, b'o', b'k', b'a', b'\n', 0, 0, 0, 0, 0, 0, 0, 0, 0xef, 0xbe, 0xad, 0xde]);
+}
+
+#[test]
+fn reports_errors_with_line_numbers() {
+    let error = assemble("nop\naddi a0, a0, 4096\n").unwrap_err();
+    assert_eq!(error.line, 2);
+    assert!(assemble("beq a0, a1, missing").is_err());
+    assert!(assemble("frobnicate a0").is_err());
+
+    // Nothing may pass the end of the address space
+    let error = assemble("nop\n.org 0xfffffffc\n.word 1").unwrap_err();
+    assert_eq!(error.to_string(), "line 3: 4 bytes at 0xfffffffc pass the end of the address space");
+    assert_eq!(assemble(".byte 0\n.zero 0xffffffff").unwrap_err().line, 2);
+    assert_eq!(assemble(".org 16\n.zero 0xfffffff0").unwrap_err().line, 2);
+}
+
+#[test]
+fn loads_into_memory() {
+    let image = assemble("li a0, 1\n.word 0x11223344").unwrap();
+    let mut memory = Memory::with_size(64);
+    image.load_into(&mut memory).unwrap();
+    assert_eq!(memory.fetch_word(0), 0x00100513);
+    assert_eq!(memory.load_word(4), 0x11223344);
+
+    let image = assemble(".org 60\n.word 1\n.word 2").unwrap();
+    assert_eq!(image.end(), 68);
+    assert_eq!(image.load_into(&mut memory), Err(LoadError { base: 60, size: 8 }));
+    assert_eq!(memory.load_word(60), 0);
+}
@@ -0,0 +1,89 @@
+use riscv_simulator::riscv_sim::*;
+use riscv_simulator::stats::Statistics;
+
+mod common;
+
+// Sums a table with loads, stores, M-extension ops and calls; a misaligned load traps
+// to a handler that skips it, and the last iteration patches an instruction ahead of it
+// in the same block.
+const PROGRAM: &str = "
+    la   t0, handler
+    csrw mtvec, t0
+    li   s0, 0x800
+    li   s1, 8
+fill:
+    mul  t1, s1, s1
+    sw   t1, 0(s0)
+    addi s0, s0, 4
+    addi s1, s1, -1
+    bnez s1, fill
+    li   s0, 0x800
+    li   s1, 8
+sum:
+    lw   t1, 0(s0)
+    call accumulate
+    addi s0, s0, 4
+    addi s1, s1, -1
+    bnez s1, sum
+    lw   t2, 1(s0)
+    li   t3, 0x00150513
+    la   t4, patched
+    sw   t3, 0(t4)
+patched:
+    nop
+    csrw mtvec, zero
+    li   a7, 93
+    ecall
+accumulate:
+    add  a0, a0, t1
+    divu t2, a0, s1
+    rem  t3, a0, s1
+    ret
+handler:
+    csrr t5, mepc
+    addi t5, t5, 4
+    csrw mepc, t5
+    mret
+";
+
+fn load(translate: bool) -> (Cpu<Statistics>, Memory) {
+    let (mut cpu, memory) = common::load_with(PROGRAM, Statistics::new());
+    cpu.set_block_translation(translate);
+    (cpu, memory)
+}
+
+#[test]
+fn blocks_match_the_interpreter() {
+    let (mut interpreted, mut expected) = load(false);
+    let (mut translated, mut memory) = load(true);
+    // 1 + 1^2 + ... + 8^2
+    assert_eq!(interpreted.run(&mut expected), StopReason::Halted { exit_code: 205 });
+    assert_eq!(translated.run(&mut memory), StopReason::Halted { exit_code: 205 });
+    assert_eq!(translated.snapshot(&memory), interpreted.snapshot(&expected));
+    assert_eq!(translated.hooks(), interpreted.hooks());
+    assert_eq!(translated.hooks().traps, 1);
+}
+
+#[test]
+fn instruction_limits_and_traps_stop_at_exact_instructions() {
+    let (mut reference, mut expected) = load(false);
+    let total = {
+        reference.run(&mut expected);
+        reference.instret()
+    };
+    for limit in 1..total {
+        let (mut interpreted, mut expected) = load(false);
+        let (mut translated, mut memory) = load(true);
+        assert_eq!(interpreted.run_for(&mut expected, limit), StopReason::InstructionLimit);
+        assert_eq!(translated.run_for(&mut memory, limit), StopReason::InstructionLimit);
+        assert_eq!(translated.snapshot(&memory), interpreted.snapshot(&expected), "after {} instructions", limit);
+    }
+
+    // Without a handler (the csrw mtvec replaced by a nop) the misaligned load stops
+    // the run with the pc on it
+    let (mut cpu, mut memory) = load(true);
+    memory.store_word(8, 0x00000013);
+    let StopReason::Trap { pc, .. } = cpu.run(&mut memory) else { panic!() };
+    assert_eq!(decode(memory.load_word(pc as usize)).unwrap().mnemonic(), "lw");
+    assert_eq!(cpu.pc(), pc);
+}
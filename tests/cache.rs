@@ -0,0 +1,120 @@
+use riscv_simulator::cache::*;
+use riscv_simulator::pipeline::{Pipeline, PipelineConfig};
+
+mod common;
+use common::run;
+
+/// Increment 64 words `stride` bytes apart from 0x400.
+fn strided(stride: u32) -> String {
+    format!(
+        "
+            li   s0, 0x400
+            li   s1, 64
+        loop:
+            lw   t0, 0(s0)
+            addi t0, t0, 1
+            sw   t0, 0(s0)
+            addi s0, s0, {}
+            addi s1, s1, -1
+            bnez s1, loop
+            li   a7, 93
+            ecall
+        ",
+        stride
+    )
+}
+
+#[test]
+fn policies_choose_victims_and_when_stores_reach_memory() {
+    // One set of two 32-byte lines
+    const A: u32 = 0x000;
+    const B: u32 = 0x100;
+    const C: u32 = 0x200;
+    for (replacement, kept) in [(Replacement::Lru, A), (Replacement::Fifo, B)] {
+        let mut cache = Cache::new(CacheConfig { replacement, ..CacheConfig::new(64, 2, 32) }).unwrap();
+        for address in [A, B, A + 4] {
+            cache.access(address, false);
+        }
+        let access = cache.access(C, false);
+        assert_eq!(access, Access { fill: true, ..Access::default() });
+        assert!(cache.contains(kept) && cache.contains(C) && !cache.contains(A ^ B ^ kept), "{:?}", replacement);
+        assert_eq!(*cache.stats(), CacheStats { reads: 4, read_misses: 3, evictions: 1, ..CacheStats::default() });
+    }
+
+    // Random replacement repeats from run to run
+    let victims = || {
+        let mut cache = Cache::new(CacheConfig { replacement: Replacement::Random, ..CacheConfig::new(256, 8, 32) }).unwrap();
+        for line in 0..64 {
+            cache.access(line * 32, false);
+        }
+        (0..64).filter(|line| cache.contains(line * 32)).collect::<Vec<u32>>()
+    };
+    assert_eq!(victims().len(), 8);
+    assert_eq!(victims(), victims());
+    assert_ne!(victims(), (56..64).collect::<Vec<u32>>());
+
+    // A dirty line is written back when evicted; written through, it never is
+    let mut cache = Cache::new(CacheConfig::new(64, 2, 32)).unwrap();
+    assert_eq!(cache.access(A, true), Access { fill: true, ..Access::default() });
+    cache.access(B, false);
+    assert_eq!(cache.access(C, false).writeback, Some(A));
+    assert_eq!(cache.stats().writebacks, 1);
+    let mut cache = Cache::new(CacheConfig { write: WritePolicy::WriteThrough, ..CacheConfig::new(64, 2, 32) }).unwrap();
+    assert_eq!(cache.access(A, true), Access { fill: true, write_through: true, ..Access::default() });
+    cache.access(B, false);
+    assert_eq!(cache.access(C, false).writeback, None);
+    let mut cache = Cache::new(CacheConfig { write_allocate: false, ..CacheConfig::new(64, 2, 32) }).unwrap();
+    assert_eq!(cache.access(A, true), Access { write_through: true, ..Access::default() });
+    assert!(!cache.contains(A));
+    assert_eq!(cache.stats().write_misses, 1);
+
+    let error = Cache::new(CacheConfig::new(1000, 2, 32)).unwrap_err();
+    assert_eq!(error, CacheError::Geometry { size: 1000, associativity: 2, line_size: 32 });
+    assert!(Cache::new(CacheConfig::new(32, 2, 32)).is_err());
+    assert!(Cache::new(CacheConfig::new(64, 1, 2)).is_err());
+}
+
+#[test]
+fn hierarchy_counts_accesses_and_times_the_pipeline() {
+    let l1d = CacheConfig::new(1024, 2, 32);
+    // Two words per line: every other load misses, and the store after it hits
+    let caches = run(&strided(16), CacheHierarchy::new(CacheConfig::default(), l1d, None).unwrap());
+    assert_eq!(caches.l1i().stats().reads, 388);
+    assert_eq!(caches.l1i().stats().misses(), 1);
+    assert_eq!(*caches.l1d().stats(), CacheStats { reads: 64, read_misses: 32, writes: 64, ..CacheStats::default() });
+    assert_eq!((caches.memory_reads, caches.memory_writes), (33, 0));
+    assert_eq!(caches.data_cycles, 128 + 32 * DEFAULT_MEMORY_LATENCY);
+
+    // Every line maps to one set, so each load evicts a line dirtied by the store
+    // before it; an L2 of larger lines halves the trips to memory
+    let l2 = CacheConfig { latency: 5, ..CacheConfig::new(64 * 1024, 4, 64) };
+    let caches = run(&strided(512), CacheHierarchy::new(CacheConfig::default(), l1d, Some(l2)).unwrap());
+    let stats = caches.l1d().stats();
+    assert_eq!((stats.read_misses, stats.evictions, stats.writebacks), (64, 62, 62));
+    let l2 = caches.l2().unwrap().stats();
+    assert_eq!((l2.reads, l2.read_misses, l2.writes, l2.write_misses), (65, 65, 62, 0));
+    let mut write_through = CacheHierarchy::new(
+        CacheConfig::default(),
+        CacheConfig { write: WritePolicy::WriteThrough, write_allocate: false, ..l1d },
+        None,
+    )
+    .unwrap();
+    write_through.set_memory_latency(50);
+    let caches = run(&strided(512), write_through);
+    assert_eq!((caches.l1d().stats().writebacks, caches.memory_writes), (0, 64));
+    assert_eq!(caches.data_cycles, 64 * 51 + 64);
+
+    // The misses stall the pipeline: a cold fetch, then a load miss every other iteration
+    let mut pipeline = Pipeline::new(PipelineConfig::default());
+    let l2 = CacheConfig { latency: 5, ..CacheConfig::new(8 * 1024, 4, 64) };
+    let mut caches = CacheHierarchy::new(CacheConfig::default(), l1d, Some(l2)).unwrap();
+    caches.set_memory_latency(50);
+    pipeline.set_caches(caches);
+    let plain = *run(&strided(16), Pipeline::new(PipelineConfig::default())).stats();
+    let pipeline = run(&strided(16), pipeline);
+    let stats = pipeline.stats();
+    assert_eq!((stats.fetch_stalls, stats.memory_stalls), (55, 16 * 55 + 16 * 5));
+    assert_eq!(stats.cycles, plain.cycles + 55 + 960);
+    assert!(pipeline.summary().contains("cache stalls  55 fetch, 960 memory\n"));
+    assert!(pipeline.summary().contains("L2   8 KiB, 4-way, 64 B lines: 33 reads (17 misses), 0 writes (0 misses)"));
+}
@@ -0,0 +1,43 @@
+// Not every test crate uses every helper
+#![allow(dead_code)]
+
+use riscv_simulator::riscv_sim::*;
+
+/// Memory given to every test machine: room for the program and its data.
+pub const MEMORY_SIZE: usize = 0x10000;
+
+/// Counts a0 up to 10 in the word at 0x2000, holding a reservation on it and its
+/// address in mscratch, then exits with 10.
+pub const PROGRAM: &str = "
+    li   t0, 0x2000
+    csrw mscratch, t0
+    lr.w t1, (t0)
+loop:
+    addi a0, a0, 1
+    sw   a0, 0(t0)
+    li   t1, 10
+    bne  a0, t1, loop
+    li   a7, 93
+    ecall
+";
+
+/// `source` assembled into fresh memory, with a CPU observed by `hooks` at its entry.
+pub fn load_with<H: Hooks>(source: &str, hooks: H) -> (Cpu<H>, Memory) {
+    let image = assemble(source).unwrap();
+    let mut memory = Memory::with_size(MEMORY_SIZE);
+    image.load_into(&mut memory).unwrap();
+    let mut cpu = Cpu::with_hooks(hooks);
+    cpu.set_pc(image.base);
+    (cpu, memory)
+}
+
+pub fn load(source: &str) -> (Cpu, Memory) {
+    load_with(source, NoHooks)
+}
+
+/// Run `source` until it exits and return the hooks that observed it.
+pub fn run<H: Hooks>(source: &str, hooks: H) -> H {
+    let (mut cpu, mut memory) = load_with(source, hooks);
+    assert!(matches!(cpu.run(&mut memory), StopReason::Halted { .. }));
+    cpu.into_hooks()
+}
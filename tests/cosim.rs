@@ -0,0 +1,87 @@
+use riscv_simulator::cosim::*;
+use riscv_simulator::riscv_sim::*;
+
+const PROGRAM: &str = "
+        li   a0, 0
+        li   a1, 4
+    loop:
+        addi a0, a0, 3
+        addi a1, a1, -1
+        bnez a1, loop
+        li   a7, 93
+        ecall
+";
+
+fn machine() -> (Cpu, Memory) {
+    let image = assemble(PROGRAM).unwrap();
+    let mut memory = Memory::with_size(0x100);
+    image.load_into(&mut memory).unwrap();
+    (Cpu::new(), memory)
+}
+
+/// Commit log of the program as produced by the simulator itself.
+fn reference_log() -> String {
+    let (mut cpu, mut memory) = machine();
+    cpu.set_commit_log(true);
+    let mut log = String::new();
+    loop {
+        let stop = cpu.step(&mut memory);
+        if let Some(commit) = cpu.last_commit().filter(|commit| !commit.trap) {
+            log.push_str(&commit.spike_instruction());
+            log.push('\n');
+            log.push_str(&commit.spike_commit());
+            log.push('\n');
+        }
+        if stop != StopReason::InstructionLimit {
+            return log;
+        }
+    }
+}
+
+fn run(log: &str) -> CosimReport {
+    let (mut cpu, mut memory) = machine();
+    cosimulate(&mut cpu, &mut memory, &parse_commit_log(log), 2)
+}
+
+#[test]
+fn parses_spike_commit_lines() {
+    let log = "core   0: 0x80000000 (0x00000297) auipc   t0, 0x0\n\
+               core   0: 3 0x80000000 (0x00000297) x5  0x80000000\n\
+               core   0: exception trap_illegal_instruction, epc 0x80000004\n\
+               core   0: 3 0x0000000080000008 (0x0182a283) x5  0xffffffff80000000 mem 0x0000000080000018\n";
+    let commits = parse_commit_log(log);
+    assert_eq!(commits.len(), 2);
+    assert_eq!((commits[0].line, commits[0].pc, commits[0].raw), (2, 0x80000000, 0x00000297));
+    assert_eq!(commits[1].register_writes, [(5, 0x80000000)]);
+}
+
+#[test]
+fn matching_log_passes() {
+    let report = run(&reference_log());
+    assert!(report.passed(), "{}", report);
+    assert_eq!(report.matched, 16);
+
+    // Leading boot code in the reference is skipped
+    let boot = "core   0: 3 0x00001000 (0x00000297) x5  0x00001000\n";
+    assert!(run(&(boot.to_string() + &reference_log())).passed());
+}
+
+#[test]
+fn reports_first_divergence_with_context() {
+    let log = reference_log().replacen("x10 0x00000006", "x10 0x00000007", 1);
+    let report = run(&log);
+    assert_eq!(report.matched, 5);
+    assert_eq!(report.divergence, Some(Divergence::RegisterWrite { expected: vec![(10, 7)], actual: vec![(10, 6)] }));
+    assert_eq!(report.before.len(), 2);
+    assert_eq!(report.after.len(), 2);
+    assert_eq!(report.expected.unwrap().line, 12);
+
+    let log = reference_log().replace("(0x00350513)", "(0x00450513)");
+    assert_eq!(run(&log).divergence, Some(Divergence::Instruction { expected: 0x00450513, actual: 0x00350513 }));
+
+    let log = reference_log().replacen("3 0x00000010", "3 0x00000014", 1);
+    assert_eq!(run(&log).divergence, Some(Divergence::Pc { expected: 0x14, actual: 0x10 }));
+
+    let log = reference_log() + "core   0: 3 0x00000020 (0x00000013)\n";
+    assert_eq!(run(&log).divergence, Some(Divergence::Stopped(StopReason::Halted { exit_code: 12 })));
+}
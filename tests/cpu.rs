@@ -0,0 +1,202 @@
+use riscv_simulator::riscv_sim::*;
+use riscv_simulator::csr;
+
+mod common;
+use common::load;
+
+#[test]
+fn step_and_instruction_limit() {
+    let (mut cpu, mut memory) = load("li a0, 1\nli a1, 2\nadd a2, a0, a1\nj .");
+    assert_eq!(cpu.step(&mut memory), StopReason::InstructionLimit);
+    assert_eq!((cpu.pc(), cpu.register(10)), (4, 1));
+    assert_eq!(cpu.run_for(&mut memory, 10), StopReason::InstructionLimit);
+    assert_eq!(cpu.register(12), 3);
+    assert_eq!(cpu.instret(), 11);
+}
+
+#[test]
+fn exit_syscall_halts_with_code() {
+    let (mut cpu, mut memory) = load("li a0, 42\nli a7, 93\necall");
+    assert_eq!(cpu.run(&mut memory), StopReason::Halted { exit_code: 42 });
+    assert_eq!(cpu.pc(), 12);
+}
+
+#[test]
+fn breakpoints_stop_and_resume() {
+    let (mut cpu, mut memory) = load("li a0, 0\nloop:\naddi a0, a0, 1\nj loop");
+    cpu.add_breakpoint(4);
+    assert_eq!(cpu.run(&mut memory), StopReason::Breakpoint { pc: 4 });
+    assert_eq!(cpu.register(10), 0);
+    assert_eq!(cpu.run(&mut memory), StopReason::Breakpoint { pc: 4 });
+    assert_eq!(cpu.register(10), 1);
+    assert_eq!(cpu.step(&mut memory), StopReason::InstructionLimit);
+    assert_eq!(cpu.register(10), 2);
+
+    let (mut cpu, mut memory) = load("nop\nebreak\nnop");
+    assert_eq!(cpu.run(&mut memory), StopReason::Breakpoint { pc: 4 });
+    assert_eq!(cpu.pc(), 8);
+}
+
+#[test]
+fn unhandled_trap_stops_at_faulting_instruction() {
+    let (mut cpu, mut memory) = load("nop\nlw a0, 2(zero)");
+    let stop = cpu.run(&mut memory);
+    assert_eq!(stop, StopReason::Trap { trap: Trap::Exception(Exception::LoadAddressMisaligned(2)), pc: 4 });
+    assert_eq!(cpu.pc(), 4);
+
+    // A pc set from outside (debugger, GDB, snapshot) may be misaligned
+    let misaligned = StopReason::Trap { trap: Trap::Exception(Exception::InstructionAddressMisaligned(6)), pc: 6 };
+    for jit in [false, true] {
+        let (mut cpu, mut memory) = load("nop\nnop\nnop\nnop");
+        cpu.set_jit(jit);
+        cpu.set_pc(6);
+        assert_eq!(cpu.step(&mut memory), misaligned);
+        assert_eq!(cpu.run(&mut memory), misaligned);
+        assert_eq!(cpu.instret(), 0);
+    }
+}
+
+#[test]
+fn traps_enter_installed_handler() {
+    let (mut cpu, mut memory) = load(
+        "
+            la   t0, handler
+            csrw mtvec, t0
+            .word 0
+        handler:
+            csrr a0, mcause
+            csrr a1, mepc
+            li   a7, 93
+            ecall
+        ",
+    );
+    // The handler itself ecalls into the same vector, so stop once it is reached
+    cpu.add_breakpoint(16);
+    assert_eq!(cpu.run(&mut memory), StopReason::Breakpoint { pc: 16 });
+    assert_eq!(cpu.read_csr(csr::MCAUSE), Some(2));
+    assert_eq!(cpu.read_csr(csr::MEPC), Some(12));
+    assert_eq!(cpu.read_csr(csr::MTVAL), Some(0));
+}
+
+#[test]
+fn wfi_idles_until_interrupt() {
+    let (mut cpu, mut memory) = load(
+        "
+            la   t0, handler
+            csrw mtvec, t0
+            li   t0, 0x80
+            csrw mie, t0
+            csrsi mstatus, 8
+            wfi
+            j    .
+        handler:
+            csrr a0, mcause
+            li   a7, 93
+            ecall
+        ",
+    );
+    assert_eq!(cpu.run(&mut memory), StopReason::WaitForInterrupt { pc: 24 });
+    cpu.set_interrupt_pending(Interrupt::MachineTimer, true);
+    cpu.add_breakpoint(36);
+    assert_eq!(cpu.run(&mut memory), StopReason::Breakpoint { pc: 36 });
+    assert_eq!(cpu.register(10), 1 << 31 | 7);
+    assert_eq!(cpu.read_csr(csr::MEPC), Some(28));
+}
+
+#[test]
+fn arithmetic_follows_the_specification() {
+    let (mut cpu, mut memory) = load(
+        "
+            li   a0, -8
+            srai a1, a0, 1
+            li   a2, 0
+            div  a3, a0, a2
+            rem  a4, a0, a2
+            li   t0, 0x80000000
+            li   t1, -1
+            div  a5, t0, t1
+            addi zero, zero, 1
+            lb   a6, data(zero)
+            li   a7, 93
+            ecall
+        data:
+            .byte 0x80
+        ",
+    );
+    assert!(matches!(cpu.run(&mut memory), StopReason::Halted { .. }));
+    assert_eq!(cpu.register(11), -4i32 as u32);
+    assert_eq!(cpu.register(13), u32::MAX);
+    assert_eq!(cpu.register(14), -8i32 as u32);
+    assert_eq!(cpu.register(15), 0x80000000);
+    assert_eq!(cpu.register(0), 0);
+    assert_eq!(cpu.register(16), 0xffffff80);
+}
+
+#[test]
+fn watchpoints_stop_after_the_access() {
+    let source = "
+            li   t0, 0x100
+            li   a0, 1
+        loop:
+            sw   a0, 0(t0)
+            lbu  a1, 2(t0)
+            addi a0, a0, 1
+            j    loop
+        ";
+    let (mut cpu, mut memory) = load(source);
+    cpu.add_watchpoint(Watchpoint::new(0x100, 4, WatchKind::Write));
+    let stop = cpu.run(&mut memory);
+    assert_eq!(stop, StopReason::Watchpoint { pc: 8, address: 0x100, kind: WatchKind::Write, write: true, old: 0, new: 1 });
+    assert_eq!(cpu.pc(), 12);
+    let stop = cpu.run(&mut memory);
+    assert_eq!(stop, StopReason::Watchpoint { pc: 8, address: 0x100, kind: WatchKind::Write, write: true, old: 1, new: 2 });
+
+    // Reads of a single byte within a wider access, and value conditions
+    let (mut cpu, mut memory) = load(source);
+    cpu.add_watchpoint(Watchpoint::new(0x102, 1, WatchKind::Read));
+    cpu.add_watchpoint(Watchpoint::new(0x100, 4, WatchKind::Write).with_condition(3));
+    let stop = cpu.run(&mut memory);
+    assert_eq!(stop, StopReason::Watchpoint { pc: 12, address: 0x102, kind: WatchKind::Read, write: false, old: 0, new: 0 });
+    assert!(cpu.remove_watchpoint(0x102, 1, WatchKind::Read));
+    let stop = cpu.run(&mut memory);
+    assert_eq!(stop, StopReason::Watchpoint { pc: 8, address: 0x100, kind: WatchKind::Write, write: true, old: 2, new: 3 });
+    assert_eq!(cpu.register(10), 3);
+}
+
+#[test]
+fn counters_follow_counteren_and_explicit_writes() {
+    let (mut cpu, mut memory) = load("rdcycle a0\nrdinstret a1\nj .");
+    let illegal = StopReason::Trap { trap: Trap::Exception(Exception::IllegalInstruction(0xc0002573)), pc: 0 };
+    cpu.set_privilege(Privilege::Supervisor);
+    assert_eq!(cpu.step(&mut memory), illegal);
+    cpu.write_csr(csr::MCOUNTEREN, 0b001);
+    assert_eq!(cpu.step(&mut memory), StopReason::InstructionLimit);
+    assert!(matches!(cpu.step(&mut memory), StopReason::Trap { pc: 4, .. }));
+
+    // U-mode needs the scounteren bit as well
+    let (mut cpu, mut memory) = load("rdcycle a0\nrdinstret a1\nj .");
+    cpu.set_privilege(Privilege::User);
+    cpu.write_csr(csr::MCOUNTEREN, 0b101);
+    assert_eq!(cpu.step(&mut memory), illegal);
+    cpu.write_csr(csr::SCOUNTEREN, 0b100);
+    assert_eq!(cpu.step(&mut memory), illegal);
+    cpu.write_csr(csr::SCOUNTEREN, 0b101);
+    assert_eq!(cpu.run_for(&mut memory, 2), StopReason::InstructionLimit);
+    assert_eq!(cpu.register(11), 1);
+
+    // The instruction that writes a counter is not counted in it
+    let (mut cpu, mut memory) = load(
+        "
+            li   t0, 100
+            csrw minstret, t0
+            csrr a0, minstret
+            csrw mcycle, t0
+            csrr a1, mcycle
+            li   a7, 93
+            ecall
+        ",
+    );
+    assert!(matches!(cpu.run(&mut memory), StopReason::Halted { .. }));
+    assert_eq!((cpu.register(10), cpu.register(11)), (100, 100));
+    assert_eq!(cpu.instret(), 105);
+}
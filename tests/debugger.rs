@@ -0,0 +1,74 @@
+use riscv_simulator::debugger::Debugger;
+use riscv_simulator::riscv_sim::*;
+use riscv_simulator::symbols::SymbolTable;
+
+const PROGRAM: &str = "
+    _start:
+        li   a0, 3
+        call square
+        la   t0, result
+        sw   a0, 0(t0)
+        li   a7, 93
+        ecall
+    square:
+        mul  a0, a0, a0
+        ret
+    result:
+        .word 0
+";
+
+fn debugger() -> Debugger {
+    let image = assemble(PROGRAM).unwrap();
+    let mut memory = Memory::with_size(0x100);
+    image.load_into(&mut memory).unwrap();
+    Debugger::new(Cpu::new(), memory, SymbolTable::new(&image.symbols))
+}
+
+#[test]
+fn breakpoints_by_symbol_and_next_over_calls() {
+    let mut debugger = debugger();
+    assert_eq!(debugger.execute("break square").unwrap(), "breakpoint at 0x00000020 <square>");
+    let output = debugger.execute("continue").unwrap();
+    assert!(output.starts_with("breakpoint at 0x00000020 <square>\n=> 0x00000020 <square>:"), "{}", output);
+    debugger.execute("delete square").unwrap();
+
+    let mut debugger = self::debugger();
+    debugger.execute("step 2").unwrap();
+    assert_eq!(debugger.cpu.pc(), 8);
+    debugger.execute("next").unwrap();
+    assert_eq!((debugger.cpu.pc(), debugger.cpu.register(10)), (0xc, 9));
+    // An empty line repeats the last command
+    debugger.execute("").unwrap();
+    assert_eq!(debugger.cpu.pc(), 0x10);
+}
+
+#[test]
+fn watchpoints_report_changes() {
+    let mut debugger = debugger();
+    debugger.execute("watch result").unwrap();
+    let output = debugger.execute("c").unwrap();
+    assert!(output.starts_with("watchpoint 0x00000028 <result>: 0x0 -> 0x9 (written by instruction at 0x00000014"), "{}", output);
+    assert_eq!(debugger.execute("c").unwrap().lines().next(), Some("halted with exit code 9"));
+
+    let mut debugger = self::debugger();
+    debugger.execute("rwatch result").unwrap();
+    debugger.execute("watch square 4 if 0").unwrap();
+    assert_eq!(debugger.execute("watch").unwrap(), "Read 0x00000028 <result> length 4\nWrite 0x00000020 <square> length 4 if 0x0");
+    debugger.execute("unwatch result").unwrap();
+    assert!(debugger.execute("unwatch result").is_err());
+}
+
+#[test]
+fn inspects_and_modifies_state() {
+    let mut debugger = debugger();
+    debugger.execute("set a0 0x10").unwrap();
+    assert_eq!(debugger.execute("p a0").unwrap(), "a0 = 0x00000010 (16)");
+    debugger.execute("set mscratch -1").unwrap();
+    assert_eq!(debugger.execute("print mscratch").unwrap(), "mscratch = 0xffffffff (-1)");
+    debugger.execute("write result+4 0xab b").unwrap();
+    assert_eq!(debugger.execute("x result 2").unwrap(), "0x00000028 <result>: 0x00000000 0x000000ab");
+    assert_eq!(debugger.execute("disas square 1").unwrap(), "   0x00000020 <square>:\t02a50533\tmul\ta0,a0,a0");
+    assert!(debugger.execute("x 0x1000").is_err());
+    assert!(debugger.execute("break nowhere").is_err());
+    assert!(debugger.execute("frobnicate").is_err());
+}
@@ -0,0 +1,75 @@
+use riscv_simulator::riscv_sim::*;
+
+mod common;
+use common::load;
+
+// Patches the `addi` at 8 to add 5 instead of 1, then runs it again
+const SELF_MODIFYING: &str = "
+    li   a0, 0
+    li   s0, 2
+patch:
+    addi a0, a0, 1
+    addi s0, s0, -1
+    li   t1, 0x00550513
+    sw   t1, 8(zero)
+    bnez s0, patch
+    li   a7, 93
+    ecall
+";
+
+#[test]
+fn stores_to_code_invalidate_decoded_instructions() {
+    for cached in [true, false] {
+        let (mut cpu, mut memory) = load(SELF_MODIFYING);
+        cpu.set_decode_cache(cached);
+        assert_eq!(cpu.run(&mut memory), StopReason::Halted { exit_code: 6 });
+        assert_eq!(cpu.instret(), 16);
+    }
+}
+
+#[test]
+fn host_writes_take_effect_after_a_flush() {
+    let (mut cpu, mut memory) = load("
+    loop:
+        addi a0, a0, 1
+        j    loop
+    ");
+    assert_eq!(cpu.run_for(&mut memory, 10), StopReason::InstructionLimit);
+    assert_eq!(cpu.register(10), 5);
+
+    // addi a0, a0, 5
+    memory.store_word(0, 0x00550513);
+    cpu.flush_decode_cache();
+    assert_eq!(cpu.run_for(&mut memory, 4), StopReason::InstructionLimit);
+    assert_eq!(cpu.register(10), 15);
+}
+
+#[test]
+fn fence_i_after_patching_runs_the_new_code() {
+    // Calls `body` three times, rewriting its addi to add 10 after the first call
+    let source = "
+        li   s0, 3
+    loop:
+        call body
+        li   t1, 0x00a50513
+        la   t2, body
+        sw   t1, 0(t2)
+        fence.i
+        addi s0, s0, -1
+        bnez s0, loop
+        li   a7, 93
+        ecall
+    body:
+        addi a0, a0, 1
+        ret
+    ";
+    let mut counts = Vec::new();
+    for (cached, translated) in [(true, true), (true, false), (false, true), (false, false)] {
+        let (mut cpu, mut memory) = load(source);
+        cpu.set_decode_cache(cached);
+        cpu.set_block_translation(translated);
+        assert_eq!(cpu.run(&mut memory), StopReason::Halted { exit_code: 21 });
+        counts.push(cpu.instret());
+    }
+    assert!(counts.iter().all(|&count| count == counts[0]), "{:?}", counts);
+}
@@ -0,0 +1,125 @@
+use riscv_simulator::riscv_sim::*;
+
+/// Small xorshift generator so the sweep needs no external crates.
+struct XorShift(u32);
+
+impl XorShift {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
+
+#[test]
+fn every_opcode_funct3_and_funct7_decodes_or_errors() {
+    let mut rng = XorShift(0x9e37_79b9);
+    for opcode in 0..128u32 {
+        for funct3 in 0..8u32 {
+            for funct7 in 0..128u32 {
+                for _ in 0..4 {
+                    // Random rd, rs1 and rs2 under fixed major opcode and function codes
+                    let fields = rng.next() & 0x01ff_8f80;
+                    let word = funct7 << 25 | funct3 << 12 | opcode | fields;
+                    match decode(word) {
+                        Ok(instruction) => {
+                            let pattern = pattern(instruction.mnemonic()).unwrap();
+                            assert_eq!(word & pattern.mask, pattern.bits, "0x{:08x}", word);
+                        }
+                        Err(error) => {
+                            assert_eq!(error.word(), word);
+                            assert!(PATTERNS.iter().all(|p| word & p.mask != p.bits), "0x{:08x}", word);
+                            let expected = if word & 0xffff == 0 || word == u32::MAX {
+                                DecodeError::Illegal(word)
+                            } else if opcode & 0b11 != 0b11
+                                || opcode & 0b11100 == 0b11100
+                                || [0x07, 0x27, 0x43, 0x47, 0x4b, 0x4f, 0x53, 0x57].contains(&opcode)
+                            {
+                                DecodeError::Unsupported(word)
+                            } else {
+                                DecodeError::Reserved(word)
+                            };
+                            assert_eq!(error, expected);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // And a sample of the whole word space
+    for _ in 0..1_000_000 {
+        let word = rng.next();
+        if let Err(error) = decode(word) {
+            assert_eq!(error.word(), word);
+        }
+    }
+}
+
+#[test]
+fn known_encodings_fail_with_the_right_error() {
+    let illegal = [0x0000_0000, 0xffff_ffff, 0x0001_0000, 0x1234_0000];
+    let unsupported = [
+        0x0000_4501, // c.li a0, 0
+        0x0000_8082, // c.ret
+        0x0000_001f, // 48-bit
+        0x0000_007f, // 80-bit and longer
+        0x0005_2007, // flw ft0, 0(a0)
+        0x00a5_2027, // fsw fa0, 0(a0)
+        0x0000_0053, // fadd.s ft0, ft0, ft0
+        0x0000_0043, // fmadd.s
+        0x0200_0057, // vector
+    ];
+    let reserved = [
+        0x0000_000b, // custom-0
+        0x0000_002b, // custom-1
+        0x0000_3003, // ld, RV64 only
+        0x0000_7003, // load funct3 7
+        0x0000_2063, // branch funct3 2
+        0x0000_1067, // jalr funct3 1
+        0x4000_1013, // slli with funct7 0x20
+        0x0400_0033, // OP funct7 0x02
+        0x0000_3023, // sd, RV64 only
+        0x0000_302f, // amo .d, RV64 only
+        0x1010_a02f, // lr.w with rs2 set
+        0x3000_202f, // amo funct5 0x06
+        0x0000_00f3, // ecall with rd set
+        0x0020_0073, // funct12 2 (uret, removed)
+        0x1050_00f3, // wfi with rd set
+        0x0000_201b, // OP-IMM-32, RV64 only
+    ];
+    for word in illegal {
+        assert_eq!(decode(word), Err(DecodeError::Illegal(word)), "0x{:08x}", word);
+    }
+    for word in unsupported {
+        assert_eq!(decode(word), Err(DecodeError::Unsupported(word)), "0x{:08x}", word);
+    }
+    for word in reserved {
+        assert_eq!(decode(word), Err(DecodeError::Reserved(word)), "0x{:08x}", word);
+    }
+    assert_eq!(DecodeError::Reserved(0x2063).to_string(), "reserved instruction encoding 0x00002063");
+
+    // Neighbours of those that are defined
+    assert_eq!(decode(0x0000_0073).map(|i| i.mnemonic()), Ok("ecall"));
+    assert_eq!(decode(0x1050_0073).map(|i| i.mnemonic()), Ok("wfi"));
+    assert_eq!(decode(0x1000_a02f).map(|i| i.mnemonic()), Ok("lr.w"));
+    assert_eq!(decode(0x0000_100f).map(|i| i.mnemonic()), Ok("fence.i"));
+    assert_eq!(decode(0x0200_0033).map(|i| i.mnemonic()), Ok("mul"));
+}
+
+#[test]
+fn sign_extend_copies_the_top_bit() {
+    assert_eq!(sign_extend(0x800, 12), 0xffff_f800);
+    assert_eq!(sign_extend(0x7ff, 12), 0x7ff);
+    assert_eq!(sign_extend(0xffff_f123, 12), 0x123);
+    assert_eq!(sign_extend(1, 1), u32::MAX);
+    assert_eq!(sign_extend(0x8000_0000, 32), 0x8000_0000);
+}
+
+#[test]
+fn opcode_constants_stay_in_their_module() {
+    assert_eq!(riscv_simulator::opcode::opcode::OPCODE_LUI, 0b0110111);
+    assert_eq!(opcode::FUNCT3_BNE, 0b001);
+    assert_eq!(decode(0x0000_0037).map(|i| i.mnemonic()), Ok("lui"));
+}
@@ -0,0 +1,102 @@
+use riscv_simulator::riscv_sim::*;
+
+fn r(rd: u8, rs1: u8, rs2: u8) -> RTypeInstruction {
+    RTypeInstruction { rd, rs1, rs2 }
+}
+
+fn i(rd: u8, rs1: u8, imm: i32) -> ITypeInstruction {
+    ITypeInstruction { rd, rs1, imm }
+}
+
+fn b(rs1: u8, rs2: u8, imm: i32) -> BTypeInstruction {
+    BTypeInstruction { rs1, rs2, imm }
+}
+
+fn csr(rd: u8, csr: u16, rs1: u8) -> CsrInstruction {
+    CsrInstruction { rd, rs1, csr }
+}
+
+#[test]
+fn words_disassemble_like_objdump() {
+    let golden = [
+        // Raw words as objdump prints them
+        (0x0000_0013, 0x0, "nop"),
+        (0x0050_0093, 0x0, "li\tra,5"),
+        (0x0005_0593, 0x0, "mv\ta1,a0"),
+        (0x0000_8067, 0x0, "ret"),
+        (0x0011_2623, 0x0, "sw\tra,12(sp)"),
+        (0xfe20_8ee3, 0x200, "beq\tra,sp,1fc"),
+        (0xffdf_f06f, 0x100, "j\tfc"),
+        (0x0080_00ef, 0x1000, "jal\t1008"),
+        (0x1234_5537, 0x0, "lui\ta0,0x12345"),
+        (0x3000_2573, 0x0, "csrr\ta0,mstatus"),
+        (0xc000_2573, 0x0, "rdcycle\ta0"),
+        (0x3052_9073, 0x0, "csrw\tmtvec,t0"),
+        (0x3020_0073, 0x0, "mret"),
+        (0x8330_000f, 0x0, "fence.tso"),
+        (0x0ff0_000f, 0x0, "fence"),
+        (0x0000_100f, 0x0, "fence.i"),
+        (0x0000_0000, 0x0, ".4byte\t0x0"),
+        (0x0000_4501, 0x0, ".4byte\t0x4501"),
+    ];
+    for (word, pc, text) in golden {
+        assert_eq!(disassemble(word, pc), text, "0x{:08x}", word);
+    }
+
+    // Every ABI register name, and the aliases with their plain forms
+    for register in 0..32u8 {
+        let text = Instruction::Add(r(register, register, register)).disassemble(0);
+        let name = ABI_NAMES[register as usize];
+        assert_eq!(text, format!("add\t{},{},{}", name, name, name));
+    }
+    let instructions = [
+        (Instruction::Addi(i(10, 11, -3)), "addi\ta0,a1,-3"),
+        (Instruction::Xori(i(10, 11, -1)), "not\ta0,a1"),
+        (Instruction::Sltiu(i(10, 11, 1)), "seqz\ta0,a1"),
+        (Instruction::Sub(r(10, 0, 11)), "neg\ta0,a1"),
+        (Instruction::Sltu(r(10, 0, 11)), "snez\ta0,a1"),
+        (Instruction::Slt(r(10, 11, 0)), "sltz\ta0,a1"),
+        (Instruction::Slt(r(10, 0, 11)), "sgtz\ta0,a1"),
+        (Instruction::Slli(i(10, 10, 3)), "slli\ta0,a0,0x3"),
+        (Instruction::Lw(i(10, 2, -4)), "lw\ta0,-4(sp)"),
+        (Instruction::Jalr(i(0, 5, 0)), "jr\tt0"),
+        (Instruction::Jalr(i(1, 5, 0)), "jalr\tt0"),
+        (Instruction::Jalr(i(5, 6, 8)), "jalr\tt0,8(t1)"),
+        (Instruction::Beq(b(10, 0, 16)), "beqz\ta0,1010"),
+        (Instruction::Bne(b(10, 0, -16)), "bnez\ta0,ff0"),
+        (Instruction::Bge(b(0, 10, 8)), "blez\ta0,1008"),
+        (Instruction::Bge(b(10, 0, 8)), "bgez\ta0,1008"),
+        (Instruction::Blt(b(10, 0, 8)), "bltz\ta0,1008"),
+        (Instruction::Blt(b(0, 10, 8)), "bgtz\ta0,1008"),
+        (Instruction::Bltu(b(10, 11, 8)), "bltu\ta0,a1,1008"),
+        (Instruction::Jal(JTypeInstruction { rd: 5, imm: 8 }), "jal\tt0,1008"),
+        (Instruction::Csrrw(csr(10, 0x7c0, 11)), "csrrw\ta0,0x7c0,a1"),
+        (Instruction::Csrrs(csr(0, 0x304, 11)), "csrs\tmie,a1"),
+        (Instruction::Csrrci(csr(0, 0x300, 8)), "csrci\tmstatus,8"),
+        (Instruction::Csrrsi(csr(10, 0x344, 2)), "csrrsi\ta0,mip,2"),
+        (Instruction::Csrrs(csr(10, 0xc82, 0)), "rdinstreth\ta0"),
+        (Instruction::Fence(FenceInstruction { rd: 0, rs1: 0, fm: 0, pred: 0b0011, succ: 0b0001 }), "fence\trw,w"),
+        (Instruction::Fence(FenceInstruction { rd: 0, rs1: 0, fm: 0, pred: 0b0001, succ: 0 }), "pause"),
+        (Instruction::LrW(AtomicInstruction { rd: 10, rs1: 12, rs2: 0, aq: true, rl: false }), "lr.w.aq\ta0,(a2)"),
+        (Instruction::AmoaddW(AtomicInstruction { rd: 10, rs1: 12, rs2: 11, aq: true, rl: true }), "amoadd.w.aqrl\ta0,a1,(a2)"),
+        (Instruction::SfenceVma(r(0, 10, 0)), "sfence.vma\ta0"),
+    ];
+    for (instruction, text) in instructions {
+        assert_eq!(instruction.disassemble(0x1000), text);
+    }
+
+    // Without a pc, targets are relative
+    assert_eq!(Instruction::Jal(JTypeInstruction { rd: 0, imm: 8 }).to_string(), "j\t.+8");
+    assert_eq!(Instruction::Bne(b(10, 11, -12)).to_string(), "bne\ta0,a1,.-12");
+}
+
+#[test]
+fn register_and_csr_names_resolve_both_ways() {
+    assert_eq!((register_name(8), register_name(31)), ("s0", "t6"));
+    assert_eq!((register_number("fp"), register_number("s0"), register_number("x8")), (Some(8), Some(8), Some(8)));
+    assert_eq!((register_number("zero"), register_number("x31")), (Some(0), Some(31)));
+    assert_eq!((register_number("x32"), register_number("a8")), (None, None));
+    assert_eq!((csr_name(0x300), csr_name(0xb02), csr_name(0x7c0)), (Some("mstatus"), Some("minstret"), None));
+    assert_eq!((csr_number("mepc"), csr_number("0x7c0"), csr_number("768")), (Some(0x341), Some(0x7c0), Some(0x300)));
+    assert_eq!((csr_number("0x1000"), csr_number("bogus")), (None, None));
+}
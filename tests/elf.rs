@@ -0,0 +1,86 @@
+use riscv_simulator::elf::*;
+use riscv_simulator::memory::Memory;
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// One PT_LOAD segment of `code` at 0x100 with 8 bytes of bss, and a symbol table
+/// holding `main`, a section symbol and a `$x` mapping symbol.
+fn executable(code: &[u8]) -> Vec<u8> {
+    let code_offset = 52 + 32;
+    let strtab = b"\0main\0$x\0";
+    let strtab_offset = code_offset + code.len();
+    let symtab_offset = strtab_offset + strtab.len();
+    let symbols: [(u32, u32, u8, u16); 4] = [(0, 0, 0, 0), (1, 0x104, 0x12, 1), (0, 0x100, 0x03, 1), (6, 0x100, 0, 1)];
+    let shoff = symtab_offset + symbols.len() * 16;
+
+    let mut out = vec![0x7f, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    push_u16(&mut out, 2);
+    push_u16(&mut out, 243);
+    push_u32(&mut out, 1);
+    push_u32(&mut out, 0x104);
+    push_u32(&mut out, 52);
+    push_u32(&mut out, shoff as u32);
+    push_u32(&mut out, 0);
+    for value in [52, 32, 1, 40, 3, 0] {
+        push_u16(&mut out, value);
+    }
+    for value in [1, code_offset as u32, 0x100, 0x100, code.len() as u32, code.len() as u32 + 8, 5, 4] {
+        push_u32(&mut out, value);
+    }
+    out.extend_from_slice(code);
+    out.extend_from_slice(strtab);
+    for (name, value, info, shndx) in symbols {
+        push_u32(&mut out, name);
+        push_u32(&mut out, value);
+        push_u32(&mut out, 0);
+        out.extend_from_slice(&[info, 0]);
+        push_u16(&mut out, shndx);
+    }
+    let sections: [[u32; 10]; 3] = [
+        [0; 10],
+        [0, 2, 0, 0, symtab_offset as u32, symbols.len() as u32 * 16, 2, 1, 4, 16],
+        [0, 3, 0, 0, strtab_offset as u32, strtab.len() as u32, 0, 0, 1, 0],
+    ];
+    for section in sections {
+        for value in section {
+            push_u32(&mut out, value);
+        }
+    }
+    out
+}
+
+#[test]
+fn loads_segments_and_symbols() {
+    let elf = ElfFile::parse(&executable(&[0x13, 0, 0, 0, 0x73, 0, 0x10, 0])).unwrap();
+    assert_eq!(elf.entry, 0x104);
+    assert_eq!(elf.end(), 0x110);
+    assert_eq!(elf.symbols.len(), 1);
+    assert_eq!(elf.symbol("main"), Some(0x104));
+
+    let mut memory = Memory::with_size(0x200);
+    memory.write_bytes(0x108, &[0xff; 8]);
+    elf.load_into(&mut memory).unwrap();
+    assert_eq!(memory.fetch_word(0x104), 0x00100073);
+    assert_eq!(memory.load_word(0x108), 0);
+
+    let mut small = Memory::with_size(0x108);
+    assert_eq!(elf.load_into(&mut small), Err(ElfError::SegmentOutOfRange { address: 0x100, size: 16 }));
+}
+
+#[test]
+fn rejects_unsupported_files() {
+    let mut data = executable(&[0; 4]);
+    assert_eq!(ElfFile::parse(b"#!/bin/sh").unwrap_err(), ElfError::NotElf);
+    assert_eq!(ElfFile::parse(&data[..40]).unwrap_err(), ElfError::Truncated);
+    data[4] = 2;
+    assert!(matches!(ElfFile::parse(&data), Err(ElfError::Unsupported(_))));
+    data[4] = 1;
+    data[18] = 62;
+    assert!(matches!(ElfFile::parse(&data), Err(ElfError::Unsupported(_))));
+}
@@ -0,0 +1,221 @@
+use riscv_simulator::riscv_sim::*;
+
+/// Small xorshift generator so the property test needs no external crates.
+struct XorShift(u32);
+
+impl XorShift {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    /// Uniform in `low..=high`.
+    fn range(&mut self, low: i32, high: i32) -> i32 {
+        low + (self.next() % (high - low + 1) as u32) as i32
+    }
+
+    fn register(&mut self) -> u8 {
+        self.range(0, 31) as u8
+    }
+
+    fn bit(&mut self) -> bool {
+        self.next() & 1 != 0
+    }
+
+    fn r(&mut self) -> RTypeInstruction {
+        RTypeInstruction { rd: self.register(), rs1: self.register(), rs2: self.register() }
+    }
+
+    fn i(&mut self) -> ITypeInstruction {
+        ITypeInstruction { rd: self.register(), rs1: self.register(), imm: self.range(-2048, 2047) }
+    }
+
+    fn shamt(&mut self) -> ITypeInstruction {
+        ITypeInstruction { rd: self.register(), rs1: self.register(), imm: self.range(0, 31) }
+    }
+
+    fn s(&mut self) -> STypeInstruction {
+        STypeInstruction { rs1: self.register(), rs2: self.register(), imm: self.range(-2048, 2047) }
+    }
+
+    fn b(&mut self) -> BTypeInstruction {
+        BTypeInstruction { rs1: self.register(), rs2: self.register(), imm: self.range(-2048, 2047) * 2 }
+    }
+
+    fn u(&mut self) -> UTypeInstruction {
+        UTypeInstruction { rd: self.register(), imm: (self.next() & 0xfffff000) as i32 }
+    }
+
+    fn j(&mut self) -> JTypeInstruction {
+        JTypeInstruction { rd: self.register(), imm: self.range(-(1 << 19), (1 << 19) - 1) * 2 }
+    }
+
+    fn atomic(&mut self) -> AtomicInstruction {
+        AtomicInstruction { rd: self.register(), rs1: self.register(), rs2: self.register(), aq: self.bit(), rl: self.bit() }
+    }
+
+    fn csr(&mut self) -> CsrInstruction {
+        CsrInstruction { rd: self.register(), rs1: self.register(), csr: self.range(0, 0xfff) as u16 }
+    }
+
+    fn fence(&mut self) -> FenceInstruction {
+        let mut field = || self.range(0, 15) as u8;
+        FenceInstruction { fm: field(), pred: field(), succ: field(), rd: self.register(), rs1: self.register() }
+    }
+}
+
+#[test]
+fn decode_encode_round_trip_for_every_pattern() {
+    let mut rng = XorShift(0x2545f491);
+    for pattern in PATTERNS {
+        for _ in 0..10_000 {
+            // Random operand bits cover every register and immediate in range
+            let word = (rng.next() & !pattern.mask) | pattern.bits;
+            let instruction = (pattern.decode)(word);
+            assert_eq!(instruction.mnemonic(), pattern.mnemonic);
+            assert_eq!(instruction.encode(), Ok(word), "{:?}", instruction);
+        }
+    }
+}
+
+/// `instruction` with random operands in range for its encoding.
+fn randomize(instruction: Instruction, rng: &mut XorShift) -> Instruction {
+    use Instruction::*;
+    match instruction {
+        Add(_) => Add(rng.r()),
+        Sub(_) => Sub(rng.r()),
+        Sll(_) => Sll(rng.r()),
+        Slt(_) => Slt(rng.r()),
+        Sltu(_) => Sltu(rng.r()),
+        Xor(_) => Xor(rng.r()),
+        Srl(_) => Srl(rng.r()),
+        Sra(_) => Sra(rng.r()),
+        Or(_) => Or(rng.r()),
+        And(_) => And(rng.r()),
+        Mul(_) => Mul(rng.r()),
+        Mulh(_) => Mulh(rng.r()),
+        Mulhsu(_) => Mulhsu(rng.r()),
+        Mulhu(_) => Mulhu(rng.r()),
+        Div(_) => Div(rng.r()),
+        Divu(_) => Divu(rng.r()),
+        Rem(_) => Rem(rng.r()),
+        Remu(_) => Remu(rng.r()),
+        SfenceVma(_) => SfenceVma(RTypeInstruction { rd: 0, ..rng.r() }),
+        Addi(_) => Addi(rng.i()),
+        Slti(_) => Slti(rng.i()),
+        Sltiu(_) => Sltiu(rng.i()),
+        Xori(_) => Xori(rng.i()),
+        Ori(_) => Ori(rng.i()),
+        Andi(_) => Andi(rng.i()),
+        Lb(_) => Lb(rng.i()),
+        Lh(_) => Lh(rng.i()),
+        Lw(_) => Lw(rng.i()),
+        Lbu(_) => Lbu(rng.i()),
+        Lhu(_) => Lhu(rng.i()),
+        Jalr(_) => Jalr(rng.i()),
+        FenceI(_) => FenceI(rng.i()),
+        Slli(_) => Slli(rng.shamt()),
+        Srli(_) => Srli(rng.shamt()),
+        Srai(_) => Srai(rng.shamt()),
+        Sb(_) => Sb(rng.s()),
+        Sh(_) => Sh(rng.s()),
+        Sw(_) => Sw(rng.s()),
+        Beq(_) => Beq(rng.b()),
+        Bne(_) => Bne(rng.b()),
+        Blt(_) => Blt(rng.b()),
+        Bge(_) => Bge(rng.b()),
+        Bltu(_) => Bltu(rng.b()),
+        Bgeu(_) => Bgeu(rng.b()),
+        Lui(_) => Lui(rng.u()),
+        Auipc(_) => Auipc(rng.u()),
+        Jal(_) => Jal(rng.j()),
+        Fence(_) => Fence(rng.fence()),
+        LrW(_) => LrW(AtomicInstruction { rs2: 0, ..rng.atomic() }),
+        ScW(_) => ScW(rng.atomic()),
+        AmoswapW(_) => AmoswapW(rng.atomic()),
+        AmoaddW(_) => AmoaddW(rng.atomic()),
+        AmoxorW(_) => AmoxorW(rng.atomic()),
+        AmoandW(_) => AmoandW(rng.atomic()),
+        AmoorW(_) => AmoorW(rng.atomic()),
+        AmominW(_) => AmominW(rng.atomic()),
+        AmomaxW(_) => AmomaxW(rng.atomic()),
+        AmominuW(_) => AmominuW(rng.atomic()),
+        AmomaxuW(_) => AmomaxuW(rng.atomic()),
+        Csrrw(_) => Csrrw(rng.csr()),
+        Csrrs(_) => Csrrs(rng.csr()),
+        Csrrc(_) => Csrrc(rng.csr()),
+        Csrrwi(_) => Csrrwi(rng.csr()),
+        Csrrsi(_) => Csrrsi(rng.csr()),
+        Csrrci(_) => Csrrci(rng.csr()),
+        Ecall | Ebreak | Sret | Mret | Wfi => instruction,
+    }
+}
+
+#[test]
+fn encode_decode_round_trip_for_generated_instructions() {
+    let mut rng = XorShift(0x6a09e667);
+    for pattern in PATTERNS {
+        let template = (pattern.decode)(pattern.bits);
+        for _ in 0..10_000 {
+            let instruction = randomize(template, &mut rng);
+            assert_eq!(instruction.mnemonic(), pattern.mnemonic);
+            assert_eq!(Instruction::decode(instruction.encode().unwrap()), Ok(instruction));
+        }
+    }
+}
+
+#[test]
+fn encode_rejects_operands_it_would_drop() {
+    let dropped = [
+        Instruction::SfenceVma(RTypeInstruction { rd: 1, rs1: 2, rs2: 3 }),
+        Instruction::LrW(AtomicInstruction { rd: 1, rs1: 2, rs2: 3, aq: false, rl: false }),
+        Instruction::Addi(ITypeInstruction { rd: 1, rs1: 2, imm: 2048 }),
+        Instruction::Slli(ITypeInstruction { rd: 1, rs1: 2, imm: 32 }),
+        Instruction::Beq(BTypeInstruction { rs1: 1, rs2: 2, imm: 3 }),
+        Instruction::Add(RTypeInstruction { rd: 32, rs1: 0, rs2: 0 }),
+        Instruction::Fence(FenceInstruction { rd: 0, rs1: 0, fm: 16, pred: 0, succ: 0 }),
+    ];
+    for instruction in dropped {
+        assert_eq!(instruction.encode(), Err(EncodeError(instruction)));
+    }
+    assert_eq!(
+        EncodeError(dropped[2]).to_string(),
+        "Addi(ITypeInstruction { rd: 1, rs1: 2, imm: 2048 }) has operands out of range for `addi`"
+    );
+}
+
+#[test]
+fn immediate_extremes_round_trip() {
+    let instructions = [
+        Instruction::Addi(ITypeInstruction { rd: 31, rs1: 31, imm: -2048 }),
+        Instruction::Addi(ITypeInstruction { rd: 1, rs1: 2, imm: 2047 }),
+        Instruction::Srai(ITypeInstruction { rd: 3, rs1: 4, imm: 31 }),
+        Instruction::Sw(STypeInstruction { rs1: 2, rs2: 1, imm: -2048 }),
+        Instruction::Sb(STypeInstruction { rs1: 2, rs2: 1, imm: 2047 }),
+        Instruction::Beq(BTypeInstruction { rs1: 5, rs2: 6, imm: -4096 }),
+        Instruction::Bgeu(BTypeInstruction { rs1: 5, rs2: 6, imm: 4094 }),
+        Instruction::Jal(JTypeInstruction { rd: 1, imm: -1048576 }),
+        Instruction::Jal(JTypeInstruction { rd: 0, imm: 1048574 }),
+        Instruction::Lui(UTypeInstruction { rd: 10, imm: 0xfffff000u32 as i32 }),
+        Instruction::Auipc(UTypeInstruction { rd: 10, imm: 0x7ffff000 }),
+        Instruction::Csrrwi(CsrInstruction { rd: 0, rs1: 31, csr: 0xfff }),
+        Instruction::ScW(AtomicInstruction { rd: 1, rs1: 2, rs2: 3, aq: true, rl: true }),
+        Instruction::Fence(FenceInstruction { rd: 0, rs1: 0, fm: 0b1000, pred: 0b0011, succ: 0b0011 }),
+        Instruction::Ecall,
+        Instruction::Wfi,
+    ];
+    for instruction in instructions {
+        assert_eq!(Instruction::decode(instruction.encode().unwrap()), Ok(instruction));
+    }
+}
+
+#[test]
+fn encodes_known_words() {
+    assert_eq!(Instruction::Addi(ITypeInstruction { rd: 1, rs1: 0, imm: 5 }).encode(), Ok(0x00500093));
+    assert_eq!(Instruction::Beq(BTypeInstruction { rs1: 1, rs2: 2, imm: -4 }).encode(), Ok(0xfe208ee3));
+    assert_eq!(Instruction::Sw(STypeInstruction { rs1: 2, rs2: 1, imm: 12 }).encode(), Ok(0x00112623));
+    assert_eq!(Instruction::Jal(JTypeInstruction { rd: 0, imm: -4 }).encode(), Ok(0xffdff06f));
+    assert_eq!(Instruction::Mret.encode(), Ok(0x30200073));
+}
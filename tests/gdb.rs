@@ -0,0 +1,126 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use riscv_simulator::gdb::GdbServer;
+use riscv_simulator::riscv_sim::*;
+
+fn server(source: &str) -> GdbServer {
+    let image = assemble(source).unwrap();
+    let mut memory = Memory::with_size(0x200);
+    image.load_into(&mut memory).unwrap();
+    GdbServer::new(Cpu::new(), memory)
+}
+
+fn reply(server: &mut GdbServer, packet: &str) -> String {
+    server.handle_packet(packet).unwrap()
+}
+
+#[test]
+fn registers_and_memory() {
+    let mut server = server("li a0, 0x1234\nj .");
+    assert_eq!(reply(&mut server, "s"), "S05");
+    assert_eq!(reply(&mut server, "s"), "S05");
+    assert_eq!(reply(&mut server, "pa"), "34120000");
+    assert_eq!(reply(&mut server, "g").len(), 33 * 8);
+    assert_eq!(reply(&mut server, "P20=08000000"), "OK");
+    assert_eq!(server.cpu.pc(), 8);
+    // mscratch is CSR 0x340, GDB register 65 + 0x340
+    assert_eq!(reply(&mut server, "P381=efbeadde"), "OK");
+    assert_eq!(server.cpu.read_csr(0x340), Some(0xdeadbeef));
+    assert_eq!(reply(&mut server, "p1041"), "03000000");
+
+    assert_eq!(reply(&mut server, "m0,4"), "37150000");
+    assert_eq!(reply(&mut server, "M10,2:aabb"), "OK");
+    assert_eq!(reply(&mut server, "m10,3"), "aabb00");
+    assert_eq!(reply(&mut server, "m1fe,4"), "0000");
+    assert_eq!(reply(&mut server, "m400,4"), "E14");
+
+    let xml = server.target_description();
+    assert!(xml.contains("<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"32\"/>"));
+    assert!(xml.contains("<reg name=\"mstatus\" bitsize=\"32\" regnum=\"833\"/>"));
+}
+
+#[test]
+fn breakpoints_watchpoints_and_exit() {
+    let mut server = server(
+        "
+            li   a0, 0
+        loop:
+            addi a0, a0, 1
+            sw   a0, 0x100(zero)
+            li   t0, 3
+            bne  a0, t0, loop
+            li   a7, 93
+            ecall
+        ",
+    );
+    assert_eq!(reply(&mut server, "Z1,4,4"), "OK");
+    assert_eq!(reply(&mut server, "c"), "T05hwbreak:;");
+    assert_eq!(reply(&mut server, "c"), "T05hwbreak:;");
+    assert_eq!(server.cpu.register(10), 1);
+    assert_eq!(reply(&mut server, "z1,4,4"), "OK");
+
+    assert_eq!(reply(&mut server, "Z2,100,4"), "OK");
+    assert_eq!(reply(&mut server, "c"), "T05watch:100;");
+    assert_eq!(server.cpu.pc(), 0xc);
+    assert_eq!(reply(&mut server, "z2,100,4"), "OK");
+    assert_eq!(reply(&mut server, "Z4,100,4"), "OK");
+    assert_eq!(reply(&mut server, "c"), "T05awatch:100;");
+    assert_eq!(reply(&mut server, "z4,100,4"), "OK");
+
+    assert_eq!(reply(&mut server, "c"), "W03");
+    assert_eq!(reply(&mut server, "?"), "W03");
+}
+
+fn send(stream: &mut TcpStream, payload: &str) {
+    let checksum = payload.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    stream.write_all(format!("${}#{:02x}", payload, checksum).as_bytes()).unwrap();
+}
+
+fn receive(stream: &mut TcpStream) -> String {
+    let mut data = Vec::new();
+    let mut byte = [0u8];
+    loop {
+        stream.read_exact(&mut byte).unwrap();
+        data.push(byte[0]);
+        if data.len() >= 3 && data[data.len() - 3] == b'#' {
+            let text = String::from_utf8(data).unwrap();
+            let start = text.find('$').unwrap();
+            return text[start + 1..text.len() - 3].to_string();
+        }
+    }
+}
+
+#[test]
+fn serves_a_tcp_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let mut server = server("loop:\naddi a0, a0, 1\nj loop");
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        server.serve(stream).unwrap();
+        server.cpu.register(10)
+    });
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    send(&mut stream, "qSupported:swbreak+");
+    assert!(receive(&mut stream).contains("qXfer:features:read+"));
+    // A corrupted checksum is rejected and must be resent
+    stream.write_all(b"$?#00").unwrap();
+    let mut nak = [0u8];
+    stream.read_exact(&mut nak).unwrap();
+    assert_eq!(nak[0], b'-');
+    send(&mut stream, "Z0,4,4");
+    assert_eq!(receive(&mut stream), "OK");
+    send(&mut stream, "c");
+    assert_eq!(receive(&mut stream), "T05swbreak:;");
+    send(&mut stream, "z0,4,4");
+    assert_eq!(receive(&mut stream), "OK");
+    send(&mut stream, "c");
+    stream.write_all(&[0x03]).unwrap();
+    assert_eq!(receive(&mut stream), "S02");
+    send(&mut stream, "D");
+    assert_eq!(receive(&mut stream), "OK");
+    assert!(handle.join().unwrap() > 1);
+}
@@ -0,0 +1,131 @@
+use riscv_simulator::riscv_sim::*;
+
+mod common;
+use common::load_with;
+
+#[derive(Default)]
+struct Recorder {
+    events: Vec<String>,
+}
+
+impl Hooks for Recorder {
+    fn fetch(&mut self, pc: u32, raw: u32) {
+        self.events.push(format!("fetch {:x} {:08x}", pc, raw));
+    }
+
+    fn pre_execute(&mut self, pc: u32, _instruction: &Instruction) {
+        self.events.push(format!("pre {:x}", pc));
+    }
+
+    fn post_execute(&mut self, pc: u32, _instruction: &Instruction, next_pc: u32) {
+        self.events.push(format!("post {:x} -> {:x}", pc, next_pc));
+    }
+
+    fn memory_read(&mut self, address: u32, size: u32, value: u32) {
+        self.events.push(format!("read {:x} {} {:x}", address, size, value));
+    }
+
+    fn memory_write(&mut self, address: u32, size: u32, value: u32) {
+        self.events.push(format!("write {:x} {} {:x}", address, size, value));
+    }
+
+    fn register_write(&mut self, register: u8, value: u32) {
+        self.events.push(format!("x{} = {:x}", register, value));
+    }
+
+    fn trap(&mut self, trap: Trap, pc: u32) {
+        self.events.push(format!("trap {:x} cause {}", pc, trap.cause()));
+    }
+
+    fn csr_read(&mut self, csr: u16, value: u32) {
+        self.events.push(format!("csr read {:x} {:x}", csr, value));
+    }
+
+    fn csr_write(&mut self, csr: u16, value: u32) {
+        self.events.push(format!("csr write {:x} {:x}", csr, value));
+    }
+}
+
+#[test]
+fn hooks_observe_execution_in_program_order() {
+    let source = "li a0, 0x40\nsb a0, 1(a0)\nlw a1, 0(a0)\ncsrw mscratch, a1\ncsrr a2, mscratch\n.word 0";
+    let (mut cpu, mut memory) = load_with(source, Recorder::default());
+    let stop = cpu.run(&mut memory);
+    assert_eq!(stop, StopReason::Trap { trap: Trap::Exception(Exception::IllegalInstruction(0)), pc: 20 });
+
+    assert_eq!(
+        cpu.into_hooks().events,
+        [
+            "fetch 0 04000513",
+            "pre 0",
+            "x10 = 40",
+            "post 0 -> 4",
+            "fetch 4 00a500a3",
+            "pre 4",
+            "write 41 1 40",
+            "post 4 -> 8",
+            "fetch 8 00052583",
+            "pre 8",
+            "read 40 4 4000",
+            "x11 = 4000",
+            "post 8 -> c",
+            "fetch c 34059073",
+            "pre c",
+            "csr write 340 4000",
+            "post c -> 10",
+            "fetch 10 34002673",
+            "pre 10",
+            "csr read 340 4000",
+            "x12 = 4000",
+            "post 10 -> 14",
+            "fetch 14 00000000",
+            "trap 14 cause 2",
+        ]
+    );
+}
+
+#[test]
+fn pre_execute_sees_decoded_instruction() {
+    struct Mnemonics(Vec<String>);
+    impl Hooks for Mnemonics {
+        fn pre_execute(&mut self, pc: u32, instruction: &Instruction) {
+            self.0.push(disassemble(instruction.encode().unwrap(), pc));
+        }
+    }
+
+    let (mut cpu, mut memory) = load_with("li a0, 5\nli a7, 93\necall", Mnemonics(Vec::new()));
+    assert_eq!(cpu.run(&mut memory), StopReason::Halted { exit_code: 5 });
+    assert_eq!(cpu.hooks().0, ["li\ta0,5", "li\ta7,93", "ecall"]);
+}
+
+#[test]
+fn host_serviced_traps_retire_and_combinators_observe_in_order() {
+    let hooks = (Recorder::default(), (Some(Recorder::default()), None::<Recorder>));
+    let (mut cpu, mut memory) = load_with("ebreak\nli a7, 93\necall", hooks);
+    assert_eq!(cpu.run(&mut memory), StopReason::Breakpoint { pc: 0 });
+    assert_eq!(cpu.run(&mut memory), StopReason::Halted { exit_code: 0 });
+
+    // The trap is reported, then the instruction retires as the host serviced it
+    let (first, (second, none)) = cpu.into_hooks();
+    assert_eq!(
+        first.events,
+        [
+            "fetch 0 00100073",
+            "pre 0",
+            "trap 0 cause 3",
+            "post 0 -> 4",
+            "fetch 4 05d00893",
+            "pre 4",
+            "x17 = 5d",
+            "post 4 -> 8",
+            "fetch 8 00000073",
+            "pre 8",
+            "trap 8 cause 11",
+            "post 8 -> c",
+        ]
+    );
+    assert_eq!(second.unwrap().events, first.events);
+    assert!(none.is_none());
+    const { assert!(!<(NoHooks, Option<NoHooks>) as Hooks>::OBSERVES) };
+    const { assert!(<(NoHooks, Option<Recorder>) as Hooks>::OBSERVES) };
+}
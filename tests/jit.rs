@@ -0,0 +1,172 @@
+use riscv_simulator::riscv_sim::*;
+
+mod common;
+use common::{load, MEMORY_SIZE};
+
+// Mixes every kind of translated instruction in a hot loop. On its last iteration a
+// misaligned load traps inside compiled code and the handler skips it; stores also
+// patch the loop's own code every few iterations.
+const PROGRAM: &str = "
+    la   t0, handler
+    csrw mtvec, t0
+    li   s0, 0x800
+    li   s1, 200
+    li   a0, 0x12345678
+loop:
+    addi a0, a0, 0x765
+    slli t1, a0, 7
+    srli t2, a0, 3
+    srai t3, a0, 29
+    xor  a1, t1, t2
+    or   a1, a1, t3
+    and  a2, a1, a0
+    sub  a2, a2, s1
+    sll  a3, a2, s1
+    srl  a4, a2, s1
+    sra  a5, a2, s1
+    slt  a6, a2, a1
+    sltu a7, a2, a1
+    slti t4, a3, -5
+    sltiu t5, a4, 100
+    xori t6, a5, -1
+    ori  t6, t6, 0x70
+    andi t6, t6, 0x7f0
+    mul  s2, a0, a1
+    mulh s3, a0, a1
+    mulhsu s4, a1, a0
+    mulhu s5, a0, a1
+    divu s6, a0, s1
+    rem  s7, a1, s1
+    sw   a0, 0(s0)
+    sh   a1, 6(s0)
+    sb   a2, 9(s0)
+    lb   s8, 3(s0)
+    lh   s9, 6(s0)
+    lbu  s10, 9(s0)
+    lhu  s11, 2(s0)
+    add  a6, a6, s8
+    add  a6, a6, s9
+    add  a6, a6, s10
+    add  a6, a6, s11
+    sltiu t1, s1, 2
+    add  t1, t1, s0
+    lw   t2, 0(t1)
+    andi t3, s1, 31
+    bnez t3, skip
+    li   t4, 0x00180813
+    la   t5, patched
+    sw   t4, 0(t5)
+skip:
+    call function
+    addi s0, s0, 16
+    addi s1, s1, -1
+    blt  zero, s1, loop
+    csrw mtvec, zero
+    li   a7, 93
+    ecall
+function:
+patched:
+    nop
+    bltu a2, a3, less
+    bge  a4, a5, done
+    bgeu a2, a3, done
+less:
+    beq  a0, a1, done
+    jalr zero, 0(ra)
+done:
+    ret
+handler:
+    csrr t5, mepc
+    addi t5, t5, 4
+    csrw mepc, t5
+    mret
+";
+
+// Blocks that only exit to each other, so compiled code runs as one chain
+const CHAINED: &str = "
+    li   s1, 3000
+loop:
+    addi a0, a0, 3
+    andi t0, s1, 4
+    beqz t0, odd
+    xori a0, a0, 0x55
+    j    next
+odd:
+    slli a1, a0, 1
+next:
+    addi s1, s1, -1
+    bnez s1, loop
+    li   a7, 93
+    ecall
+";
+
+fn interpreted(limit: u64) -> (Cpu, Memory, StopReason) {
+    interpreted_program(PROGRAM, limit)
+}
+
+fn interpreted_program(source: &str, limit: u64) -> (Cpu, Memory, StopReason) {
+    let (mut cpu, mut memory) = load(source);
+    cpu.set_block_translation(false);
+    let stop = cpu.run_for(&mut memory, limit);
+    (cpu, memory, stop)
+}
+
+#[test]
+fn compiled_blocks_match_the_interpreter() {
+    let (mut cpu, mut memory) = load(PROGRAM);
+    if !cpu.set_jit(true) {
+        return;
+    }
+    let stop = cpu.run_for(&mut memory, u64::MAX);
+    assert!(cpu.compiled_blocks() > 0);
+    let (expected, expected_memory, expected_stop) = interpreted(u64::MAX);
+    assert!(matches!(expected_stop, StopReason::Halted { .. }));
+    assert_eq!(stop, expected_stop);
+    assert_eq!(cpu.snapshot(&memory), expected.snapshot(&expected_memory));
+    // The misaligned load trapped once, on the last iteration
+    assert_eq!(cpu.csrs().mcause, 4);
+}
+
+#[test]
+fn compiles_blocks_with_many_side_exits() {
+    // Every store can fault, so each needs an exit stub of its own
+    let stores: String = (0..64).map(|i| format!("sw s1, {}(s0)\n", 4 * i)).collect();
+    let source = format!("li s0, 0x1000\nli s1, 100\nloop:\n{}addi s1, s1, -1\nbnez s1, loop\nli a7, 93\necall", stores);
+    let (mut cpu, mut memory) = load(&source);
+    if !cpu.set_jit(true) {
+        return;
+    }
+    assert_eq!(cpu.run(&mut memory), StopReason::Halted { exit_code: 0 });
+    assert!(cpu.compiled_blocks() > 0);
+    let (expected, expected_memory, _) = interpreted_program(&source, u64::MAX);
+    assert_eq!(cpu.snapshot(&memory), expected.snapshot(&expected_memory));
+    assert_eq!(memory.load_word(0x10fc), 1);
+}
+
+#[test]
+fn compiled_blocks_stop_at_exact_instructions() {
+    for source in [PROGRAM, CHAINED] {
+        let total = interpreted_program(source, u64::MAX).0.instret();
+        for limit in (1..total).step_by(97).chain([total - 40, total - 8]) {
+            let (mut cpu, mut memory) = load(source);
+            if !cpu.set_jit(true) {
+                return;
+            }
+            let stop = cpu.run_for(&mut memory, limit);
+            let (expected, expected_memory, expected_stop) = interpreted_program(source, limit);
+            assert_eq!(stop, expected_stop);
+            assert_eq!(cpu.snapshot(&memory), expected.snapshot(&expected_memory), "after {} instructions", limit);
+        }
+    }
+
+    // A store past the end of memory leaves compiled code through a side exit
+    let (mut cpu, mut memory) = load(PROGRAM);
+    cpu.set_jit(true);
+    cpu.run_for(&mut memory, interpreted(u64::MAX).0.instret() / 2);
+    cpu.set_register(8, MEMORY_SIZE as u32 - 4);
+    cpu.write_csr(0x305, 0);
+    let StopReason::Trap { pc, trap } = cpu.run(&mut memory) else { panic!() };
+    assert_eq!(trap, Trap::Exception(Exception::StoreAccessFault(MEMORY_SIZE as u32 + 2)));
+    assert_eq!(decode(memory.load_word(pc as usize)).unwrap().mnemonic(), "sh");
+    assert_eq!(memory.load_word(MEMORY_SIZE - 4), cpu.register(10));
+}
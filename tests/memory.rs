@@ -0,0 +1,58 @@
+use riscv_simulator::riscv_sim::*;
+
+#[test]
+fn little_endian_stores_load_back() {
+    let mut memory = Memory::with_size(0x100);
+    assert_eq!(memory.data_endianness(), Endianness::Little);
+    memory.store_word(0x10, 0x1122_3344);
+    memory.store_halfword(0x14, 0xaabb);
+    memory.store_byte(0x16, 0xcc);
+    assert_eq!(&memory.bytes()[0x10..0x17], &[0x44, 0x33, 0x22, 0x11, 0xbb, 0xaa, 0xcc]);
+    assert_eq!(memory.load_word(0x10), 0x1122_3344);
+    assert_eq!((memory.load_halfword(0x10), memory.load_halfword(0x12)), (0x3344, 0x1122));
+    assert_eq!((memory.load_byte(0x10), memory.load_byte(0x13)), (0x44, 0x11));
+    assert_eq!(memory.load_word(0x14), 0x00cc_aabb);
+    assert_eq!(memory.fetch_word(0x10), 0x1122_3344);
+}
+
+#[test]
+fn big_endian_data_mode_leaves_fetch_little_endian() {
+    let mut memory = Memory::with_size(0x100);
+    memory.set_data_endianness(Endianness::Big);
+    memory.store_word(0x10, 0x1122_3344);
+    memory.store_halfword(0x14, 0xaabb);
+    assert_eq!(&memory.bytes()[0x10..0x16], &[0x11, 0x22, 0x33, 0x44, 0xaa, 0xbb]);
+    assert_eq!(memory.load_word(0x10), 0x1122_3344);
+    assert_eq!((memory.load_halfword(0x10), memory.load_halfword(0x14)), (0x1122, 0xaabb));
+    assert_eq!(memory.load_byte(0x10), 0x11);
+    assert_eq!(memory.fetch_word(0x10), 0x4433_2211);
+    memory.set_data_endianness(Endianness::Little);
+    assert_eq!(memory.load_word(0x10), 0x4433_2211);
+
+    // mstatush.MBE switches the data accesses of a program running in M-mode
+    let image = assemble(
+        "
+        li   s0, 0x200
+        li   t0, 0x11223344
+        sw   t0, 0(s0)
+        lbu  a0, 0(s0)
+        li   t1, 32
+        csrs 0x310, t1
+        sw   t0, 4(s0)
+        lbu  a1, 4(s0)
+        lw   a2, 0(s0)
+        lhu  a3, 4(s0)
+        li   a7, 93
+        ecall
+        ",
+    )
+    .unwrap();
+    let mut memory = Memory::with_size(0x400);
+    image.load_into(&mut memory).unwrap();
+    let mut cpu = Cpu::new();
+    assert!(matches!(cpu.run(&mut memory), StopReason::Halted { .. }));
+    assert_eq!(cpu.data_endianness(), Endianness::Big);
+    assert_eq!((cpu.register(10), cpu.register(11)), (0x44, 0x11));
+    assert_eq!((cpu.register(12), cpu.register(13)), (0x4433_2211, 0x1122));
+    assert_eq!(&memory.bytes()[0x200..0x208], &[0x44, 0x33, 0x22, 0x11, 0x11, 0x22, 0x33, 0x44]);
+}
@@ -0,0 +1,67 @@
+use riscv_simulator::pipeline::{Forwarding, Pipeline, PipelineConfig, PipelineStats};
+
+mod common;
+
+fn run(source: &str, config: PipelineConfig) -> PipelineStats {
+    *common::run(source, Pipeline::new(config)).stats()
+}
+
+fn forwarding(forwarding: Forwarding) -> PipelineConfig {
+    PipelineConfig { forwarding, ..PipelineConfig::default() }
+}
+
+#[test]
+fn forwarding_decides_data_hazard_stalls() {
+    let chain = "
+        addi a0, zero, 1
+        addi a0, a0, 1
+        addi a0, a0, 1
+        addi a0, a0, 1
+        li   a7, 93
+        ecall
+    ";
+    // Six instructions fill and drain the pipeline in 10 cycles
+    for (mode, stalls) in [(Forwarding::Full, 0), (Forwarding::Memory, 3), (Forwarding::None, 6)] {
+        let stats = run(chain, forwarding(mode));
+        assert_eq!((stats.instructions, stats.data_stalls, stats.cycles), (6, stalls, 10 + stalls), "{:?}", mode);
+    }
+
+    let load_use = "
+        li   s0, 0x100
+        lw   a1, 0(s0)
+        add  a2, a1, a1
+        li   a7, 93
+        ecall
+    ";
+    let stats = run(load_use, forwarding(Forwarding::Full));
+    assert_eq!((stats.data_stalls, stats.load_use_stalls, stats.cycles), (0, 1, 10));
+    let stats = run(load_use, forwarding(Forwarding::None));
+    assert_eq!((stats.data_stalls, stats.load_use_stalls, stats.cycles), (2, 2, 13));
+}
+
+#[test]
+fn branches_and_multiplies_cost_extra_cycles() {
+    let source = "
+        li   s1, 3
+    loop:
+        addi s1, s1, -1
+        bnez s1, loop
+        mul  a0, s1, s1
+        addi a1, a0, 1
+        li   a7, 93
+        ecall
+    ";
+    // Two taken branches and a multiply that the next instruction waits out
+    let stats = run(source, PipelineConfig::default());
+    assert_eq!(stats.instructions, 11);
+    assert_eq!((stats.control_stalls, stats.structural_stalls, stats.data_stalls), (4, 2, 0));
+    assert_eq!(stats.cycles, 21);
+    assert!((stats.cpi() - 21.0 / 11.0).abs() < 1e-9);
+
+    let config = PipelineConfig { branch_penalty: 1, mul_latency: 6, ..PipelineConfig::default() };
+    let stats = run(source, config);
+    assert_eq!((stats.control_stalls, stats.structural_stalls, stats.cycles), (2, 5, 22));
+
+    let pipeline = common::run(source, Pipeline::default());
+    assert!(pipeline.summary().starts_with("instructions  11\ncycles        21\nCPI           1.909\n"));
+}
@@ -0,0 +1,87 @@
+use riscv_simulator::pipeline::{Pipeline, PipelineConfig};
+use riscv_simulator::predictor::*;
+
+mod common;
+use common::run;
+
+// A loop of 100 iterations around a branch taken every other iteration
+const ALTERNATING: &str = "
+        li   s1, 100
+    loop:
+        andi t0, s1, 1
+        beqz t0, skip
+        addi s2, s2, 1
+    skip:
+        addi s1, s1, -1
+        bnez s1, loop
+        li   a7, 93
+        ecall
+";
+const INNER: u32 = 8;
+const LOOP: u32 = 20;
+
+#[test]
+fn history_predicts_patterns_that_counters_cannot() {
+    let kinds: [(Box<dyn DirectionPredictor>, u64, u64); 5] = [
+        (Box::new(Static::NotTaken), 50, 99),
+        (Box::new(Static::BackwardTaken), 50, 2),
+        (Box::new(Bimodal::new(64)), 100, 2),
+        (Box::new(Gshare::new(64, 6)), 3, 6),
+        (Box::new(Tournament::new(64)), 4, 2),
+    ];
+    for (direction, inner, outer) in kinds {
+        let name = format!("{:?}", direction);
+        let predictor = run(ALTERNATING, BranchPredictor::new(direction));
+        assert_eq!(predictor.branch(INNER), Some(BranchStats { executed: 100, mispredicted: inner }), "{}", name);
+        assert_eq!(predictor.branch(LOOP), Some(BranchStats { executed: 100, mispredicted: outer }), "{}", name);
+        assert_eq!(predictor.conditional.mispredicted, inner + outer);
+        assert_eq!(predictor.branches().len(), 2);
+    }
+
+    // Only mispredicted transfers pay the branch penalty
+    let stats = *run(ALTERNATING, Pipeline::new(PipelineConfig::default())).stats();
+    assert_eq!(stats.control_stalls, 2 * (50 + 99));
+    let mut pipeline = Pipeline::new(PipelineConfig::default());
+    pipeline.set_predictor(BranchPredictor::new(Box::new(Tournament::new(64))));
+    let pipeline = run(ALTERNATING, pipeline);
+    assert_eq!(pipeline.stats().control_stalls, 2 * 6);
+    assert!(pipeline.summary().contains("conditional   200 executed, 6 mispredicted (97.00% accurate)\n"));
+    assert!(pipeline.summary().contains("returns       0 executed, 0 mispredicted (accuracy n/a)\n"));
+    assert!(pipeline.summary().ends_with("0x00000008       100             4\n0x00000014       100             2\n"));
+}
+
+#[test]
+fn return_stack_predicts_returns_to_many_callers() {
+    let source = "
+            li   s1, 10
+        loop:
+            jal  ra, f
+            jal  ra, f
+            addi s1, s1, -1
+            bnez s1, loop
+            li   a7, 93
+            ecall
+        f:
+            mv   s3, ra
+            jal  ra, h
+            mv   ra, s3
+            ret
+        h:
+            ret
+    ";
+    // Returns from f alternate between its callers, which the BTB alone cannot follow,
+    // and a stack one deep loses f's return address to h's
+    for (depth, mispredicted) in [(8, 0), (1, 20), (0, 21)] {
+        let tables = BranchPredictor::with_tables(Box::new(Bimodal::new(64)), Btb::new(64), ReturnStack::new(depth));
+        let predictor = run(source, tables);
+        assert_eq!(predictor.returns, BranchStats { executed: 40, mispredicted }, "depth {}", depth);
+        // Each call site misses in the BTB once
+        assert_eq!(predictor.jumps, BranchStats { executed: 40, mispredicted: 3 });
+    }
+
+    // Calls that alias in a small BTB evict each other's targets
+    let tables = BranchPredictor::with_tables(Box::new(Bimodal::new(64)), Btb::new(1), ReturnStack::new(8));
+    let predictor = run(source, tables);
+    assert_eq!(predictor.jumps.mispredicted, 40);
+    assert_eq!(predictor.branch(4), Some(BranchStats { executed: 10, mispredicted: 10 }));
+}
@@ -0,0 +1,71 @@
+use riscv_simulator::profile::Profiler;
+use riscv_simulator::riscv_sim::*;
+use riscv_simulator::symbols::SymbolTable;
+
+mod common;
+use common::load_with;
+
+const PROGRAM: &str = "
+_start:
+    li   s0, 2
+loop:
+    li   a0, 7
+    call square
+    addi s0, s0, -1
+    bnez s0, loop
+    li   a7, 93
+    ecall
+square:
+    addi sp, sp, -16
+    sw   ra, 12(sp)
+    call mul2
+    lw   ra, 12(sp)
+    addi sp, sp, 16
+    ret
+mul2:
+    mul  a0, a0, a0
+    ret
+";
+
+fn profile(cycle_cost: fn(&Instruction) -> u64) -> (Profiler, SymbolTable) {
+    let (mut cpu, mut memory) = load_with(PROGRAM, Profiler::with_cycle_cost(cycle_cost));
+    cpu.set_register(2, 0x1000);
+    assert_eq!(cpu.run(&mut memory), StopReason::Halted { exit_code: 49 });
+    assert_eq!(cpu.hooks().call_stack(), [0]);
+    (cpu.into_hooks(), SymbolTable::new(&assemble(PROGRAM).unwrap().symbols))
+}
+
+#[test]
+fn counts_per_pc_and_folds_call_stacks() {
+    let (profiler, symbols) = profile(|instruction| if matches!(instruction, Instruction::Mul(_)) { 5 } else { 1 });
+    assert_eq!(profiler.instructions(), 31);
+    assert_eq!(profiler.cycles(), 39);
+    let mul = profiler.pcs()[&0x3c];
+    assert_eq!((mul.raw, mul.instructions, mul.cycles), (0x02a50533, 2, 10));
+    assert_eq!(profiler.pcs()[&0].instructions, 1);
+
+    assert_eq!(profiler.folded(&symbols), "_start 13\n_start;square 14\n_start;square;mul2 12\n");
+}
+
+#[test]
+fn annotated_listing_groups_by_symbol() {
+    let (profiler, symbols) = profile(|_| 1);
+    let listing = profiler.annotated(&symbols);
+    assert!(listing.starts_with("31 instructions, 31 cycles\n"));
+    assert!(listing.contains("\n0000003c <mul2>:\n    6.45%            2          2  0x0000003c:\t02a50533\tmul\ta0,a0,a0\n"));
+    assert!(listing.contains("\n00000004 <loop>:\n"));
+}
+
+#[test]
+fn only_entered_handlers_push_frames() {
+    // EBREAK and exit serviced by the host retire like any other instruction
+    let (mut cpu, mut memory) = load_with("li a0, 1\nebreak\naddi a0, a0, 1\nli a7, 93\necall", Profiler::new());
+    assert_eq!(cpu.run(&mut memory), StopReason::Breakpoint { pc: 4 });
+    assert_eq!(cpu.run(&mut memory), StopReason::Halted { exit_code: 2 });
+    assert_eq!(cpu.hooks().call_stack(), [0]);
+
+    let (mut cpu, mut memory) = load_with("la t0, handler\ncsrw mtvec, t0\necall\nhandler:\nnop\nnop", Profiler::new());
+    cpu.add_breakpoint(20);
+    assert_eq!(cpu.run(&mut memory), StopReason::Breakpoint { pc: 20 });
+    assert_eq!(cpu.hooks().call_stack(), [0, 16]);
+}
@@ -0,0 +1,51 @@
+use riscv_simulator::reverse::History;
+use riscv_simulator::riscv_sim::*;
+
+mod common;
+use common::{load, PROGRAM};
+
+#[test]
+fn reverse_steps_restore_every_earlier_state() {
+    let (mut cpu, mut memory) = load(PROGRAM);
+    // A short interval makes stepping back replay from several checkpoints
+    let mut history = History::with_limits(4, 16);
+    let mut states = vec![cpu.snapshot(&memory)];
+    for _ in 0..25 {
+        assert_eq!(history.step(&mut cpu, &mut memory), StopReason::InstructionLimit);
+        states.push(cpu.snapshot(&memory));
+    }
+    assert_eq!(history.depth(), 25);
+
+    while let Some(expected) = states.pop() {
+        assert_eq!(cpu.snapshot(&memory), expected);
+        assert_eq!(history.reverse_step(&mut cpu, &mut memory), !states.is_empty());
+    }
+    assert_eq!(history.depth(), 0);
+    assert_eq!(history.run_for(&mut cpu, &mut memory, u64::MAX), StopReason::Halted { exit_code: 10 });
+}
+
+#[test]
+fn reverse_continue_stops_at_watchpoints_and_breakpoints() {
+    let (mut cpu, mut memory) = load(PROGRAM);
+    let mut history = History::with_limits(4, 64);
+    assert_eq!(history.reverse_for(&mut cpu, &mut memory, u64::MAX), StopReason::NoHistory { pc: 0 });
+    assert_eq!(history.run_for(&mut cpu, &mut memory, u64::MAX), StopReason::Halted { exit_code: 10 });
+
+    cpu.add_watchpoint(Watchpoint::new(0x2000, 4, WatchKind::Write));
+    for value in (9..=10).rev() {
+        let stop = history.reverse_for(&mut cpu, &mut memory, u64::MAX);
+        let StopReason::Watchpoint { pc, address, write, old, new, .. } = stop else { panic!("{:?}", stop) };
+        assert_eq!((address, write, old, new), (0x2000, true, value - 1, value));
+        assert_eq!(decode(memory.load_word(pc as usize)).unwrap().mnemonic(), "sw");
+        assert_eq!(memory.load_word(0x2000), value - 1);
+    }
+
+    cpu.remove_watchpoint(0x2000, 4, WatchKind::Write);
+    let store = cpu.pc();
+    cpu.add_breakpoint(store);
+    assert_eq!(history.reverse_for(&mut cpu, &mut memory, u64::MAX), StopReason::Breakpoint { pc: store });
+    assert_eq!((cpu.register(10), memory.load_word(0x2000)), (8, 7));
+    cpu.remove_breakpoint(store);
+    assert_eq!(history.reverse_for(&mut cpu, &mut memory, u64::MAX), StopReason::NoHistory { pc: 0 });
+    assert_eq!((cpu.register(10), memory.load_word(0x2000), cpu.instret()), (0, 0, 0));
+}
@@ -0,0 +1,77 @@
+use riscv_simulator::riscv_sim::*;
+use riscv_simulator::rvfi::*;
+use riscv_simulator::trace::Commit;
+
+fn records(source: &str) -> Vec<RvfiRecord> {
+    let image = assemble(source).unwrap();
+    let mut memory = Memory::with_size(0x100);
+    image.load_into(&mut memory).unwrap();
+    let mut cpu = Cpu::new();
+    cpu.set_commit_log(true);
+    let mut writer = RvfiWriter::new(Vec::new(), RvfiFormat::Binary).unwrap();
+    loop {
+        let stop = cpu.step(&mut memory);
+        if let Some(commit) = cpu.last_commit() {
+            writer.write(commit).unwrap();
+        }
+        if stop != StopReason::InstructionLimit {
+            return read_binary(&writer.into_inner()).unwrap();
+        }
+    }
+}
+
+#[test]
+fn records_operands_memory_traps_and_halt() {
+    let records = records(
+        "
+            la   t0, handler
+            csrw mtvec, t0
+            li   a0, 0x40
+            li   a1, -2
+            sh   a1, 2(a0)
+            amoadd.w a2, a1, (a0)
+            .word 0
+        handler:
+            csrw mtvec, zero
+            li   a7, 93
+            ecall
+        ",
+    );
+    assert_eq!(records.len(), 11);
+    assert!(records.iter().enumerate().all(|(index, record)| record.order == index as u64));
+
+    let store = records[5];
+    assert_eq!((store.rs1_addr, store.rs1_rdata, store.rs2_addr, store.rs2_rdata), (10, 0x40, 11, 0xfffffffe));
+    assert_eq!((store.mem_addr, store.mem_wmask, store.mem_wdata, store.rd_addr), (0x42, 0x3, 0xfffe, 0));
+
+    let amo = records[6];
+    assert_eq!((amo.mem_rmask, amo.mem_wmask, amo.mem_rdata, amo.mem_wdata), (0xf, 0xf, 0xfffe0000, 0xfffdfffe));
+    assert_eq!((amo.rd_addr, amo.rd_wdata), (12, 0xfffe0000));
+
+    let trap = records[7];
+    assert!(trap.trap && !trap.halt);
+    assert_eq!((trap.insn, trap.pc_rdata, trap.pc_wdata, trap.rd_addr), (0, 0x1c, 0x20, 0));
+
+    assert!(records[8].intr);
+    assert_eq!(records[8].mode, 3);
+    assert!(records[10].halt && !records[10].trap);
+}
+
+#[test]
+fn text_format_lists_every_field() {
+    let mut writer = RvfiWriter::new(Vec::new(), RvfiFormat::Text).unwrap();
+    let mut commit = Commit::new(0, Privilege::User, 0x10);
+    commit.raw = 0x00a50533;
+    commit.next_pc = 0x14;
+    commit.rs1 = (10, 3);
+    commit.rs2 = (10, 3);
+    commit.register_writes.push((10, 6));
+    writer.write(&commit).unwrap();
+    let text = String::from_utf8(writer.into_inner()).unwrap();
+    assert_eq!(
+        text,
+        "order=0 insn=0x00a50533 trap=0 halt=0 intr=0 mode=0 rs1_addr=10 rs2_addr=10 rs1_rdata=0x00000003 \
+         rs2_rdata=0x00000003 rd_addr=10 rd_wdata=0x00000006 pc_rdata=0x00000010 pc_wdata=0x00000014 \
+         mem_addr=0x00000000 mem_rmask=0x0 mem_wmask=0x0 mem_rdata=0x00000000 mem_wdata=0x00000000\n"
+    );
+}
@@ -0,0 +1,141 @@
+use riscv_simulator::riscv_sim::*;
+use riscv_simulator::rvwmo::{Litmus, Observation};
+
+const X: u32 = 0x100;
+const Y: u32 = 0x104;
+
+/// A litmus test of `source` observing a0 of each hart. Every hart starts at 0, with x
+/// and y addressed through s0 and s1, and branches to its own code by mhartid.
+fn litmus(source: &str, harts: usize) -> Litmus {
+    let source = format!("li s0, {}\nli s1, {}\n{}", X, Y, source);
+    let image = assemble(&source).unwrap();
+    let mut memory = Memory::with_size(0x200);
+    image.load_into(&mut memory).unwrap();
+    let starts = (0..harts).map(|_| 0).collect();
+    let mut litmus = Litmus::new(memory, starts);
+    for hart in 0..harts {
+        litmus.observe(Observation::Register { hart, register: 10 });
+    }
+    litmus
+}
+
+const DISPATCH: &str = "
+    csrr t0, mhartid
+    bnez t0, hart1
+hart0:
+";
+
+#[test]
+fn stores_become_visible_out_of_order_unless_fenced() {
+    // Store buffering: each hart writes one location and reads the other
+    for (fence, allowed) in [("", true), ("fence rw, rw", false)] {
+        let source = format!(
+            "{}
+                li   t1, 1
+                sw   t1, 0(s0)
+                {fence}
+                lw   a0, 0(s1)
+                li   a7, 93
+                ecall
+            hart1:
+                li   t1, 1
+                sw   t1, 0(s1)
+                {fence}
+                lw   a0, 0(s0)
+                li   a7, 93
+                ecall
+            ",
+            DISPATCH,
+            fence = fence
+        );
+        let exploration = litmus(&source, 2).explore();
+        assert!(exploration.complete);
+        assert!(exploration.allows(&[0, 1]) && exploration.allows(&[1, 0]) && exploration.allows(&[1, 1]));
+        assert_eq!(exploration.allows(&[0, 0]), allowed, "{}", exploration.summary());
+    }
+
+    // Message passing: the flag can be seen before the data it publishes
+    for (fences, allowed) in [(("", ""), true), (("fence w, w", "fence r, r"), false)] {
+        let source = format!(
+            "{}
+                li   t1, 42
+                sw   t1, 0(s0)
+                {writer}
+                li   t1, 1
+                sw   t1, 0(s1)
+                li   a0, 0
+                li   a7, 93
+                ecall
+            hart1:
+                lw   t1, 0(s1)
+                {reader}
+                lw   t2, 0(s0)
+                slli a0, t1, 8
+                or   a0, a0, t2
+                li   a7, 93
+                ecall
+            ",
+            DISPATCH,
+            writer = fences.0,
+            reader = fences.1
+        );
+        let exploration = litmus(&source, 2).explore();
+        assert!(exploration.allows(&[0, 0]) && exploration.allows(&[0, 42]) && exploration.allows(&[0, 256 + 42]));
+        assert_eq!(exploration.allows(&[0, 256]), allowed, "{}", exploration.summary());
+    }
+}
+
+#[test]
+fn atomics_and_forwarding_see_a_single_order() {
+    // Two LR/SC increments and an AMO add never lose an update, and harts 0 and 2
+    // read their own store to y, or a later one
+    let source = "
+        csrr t0, mhartid
+        li   t1, 1
+        beq  t0, t1, hart1
+        li   t1, 2
+        beq  t0, t1, hart2
+    retry:
+        lr.w t1, (s0)
+        addi t1, t1, 1
+        sc.w t2, t1, (s0)
+        bnez t2, retry
+        li   t1, 7
+        sw   t1, 0(s1)
+        lw   a0, 0(s1)
+        li   a7, 93
+        ecall
+    hart1:
+        li   t1, 1
+        amoadd.w zero, t1, (s0)
+    increment:
+        lr.w t1, (s0)
+        addi t1, t1, 1
+        sc.w t2, t1, (s0)
+        bnez t2, increment
+        li   a0, 0
+        li   a7, 93
+        ecall
+    hart2:
+        li   t1, 9
+        sw   t1, 0(s1)
+        lw   a0, 0(s1)
+        li   a7, 93
+        ecall
+    ";
+    let mut litmus = litmus(source, 3);
+    litmus.observe(Observation::Memory { address: X });
+    litmus.observe(Observation::Memory { address: Y });
+    let exploration = litmus.explore();
+    assert!(exploration.complete);
+    for outcome in &exploration.outcomes {
+        assert_eq!(outcome[3], 3, "{}", exploration.summary());
+        assert!([outcome[0], outcome[2], outcome[4]].iter().all(|value| [7, 9].contains(value)));
+    }
+    assert!(exploration.allows(&[7, 0, 9, 3, 9]) && exploration.allows(&[9, 0, 9, 3, 9]) && exploration.allows(&[7, 0, 7, 3, 7]));
+    assert!(exploration.summary().starts_with("hart 0 x10=7, hart 1 x10=0, hart 2 x10=7, [0x100]=3, [0x104]=7\n"));
+
+    // A state limit cuts the search short
+    litmus.set_max_states(10);
+    assert!(!litmus.explore().complete);
+}
@@ -0,0 +1,125 @@
+use riscv_simulator::riscv_sim::*;
+use riscv_simulator::smp::{System, SystemStop};
+
+// Every hart adds 50 to a shared counter with LR/SC, records its mhartid and exits with it
+const COUNTER: &str = "
+    csrr s1, mhartid
+    li   s0, 0x1000
+    li   s2, 50
+loop:
+    lr.w t0, (s0)
+    addi t0, t0, 1
+    sc.w t1, t0, (s0)
+    bnez t1, loop
+    addi s2, s2, -1
+    bnez s2, loop
+    slli t2, s1, 2
+    add  t2, t2, s0
+    sw   s1, 4(t2)
+    mv   a0, s1
+    li   a7, 93
+    ecall
+";
+
+fn system(source: &str, harts: usize, quantum: u64) -> System {
+    let image = assemble(source).unwrap();
+    let mut memory = Memory::with_size(0x2000);
+    image.load_into(&mut memory).unwrap();
+    let mut system = System::new(harts, memory);
+    system.set_quantum(quantum);
+    system
+}
+
+#[test]
+fn harts_share_memory_and_reservations() {
+    for quantum in [1, 2, 5, 1000] {
+        let mut system = system(COUNTER, 4, quantum);
+        assert_eq!(system.run(), SystemStop::Halted);
+        assert_eq!(system.memory().load_word(0x1000), 200, "quantum {}", quantum);
+        for hart in 0..4 {
+            assert_eq!(system.exit_code(hart), Some(hart as u32));
+            assert_eq!(system.memory().load_word(0x1004 + 4 * hart), hart as u32);
+        }
+    }
+
+    // Running in pieces interleaves the harts exactly as one run does
+    let mut whole = system(COUNTER, 3, 5);
+    whole.run();
+    let mut pieces = system(COUNTER, 3, 5);
+    while pieces.run_for(3) == SystemStop::InstructionLimit {}
+    for hart in 0..3 {
+        assert_eq!(pieces.hart(hart).snapshot(pieces.memory()), whole.hart(hart).snapshot(whole.memory()));
+    }
+}
+
+#[test]
+fn stores_from_other_harts_break_reservations_and_cached_code() {
+    // Hart 1 stores to the reserved word between hart 0's LR and SC, unless hart 0
+    // finishes within its first turn
+    let reservation = "
+        csrr t0, mhartid
+        li   s0, 0x1000
+        bnez t0, other
+        lr.w t1, (s0)
+        nop
+        nop
+        sc.w a0, t1, (s0)
+        li   a7, 93
+        ecall
+    other:
+        sw   t0, 0(s0)
+        li   a0, 0
+        li   a7, 93
+        ecall
+    ";
+    for (quantum, failed) in [(1, 1), (1000, 0)] {
+        let mut system = system(reservation, 2, quantum);
+        assert_eq!(system.run(), SystemStop::Halted);
+        assert_eq!(system.exit_code(0), Some(failed));
+    }
+
+    // Hart 1 patches code hart 0 keeps running, then raises a flag
+    let patching = "
+        csrr t0, mhartid
+        li   s0, 0x1000
+        bnez t0, other
+    poll:
+        lw   t1, 0(s0)
+    patched:
+        li   a0, 1
+        beqz t1, poll
+        li   a7, 93
+        ecall
+    other:
+        li   t1, 300
+    delay:
+        addi t1, t1, -1
+        bnez t1, delay
+        li   t1, 0x00700513
+        la   t2, patched
+        sw   t1, 0(t2)
+        sw   t0, 0(s0)
+        li   a0, 0
+        li   a7, 93
+        ecall
+    ";
+    for quantum in [1, 3, 100] {
+        let mut system = system(patching, 2, quantum);
+        for hart in 0..2 {
+            system.hart_mut(hart).set_jit(true);
+        }
+        assert_eq!(system.run(), SystemStop::Halted);
+        assert_eq!(system.exit_code(0), Some(7), "quantum {}", quantum);
+    }
+
+    // Breakpoints stop the system at the hart that hit them, which then resumes
+    let mut system = system(patching, 2, 1);
+    let patched = assemble(patching).unwrap().symbol("patched").unwrap();
+    system.hart_mut(0).add_breakpoint(patched);
+    let stop = SystemStop::Hart { hart: 0, reason: StopReason::Breakpoint { pc: patched } };
+    assert_eq!(system.run(), stop);
+    assert_eq!(system.run(), stop);
+    system.hart_mut(0).remove_breakpoint(patched);
+    assert_eq!(system.run(), SystemStop::Halted);
+    assert_eq!(system.exit_code(0), Some(7));
+}
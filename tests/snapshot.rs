@@ -0,0 +1,52 @@
+use riscv_simulator::riscv_sim::*;
+use riscv_simulator::csr;
+use riscv_simulator::snapshot::{Snapshot, SnapshotError};
+
+mod common;
+use common::{load, MEMORY_SIZE, PROGRAM};
+
+#[test]
+fn restored_machine_resumes_identically() {
+    let (mut cpu, mut memory) = load(PROGRAM);
+    cpu.run_for(&mut memory, 12);
+    cpu.write_csr(csr::MSTATUSH, csr::MSTATUSH_MBE);
+    let data = cpu.snapshot(&memory).to_bytes();
+    assert!(data.len() < 2 * 4096 + 512, "zero pages are not stored");
+
+    let snapshot = Snapshot::from_bytes(&data).unwrap();
+    assert_eq!(snapshot, cpu.snapshot(&memory));
+    assert_eq!(snapshot.reservation, Some(0x2000));
+    assert_eq!(snapshot.data_endianness, Endianness::Big);
+    assert_eq!(cpu.run(&mut memory), StopReason::Halted { exit_code: 10 });
+    let finished = cpu.snapshot(&memory);
+
+    let (mut restored, mut other) = (Cpu::new(), Memory::with_size(0x10));
+    restored.restore(&snapshot, &mut other);
+    assert_eq!((restored.pc(), restored.register(10), restored.read_csr(0x340)), (16, 3, Some(0x2000)));
+    assert_eq!(restored.instret(), 12);
+    assert_eq!(other.data_endianness(), Endianness::Big);
+    assert_eq!(other.size(), MEMORY_SIZE);
+    assert_eq!(restored.run(&mut other), StopReason::Halted { exit_code: 10 });
+    assert_eq!(restored.snapshot(&other), finished);
+}
+
+#[test]
+fn rejects_invalid_snapshots() {
+    let (cpu, memory) = load(PROGRAM);
+    let data = cpu.snapshot(&memory).to_bytes();
+    assert_eq!(Snapshot::from_bytes(b"\x7fELF"), Err(SnapshotError::NotSnapshot));
+
+    let mut newer = data.clone();
+    newer[6] = 2;
+    assert_eq!(Snapshot::from_bytes(&newer), Err(SnapshotError::UnsupportedVersion(2)));
+    assert_eq!(Snapshot::from_bytes(&data[..data.len() - 1]), Err(SnapshotError::Truncated));
+
+    let mut unknown = data.clone();
+    unknown[8..12].copy_from_slice(b"UART");
+    assert_eq!(Snapshot::from_bytes(&unknown), Err(SnapshotError::Malformed("unknown section")));
+
+    let mut huge = data.clone();
+    let size = data.windows(4).position(|tag| tag == b"MEM ").unwrap() + 9;
+    huge[size..size + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(Snapshot::from_bytes(&huge), Err(SnapshotError::Malformed("memory size too large")));
+}
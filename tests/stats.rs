@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use riscv_simulator::riscv_sim::*;
+use riscv_simulator::stats::Statistics;
+
+mod common;
+use common::{load_with, run};
+
+#[test]
+fn counts_mix_branches_and_memory_traffic() {
+    let statistics = run("
+            li   a0, 3
+            li   a1, 0x80
+        loop:
+            sb   a0, 0(a1)
+            lhu  a2, 0(a1)
+            addi a0, a0, -1
+            bnez a0, loop
+            amoadd.w a3, a0, (a1)
+            li   a7, 93
+            ecall
+    ", Statistics::new());
+    assert_eq!(statistics.instructions(), 17);
+    assert_eq!(statistics.mnemonics["addi"], 6);
+    assert_eq!(statistics.mnemonics["bne"], 3);
+    assert_eq!((statistics.branches_taken, statistics.branches_not_taken), (2, 1));
+    assert_eq!((statistics.loads, statistics.load_bytes), (4, 10));
+    assert_eq!((statistics.stores, statistics.store_bytes), (4, 7));
+    assert_eq!(statistics.traps, 0, "the exit ecall is serviced by the host");
+    let formats: Vec<(String, u64)> = ["B", "I", "S", "System", "Atomic"].iter().zip([3, 9, 3, 1, 1]).map(|(f, n)| (f.to_string(), n)).collect();
+    assert_eq!(statistics.formats(), formats);
+}
+
+#[test]
+fn counts_handled_traps_and_branches_by_condition() {
+    for blocks in [false, true] {
+        let (mut cpu, mut memory) = load_with("
+                la   t0, handler
+                csrw mtvec, t0
+                beq  zero, zero, next
+            next:
+                bne  zero, zero, next
+                .word 0
+            handler:
+                csrw mtvec, zero
+                li   a7, 93
+                ecall
+        ", Statistics::new());
+        cpu.set_block_translation(blocks);
+        assert!(matches!(cpu.run(&mut memory), StopReason::Halted { .. }));
+        let statistics = cpu.into_hooks();
+        assert_eq!((statistics.branches_taken, statistics.branches_not_taken), (1, 1));
+        assert_eq!(statistics.traps, 1);
+    }
+}
+
+#[test]
+fn reports_as_text_and_json() {
+    let statistics = run("li a0, 1\nadd a0, a0, a0\nli a7, 93\necall", Statistics::new());
+    let elapsed = Duration::from_millis(1);
+    assert!((statistics.mips(elapsed) - 0.004).abs() < 1e-9);
+
+    let summary = statistics.summary(elapsed);
+    assert!(summary.starts_with("instructions  4\nelapsed       0.001000 s (0.00 MIPS)\n"));
+    assert!(summary.contains("\naddi                2   50.00%\n"));
+
+    let json = statistics.to_json(elapsed);
+    assert!(json.contains("  \"instructions\": 4,\n  \"seconds\": 0.001000,\n  \"mips\": 0.004,\n"));
+    assert!(json.contains("  \"formats\": {\"I\": 2, \"R\": 1, \"System\": 1},\n"));
+    assert!(json.ends_with("  \"mnemonics\": {\"addi\": 2, \"add\": 1, \"ecall\": 1}\n}\n"));
+}
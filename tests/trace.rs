@@ -0,0 +1,54 @@
+use riscv_simulator::riscv_sim::*;
+
+fn commit_lines(source: &str) -> Vec<(String, String)> {
+    let image = assemble(source).unwrap();
+    let mut memory = Memory::with_size(0x100);
+    image.load_into(&mut memory).unwrap();
+    let mut cpu = Cpu::new();
+    cpu.set_commit_log(true);
+    let mut lines = Vec::new();
+    loop {
+        let stop = cpu.step(&mut memory);
+        if let Some(commit) = cpu.last_commit().filter(|commit| !commit.trap) {
+            lines.push((commit.spike_instruction(), commit.spike_commit()));
+        }
+        if stop != StopReason::InstructionLimit {
+            return lines;
+        }
+    }
+}
+
+#[test]
+fn logs_commits_in_spike_format() {
+    let lines = commit_lines(
+        "
+            li   a0, 5
+            sw   a0, 0x40(zero)
+            lh   a1, 0x40(zero)
+            sb   a0, 0x41(zero)
+            csrw mscratch, a0
+            amoadd.w a2, a0, (zero)
+            ebreak
+        ",
+    );
+    let expected = [
+        ("core   0: 0x00000000 (0x00500513) li      a0, 5", "core   0: 3 0x00000000 (0x00500513) x10 0x00000005"),
+        ("core   0: 0x00000004 (0x04a02023) sw      a0, 64(zero)", "core   0: 3 0x00000004 (0x04a02023) mem 0x00000040 0x00000005"),
+        ("core   0: 0x00000008 (0x04001583) lh      a1, 64(zero)", "core   0: 3 0x00000008 (0x04001583) x11 0x00000005 mem 0x00000040"),
+        ("core   0: 0x0000000c (0x04a000a3) sb      a0, 65(zero)", "core   0: 3 0x0000000c (0x04a000a3) mem 0x00000041 0x05"),
+        ("core   0: 0x00000010 (0x34051073) csrw    mscratch, a0", "core   0: 3 0x00000010 (0x34051073) c832_mscratch 0x00000005"),
+        (
+            "core   0: 0x00000014 (0x00a0262f) amoadd.w a2, a0, (zero)",
+            "core   0: 3 0x00000014 (0x00a0262f) x12 0x00500513 mem 0x00000000 mem 0x00000000 0x00500518",
+        ),
+        ("core   0: 0x00000018 (0x00100073) ebreak", "core   0: 3 0x00000018 (0x00100073)"),
+    ];
+    let expected: Vec<(String, String)> = expected.iter().map(|(a, b)| (a.to_string(), b.to_string())).collect();
+    assert_eq!(lines, expected);
+}
+
+#[test]
+fn trapped_instructions_are_not_committed() {
+    let lines = commit_lines("li a0, 1\nlw a1, 2(zero)");
+    assert_eq!(lines.len(), 1);
+}